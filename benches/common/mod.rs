@@ -0,0 +1,51 @@
+//! ベンチマーク用の合成データの生成
+
+// ベンチマークごとに使う関数が異なるため、使わない関数があっても警告しない
+#![allow(dead_code)]
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// 日本の範囲内の座標を決まった順序で生成する (実行ごとに同じ値になるよう、乱数のシードは固定)
+pub struct Coordinates {
+    state: u64,
+}
+
+impl Coordinates {
+    pub fn new() -> Self {
+        Coordinates {
+            state: 0x9E37_79B9_7F4A_7C15,
+        }
+    }
+
+    /// 0以上1未満の一様な値 (xorshift64*)
+    fn next_unit(&mut self) -> f64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        let value = self.state.wrapping_mul(0x2545_F491_4F6C_DD1D);
+        (value >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+impl Iterator for Coordinates {
+    type Item = (f64, f64);
+
+    /// 本州付近 (緯度33〜40度、経度130〜141度) の座標
+    fn next(&mut self) -> Option<(f64, f64)> {
+        let lat = 33.0 + self.next_unit() * 7.0;
+        let lon = 130.0 + self.next_unit() * 11.0;
+        Some((lat, lon))
+    }
+}
+
+/// `id,name,lat,lon` の列を持つ合成CSVを `rows` 行書き出す
+pub fn write_csv(path: &Path, rows: usize) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "id,name,lat,lon")?;
+    for (id, (lat, lon)) in Coordinates::new().take(rows).enumerate() {
+        writeln!(writer, "{},地点{},{:.6},{:.6}", id, id, lat, lon)?;
+    }
+    writer.flush()
+}
@@ -0,0 +1,85 @@
+//! メッシュコードの計算のベンチマーク
+
+mod common;
+
+use clap::ValueEnum;
+use common::Coordinates;
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use meshify::MeshLevel;
+use std::hint::black_box;
+
+/// 1回の計測で計算する座標の数
+const POINTS: usize = 10_000;
+
+fn bench_get_mesh_code(c: &mut Criterion) {
+    let points = Coordinates::new().take(POINTS).collect::<Vec<(f64, f64)>>();
+    let mut group = c.benchmark_group("get_mesh_code");
+    group.throughput(Throughput::Elements(POINTS as u64));
+    for level in MeshLevel::value_variants() {
+        let name = level.to_possible_value().unwrap();
+        group.bench_with_input(
+            BenchmarkId::from_parameter(name.get_name()),
+            level,
+            |b, &level| {
+                b.iter(|| {
+                    for &(lat, lon) in &points {
+                        black_box(meshify::get_mesh_code(
+                            black_box(lat),
+                            black_box(lon),
+                            level,
+                        ));
+                    }
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+/// バッファを使い回す `write_mesh_code` と、行ごとに `String` を確保する `get_mesh_code` の比較
+fn bench_write_mesh_code(c: &mut Criterion) {
+    let points = Coordinates::new().take(POINTS).collect::<Vec<(f64, f64)>>();
+    let mut group = c.benchmark_group("write_mesh_code");
+    group.throughput(Throughput::Elements(POINTS as u64));
+    group.bench_function("eighth", |b| {
+        let mut code = String::new();
+        b.iter(|| {
+            for &(lat, lon) in &points {
+                code.clear();
+                meshify::write_mesh_code(
+                    &mut code,
+                    black_box(lat),
+                    black_box(lon),
+                    MeshLevel::Eighth,
+                );
+                black_box(&code);
+            }
+        })
+    });
+    group.finish();
+}
+
+fn bench_mesh_code_to_center(c: &mut Criterion) {
+    let codes = Coordinates::new()
+        .take(POINTS)
+        .map(|(lat, lon)| meshify::get_mesh_code(lat, lon, MeshLevel::Standard))
+        .collect::<Vec<String>>();
+    let mut group = c.benchmark_group("mesh_code_to_center");
+    group.throughput(Throughput::Elements(POINTS as u64));
+    group.bench_function("standard", |b| {
+        b.iter(|| {
+            for code in &codes {
+                black_box(meshify::mesh_code_to_center(black_box(code)).unwrap());
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_get_mesh_code,
+    bench_write_mesh_code,
+    bench_mesh_code_to_center
+);
+criterion_main!(benches);
@@ -0,0 +1,60 @@
+//! CSVの読み込みからメッシュコード付きのCSVの書き出しまで、コマンド全体のベンチマーク
+//!
+//! ビルドした meshify を合成CSVに対して実行し、処理時間を測る。行数は環境変数
+//! `MESHIFY_BENCH_ROWS` で変えられる (既定は100万行)。測地系の変換 (Proj) の有無で比べられるよう、
+//! 世界測地系の入力と日本測地系 (`--datum tokyo`) の入力の両方を測る。
+
+mod common;
+
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+const DEFAULT_ROWS: usize = 1_000_000;
+
+/// meshify を実行し、出力は捨てる
+fn run_meshify(input: &Path, extra_args: &[&str]) {
+    let status = Command::new(env!("CARGO_BIN_EXE_meshify"))
+        .args(["--lat", "lat", "--lon", "lon", "--quiet", "--output", "-"])
+        .args(extra_args)
+        .arg(input)
+        .stdout(std::process::Stdio::null())
+        .status()
+        .expect("meshify を実行できる");
+    assert!(status.success(), "meshify が失敗しました: {}", status);
+}
+
+fn bench_pipeline(c: &mut Criterion) {
+    let rows = env::var("MESHIFY_BENCH_ROWS")
+        .ok()
+        .and_then(|rows| rows.parse().ok())
+        .unwrap_or(DEFAULT_ROWS);
+    let input = env::temp_dir().join(format!("meshify_bench_{}.csv", rows));
+    if !input.exists() {
+        common::write_csv(&input, rows).expect("合成CSVを書き出せる");
+    }
+
+    let mut group = c.benchmark_group("pipeline");
+    group.throughput(Throughput::Elements(rows as u64));
+    // 1回の実行に数秒かかるため、計測回数は最小限にする
+    group.sample_size(10);
+    group.bench_function("wgs", |b| b.iter(|| run_meshify(&input, &[])));
+    group.bench_function("tokyo", |b| {
+        b.iter(|| run_meshify(&input, &["--datum", "tokyo"]))
+    });
+    group.bench_function("wgs_all_levels", |b| {
+        b.iter(|| {
+            run_meshify(
+                &input,
+                &[
+                    "--level", "first", "--level", "standard", "--level", "eighth",
+                ],
+            )
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_pipeline);
+criterion_main!(benches);
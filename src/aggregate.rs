@@ -0,0 +1,228 @@
+//! メッシュごとの点数・重みの集計
+
+use crate::output::RecordWriter;
+use clap::ValueEnum;
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+/// 集計結果の並び順
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum AggregateOrder {
+    /// メッシュコードの昇順
+    Code,
+    /// 集計値の降順 (`--weight` 指定時は重みの合計、それ以外は件数。同じ値の場合はメッシュコードの昇順)
+    Count,
+}
+
+/// 集計の方法
+#[derive(clap::Args, Clone, Debug)]
+pub struct Aggregation {
+    /// 集計結果の並び順
+    #[arg(long, default_value = "code")]
+    pub sort: AggregateOrder,
+
+    /// 件数の代わりに、この列の値の合計をメッシュごとに weight_sum 列へ出力する
+    #[arg(long)]
+    pub weight: Option<String>,
+
+    /// `--weight` 指定時も件数を count 列に出力する
+    #[arg(long, requires = "weight")]
+    pub with_count: bool,
+}
+
+/// 重み列の値を解析する。数値として解釈できない値や、無限大・NaN の場合は `None` を返す
+pub fn parse_weight(s: &str) -> Option<f64> {
+    s.trim().parse::<f64>().ok().filter(|w| w.is_finite())
+}
+
+/// メッシュごとの集計値
+#[derive(Copy, Clone, Debug, Default)]
+struct Total {
+    count: u64,
+    weight_sum: f64,
+}
+
+/// 行を書き出す代わりにメッシュコードごとの件数 (と重みの合計) を数え、終了時にCSVとして書き出す
+///
+/// メッシュコードが空欄の行 (範囲外の座標を残した行) は数えない。重みの値は呼び出し側で検証済みであること。
+pub struct MeshCounter<W: Write> {
+    writer: csv::Writer<W>,
+    aggregation: Aggregation,
+    mesh_column: String,
+    weight_index: Option<usize>,
+    totals: HashMap<String, Total>,
+}
+
+impl<W: Write> MeshCounter<W> {
+    pub fn new(writer: csv::Writer<W>, aggregation: &Aggregation, mesh_column: &str) -> Self {
+        MeshCounter {
+            writer,
+            aggregation: aggregation.clone(),
+            mesh_column: mesh_column.to_string(),
+            weight_index: None,
+            totals: HashMap::new(),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner().ok().unwrap()
+    }
+}
+
+impl<W: Write> RecordWriter for MeshCounter<W> {
+    fn write_headers(&mut self, headers: &[String]) -> io::Result<()> {
+        if let Some(weight) = &self.aggregation.weight {
+            let index = headers.iter().position(|h| h == weight).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "重み列が見つかりません")
+            })?;
+            self.weight_index = Some(index);
+        }
+        Ok(())
+    }
+
+    fn write_row(&mut self, fields: &[&str], mesh_code: &str) -> io::Result<()> {
+        if mesh_code.is_empty() {
+            return Ok(());
+        }
+        let weight = match self.weight_index {
+            Some(index) => parse_weight(fields[index]).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("重みの値「{}」が不正です", fields[index]),
+                )
+            })?,
+            None => 0.0,
+        };
+        let total = self.totals.entry(mesh_code.to_string()).or_default();
+        total.count += 1;
+        total.weight_sum += weight;
+        Ok(())
+    }
+
+    /// 集計結果は最後にまとめて書き出すため、途中では何もしない
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let weighted = self.aggregation.weight.is_some();
+        let mut totals = std::mem::take(&mut self.totals)
+            .into_iter()
+            .collect::<Vec<(String, Total)>>();
+        match self.aggregation.sort {
+            AggregateOrder::Code => totals.sort_unstable_by(|(a, _), (b, _)| a.cmp(b)),
+            AggregateOrder::Count if weighted => {
+                totals.sort_unstable_by(|(a_code, a), (b_code, b)| {
+                    b.weight_sum
+                        .total_cmp(&a.weight_sum)
+                        .then(a_code.cmp(b_code))
+                })
+            }
+            AggregateOrder::Count => totals.sort_unstable_by(|(a_code, a), (b_code, b)| {
+                b.count.cmp(&a.count).then(a_code.cmp(b_code))
+            }),
+        }
+
+        let with_count = !weighted || self.aggregation.with_count;
+        let mut headers = vec![self.mesh_column.as_str()];
+        if with_count {
+            headers.push("count");
+        }
+        if weighted {
+            headers.push("weight_sum");
+        }
+        self.writer.write_record(headers)?;
+        for (code, total) in totals {
+            let mut record = vec![code];
+            if with_count {
+                record.push(total.count.to_string());
+            }
+            if weighted {
+                record.push(total.weight_sum.to_string());
+            }
+            self.writer.write_record(record)?;
+        }
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aggregate(aggregation: Aggregation, rows: &[(&str, &str)]) -> String {
+        let mut counter = MeshCounter::new(
+            csv::Writer::from_writer(Vec::new()),
+            &aggregation,
+            "mesh_code",
+        );
+        let headers = ["weight".to_string(), "mesh_code".to_string()];
+        counter.write_headers(&headers).unwrap();
+        for (weight, code) in rows {
+            counter.write_row(&[weight, code], code).unwrap();
+        }
+        counter.finish().unwrap();
+        String::from_utf8(counter.into_inner()).unwrap()
+    }
+
+    fn aggregation(sort: AggregateOrder, weight: Option<&str>, with_count: bool) -> Aggregation {
+        Aggregation {
+            sort,
+            weight: weight.map(String::from),
+            with_count,
+        }
+    }
+
+    const ROWS: [(&str, &str); 5] = [
+        ("1", "53394611"),
+        ("10", "53394610"),
+        ("2.5", "53394611"),
+        ("100", ""),
+        ("4", "52394000"),
+    ];
+
+    #[test]
+    fn test_mesh_counter() {
+        assert_eq!(
+            aggregate(aggregation(AggregateOrder::Code, None, false), &ROWS),
+            "mesh_code,count\n52394000,1\n53394610,1\n53394611,2\n"
+        );
+        assert_eq!(
+            aggregate(aggregation(AggregateOrder::Count, None, false), &ROWS),
+            "mesh_code,count\n53394611,2\n52394000,1\n53394610,1\n"
+        );
+        assert_eq!(
+            aggregate(aggregation(AggregateOrder::Code, None, false), &[]),
+            "mesh_code,count\n"
+        );
+    }
+
+    #[test]
+    fn test_mesh_counter_weight() {
+        assert_eq!(
+            aggregate(
+                aggregation(AggregateOrder::Count, Some("weight"), false),
+                &ROWS
+            ),
+            "mesh_code,weight_sum\n53394610,10\n52394000,4\n53394611,3.5\n"
+        );
+        assert_eq!(
+            aggregate(
+                aggregation(AggregateOrder::Code, Some("weight"), true),
+                &ROWS
+            ),
+            "mesh_code,count,weight_sum\n52394000,1,4\n53394610,1,10\n53394611,2,3.5\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_weight() {
+        assert_eq!(parse_weight(" 12.5 "), Some(12.5));
+        assert_eq!(parse_weight("-3"), Some(-3.0));
+        assert_eq!(parse_weight(""), None);
+        assert_eq!(parse_weight("abc"), None);
+        assert_eq!(parse_weight("NaN"), None);
+        assert_eq!(parse_weight("inf"), None);
+    }
+}
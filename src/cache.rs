@@ -0,0 +1,205 @@
+//! 同じ座標が繰り返し現れる入力での、変換結果とメッシュ計算の結果の再利用
+
+use clap::ValueEnum;
+use meshify::{DatumConverter, MeshifyError};
+use std::collections::HashMap;
+
+/// キャッシュに持つ座標の数の上限。超えた後に現れた座標はキャッシュせずにその都度計算する
+const MAX_ENTRIES: usize = 1 << 20;
+
+/// キャッシュのキーにする座標の正規化の方法
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum CacheKey {
+    /// 値のビット表現をキーにする (`-0.0` は `0.0` とみなす)。結果はキャッシュしない場合と変わらない
+    Bits,
+    /// 小数点以下 `--coord-cache-digits` 桁に丸めた値をキーにする
+    ///
+    /// 丸めると同じになる座標には、最初に現れた座標の結果を使う。キャッシュはワーカーのスレッドごとに持つため、
+    /// 複数のスレッドでは、どの座標が最初になるかが各ワーカーの処理したチャンクによって変わりうる。
+    Rounded,
+}
+
+/// 座標をキーの表現にする
+#[derive(Copy, Clone, Debug)]
+struct KeyFormat {
+    key: CacheKey,
+    /// `Rounded` の場合に掛ける 10^桁数
+    scale: f64,
+}
+
+impl KeyFormat {
+    fn key(self, lat: f64, lon: f64) -> (u64, u64) {
+        let normalize = |value: f64| match self.key {
+            // -0.0 と 0.0 はビット表現が異なるため、そろえてから比べる
+            CacheKey::Bits => (value + 0.0).to_bits(),
+            CacheKey::Rounded => ((value * self.scale).round() as i64) as u64,
+        };
+        (normalize(lat), normalize(lon))
+    }
+}
+
+/// 入力の座標から世界測地系への変換結果のキャッシュ (座標を変換するワーカーのスレッドごとに使う)
+pub struct CoordCache {
+    format: Option<KeyFormat>,
+    /// 変換器ごと (`--datum-column` の測地系ごと) に分けるため、変換器のアドレスもキーに含める
+    entries: HashMap<(usize, u64, u64), (f64, f64)>,
+    lookups: u64,
+    hits: u64,
+}
+
+impl CoordCache {
+    /// `key` が `None` の場合はキャッシュせず、常に変換する
+    pub fn new(key: Option<CacheKey>, digits: u32) -> Self {
+        CoordCache {
+            format: key.map(|key| KeyFormat {
+                key,
+                scale: 10f64.powi(digits as i32),
+            }),
+            entries: HashMap::new(),
+            lookups: 0,
+            hits: 0,
+        }
+    }
+
+    /// 座標を世界測地系に変換する。同じキーの座標を変換済みなら、その結果を返す
+    pub fn convert(
+        &mut self,
+        converter: &DatumConverter,
+        lat: f64,
+        lon: f64,
+    ) -> Result<(f64, f64), MeshifyError> {
+        let Some(format) = self.format else {
+            return converter.to_wgs(lat, lon);
+        };
+        let (lat_key, lon_key) = format.key(lat, lon);
+        let key = (
+            converter as *const DatumConverter as usize,
+            lat_key,
+            lon_key,
+        );
+        self.lookups += 1;
+        if let Some(&wgs) = self.entries.get(&key) {
+            self.hits += 1;
+            return Ok(wgs);
+        }
+        let wgs = converter.to_wgs(lat, lon)?;
+        if self.entries.len() < MAX_ENTRIES {
+            self.entries.insert(key, wgs);
+        }
+        Ok(wgs)
+    }
+
+    /// キャッシュ (ワーカーごとに持つ場合はその合計) の参照回数とヒット率を `-v` のログに出す
+    pub fn log_stats(caches: &[CoordCache]) {
+        let lookups = caches.iter().map(|cache| cache.lookups).sum::<u64>();
+        if lookups == 0 {
+            return;
+        }
+        let hits = caches.iter().map(|cache| cache.hits).sum::<u64>();
+        log::debug!(
+            "座標のキャッシュ: 参照 {}回、ヒット {}回 (ヒット率 {:.1}%)、保持している座標 {}件",
+            lookups,
+            hits,
+            hits as f64 / lookups as f64 * 100.0,
+            caches
+                .iter()
+                .map(|cache| cache.entries.len())
+                .sum::<usize>()
+        );
+    }
+}
+
+/// 世界測地系の座標ごとの、追加列 (メッシュコードなど) の値のキャッシュ (チャンクを計算するワーカーごとに使う)
+///
+/// 同じワーカーの `CoordCache` で同じキーの座標は同じ値に変換されるため、ビット表現をそのままキーにしてよい。
+pub struct FieldCache {
+    enabled: bool,
+    entries: HashMap<(u64, u64), Vec<String>>,
+}
+
+impl FieldCache {
+    pub fn new(key: Option<CacheKey>) -> Self {
+        FieldCache {
+            enabled: key.is_some(),
+            entries: HashMap::new(),
+        }
+    }
+
+    /// 座標の追加列の値を返す。計算済みでなければ `compute` で求める
+    pub fn get_or_compute(
+        &mut self,
+        lat: f64,
+        lon: f64,
+        compute: impl FnOnce() -> Vec<String>,
+    ) -> Vec<String> {
+        if !self.enabled {
+            return compute();
+        }
+        let key = (lat.to_bits(), lon.to_bits());
+        if let Some(fields) = self.entries.get(&key) {
+            return fields.clone();
+        }
+        let fields = compute();
+        if self.entries.len() < MAX_ENTRIES {
+            self.entries.insert(key, fields.clone());
+        }
+        fields
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use meshify::Datum;
+
+    #[test]
+    fn test_coord_cache() {
+        let converter = DatumConverter::new(Datum::WGS).unwrap();
+        let mut cache = CoordCache::new(Some(CacheKey::Bits), 0);
+        assert_eq!(
+            cache.convert(&converter, 35.68, 139.76).unwrap(),
+            (35.68, 139.76)
+        );
+        assert_eq!(
+            cache.convert(&converter, 35.68, 139.76).unwrap(),
+            (35.68, 139.76)
+        );
+        assert_eq!(cache.convert(&converter, 0.0, -0.0).unwrap(), (0.0, -0.0));
+        assert_eq!(cache.convert(&converter, -0.0, 0.0).unwrap().0, 0.0);
+        assert_eq!((cache.lookups, cache.hits), (4, 2));
+
+        // 丸めると同じになる座標は、最初に現れた座標の結果を使う
+        let mut cache = CoordCache::new(Some(CacheKey::Rounded), 3);
+        assert_eq!(
+            cache.convert(&converter, 35.6801, 139.7601).unwrap(),
+            (35.6801, 139.7601)
+        );
+        assert_eq!(
+            cache.convert(&converter, 35.6799, 139.7599).unwrap(),
+            (35.6801, 139.7601)
+        );
+        assert_eq!(
+            cache.convert(&converter, 35.681, 139.76).unwrap(),
+            (35.681, 139.76)
+        );
+        assert_eq!(cache.hits, 1);
+
+        let mut cache = CoordCache::new(None, 0);
+        cache.convert(&converter, 35.68, 139.76).unwrap();
+        assert_eq!(cache.lookups, 0);
+    }
+
+    #[test]
+    fn test_field_cache() {
+        let mut cache = FieldCache::new(Some(CacheKey::Bits));
+        let mut computed = 0;
+        for _ in 0..3 {
+            let fields = cache.get_or_compute(35.68, 139.76, || {
+                computed += 1;
+                vec!["53394610".to_string()]
+            });
+            assert_eq!(fields, ["53394610"]);
+        }
+        assert_eq!(computed, 1);
+    }
+}
@@ -0,0 +1,89 @@
+//! gzip圧縮された入出力の読み書き
+
+use flate2::Compression;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+/// gzipデータの先頭2バイト
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// パスの拡張子が `.gz` かどうか
+pub fn is_gzip_path(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("gz"))
+}
+
+/// 入力の先頭がgzipのマジックナンバーであれば展開しながら読む
+///
+/// 拡張子ではなく中身で判定するため、標準入力から圧縮データを流し込んだ場合でも扱える。
+pub fn decompress(input: Box<dyn Read>) -> io::Result<Box<dyn Read>> {
+    let mut input = BufReader::new(input);
+    if input.fill_buf()?.starts_with(&GZIP_MAGIC) {
+        // 複数のgzipメンバーを連結したファイル (`cat a.gz b.gz`) も最後まで読めるようにする
+        Ok(Box::new(MultiGzDecoder::new(input)))
+    } else {
+        Ok(Box::new(input))
+    }
+}
+
+/// 出力をgzipで圧縮する (`level` は0〜9)
+///
+/// gzipの末尾 (CRCとサイズ) は書き出し先を破棄する際に書き込まれる。
+pub fn compress(output: Box<dyn Write>, level: u32) -> Box<dyn Write> {
+    Box::new(GzEncoder::new(output, Compression::new(level)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// 破棄された後も書き込まれた内容を取り出せる出力先
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let buffer = SharedBuffer::default();
+        let mut output = compress(Box::new(buffer.clone()), 6);
+        output.write_all(b"id,lat,lon\n1,35.68,139.76\n").unwrap();
+        drop(output);
+
+        let compressed = buffer.0.lock().unwrap().clone();
+        assert!(compressed.starts_with(&GZIP_MAGIC));
+        let mut text = String::new();
+        decompress(Box::new(io::Cursor::new(compressed)))
+            .unwrap()
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(text, "id,lat,lon\n1,35.68,139.76\n");
+
+        // 圧縮されていない入力はそのまま読む
+        let mut text = String::new();
+        decompress(Box::new(io::Cursor::new(b"id\n1\n".to_vec())))
+            .unwrap()
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(text, "id\n1\n");
+    }
+
+    #[test]
+    fn test_is_gzip_path() {
+        assert!(is_gzip_path(Path::new("data/points.csv.gz")));
+        assert!(is_gzip_path(Path::new("POINTS.CSV.GZ")));
+        assert!(!is_gzip_path(Path::new("points.csv")));
+        assert!(!is_gzip_path(Path::new("-")));
+    }
+}
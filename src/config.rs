@@ -0,0 +1,272 @@
+//! 設定ファイル (TOML) からのオプションの既定値の読み込み
+//!
+//! 設定ファイルの項目名はオプションの長い名前 (`lat`、`output-encoding` など。`_` 区切りも可) で、
+//! 値はコマンドラインで指定する値と同じ表記にする。
+//!
+//! ```toml
+//! lat = "緯度"
+//! lon = "経度"
+//! datum = "jgs"
+//! level = ["standard", "half"]
+//! output-encoding = "shift_jis"
+//! no-progress = true
+//! ```
+//!
+//! 設定ファイルの値はコマンドライン引数に変換して、ユーザーが指定した引数の後ろに加える。
+//! コマンドラインで指定したオプションと、それと同時に指定できないオプション (`--lat` に対する
+//! `--latlon` など) は設定ファイルから加えないため、常にコマンドラインの指定が優先される。
+
+use clap::parser::ValueSource;
+use clap::{ArgAction, ArgMatches, Command, FromArgMatches};
+use std::error::Error;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// `--config` を指定しない場合に読み込む、カレントディレクトリの設定ファイル
+const DEFAULT_PATH: &str = "meshify.toml";
+
+/// 設定ファイルを読み込む際の引数
+#[derive(clap::Args, Debug)]
+pub struct ConfigArgs {
+    /// オプションの既定値を読み込む設定ファイル (TOML。指定しない場合はカレントディレクトリの meshify.toml があれば読み込む)
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+}
+
+/// コマンドライン引数に、設定ファイルから読み込んだ既定値の引数を加える
+///
+/// 設定ファイルが無い場合や、コマンドライン引数自体を解釈できない場合 (ヘルプの表示など) は
+/// そのまま返し、通常の解析でエラーやヘルプを表示させる。
+pub fn args_with_config(
+    command: Command,
+    args: Vec<OsString>,
+) -> Result<Vec<OsString>, Box<dyn Error>> {
+    let mut command = command.ignore_errors(true);
+    command.build();
+    let Ok(matches) = command.clone().try_get_matches_from(&args) else {
+        return Ok(args);
+    };
+    let (subcommand, matches) = match matches.subcommand() {
+        Some((name, matches)) => (command.find_subcommand(name), matches),
+        None => (None, &matches),
+    };
+
+    let config = ConfigArgs::from_arg_matches(matches).map_err(|e| e.to_string())?;
+    let path = match config.config {
+        Some(path) => path,
+        None if Path::new(DEFAULT_PATH).is_file() => PathBuf::from(DEFAULT_PATH),
+        None => return Ok(args),
+    };
+    let text = fs::read_to_string(&path)
+        .map_err(|e| format!("設定ファイル「{}」を読み込めません: {}", path.display(), e))?;
+    let table = text
+        .parse::<toml::Table>()
+        .map_err(|e| format!("設定ファイル「{}」を解釈できません: {}", path.display(), e))?;
+    let defaults = config_args(&command, subcommand.unwrap_or(&command), matches, &table)
+        .map_err(|e| format!("設定ファイル「{}」: {}", path.display(), e))?;
+
+    // `--` 以降はすべて入力ファイルとして扱われるため、その前に加える
+    let mut args = args;
+    let position = args
+        .iter()
+        .position(|arg| arg == "--")
+        .unwrap_or(args.len());
+    args.splice(position..position, defaults);
+    Ok(args)
+}
+
+/// 設定ファイルの項目を、実行するコマンドの引数に変換する
+///
+/// `root` はすべてのサブコマンドを含むコマンドで、どのサブコマンドにも無い項目はエラーにする。
+/// 実行するコマンド (`command`) に無い項目 (`grid` の実行時の `lat` など) は無視する。
+fn config_args(
+    root: &Command,
+    command: &Command,
+    matches: &ArgMatches,
+    table: &toml::Table,
+) -> Result<Vec<OsString>, String> {
+    let is_explicit = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+    let mut args = Vec::new();
+    for (key, value) in table {
+        let long = key.replace('_', "-");
+        let Some(arg) = command
+            .get_arguments()
+            .find(|arg| is_configurable(arg, &long))
+        else {
+            let known = root.get_subcommands().chain([root]).any(|command| {
+                command
+                    .get_arguments()
+                    .any(|arg| is_configurable(arg, &long))
+            });
+            if known {
+                continue;
+            }
+            return Err(format!("項目「{}」に対応するオプションがありません", key));
+        };
+
+        // コマンドラインで指定されたオプション、またはそれと同時に指定できないオプションは加えない
+        let id = arg.get_id().as_str();
+        let overridden = is_explicit(id)
+            || command.get_arguments().any(|other| {
+                is_explicit(other.get_id().as_str())
+                    && (command.get_arg_conflicts_with(arg).contains(&other)
+                        || command.get_arg_conflicts_with(other).contains(&arg))
+            });
+        if overridden {
+            continue;
+        }
+        args.extend(
+            option_args(&long, arg.get_action(), value)
+                .map_err(|e| format!("項目「{}」の値が不正です: {}", key, e))?,
+        );
+    }
+    Ok(args)
+}
+
+/// 設定ファイルで指定できるオプションか (ヘルプ・バージョンの表示と `--config` 自体は除く)
+fn is_configurable(arg: &clap::Arg, long: &str) -> bool {
+    arg.get_long() == Some(long)
+        && long != "config"
+        && !matches!(
+            arg.get_action(),
+            ArgAction::Help | ArgAction::HelpShort | ArgAction::HelpLong | ArgAction::Version
+        )
+}
+
+/// 設定ファイルの1項目を、コマンドラインの引数の並びに変換する
+fn option_args(
+    long: &str,
+    action: &ArgAction,
+    value: &toml::Value,
+) -> Result<Vec<OsString>, String> {
+    let flag = || OsString::from(format!("--{}", long));
+    match (action, value) {
+        (ArgAction::SetTrue, toml::Value::Boolean(set)) => Ok(set.then(flag).into_iter().collect()),
+        (ArgAction::SetTrue, _) => Err("true か false で指定してください".to_string()),
+        (ArgAction::Count, toml::Value::Integer(count)) => {
+            let count = usize::try_from(*count).map_err(|_| "0以上の整数で指定してください")?;
+            Ok(vec![flag(); count])
+        }
+        (ArgAction::Count, _) => Err("0以上の整数で指定してください".to_string()),
+        (_, toml::Value::Array(values)) => values
+            .iter()
+            .map(|value| Ok(format!("--{}={}", long, scalar(value)?).into()))
+            .collect(),
+        (_, value) => Ok(vec![format!("--{}={}", long, scalar(value)?).into()]),
+    }
+}
+
+/// 設定ファイルの値を、コマンドラインで指定する値の表記にする
+fn scalar(value: &toml::Value) -> Result<String, String> {
+    match value {
+        toml::Value::String(s) => Ok(s.clone()),
+        toml::Value::Integer(n) => Ok(n.to_string()),
+        toml::Value::Float(x) => Ok(x.to_string()),
+        toml::Value::Boolean(b) => Ok(b.to_string()),
+        toml::Value::Datetime(_) | toml::Value::Array(_) | toml::Value::Table(_) => {
+            Err("文字列・数値・真偽値のいずれかで指定してください".to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{CommandFactory, Parser};
+
+    #[derive(Parser)]
+    struct TestCli {
+        #[arg(long, conflicts_with = "latlon")]
+        lat: Option<String>,
+        #[arg(long)]
+        latlon: Option<String>,
+        #[arg(long)]
+        level: Vec<String>,
+        #[arg(long)]
+        no_progress: bool,
+        #[arg(short, long, action = ArgAction::Count)]
+        verbose: u8,
+        #[command(flatten)]
+        config: ConfigArgs,
+    }
+
+    fn args(cli_args: &[&str], config: &str) -> Result<Vec<String>, String> {
+        let mut command = TestCli::command();
+        command.build();
+        let matches = command
+            .clone()
+            .try_get_matches_from(["meshify"].iter().chain(cli_args))
+            .unwrap();
+        let table = config.parse::<toml::Table>().unwrap();
+        let args = config_args(&command, &command, &matches, &table)?;
+        Ok(args
+            .into_iter()
+            .map(|arg| arg.into_string().unwrap())
+            .collect())
+    }
+
+    #[test]
+    fn test_config_args() {
+        let config =
+            "lat = \"緯度\"\nlevel = [\"first\", \"standard\"]\nno_progress = true\nverbose = 2";
+        assert_eq!(
+            args(&[], config).unwrap(),
+            [
+                "--lat=緯度",
+                "--level=first",
+                "--level=standard",
+                "--no-progress",
+                "--verbose",
+                "--verbose"
+            ]
+        );
+
+        // コマンドラインで指定したオプションと、それと同時に指定できないオプションは加えない
+        assert_eq!(
+            args(&["--level", "half", "--latlon", "pos"], config).unwrap(),
+            ["--no-progress", "--verbose", "--verbose"]
+        );
+        assert_eq!(args(&[], "no-progress = false").unwrap(), [] as [&str; 0]);
+    }
+
+    #[test]
+    fn test_documented_example() {
+        // モジュールの説明の設定ファイルの例が、そのまま実際のコマンドの引数として解釈できる
+        let source = include_str!("config.rs");
+        let example = source
+            .split_once("//! ```toml\n")
+            .and_then(|(_, rest)| rest.split_once("//! ```\n"))
+            .unwrap()
+            .0
+            .lines()
+            .map(|line| line.trim_start_matches("//! "))
+            .collect::<Vec<&str>>()
+            .join("\n");
+        let table = example.parse::<toml::Table>().unwrap();
+
+        // args_with_config と同じく、設定ファイルの値を加える前の不足 (--lat など) はエラーにしない
+        let mut command = crate::Cli::command().ignore_errors(true);
+        command.build();
+        let matches = command
+            .clone()
+            .try_get_matches_from(["meshify", "in.csv"])
+            .unwrap();
+        let defaults = config_args(&command, &command, &matches, &table).unwrap();
+        assert!(defaults.contains(&OsString::from("--datum=jgs")));
+        let args = [OsString::from("meshify")]
+            .into_iter()
+            .chain(defaults)
+            .chain([OsString::from("in.csv")]);
+        assert!(crate::Cli::try_parse_from(args).is_ok());
+    }
+
+    #[test]
+    fn test_config_args_error() {
+        assert!(args(&[], "lng = \"経度\"").is_err());
+        assert!(args(&[], "config = \"other.toml\"").is_err());
+        assert!(args(&[], "no_progress = \"yes\"").is_err());
+        assert!(args(&[], "verbose = -1").is_err());
+        assert!(args(&[], "lat = { name = \"緯度\" }").is_err());
+    }
+}
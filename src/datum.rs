@@ -0,0 +1,240 @@
+//! 入力座標の測地系と世界測地系への変換
+
+use crate::error::MeshifyError;
+use clap::ValueEnum;
+use proj::Proj;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+/// 入力座標の測地系
+#[derive(Copy, Clone, Debug, ValueEnum)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum Datum {
+    /// 世界測地系 (WGS84, EPSG:4326)
+    WGS,
+    /// 日本測地系 (Tokyo Datum, EPSG:4301)
+    JGS,
+    /// 日本測地系2011 (JGD2011, EPSG:6668)
+    JGD2011,
+}
+
+impl Datum {
+    /// WGS84へ変換する際の変換元のCRS。WGS84そのものの場合は変換不要のため `None`
+    fn source_crs(self) -> Option<&'static str> {
+        match self {
+            Datum::WGS => None,
+            Datum::JGS => Some("EPSG:4301"),
+            // WGS84との差は数cm程度だが、測地系として区別して扱う
+            Datum::JGD2011 => Some("EPSG:6668"),
+        }
+    }
+}
+
+/// 入力座標を世界測地系 (WGS84) の緯度経度に変換する
+///
+/// WGS84の入力ではPROJの変換を作らないため、PROJのデータベースを読み込まずに済む。
+/// 変換には [`ThreadLocalProj`] を使うため、複数のスレッドから共有して使える。
+pub struct DatumConverter {
+    /// 変換元のCRS (WGS84からの変換では `None`)
+    crs: Option<String>,
+    /// 測地系ごとの変換 (WGS84からの変換では `None`)
+    proj: Option<ThreadLocalProj>,
+    /// WGS84から入力の測地系に戻す逆変換。中心座標を入力の測地系で求める場合にしか使わないため、初めて使う際に作る
+    inverse: OnceLock<ThreadLocalProj>,
+}
+
+/// `from` から `to` への変換を作る。`crs` はエラーメッセージに示す入力のCRS
+fn create_proj(from: &str, to: &str, crs: &str) -> Result<Proj, MeshifyError> {
+    Proj::new_known_crs(from, to, None).map_err(|source| MeshifyError::ProjCreateError {
+        crs: crs.to_string(),
+        source,
+    })
+}
+
+thread_local! {
+    /// スレッドごとに作ったPROJの変換 (キーは [`ThreadLocalProj::key`])
+    static PROJS: RefCell<HashMap<String, Proj>> = RefCell::new(HashMap::new());
+}
+
+/// スレッドごとに一度だけ作って使い回すPROJの変換
+///
+/// `proj::Proj` はスレッド間で共有できないため、変換元と変換先のCRSだけを持ち、各スレッドで初めて
+/// 変換する際にそのスレッド用の変換を作る。同じCRSの組の変換はスレッド内で共有し、スレッドが終わるまで残す。
+pub struct ThreadLocalProj {
+    from: String,
+    to: String,
+    /// エラーメッセージに示す入力のCRS
+    crs: String,
+    /// スレッドごとの変換を探すキー (変換元と変換先のCRS)
+    key: String,
+}
+
+impl ThreadLocalProj {
+    /// CRSの指定の誤りは処理を始める前に分かるよう、呼び出したスレッドの変換はここで作る
+    pub fn new(from: &str, to: &str, crs: &str) -> Result<Self, MeshifyError> {
+        let proj = ThreadLocalProj {
+            from: from.to_string(),
+            to: to.to_string(),
+            crs: crs.to_string(),
+            key: format!("{}\n{}", from, to),
+        };
+        PROJS.with_borrow_mut(|projs| proj.get_or_create(projs).map(|_| ()))?;
+        Ok(proj)
+    }
+
+    fn get_or_create<'a>(
+        &self,
+        projs: &'a mut HashMap<String, Proj>,
+    ) -> Result<&'a Proj, MeshifyError> {
+        if !projs.contains_key(&self.key) {
+            let created = create_proj(&self.from, &self.to, &self.crs)?;
+            projs.insert(self.key.clone(), created);
+        }
+        Ok(&projs[&self.key])
+    }
+
+    /// 座標 (`lat`, `lon`) を変換する。PROJには (経度, 緯度) の順で渡し、結果はPROJの返す順のまま返す
+    pub fn convert(&self, lat: f64, lon: f64) -> Result<(f64, f64), MeshifyError> {
+        PROJS.with_borrow_mut(|projs| {
+            self.get_or_create(projs)?
+                .convert((lon, lat))
+                .map_err(|source| MeshifyError::ProjError { lat, lon, source })
+        })
+    }
+}
+
+impl DatumConverter {
+    pub fn new(datum: Datum) -> Result<Self, MeshifyError> {
+        match datum.source_crs() {
+            Some(source) => Self::from_crs(source),
+            None => Ok(DatumConverter {
+                crs: None,
+                proj: None,
+                inverse: OnceLock::new(),
+            }),
+        }
+    }
+
+    /// 任意のCRS (`EPSG:2451` など) からWGS84へ変換する
+    ///
+    /// 平面直角座標系やUTMのような投影座標系も指定できる。その場合 `to_wgs` の引数は (北距, 東距) として扱われる
+    /// (PROJ には軸の順をそろえて (東距, 北距) の順で渡す)。
+    ///
+    /// CRSの指定の誤りは処理を始める前に分かるよう、世界測地系への変換はここで作る。
+    pub fn from_crs(crs: &str) -> Result<Self, MeshifyError> {
+        Ok(DatumConverter {
+            crs: Some(crs.to_string()),
+            proj: Some(ThreadLocalProj::new(crs, "EPSG:4326", crs)?),
+            inverse: OnceLock::new(),
+        })
+    }
+
+    /// 緯度経度を世界測地系に変換して (緯度, 経度) で返す
+    pub fn to_wgs(&self, lat: f64, lon: f64) -> Result<(f64, f64), MeshifyError> {
+        match &self.proj {
+            Some(proj) => {
+                // PROJは (経度, 緯度) の順
+                let (converted_lon, converted_lat) = proj.convert(lat, lon)?;
+                Ok((converted_lat, converted_lon))
+            }
+            None => Ok((lat, lon)),
+        }
+    }
+
+    /// 世界測地系の緯度経度を入力の測地系に戻して (緯度, 経度) で返す (`to_wgs` の逆変換)
+    pub fn from_wgs(&self, lat: f64, lon: f64) -> Result<(f64, f64), MeshifyError> {
+        let Some(crs) = &self.crs else {
+            return Ok((lat, lon));
+        };
+        let inverse = match self.inverse.get() {
+            Some(inverse) => inverse,
+            None => {
+                let created = ThreadLocalProj::new("EPSG:4326", crs, crs)?;
+                self.inverse.get_or_init(|| created)
+            }
+        };
+        let (converted_lon, converted_lat) = inverse.convert(lat, lon)?;
+        Ok((converted_lat, converted_lon))
+    }
+}
+
+/// 世界測地系の緯度経度を、Webメルカトル (EPSG:3857) の座標に変換する
+///
+/// 地図タイルの座標を求める場合などに使う。PROJの変換は処理を始める前に作り、CRSを扱えない環境では
+/// その時点でエラーにする。
+pub struct WebMercator {
+    proj: ThreadLocalProj,
+}
+
+impl WebMercator {
+    pub fn new() -> Result<Self, MeshifyError> {
+        Ok(WebMercator {
+            proj: ThreadLocalProj::new("EPSG:4326", "EPSG:3857", "EPSG:3857")?,
+        })
+    }
+
+    /// 世界測地系の緯度経度を変換して (X, Y) をメートル単位で返す
+    pub fn convert(&self, lat: f64, lon: f64) -> Result<(f64, f64), MeshifyError> {
+        self.proj.convert(lat, lon)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lazy_inverse() {
+        let converter = DatumConverter::new(Datum::WGS).unwrap();
+        assert!(converter.proj.is_none());
+        assert_eq!(converter.from_wgs(35.68, 139.76).unwrap(), (35.68, 139.76));
+        assert!(converter.inverse.get().is_none());
+
+        // 逆変換は初めて使う際に作る
+        let converter = DatumConverter::new(Datum::JGS).unwrap();
+        assert!(converter.proj.is_some());
+        assert!(converter.inverse.get().is_none());
+        converter.from_wgs(35.68, 139.76).unwrap();
+        assert!(converter.inverse.get().is_some());
+    }
+
+    #[test]
+    fn test_thread_local_proj() {
+        use rayon::prelude::*;
+
+        let converter = DatumConverter::new(Datum::JGS).unwrap();
+        let points = (0..1000)
+            .map(|i| (30.0 + i as f64 * 0.01, 130.0 + i as f64 * 0.013))
+            .collect::<Vec<(f64, f64)>>();
+        let serial = points
+            .iter()
+            .map(|&(lat, lon)| converter.to_wgs(lat, lon).unwrap())
+            .collect::<Vec<(f64, f64)>>();
+
+        // 各ワーカースレッドが自分の変換を作り、1スレッドで変換した場合と同じ結果になる
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .build()
+            .unwrap();
+        let parallel = pool.install(|| {
+            points
+                .par_iter()
+                .map(|&(lat, lon)| converter.to_wgs(lat, lon).unwrap())
+                .collect::<Vec<(f64, f64)>>()
+        });
+        assert_eq!(serial, parallel);
+        let inverse = pool.install(|| {
+            points
+                .par_iter()
+                .map(|&(lat, lon)| converter.from_wgs(lat, lon).unwrap())
+                .collect::<Vec<(f64, f64)>>()
+        });
+        assert_eq!(inverse.len(), points.len());
+
+        let err = ThreadLocalProj::new("unknown", "EPSG:4326", "unknown")
+            .err()
+            .unwrap();
+        assert!(matches!(err, MeshifyError::ProjCreateError { .. }));
+    }
+}
@@ -0,0 +1,192 @@
+//! 度分秒 (DMS) 形式の緯度経度の解析
+
+/// 数値の後に付く単位
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Unit {
+    Degree,
+    Minute,
+    Second,
+}
+
+/// 度分秒形式の座標を十進度に変換する
+///
+/// `35°41'22.2"N`・`139 41 30.1 E`・`北緯35度41分22.2秒` のような表記を受け付ける。
+/// 数字や記号は全角でもよく、区切りの空白の有無も問わない。単位記号が無い数値は、度・分・秒の順に解釈する。
+/// 方位記号 (N/S/E/W、北緯/南緯/東経/西経) か先頭の `-` で南緯・西経を表す。解釈できない場合は `None` を返す。
+///
+/// ```
+/// let lat = meshify::parse_dms("35°41'22.2\"N").unwrap();
+/// assert!((lat - 35.689_5).abs() < 1e-9);
+/// assert_eq!(meshify::parse_dms("西経 74°0'0\""), Some(-74.0));
+/// assert_eq!(meshify::parse_dms("35°61'"), None);
+/// ```
+pub fn parse_dms(s: &str) -> Option<f64> {
+    let normalized = s.chars().map(normalize_char).collect::<String>();
+    let mut rest = normalized.trim();
+
+    // 方位記号は前後どちらに付いていてもよい
+    let mut negative = None;
+    for (prefix, is_negative) in [
+        ("北緯", false),
+        ("東経", false),
+        ("南緯", true),
+        ("西経", true),
+    ] {
+        if let Some(stripped) = rest.strip_prefix(prefix) {
+            negative = Some(is_negative);
+            rest = stripped.trim_start();
+        }
+    }
+    if negative.is_none() {
+        let hemisphere = |c: char| match c.to_ascii_uppercase() {
+            'N' | 'E' => Some(false),
+            'S' | 'W' => Some(true),
+            _ => None,
+        };
+        if let Some(is_negative) = rest.chars().next().and_then(hemisphere) {
+            negative = Some(is_negative);
+            rest = rest[1..].trim_start();
+        } else if let Some(is_negative) = rest.chars().next_back().and_then(hemisphere) {
+            negative = Some(is_negative);
+            rest = rest[..rest.len() - 1].trim_end();
+        }
+    }
+    if let Some(stripped) = rest.strip_prefix('-') {
+        // 方位記号と負号の併用は意味が曖昧なので受け付けない
+        if negative.is_some() {
+            return None;
+        }
+        negative = Some(true);
+        rest = stripped;
+    }
+
+    let mut values = [None; 3];
+    let mut last_unit: Option<Unit> = None;
+    let mut chars = rest.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let mut number = String::new();
+        while let Some(c) = chars.next_if(|c| c.is_ascii_digit() || *c == '.') {
+            number.push(c);
+        }
+        let value: f64 = number.parse().ok()?;
+
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let unit = match chars.peek().copied().and_then(unit_of) {
+            Some(unit) => {
+                chars.next();
+                // `''` (シングルクォート2つ) は秒として扱う
+                if unit == Unit::Minute && chars.next_if_eq(&'\'').is_some() {
+                    Unit::Second
+                } else {
+                    unit
+                }
+            }
+            None => match last_unit {
+                None => Unit::Degree,
+                Some(Unit::Degree) => Unit::Minute,
+                Some(Unit::Minute) => Unit::Second,
+                Some(Unit::Second) => return None,
+            },
+        };
+
+        // 度・分・秒の順に、それぞれ1回だけ現れる必要がある
+        if last_unit.is_some_and(|last| unit <= last) {
+            return None;
+        }
+        values[unit as usize] = Some(value);
+        last_unit = Some(unit);
+    }
+
+    let degrees = values[0]?;
+    let minutes = values[1].unwrap_or(0.0);
+    let seconds = values[2].unwrap_or(0.0);
+    if degrees > 180.0 || minutes >= 60.0 || seconds >= 60.0 {
+        return None;
+    }
+    // 小数の度・分の後にさらに細かい単位が続く表記は曖昧なため受け付けない
+    if (values[1].is_some() && degrees.fract() != 0.0)
+        || (values[2].is_some() && minutes.fract() != 0.0)
+    {
+        return None;
+    }
+
+    let value = degrees + minutes / 60.0 + seconds / 3600.0;
+    Some(if negative == Some(true) {
+        -value
+    } else {
+        value
+    })
+}
+
+/// 全角の英数字・記号を半角にし、負号の揺れを `-` にそろえる
+fn normalize_char(c: char) -> char {
+    match c {
+        '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+        '\u{3000}' => ' ',
+        '−' | '‐' => '-',
+        _ => c,
+    }
+}
+
+/// 単位記号を判定する
+fn unit_of(c: char) -> Option<Unit> {
+    match c {
+        '°' | 'º' | '˚' | '度' => Some(Unit::Degree),
+        '\'' | '′' | '‘' | '’' | '分' => Some(Unit::Minute),
+        '"' | '″' | '“' | '”' | '秒' => Some(Unit::Second),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.unwrap_or_else(|| panic!("{} を期待したが解析できなかった", expected));
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_parse_dms() {
+        let expected = 35.0 + 41.0 / 60.0 + 22.2 / 3600.0;
+        assert_close(parse_dms("35°41'22.2\"N"), expected);
+        assert_close(parse_dms("N 35° 41′ 22.2″"), expected);
+        assert_close(parse_dms("35 41 22.2"), expected);
+        assert_close(parse_dms("北緯35度41分22.2秒"), expected);
+        assert_close(parse_dms("３５°４１′２２．２″Ｎ"), expected);
+        assert_close(parse_dms("35°41'22.2''"), expected);
+        assert_close(
+            parse_dms("139°41'30\"W"),
+            -(139.0 + 41.0 / 60.0 + 30.0 / 3600.0),
+        );
+        assert_close(parse_dms("-33°52'"), -(33.0 + 52.0 / 60.0));
+        assert_close(parse_dms("35.6895"), 35.6895);
+    }
+
+    #[test]
+    fn test_parse_dms_invalid() {
+        assert_eq!(parse_dms(""), None);
+        assert_eq!(parse_dms("N"), None);
+        assert_eq!(parse_dms("abc"), None);
+        assert_eq!(parse_dms("41'"), None);
+        assert_eq!(parse_dms("35°60'"), None);
+        assert_eq!(parse_dms("35°41'60\""), None);
+        assert_eq!(parse_dms("35\"41'"), None);
+        assert_eq!(parse_dms("35°41°"), None);
+        assert_eq!(parse_dms("35.5°30'"), None);
+        assert_eq!(parse_dms("35 41 22 10"), None);
+        assert_eq!(parse_dms("-35°N"), None);
+        assert_eq!(parse_dms("200°"), None);
+    }
+}
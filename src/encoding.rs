@@ -0,0 +1,209 @@
+//! CLIで扱う文字エンコーディングの解析と変換
+
+use clap::ValueEnum;
+use encoding_rs::{EncoderResult, Encoding};
+use std::io::{self, Read, Write};
+
+/// 出力エンコーディングで表現できない文字が現れた場合の挙動
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Unmappable {
+    /// `?` に置き換えて出力を続ける
+    Replace,
+    /// エラーとして処理を中断する
+    Error,
+}
+
+/// UTF-8のBOM
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// 入力の先頭にUTF-8のBOMがあれば取り除く
+///
+/// Excelで保存したCSVなどは先頭にBOMが付くため、取り除かないと最初の列名やNDJSONの1行目が一致しない。
+/// 圧縮の展開後のように少しずつしか読めない入力でも判定できるよう、3バイトそろうまで読む。
+pub fn strip_utf8_bom(mut input: Box<dyn Read>) -> io::Result<Box<dyn Read>> {
+    let mut head = [0; UTF8_BOM.len()];
+    let mut len = 0;
+    while len < head.len() {
+        match input.read(&mut head[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    if head[..len] == *UTF8_BOM {
+        return Ok(input);
+    }
+    Ok(Box::new(io::Cursor::new(head[..len].to_vec()).chain(input)))
+}
+
+/// 文字エンコーディングの指定を解析する
+///
+/// WHATWG Encoding Standard のラベル (`shift_jis`, `sjis`, `euc-jp` など) に加えて、
+/// Windowsでの呼び名である `cp932` も Shift_JIS として受け付ける。
+pub fn parse_encoding(s: &str) -> Result<&'static Encoding, String> {
+    let label = s.trim().to_ascii_lowercase();
+    let label = match label.as_str() {
+        "cp932" => "windows-31j",
+        other => other,
+    };
+    Encoding::for_label(label.as_bytes())
+        .ok_or_else(|| format!("未対応の文字エンコーディングです: 「{}」", s))
+}
+
+/// UTF-8で書き込まれたバイト列を、指定のエンコーディングに変換して書き出す
+pub struct EncodingWriter<W: Write> {
+    inner: W,
+    encoder: encoding_rs::Encoder,
+    unmappable: Unmappable,
+    /// 書き込みの境界で分断された、未完のUTF-8シーケンス
+    pending: Vec<u8>,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> EncodingWriter<W> {
+    pub fn new(inner: W, encoding: &'static Encoding, unmappable: Unmappable) -> Self {
+        EncodingWriter {
+            inner,
+            encoder: encoding.new_encoder(),
+            unmappable,
+            pending: Vec::new(),
+            buffer: Vec::with_capacity(8 * 1024),
+        }
+    }
+
+    fn encode(&mut self, mut src: &str) -> io::Result<()> {
+        self.buffer.clear();
+        loop {
+            self.buffer.reserve(
+                self.encoder
+                    .max_buffer_length_from_utf8_without_replacement(src.len())
+                    .unwrap_or(src.len() * 4)
+                    .max(8),
+            );
+            let (result, read) = self.encoder.encode_from_utf8_to_vec_without_replacement(
+                src,
+                &mut self.buffer,
+                false,
+            );
+            src = &src[read..];
+            match result {
+                EncoderResult::InputEmpty => break,
+                EncoderResult::OutputFull => continue,
+                EncoderResult::Unmappable(c) => match self.unmappable {
+                    Unmappable::Replace => self.buffer.push(b'?'),
+                    Unmappable::Error => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "文字「{}」は出力エンコーディング {} で表現できません",
+                                c,
+                                self.encoder.encoding().name()
+                            ),
+                        ));
+                    }
+                },
+            }
+        }
+        self.inner.write_all(&self.buffer)
+    }
+}
+
+impl<W: Write> Write for EncodingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        let valid_up_to = match std::str::from_utf8(&self.pending) {
+            Ok(s) => s.len(),
+            // 末尾が途中で切れているだけなら、続きが来るまで保留する
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        };
+        let pending = std::mem::take(&mut self.pending);
+        // valid_up_to までは from_utf8 で検証済み
+        let src = std::str::from_utf8(&pending[..valid_up_to]).unwrap();
+        let result = self.encode(src);
+        self.pending = pending[valid_up_to..].to_vec();
+        result.map(|_| buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_encoding() {
+        assert_eq!(parse_encoding("sjis"), Ok(encoding_rs::SHIFT_JIS));
+        assert_eq!(parse_encoding("Shift_JIS"), Ok(encoding_rs::SHIFT_JIS));
+        assert_eq!(parse_encoding("cp932"), Ok(encoding_rs::SHIFT_JIS));
+        assert_eq!(parse_encoding("utf-8"), Ok(encoding_rs::UTF_8));
+        assert!(parse_encoding("sjiss").is_err());
+    }
+
+    #[test]
+    fn test_strip_utf8_bom() {
+        let read = |bytes: &'static [u8]| {
+            let mut text = String::new();
+            strip_utf8_bom(Box::new(bytes))
+                .unwrap()
+                .read_to_string(&mut text)
+                .unwrap();
+            text
+        };
+        assert_eq!(read(b"\xEF\xBB\xBFlat,lon\n"), "lat,lon\n");
+        assert_eq!(read(b"lat,lon\n"), "lat,lon\n");
+        assert_eq!(read(b"\xEF\xBB\xBF"), "");
+        assert_eq!(read(b"la"), "la");
+
+        // 1バイトずつしか読めない入力でも取り除く
+        let mut text = String::new();
+        strip_utf8_bom(Box::new(ByteReader(b"\xEF\xBB\xBFid".to_vec().into_iter())))
+            .unwrap()
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(text, "id");
+    }
+
+    /// 1回の読み込みで1バイトだけ返す入力
+    struct ByteReader(std::vec::IntoIter<u8>);
+
+    impl Read for ByteReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match (self.0.next(), buf.first_mut()) {
+                (Some(b), Some(slot)) => {
+                    *slot = b;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn test_encoding_writer_split_sequence() {
+        // UTF-8のマルチバイト文字が書き込みの途中で分断されても正しく変換される
+        let text = "緯度,経度\n";
+        let mut writer = EncodingWriter::new(Vec::new(), encoding_rs::SHIFT_JIS, Unmappable::Error);
+        for b in text.as_bytes() {
+            writer.write_all(&[*b]).unwrap();
+        }
+        let (expected, _, _) = encoding_rs::SHIFT_JIS.encode(text);
+        assert_eq!(writer.inner, expected.into_owned());
+    }
+
+    #[test]
+    fn test_encoding_writer_unmappable() {
+        // 絵文字は Shift_JIS で表現できない
+        let mut writer =
+            EncodingWriter::new(Vec::new(), encoding_rs::SHIFT_JIS, Unmappable::Replace);
+        writer.write_all("a😀b".as_bytes()).unwrap();
+        assert_eq!(writer.inner, b"a?b");
+
+        let mut writer = EncodingWriter::new(Vec::new(), encoding_rs::SHIFT_JIS, Unmappable::Error);
+        assert!(writer.write_all("a😀b".as_bytes()).is_err());
+    }
+}
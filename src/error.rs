@@ -0,0 +1,137 @@
+//! 入力の処理で発生するエラー
+
+use crate::mesh::MeshError;
+use thiserror::Error;
+
+/// CSVの処理や座標の変換で発生するエラー
+///
+/// 種類ごとに分岐できるよう、行番号や値など原因の特定に必要な情報をバリアントに持たせる。
+/// `Display` はそのままCLIで表示できる日本語のメッセージになる。
+#[derive(Debug, Error)]
+pub enum MeshifyError {
+    /// 指定した列が入力にない
+    #[error(
+        "{label}「{name}」が見つかりません{}。入力にある列: {}",
+        suggestion_note(.suggestion),
+        quote_all(.columns)
+    )]
+    ColumnNotFound {
+        /// 列の役割 (「緯度列」など)
+        label: &'static str,
+        /// 指定された列名
+        name: String,
+        /// 指定された列名に近い列名
+        suggestion: Option<String>,
+        /// 入力にある列名
+        columns: Vec<String>,
+    },
+
+    /// 大文字小文字などを無視して列名を探した結果、複数の列が一致した
+    #[error("列名「{name}」に一致する列が複数あります: {}", quote_all(.candidates))]
+    AmbiguousColumn {
+        name: String,
+        candidates: Vec<String>,
+    },
+
+    /// 列名の正規表現 (`--lat-regex` など) に一致する列が入力にない
+    #[error("{label}の正規表現「{pattern}」に一致する列がありません。入力にある列: {}", quote_all(.columns))]
+    NoColumnMatched {
+        label: &'static str,
+        pattern: String,
+        columns: Vec<String>,
+    },
+
+    /// 列名の正規表現に一致する列が複数ある
+    #[error("{label}の正規表現「{pattern}」に一致する列が複数あります: {}", quote_all(.candidates))]
+    AmbiguousColumnPattern {
+        label: &'static str,
+        pattern: String,
+        candidates: Vec<String>,
+    },
+
+    /// 指定した列名の列が入力に複数ある (`positions` は0始まりの列の位置)
+    #[error(
+        "列名「{name}」の列が複数あります (列の位置: {})。--lat-index・--lon-index などで列の位置を指定するか、列名が重複しないようにしてください",
+        join_positions(.positions)
+    )]
+    DuplicateColumn { name: String, positions: Vec<usize> },
+
+    /// GeoJSONのFeatureのジオメトリがPointでない (`line` はFeatureの番号)
+    #[error("{line}行目: ジオメトリ「{geometry}」はPointではありません")]
+    NonPointGeometry { line: u64, geometry: String },
+
+    /// 値を解析できない (`line` は入力の行番号、`field` は値の種類で「緯度」など)
+    #[error("{line}行目: {field}の値「{value}」が不正です")]
+    ParseError {
+        line: u64,
+        field: &'static str,
+        value: String,
+    },
+
+    /// 緯度経度が1列にまとめられた値を、緯度と経度に分割できない
+    #[error("{line}行目: 緯度経度の値「{value}」を緯度と経度に分割できません")]
+    SplitError { line: u64, value: String },
+
+    /// 座標の値が空欄 (`field` は「緯度」など)
+    #[error("{line}行目: {field}の値が空欄です")]
+    EmptyCoord { line: u64, field: &'static str },
+
+    /// 座標が地域メッシュの計算対象となる日本の範囲外
+    #[error("{line}行目: 座標 ({lat}, {lon}) が日本の範囲外です")]
+    OutOfRange { line: u64, lat: f64, lon: f64 },
+
+    /// 測地系の変換を作成できない
+    #[error("CRS「{crs}」からの変換を作成できません: {source}")]
+    ProjCreateError {
+        crs: String,
+        source: proj::ProjCreateError,
+    },
+
+    /// 座標を変換できない
+    #[error("座標 ({lat}, {lon}) を変換できません: {source}")]
+    ProjError {
+        lat: f64,
+        lon: f64,
+        source: proj::ProjError,
+    },
+
+    /// メッシュコードが不正
+    #[error(transparent)]
+    Mesh(#[from] MeshError),
+}
+
+impl MeshifyError {
+    /// エラーが入力の特定の行に関するものであれば、その行番号
+    pub fn line(&self) -> Option<u64> {
+        match self {
+            MeshifyError::ParseError { line, .. }
+            | MeshifyError::SplitError { line, .. }
+            | MeshifyError::EmptyCoord { line, .. }
+            | MeshifyError::OutOfRange { line, .. } => Some(*line),
+            _ => None,
+        }
+    }
+}
+
+fn suggestion_note(suggestion: &Option<String>) -> String {
+    match suggestion {
+        Some(candidate) => format!(" (「{}」の誤りではありませんか?)", candidate),
+        None => String::new(),
+    }
+}
+
+fn join_positions(positions: &[usize]) -> String {
+    positions
+        .iter()
+        .map(usize::to_string)
+        .collect::<Vec<String>>()
+        .join("、")
+}
+
+fn quote_all(names: &[String]) -> String {
+    names
+        .iter()
+        .map(|name| format!("「{}」", name))
+        .collect::<Vec<String>>()
+        .join("、")
+}
@@ -0,0 +1,108 @@
+//! スキップした行の書き出し (`--error-output`)
+
+use crate::RowPosition;
+use crate::summary::SkipReason;
+use std::error::Error;
+use std::io::{self, Write};
+
+/// スキップした行を、元の列のまま別のCSVに書き出す
+///
+/// 元の列の後ろに、ファイル上の行番号 (`skip_line`)・データの行番号 (`skip_row`)・スキップした理由 (`skip_reason`)
+/// の列を加える。
+/// 複数の入力を処理する場合も1つのファイルにまとめるため、全入力のヘッダーが一致しなければならない。
+pub struct ErrorOutput<W: Write> {
+    writer: csv::Writer<W>,
+    headers: Option<csv::StringRecord>,
+}
+
+impl<W: Write> ErrorOutput<W> {
+    pub fn new(writer: csv::Writer<W>) -> Self {
+        ErrorOutput {
+            writer,
+            headers: None,
+        }
+    }
+
+    /// 入力を読み始める。最初の入力では見出し行を書き出し、2つ目以降はヘッダーが同じか確かめる
+    ///
+    /// ヘッダーが無い入力 (`has_headers` が偽) の場合は見出し行を書かず、列数も確かめない。
+    pub fn start_input(
+        &mut self,
+        headers: &csv::StringRecord,
+        has_headers: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        if !has_headers {
+            return Ok(());
+        }
+        match &self.headers {
+            Some(first) if first != headers => {
+                Err("--error-output には、ヘッダーが同じ入力しかまとめて書き出せません".into())
+            }
+            Some(_) => Ok(()),
+            None => {
+                let mut record = headers.clone();
+                record.push_field("skip_line");
+                record.push_field("skip_row");
+                record.push_field("skip_reason");
+                self.writer.write_record(&record)?;
+                self.headers = Some(headers.clone());
+                Ok(())
+            }
+        }
+    }
+
+    /// スキップした1行を書き出す
+    pub fn write(
+        &mut self,
+        record: &csv::StringRecord,
+        position: RowPosition,
+        reason: SkipReason,
+    ) -> io::Result<()> {
+        let line = position.line.to_string();
+        let row = position.row.to_string();
+        let reason = reason.to_string();
+        let fields = record
+            .iter()
+            .chain([line.as_str(), row.as_str(), reason.as_str()]);
+        Ok(self.writer.write_record(fields)?)
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    #[cfg(test)]
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner().ok().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_output() {
+        let mut errors = ErrorOutput::new(csv::Writer::from_writer(Vec::new()));
+        let headers = csv::StringRecord::from(vec!["id", "lat", "lon"]);
+        errors.start_input(&headers, true).unwrap();
+        errors
+            .write(
+                &csv::StringRecord::from(vec!["1", "abc", "139.76"]),
+                RowPosition { line: 2, row: 1 },
+                SkipReason::InvalidLat,
+            )
+            .unwrap();
+
+        // 同じヘッダーの入力は続けて書き出せるが、異なるヘッダーの入力はエラー
+        errors.start_input(&headers, true).unwrap();
+        let other = csv::StringRecord::from(vec!["id", "lon", "lat"]);
+        assert!(errors.start_input(&other, true).is_err());
+
+        errors.finish().unwrap();
+        assert_eq!(
+            String::from_utf8(errors.into_inner()).unwrap(),
+            "id,lat,lon,skip_line,skip_row,skip_reason\n1,abc,139.76,2,1,緯度不正\n"
+        );
+    }
+}
@@ -0,0 +1,219 @@
+//! Excel (xlsx) の入力の読み込み
+//!
+//! シートをCSVにしてから読むことで、列の指定やスキップの扱いをCSVの入力と共通にする。
+
+use calamine::{Data, Reader, Xlsx};
+use std::error::Error;
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+/// `--all-sheets` で最後に加えるシート名の列の名前
+pub const SHEET_COLUMN: &str = "sheet";
+
+/// パスの拡張子が `.xlsx` かどうか
+pub fn is_xlsx_path(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("xlsx"))
+}
+
+/// xlsxのシートを、区切り文字が `,` のCSVにする。`sheet` を指定しない場合は最初のシートを読む
+///
+/// 値のあるセルの範囲の最初の行をヘッダーとする。行番号がExcelの行番号と一致するよう、範囲より上の
+/// 行の数だけ空行を入れる (CSVの読み込みでは空行は読み飛ばされる)。
+pub fn sheet_to_csv(input: impl Read, sheet: Option<&str>) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut workbook = open_workbook(input)?;
+    let names = workbook.sheet_names();
+    let name = match sheet {
+        Some(sheet) => names.iter().find(|name| *name == sheet).ok_or_else(|| {
+            format!(
+                "シート「{}」がありません (シート: {})",
+                sheet,
+                names.join(", ")
+            )
+        })?,
+        None => names.first().ok_or("xlsxファイルにシートがありません")?,
+    };
+    let range = workbook.worksheet_range(name)?;
+
+    let mut output = Vec::new();
+    if let Some((first_row, _)) = range.start() {
+        output.resize(first_row as usize, b'\n');
+    }
+    let mut writer = csv::Writer::from_writer(output);
+    for row in range.rows() {
+        writer.write_record(row.iter().map(cell_text))?;
+    }
+    Ok(writer.into_inner().map_err(|e| e.into_error())?)
+}
+
+/// xlsxのすべてのシートを順につなげ、シート名の `sheet` 列を最後に加えたCSVにする
+///
+/// `has_headers` の場合は、最初のシートのヘッダーに `sheet` 列を加えて1行目とし、以降のシートのヘッダーは
+/// 読み飛ばす。ヘッダー (`has_headers` でない場合は列の数) が最初のシートと一致しないシートは、行を
+/// つなげられないため警告を出して読み飛ばす。値の無いシートも読み飛ばす。
+/// 行番号はシートを順につないだCSVの行番号になり、Excelの行番号とは一致しない。
+pub fn all_sheets_to_csv(input: impl Read, has_headers: bool) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut workbook = open_workbook(input)?;
+    let names = workbook.sheet_names();
+    if names.is_empty() {
+        return Err("xlsxファイルにシートがありません".into());
+    }
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    // 最初のシートの名前と、ヘッダー (`has_headers` でない場合は列の数だけの空欄)
+    let mut first: Option<(&str, Vec<String>)> = None;
+    for name in &names {
+        let range = workbook.worksheet_range(name)?;
+        let mut rows = range
+            .rows()
+            .map(|row| row.iter().map(cell_text).collect::<Vec<String>>());
+        let headers = match (has_headers, range.is_empty()) {
+            (_, true) => continue,
+            (true, false) => rows.next().unwrap_or_default(),
+            (false, false) => vec![String::new(); range.width()],
+        };
+        match &first {
+            Some((first_name, first_headers)) if *first_headers != headers => {
+                log::warn!(
+                    "シート「{}」の{}がシート「{}」と一致しないため、このシートを読み飛ばします",
+                    name,
+                    if has_headers {
+                        "ヘッダー"
+                    } else {
+                        "列の数"
+                    },
+                    first_name
+                );
+                continue;
+            }
+            Some(_) => {}
+            None => {
+                if has_headers {
+                    if headers.iter().any(|header| header == SHEET_COLUMN) {
+                        return Err(format!(
+                            "シート「{}」に「{}」列があるため、シート名の列を加えられません",
+                            name, SHEET_COLUMN
+                        )
+                        .into());
+                    }
+                    writer
+                        .write_record(headers.iter().map(String::as_str).chain([SHEET_COLUMN]))?;
+                }
+                first = Some((name, headers));
+            }
+        }
+        for row in rows {
+            writer.write_record(row.iter().map(String::as_str).chain([name.as_str()]))?;
+        }
+    }
+    Ok(writer.into_inner().map_err(|e| e.into_error())?)
+}
+
+/// xlsxのブックを開く
+fn open_workbook(mut input: impl Read) -> Result<Xlsx<Cursor<Vec<u8>>>, Box<dyn Error>> {
+    // zipの読み込みにはシークが必要なため、標準入力なども扱えるよう全体をメモリに読み込む
+    let mut bytes = Vec::new();
+    input.read_to_end(&mut bytes)?;
+    Ok(
+        Xlsx::new(Cursor::new(bytes))
+            .map_err(|e| format!("xlsxファイルとして読めません: {}", e))?,
+    )
+}
+
+/// セルの値の文字列
+///
+/// 数値のセルは値を誤差なく表せる最短の10進表記にするため、数値と文字列が混在する緯度経度の列でも、
+/// 文字列のセルと同じように読める。
+fn cell_text(cell: &Data) -> String {
+    match cell {
+        Data::Float(value) => value.to_string(),
+        Data::Int(value) => value.to_string(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_xlsxwriter::Workbook;
+
+    #[test]
+    fn test_sheet_to_csv() {
+        let mut workbook = Workbook::new();
+        let sheet = workbook.add_worksheet().set_name("points").unwrap();
+        // 1行目を空けて、2行目をヘッダーにする
+        sheet.write_string(1, 0, "lat").unwrap();
+        sheet.write_string(1, 1, "lon").unwrap();
+        sheet.write_number(2, 0, 35.6895).unwrap();
+        sheet.write_number(2, 1, 139.0).unwrap();
+        sheet.write_string(3, 0, " 35.68 ").unwrap();
+        sheet.write_string(3, 1, "139.76").unwrap();
+        workbook.add_worksheet().set_name("other").unwrap();
+        workbook
+            .worksheet_from_name("other")
+            .unwrap()
+            .write_string(0, 0, "id")
+            .unwrap();
+        let bytes = workbook.save_to_buffer().unwrap();
+
+        let csv = sheet_to_csv(bytes.as_slice(), None).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "\nlat,lon\n35.6895,139\n 35.68 ,139.76\n"
+        );
+        let csv = sheet_to_csv(bytes.as_slice(), Some("other")).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "id\n");
+
+        let err = sheet_to_csv(bytes.as_slice(), Some("missing")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "シート「missing」がありません (シート: points, other)"
+        );
+        assert!(sheet_to_csv(&b"lat,lon\n"[..], None).is_err());
+        assert!(all_sheets_to_csv(&b"lat,lon\n"[..], true).is_err());
+        assert!(is_xlsx_path(Path::new("points.XLSX")));
+        assert!(!is_xlsx_path(Path::new("points.csv")));
+    }
+
+    #[test]
+    fn test_all_sheets_to_csv() {
+        let mut workbook = Workbook::new();
+        for (name, rows) in [
+            ("東京", &[["lat", "lon"], ["35.68", "139.76"]][..]),
+            ("空", &[][..]),
+            (
+                "大阪",
+                &[["lat", "lon"], ["34.69", "135.5"], ["34.7", "135.49"]][..],
+            ),
+            ("別の列", &[["id", "lat"], ["1", "35"]][..]),
+        ] {
+            let sheet = workbook.add_worksheet().set_name(name).unwrap();
+            for (row, values) in rows.iter().enumerate() {
+                for (col, value) in values.iter().enumerate() {
+                    sheet.write_string(row as u32, col as u16, *value).unwrap();
+                }
+            }
+        }
+        let bytes = workbook.save_to_buffer().unwrap();
+
+        // ヘッダーが一致しないシートと、値の無いシートは読み飛ばす
+        let csv = all_sheets_to_csv(bytes.as_slice(), true).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "lat,lon,sheet\n35.68,139.76,東京\n34.69,135.5,大阪\n34.7,135.49,大阪\n"
+        );
+        // ヘッダーが無い場合は、どのシートも列の数が同じならつなげる
+        let csv = all_sheets_to_csv(bytes.as_slice(), false).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "lat,lon,東京\n35.68,139.76,東京\nlat,lon,大阪\n34.69,135.5,大阪\n34.7,135.49,大阪\nid,lat,別の列\n1,35,別の列\n"
+        );
+
+        let mut workbook = Workbook::new();
+        workbook
+            .add_worksheet()
+            .write_string(0, 0, SHEET_COLUMN)
+            .unwrap();
+        let bytes = workbook.save_to_buffer().unwrap();
+        assert!(all_sheets_to_csv(bytes.as_slice(), true).is_err());
+    }
+}
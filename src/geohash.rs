@@ -0,0 +1,96 @@
+//! メッシュコードから GeoHash への変換
+
+use crate::mesh::{MeshError, mesh_code_to_center};
+
+/// GeoHash の32進数の文字 (a・i・l・o を除く)
+const BASE32: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// GeoHash の最大の桁数。12桁 (60ビット) で緯度経度の1辺が数cmになり、f64 の精度で求められる範囲に収まる
+pub const MAX_PRECISION: usize = 12;
+
+/// 緯度経度を、`precision` 桁の GeoHash にする
+///
+/// 経度・緯度の順に1ビットずつ範囲を2等分し、5ビットごとに1文字にする。範囲の境界ちょうどの座標は
+/// 北側・東側のセルに含める。
+///
+/// ```
+/// assert_eq!(meshify::geohash::encode(57.64911, 10.40744, 11), "u4pruydqqvj");
+/// ```
+pub fn encode(lat: f64, lon: f64, precision: usize) -> String {
+    assert!(
+        (1..=MAX_PRECISION).contains(&precision),
+        "GeoHashの桁数は1〜12"
+    );
+    let mut lat_range = (-90.0, 90.0);
+    let mut lon_range = (-180.0, 180.0);
+    let mut hash = String::with_capacity(precision);
+    let mut is_lon = true;
+    for _ in 0..precision {
+        let mut index = 0;
+        for _ in 0..5 {
+            let (value, range) = if is_lon {
+                (lon, &mut lon_range)
+            } else {
+                (lat, &mut lat_range)
+            };
+            let mid = (range.0 + range.1) / 2.0;
+            index <<= 1;
+            if value >= mid {
+                index |= 1;
+                range.0 = mid;
+            } else {
+                range.1 = mid;
+            }
+            is_lon = !is_lon;
+        }
+        hash.push(char::from(BASE32[index]));
+    }
+    hash
+}
+
+/// メッシュコードの中心座標の GeoHash を求める
+///
+/// GeoHash のセルとメッシュの矩形は境界がそろわないため、メッシュの矩形全体ではなく中心の1点を表す。
+/// 桁数がメッシュに比べて少ない (セルが大きい) 場合も、セルがメッシュ全体を含むとは限らない。
+/// 桁数は1〜12 で、それ以外はエラーにする。
+///
+/// ```
+/// // 基準地域メッシュ (約1km四方) の中心を、約150m四方のセル (7桁) で表す
+/// assert_eq!(meshify::mesh_to_geohash("53394611", 7).unwrap(), "xn76ux0");
+/// ```
+pub fn mesh_to_geohash(code: &str, precision: usize) -> Result<String, MeshError> {
+    if !(1..=MAX_PRECISION).contains(&precision) {
+        return Err(MeshError::InvalidPrecision(precision));
+    }
+    let (lat, lon) = mesh_code_to_center(code)?;
+    Ok(encode(lat, lon, precision))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode(35.681236, 139.767125, 9), "xn76urx66");
+        assert_eq!(encode(0.0, 0.0, 1), "s");
+        assert_eq!(encode(-90.0, -180.0, 5), "00000");
+        // 桁を増やしても、短い GeoHash が接頭辞になる
+        let long = encode(35.68, 139.76, MAX_PRECISION);
+        assert!(long.starts_with(&encode(35.68, 139.76, 6)));
+    }
+
+    #[test]
+    fn test_mesh_to_geohash() {
+        assert_eq!(mesh_to_geohash("5339", 1).unwrap(), "x");
+        assert_eq!(
+            mesh_to_geohash("53394611", 0),
+            Err(MeshError::InvalidPrecision(0))
+        );
+        assert_eq!(
+            mesh_to_geohash("53394611", 13),
+            Err(MeshError::InvalidPrecision(13))
+        );
+        assert!(mesh_to_geohash("53a9", 7).is_err());
+    }
+}
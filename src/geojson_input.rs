@@ -0,0 +1,210 @@
+//! GeoJSON (RFC 7946) の FeatureCollection の入力の読み取り
+//!
+//! 各Featureの `properties` をNDJSONの1行のオブジェクトと同じように扱い、座標はジオメトリから読む。
+
+use crate::ndjson::field_text;
+use clap::ValueEnum;
+use serde_json::{Map, Value};
+use std::error::Error;
+use std::io::Read;
+
+/// Point以外のジオメトリを持つFeatureの扱い
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum NonPoint {
+    /// 警告を出してスキップする
+    Skip,
+    /// ジオメトリのすべての頂点を囲む矩形の中心を代表点とする (ポリゴンの形によっては、代表点がポリゴンの外になる)
+    BboxCenter,
+}
+
+/// FeatureCollection を読み、Featureの配列を返す
+///
+/// `features` の配列を取り出すため、全体をメモリに読み込む。
+pub fn read_features(input: impl Read) -> Result<Vec<Value>, Box<dyn Error>> {
+    let value: Value =
+        serde_json::from_reader(input).map_err(|e| format!("GeoJSONとして読めません: {}", e))?;
+    let Value::Object(mut collection) = value else {
+        return Err("GeoJSONの入力は FeatureCollection のみに対応しています".into());
+    };
+    if collection.get("type").and_then(Value::as_str) != Some("FeatureCollection") {
+        return Err("GeoJSONの入力は FeatureCollection のみに対応しています".into());
+    }
+    match collection.remove("features") {
+        Some(Value::Array(features)) => Ok(features),
+        _ => Err("FeatureCollection に features の配列がありません".into()),
+    }
+}
+
+/// Featureを、`properties` を除いたFeatureと `properties` に分ける
+///
+/// Featureでない値や、`properties` がオブジェクトでないFeatureは `None` にする。`properties` が
+/// `null` の場合や無い場合は、空のオブジェクトとする。
+pub fn split_feature(feature: Value) -> Option<(Map<String, Value>, Map<String, Value>)> {
+    let Value::Object(mut feature) = feature else {
+        return None;
+    };
+    if feature.get("type").and_then(Value::as_str) != Some("Feature") {
+        return None;
+    }
+    let properties = match feature.remove("properties") {
+        Some(Value::Object(properties)) => properties,
+        None | Some(Value::Null) => Map::new(),
+        Some(_) => return None,
+    };
+    Some((feature, properties))
+}
+
+/// ジオメトリの種類の名前 (警告に使う)
+pub fn geometry_type(geometry: &Value) -> &str {
+    geometry
+        .get("type")
+        .and_then(Value::as_str)
+        .unwrap_or("不明")
+}
+
+/// ジオメトリから、メッシュコードを求める座標の緯度と経度の値を読む
+///
+/// Pointの座標 (経度, 緯度) は値のまま返し、数値かどうかや範囲の検証は他の入力形式と共通にする。
+/// ジオメトリが `null` の場合は、座標が空欄の行と同じように扱えるよう空の値にする。Point以外の
+/// ジオメトリは、`non_point` が `Skip` なら `None` を返す。
+pub fn point_values(geometry: &Value, non_point: NonPoint) -> Option<[String; 2]> {
+    if geometry.is_null() {
+        return Some([String::new(), String::new()]);
+    }
+    if geometry_type(geometry) == "Point" {
+        let coordinates = geometry.get("coordinates").unwrap_or(&Value::Null);
+        if !coordinates.is_array() {
+            // 配列でない座標は、その表記のまま緯度・経度の値として読ませ、不正な値として扱う
+            let text = field_text(coordinates).into_owned();
+            return Some([text.clone(), text]);
+        }
+        let value = |i: usize| {
+            coordinates
+                .get(i)
+                .map_or(String::new(), |v| field_text(v).into_owned())
+        };
+        return Some([value(1), value(0)]);
+    }
+    if non_point == NonPoint::Skip {
+        return None;
+    }
+    let mut bounds = None;
+    extend_bounds(geometry, &mut bounds);
+    Some(match bounds {
+        Some((min_lon, min_lat, max_lon, max_lat)) => [
+            ((min_lat + max_lat) / 2.0).to_string(),
+            ((min_lon + max_lon) / 2.0).to_string(),
+        ],
+        None => [String::new(), String::new()],
+    })
+}
+
+/// ジオメトリのすべての頂点を囲む矩形 (最小経度, 最小緯度, 最大経度, 最大緯度) を広げる
+fn extend_bounds(geometry: &Value, bounds: &mut Option<(f64, f64, f64, f64)>) {
+    if let Some(Value::Array(geometries)) = geometry.get("geometries") {
+        for geometry in geometries {
+            extend_bounds(geometry, bounds);
+        }
+    }
+    if let Some(coordinates) = geometry.get("coordinates") {
+        extend_positions(coordinates, bounds);
+    }
+}
+
+/// 座標の入れ子の配列から、数値の組 (経度, 緯度) を探して矩形を広げる
+fn extend_positions(coordinates: &Value, bounds: &mut Option<(f64, f64, f64, f64)>) {
+    let Value::Array(items) = coordinates else {
+        return;
+    };
+    if let [lon, lat, ..] = &items[..]
+        && let (Some(lon), Some(lat)) = (lon.as_f64(), lat.as_f64())
+    {
+        *bounds = Some(match *bounds {
+            None => (lon, lat, lon, lat),
+            Some((min_lon, min_lat, max_lon, max_lat)) => (
+                min_lon.min(lon),
+                min_lat.min(lat),
+                max_lon.max(lon),
+                max_lat.max(lat),
+            ),
+        });
+        return;
+    }
+    for item in items {
+        extend_positions(item, bounds);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_read_features() {
+        let input = r#"{"type":"FeatureCollection","features":[{"type":"Feature","properties":{"id":1},"geometry":null}]}"#;
+        let features = read_features(input.as_bytes()).unwrap();
+        assert_eq!(features.len(), 1);
+
+        let (feature, properties) = split_feature(features[0].clone()).unwrap();
+        assert_eq!(properties, *json!({ "id": 1 }).as_object().unwrap());
+        assert_eq!(feature.get("properties"), None);
+        assert_eq!(feature.get("geometry"), Some(&Value::Null));
+        let (_, properties) = split_feature(json!({ "type": "Feature" })).unwrap();
+        assert!(properties.is_empty());
+        assert!(split_feature(json!({ "type": "Feature", "properties": [1] })).is_none());
+        assert!(split_feature(json!([1, 2])).is_none());
+
+        let feature = r#"{"type":"Feature","properties":{},"geometry":null}"#;
+        assert!(read_features(feature.as_bytes()).is_err());
+        assert!(read_features(&br#"{"type":"FeatureCollection"}"#[..]).is_err());
+        assert!(read_features(&b"not json"[..]).is_err());
+    }
+
+    #[test]
+    fn test_point_values() {
+        let point = json!({ "type": "Point", "coordinates": [139.76, 35.68] });
+        assert_eq!(
+            point_values(&point, NonPoint::Skip),
+            Some(["35.68".to_string(), "139.76".to_string()])
+        );
+        let point = json!({ "type": "Point", "coordinates": ["139.76", "35.68", 10.0] });
+        assert_eq!(
+            point_values(&point, NonPoint::Skip),
+            Some(["35.68".to_string(), "139.76".to_string()])
+        );
+        let point = json!({ "type": "Point", "coordinates": [] });
+        assert_eq!(
+            point_values(&point, NonPoint::Skip),
+            Some([String::new(), String::new()])
+        );
+        assert_eq!(
+            point_values(&Value::Null, NonPoint::Skip),
+            Some([String::new(), String::new()])
+        );
+
+        let line = json!({ "type": "LineString", "coordinates": [[139.0, 35.0], [140.0, 36.0]] });
+        assert_eq!(geometry_type(&line), "LineString");
+        assert_eq!(point_values(&line, NonPoint::Skip), None);
+        assert_eq!(
+            point_values(&line, NonPoint::BboxCenter),
+            Some(["35.5".to_string(), "139.5".to_string()])
+        );
+        let collection = json!({
+            "type": "GeometryCollection",
+            "geometries": [
+                { "type": "Point", "coordinates": [139.0, 35.0] },
+                { "type": "Polygon", "coordinates": [[[139.5, 35.5], [140.0, 36.0], [139.0, 36.0], [139.5, 35.5]]] },
+            ],
+        });
+        assert_eq!(
+            point_values(&collection, NonPoint::BboxCenter),
+            Some(["35.5".to_string(), "139.5".to_string()])
+        );
+        let empty = json!({ "type": "MultiPoint", "coordinates": [] });
+        assert_eq!(
+            point_values(&empty, NonPoint::BboxCenter),
+            Some([String::new(), String::new()])
+        );
+    }
+}
@@ -0,0 +1,79 @@
+//! メッシュ矩形をGISツール向けのジオメトリとして書き出す
+
+use crate::precision::format_decimal;
+use clap::ValueEnum;
+use meshify::{MeshError, mesh_code_to_bounds, strip_mesh_code_separators};
+
+/// 出力するジオメトリの形式
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum GeometryFormat {
+    /// WKT形式 (`POLYGON((経度 緯度, ...))`)
+    Wkt,
+}
+
+/// ポリゴンの頂点を並べる向き
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Orientation {
+    /// 左回り (反時計回り)。OGCの外周リングの向き
+    Ccw,
+    /// 右回り (時計回り)
+    Cw,
+}
+
+/// メッシュ矩形の外周リングを (経度, 緯度) で返す。始点と終点は同じ座標になる
+///
+/// `--mesh-format hyphenated` のコードも受け付けるよう、`-` の区切りは除いて解釈する。
+pub fn mesh_polygon(code: &str, orientation: Orientation) -> Result<[(f64, f64); 5], MeshError> {
+    let (min_lon, min_lat, max_lon, max_lat) =
+        mesh_code_to_bounds(&strip_mesh_code_separators(code))?;
+    let mut ring = [
+        (min_lon, min_lat),
+        (max_lon, min_lat),
+        (max_lon, max_lat),
+        (min_lon, max_lat),
+        (min_lon, min_lat),
+    ];
+    if orientation == Orientation::Cw {
+        ring.reverse();
+    }
+    Ok(ring)
+}
+
+/// 外周リングをWKTのPOLYGON文字列にする
+///
+/// `precision` を指定すると小数点以下をその桁数で四捨五入する。指定しない場合は誤差なく表せる最短の表記になる。
+pub fn polygon_wkt(ring: &[(f64, f64)], precision: Option<usize>) -> String {
+    let points = ring
+        .iter()
+        .map(|&(lon, lat)| {
+            format!(
+                "{} {}",
+                format_decimal(lon, precision),
+                format_decimal(lat, precision)
+            )
+        })
+        .collect::<Vec<String>>();
+    format!("POLYGON(({}))", points.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_polygon_wkt() {
+        let ring = mesh_polygon("5339", Orientation::Ccw).unwrap();
+        assert_eq!(
+            polygon_wkt(&ring, Some(4)),
+            "POLYGON((139.0000 35.3333, 140.0000 35.3333, 140.0000 36.0000, 139.0000 36.0000, 139.0000 35.3333))"
+        );
+
+        let ring = mesh_polygon("5339", Orientation::Cw).unwrap();
+        assert_eq!(
+            polygon_wkt(&ring, Some(1)),
+            "POLYGON((139.0 35.3, 139.0 36.0, 140.0 36.0, 140.0 35.3, 139.0 35.3))"
+        );
+
+        assert!(mesh_polygon("53", Orientation::Ccw).is_err());
+    }
+}
@@ -0,0 +1,30 @@
+//! Ctrl-C (SIGINT) による処理の中断
+//!
+//! 1回目の Ctrl-C では中断の要求を記録するだけにし、処理側がそれまでに処理した行を書き出してから
+//! 出力を閉じて終了する。書き出しが終わらない場合に備え、2回目の Ctrl-C ではその場で終了する。
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// 中断が要求されたか
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// 強制終了した場合の終了コード (128 + SIGINT)
+const EXIT_CODE: i32 = 130;
+
+/// Ctrl-C のハンドラを設定する。以降は Ctrl-C で即座には終了しなくなる
+pub fn install() -> Result<(), ctrlc::Error> {
+    ctrlc::set_handler(|| {
+        if INTERRUPTED.swap(true, Ordering::SeqCst) {
+            eprintln!("[エラー] 再度中断されたため、出力を書き終えずに終了します");
+            std::process::exit(EXIT_CODE);
+        }
+        eprintln!(
+            "[警告] 中断を受け付けました。処理済みの行を書き出してから終了します (もう一度押すと即座に終了します)"
+        );
+    })
+}
+
+/// 中断が要求されたか
+pub fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
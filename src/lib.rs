@@ -0,0 +1,29 @@
+//! 緯度経度から地域メッシュコード (JIS X 0410) を計算するライブラリ
+//!
+//! ```
+//! use meshify::MeshLevel;
+//!
+//! let code = meshify::get_mesh_code(35.0, 139.0, MeshLevel::Standard);
+//! assert_eq!(code, "52394000");
+//! ```
+
+pub mod datum;
+pub mod dms;
+pub mod error;
+pub mod geohash;
+pub mod mesh;
+pub mod plane;
+pub mod prefecture;
+pub mod utm;
+
+pub use datum::{Datum, DatumConverter, ThreadLocalProj, WebMercator};
+pub use dms::parse_dms;
+pub use error::MeshifyError;
+pub use geohash::mesh_to_geohash;
+pub use mesh::{
+    MeshComponents, MeshError, MeshLevel, child_meshes, count_meshes_in_bbox, descendant_meshes,
+    get_mesh_code, get_mesh_code_u64, haversine_distance_m, hyphenate_mesh_code, is_within_japan,
+    level_of, mesh_area_km2, mesh_code_from_u64, mesh_code_to_bounds, mesh_code_to_center,
+    mesh_components, mesh_neighbors, meshes_in_bbox, parent_mesh, strip_mesh_code_separators,
+    try_get_mesh_code, try_mesh_components, validate_mesh_code, write_mesh_code,
+};
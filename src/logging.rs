@@ -0,0 +1,108 @@
+//! 警告や処理の詳細をstderrに出力するログの設定
+
+use log::LevelFilter;
+use log::kv::{self, Key, Value, VisitSource};
+use std::io::Write;
+
+/// ログの詳細度を指定する引数
+#[derive(clap::Args, Debug)]
+pub struct LogArgs {
+    /// 処理の詳細を表示する (`-v` で変換の設定やファイルごとの処理、`-vv` で行ごとの変換結果まで)
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// 行ごとの警告、進捗バー、終了時のサマリを表示しない (エラーのみ表示する)
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+}
+
+impl LogArgs {
+    /// 表示するログの最も詳細なレベル。既定では警告と処理結果のサマリ (info) まで表示する
+    fn level(&self) -> LevelFilter {
+        match (self.quiet, self.verbose) {
+            (true, _) => LevelFilter::Error,
+            (false, 0) => LevelFilter::Info,
+            (false, 1) => LevelFilter::Debug,
+            (false, _) => LevelFilter::Trace,
+        }
+    }
+}
+
+/// ログの出力先をstderrに設定する
+///
+/// 警告は従来通り `[警告] ...` の形式で出力する。`-v` 以上を指定した場合は、行番号や座標などの
+/// 構造化フィールドを `key=value` の形でメッセージの後に付け、後処理で抽出しやすくする。
+/// 環境変数 `RUST_LOG` を指定した場合は、そちらのレベルを優先する。
+pub fn init(args: &LogArgs) {
+    let show_fields = args.verbose > 0;
+    env_logger::Builder::new()
+        .filter_level(args.level())
+        .parse_default_env()
+        .format(move |buf, record| {
+            write!(buf, "{}{}", prefix(record.level()), record.args())?;
+            if show_fields {
+                // ログの書き出しの失敗はフィールドの途中でも諦めるしかないため、エラーは無視する
+                let _ = record.key_values().visit(&mut FieldWriter(buf));
+            }
+            writeln!(buf)
+        })
+        .init();
+}
+
+/// ログのレベルごとにメッセージの前に付ける見出し。処理結果のサマリ (info) には付けない
+fn prefix(level: log::Level) -> &'static str {
+    match level {
+        log::Level::Error => "[エラー] ",
+        log::Level::Warn => "[警告] ",
+        log::Level::Info => "",
+        log::Level::Debug => "[詳細] ",
+        log::Level::Trace => "[追跡] ",
+    }
+}
+
+/// 構造化フィールドを ` key=value` の形で書き出す
+struct FieldWriter<'a, W: Write>(&'a mut W);
+
+impl<'kvs, W: Write> VisitSource<'kvs> for FieldWriter<'_, W> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        write!(self.0, " {}={}", key, value).map_err(|_| kv::Error::msg("ログを書き出せません"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use log::kv::Source;
+
+    #[derive(Parser)]
+    struct TestCli {
+        #[command(flatten)]
+        log: LogArgs,
+    }
+
+    fn level(args: &[&str]) -> LevelFilter {
+        TestCli::try_parse_from(["meshify"].iter().chain(args))
+            .unwrap()
+            .log
+            .level()
+    }
+
+    #[test]
+    fn test_level() {
+        assert_eq!(level(&[]), LevelFilter::Info);
+        assert_eq!(level(&["-v"]), LevelFilter::Debug);
+        assert_eq!(level(&["-vv"]), LevelFilter::Trace);
+        assert_eq!(level(&["-vvv"]), LevelFilter::Trace);
+        assert_eq!(level(&["--quiet"]), LevelFilter::Error);
+        assert!(TestCli::try_parse_from(["meshify", "-q", "-v"]).is_err());
+    }
+
+    #[test]
+    fn test_field_writer() {
+        let fields: [(&str, Value); 2] = [("line", Value::from(3u64)), ("lat", Value::from(35.5))];
+        let mut buf = Vec::new();
+        fields.visit(&mut FieldWriter(&mut buf)).unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), " line=3 lat=35.5");
+    }
+}
@@ -1,237 +1,6987 @@
-use clap::{Parser, ValueEnum};
-use proj::Proj;
+mod aggregate;
+mod cache;
+mod compression;
+mod config;
+mod encoding;
+mod error_output;
+mod excel;
+mod geojson_input;
+mod geometry;
+mod interrupt;
+mod logging;
+mod ndjson;
+mod output;
+mod parquet_output;
+mod pipeline;
+mod precision;
+mod progress;
+mod resume;
+mod sqlite_output;
+mod summary;
+mod unique;
+
+use aggregate::{Aggregation, MeshCounter, parse_weight};
+use cache::{CacheKey, CoordCache, FieldCache};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use config::ConfigArgs;
+use encoding::{EncodingWriter, Unmappable, parse_encoding, strip_utf8_bom};
+use encoding_rs::Encoding;
+use encoding_rs_io::DecodeReaderBytesBuilder;
+use error_output::ErrorOutput;
+use geojson_input::NonPoint;
+use geometry::{GeometryFormat, Orientation, mesh_polygon, polygon_wkt};
+use logging::LogArgs;
+use meshify::{
+    Datum, DatumConverter, MeshComponents, MeshLevel, MeshifyError, WebMercator,
+    count_meshes_in_bbox, get_mesh_code, haversine_distance_m, hyphenate_mesh_code,
+    is_within_japan, mesh_area_km2, mesh_code_to_bounds, mesh_code_to_center, mesh_to_geohash,
+    meshes_in_bbox, parent_mesh, parse_dms, strip_mesh_code_separators, try_mesh_components,
+    validate_mesh_code,
+};
+use meshify::{plane, prefecture::prefectures_of_mesh, utm};
+use ndjson::ObjectWriter;
+use output::{GeoJsonWriter, OutputFormat, RecordWriter};
+use parquet_output::{ParquetSchema, ParquetWriter};
+use pipeline::Pipeline;
+use precision::format_decimal;
+use progress::Progress;
+use regex::Regex;
+use resume::{AppendWriter, ExistingOutput};
+use serde_json::Value;
+use sqlite_output::{SqliteSchema, SqliteWriter};
+use std::borrow::Cow;
+use std::collections::HashSet;
 use std::error::Error;
-use std::fs::File;
-use std::path::PathBuf;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use summary::{SkipReason, Summary};
+use unique::{SortedWriter, UniqueOrder, UniqueOutput};
+
+/// CSVファイル内の緯度経度に地域メッシュコードを付与するツール
+#[derive(Parser, Debug)]
+#[command(
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
 
-/// 入力座標の測地系
-#[derive(Copy, Clone, Debug, ValueEnum)]
-enum Datum {
-    WGS,
-    JGS,
+    #[command(flatten)]
+    encode: Option<EncodeArgs>,
+
+    #[command(flatten)]
+    csv: CsvArgs,
+
+    #[command(flatten)]
+    log: LogArgs,
+
+    #[command(flatten)]
+    config: ConfigArgs,
 }
 
-/// 計算するメッシュのレベル
-#[derive(Copy, Clone, Debug, ValueEnum)]
-enum MeshLevel {
-    Standard,
-    Half,
-    Quarter,
-    Eighth,
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// メッシュコード列から中心座標を逆算して列を追加する
+    Decode(DecodeArgs),
+    /// メッシュコード列の妥当性を検証し、不正な行を報告する
+    Validate(ValidateArgs),
+    /// 矩形領域を覆うメッシュの一覧を出力する
+    Grid(GridArgs),
+    /// 緯度経度からメッシュコードを求め、行の代わりにメッシュごとの点数を出力する
+    Aggregate(Box<AggregateArgs>),
+    /// 1点の緯度経度のメッシュコードを、コードだけ標準出力に表示する
+    Point(PointArgs),
 }
 
-/// CSVファイル内の緯度経度に地域メッシュコードを付与するツール
-#[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
-struct Args {
-    /// 緯度が含まれる列名
-    #[arg(long)]
-    lat: String,
+/// 緯度経度からメッシュコードを付与する際の引数
+#[derive(clap::Args, Debug)]
+struct EncodeArgs {
+    /// 緯度が含まれる列名 (発地と着地など複数の座標がある場合は、`--lon` と同じ数だけ繰り返し指定する)
+    ///
+    /// `--format-in geojson` では座標をジオメトリから読むため指定しない (指定の有無は `check_format` で確かめる)。
+    #[arg(long, requires = "lon", required_unless_present_any = ["latlon", "lat_index", "lat_regex", "format_in"])]
+    lat: Vec<String>,
 
     /// 経度が含まれる列名
+    #[arg(long, requires = "lat", required_unless_present_any = ["latlon", "lat_index", "lat_regex", "format_in"])]
+    lon: Vec<String>,
+
+    /// 座標列の組が複数ある場合に、組ごとの追加列の列名に付けるサフィックス (`--lat` と同じ数だけ指定する)
+    ///
+    /// 指定しない場合は緯度の列名を使う (`--lat origin_lat` なら mesh_code_origin_lat)。
+    #[arg(long, requires = "lat")]
+    pair_suffix: Vec<String>,
+
+    /// 緯度が含まれる列の位置 (0始まり。`--lat`/`--lon` とは排他)
+    #[arg(long, requires = "lon_index", conflicts_with_all = ["lat", "lon", "latlon"])]
+    lat_index: Option<usize>,
+
+    /// 経度が含まれる列の位置 (0始まり)
+    #[arg(long, requires = "lat_index", conflicts_with_all = ["lat", "lon", "latlon"])]
+    lon_index: Option<usize>,
+
+    /// 緯度が含まれる列名に一致する正規表現 (`^lat_deg_` など。`--lat`/`--lon` とは排他)
+    ///
+    /// 一致する列が複数ある場合はエラーにする。大文字小文字を区別しない場合は `(?i)` を付ける。
+    #[arg(long, requires = "lon_regex", value_parser = parse_regex, conflicts_with_all = ["lat", "lon", "latlon", "lat_index", "lon_index"])]
+    lat_regex: Option<Regex>,
+
+    /// 経度が含まれる列名に一致する正規表現
+    #[arg(long, requires = "lat_regex", value_parser = parse_regex, conflicts_with_all = ["lat", "lon", "latlon", "lat_index", "lon_index"])]
+    lon_regex: Option<Regex>,
+
+    /// 緯度と経度が1列にまとめて入っている列名 (`--lat`/`--lon` とは排他)
+    #[arg(long, conflicts_with_all = ["lat", "lon"])]
+    latlon: Option<String>,
+
+    /// 緯度経度の値の表記
+    #[arg(long, default_value = "decimal")]
+    coord_format: CoordFormat,
+
+    /// 緯度経度の値の小数点をカンマとして読む (`35,6895`)。カンマ以外の区切り文字 (`--delimiter ';'` など) と合わせて指定する
     #[arg(long)]
-    lon: String,
+    decimal_comma: bool,
+
+    /// `--latlon` の列で緯度と経度を区切る文字列
+    #[arg(long, default_value = ",", requires = "latlon")]
+    latlon_separator: String,
+
+    /// 標高などのZ値が含まれる列名。値はメッシュコードの計算には使わず、そのまま出力する
+    #[arg(long)]
+    z: Option<String>,
+
+    /// `--z` の列の値が数値でない行の扱い (空欄は値が無いものとして、そのまま出力する)
+    #[arg(long, default_value = "keep", requires = "z")]
+    invalid_z: InvalidZ,
 
     /// 入力座標の測地系
     #[arg(short, long, default_value = "wgs")]
     datum: Datum,
 
-    /// 出力先のファイルパス (指定しない場合は、<入力ファイル名>_mesh.csv に出力)
+    /// 入力座標のCRSをEPSGコードなどで指定する (例: EPSG:2451。`--datum` とは排他)
+    ///
+    /// 投影座標系では `--lat` に北距 (平面直角座標系のX、UTMのY)、`--lon` に東距 (平面直角座標系のY、UTMのX) の列を指定する。
+    /// 平面直角座標系 (JGD2011) は `plane:VIII` や `plane:8` のように系番号で、UTM (WGS84) は `utm:54N` や
+    /// `utm:53` のようにゾーンでも指定できる。
+    #[arg(long, conflicts_with = "datum", value_parser = parse_source_crs)]
+    source_crs: Option<String>,
+
+    /// 入力座標を平面直角座標系 (JGD2011) とし、系番号をこの位置 (緯度,経度 の概算) から推定する
+    ///
+    /// 原点が最も近い系を選ぶため、系の区域の境界付近では `--source-crs plane:<系番号>` で明示すること。
+    #[arg(long, value_parser = parse_plane_auto, allow_hyphen_values = true, conflicts_with_all = ["datum", "source_crs"])]
+    plane_auto: Option<u8>,
+
+    /// 行ごとの測地系 (`wgs`・`jgs`・`jgd2011`) が入った列名。複数のデータを結合したCSVなどで、行ごとに変換方法を切り替える (`--datum`・`--source-crs` とは排他)
+    #[arg(long, conflicts_with_all = ["datum", "source_crs", "plane_auto"])]
+    datum_column: Option<String>,
+
+    /// `--datum-column` の値が不正な行で使う測地系 (指定しない場合、値が不正な行は警告を出してスキップする)
+    #[arg(long, requires = "datum_column")]
+    default_datum: Option<Datum>,
+
+    /// `--lat` と `--lon` の列の値を入れ替えて解釈する (X・Y の軸の順序が逆に記録されたデータ向け)
+    #[arg(long)]
+    swap_xy: bool,
+
+    /// 列名を、前後の空白を除いて大文字小文字を区別せずに探す (`Latitude` や ` lat ` にも一致させる)
+    #[arg(long)]
+    ignore_case_headers: bool,
+
+    /// 指定した列名の列が入力に複数ある場合の扱い
+    #[arg(long, value_enum, default_value_t = DuplicateHeader::First)]
+    on_duplicate_header: DuplicateHeader,
+
+    /// 出力先のファイルパス (`-` で標準出力。入力が複数ある場合は結合して出力する。指定しない場合は、入力ごとに <入力ファイル名>_mesh.csv に出力。標準入力からの場合は標準出力)
     #[arg(short, long)]
     output: Option<PathBuf>,
 
-    /// 計算するメッシュのレベル
+    /// 入力形式
+    #[arg(long, default_value = "csv")]
+    format_in: InputFormat,
+
+    /// 出力形式 (指定しない場合、NDJSONの入力は ndjson、GeoJSONの入力は geojson、それ以外は csv。geojson の場合、メッシュ矩形のジオメトリは最初に指定したレベルで作る)
+    #[arg(long)]
+    format: Option<OutputFormat>,
+
+    /// `--format-in geojson` で、Point以外のジオメトリを持つFeatureの扱い
+    #[arg(long, value_enum, default_value_t = NonPoint::Skip)]
+    non_point: NonPoint,
+
+    /// `--format parquet` で出力する場合の、列の型の決め方
+    #[arg(long, default_value = "string")]
+    parquet_schema: ParquetSchema,
+
+    /// `--format sqlite` で書き出すテーブルの名前
+    #[arg(long, default_value = "points")]
+    table: String,
+
+    /// `--format sqlite` で出力する場合の、列の型の決め方
+    #[arg(long, default_value = "text")]
+    sqlite_schema: SqliteSchema,
+
+    /// `--format sqlite` で出力する場合に、メッシュコード列にインデックスを張る (メッシュごとの集計が速くなる)
+    #[arg(long)]
+    sqlite_index: bool,
+
+    /// 計算するメッシュのレベル (複数指定すると、レベルごとに <列名>_<レベル名> の列を追加する)
     #[arg(short, long, default_value = "standard")]
-    level: MeshLevel,
+    level: Vec<MeshLevel>,
+
+    /// 追加するメッシュコード列の列名 (レベルを複数指定した場合は列名の接頭辞)
+    #[arg(long, default_value = "mesh_code")]
+    mesh_column: String,
+
+    /// 追加する列を入れる位置 (`first`、0始まりの列の位置、`after:<列名>` で既存の列の直後。指定しない場合は末尾)
+    ///
+    /// 追加する列が複数ある場合は、まとめてこの位置に入れる。--force で既存の同名の列に上書きする列は、元の位置のままにする。
+    #[arg(long, value_parser = parse_column_position)]
+    mesh_column_position: Option<ColumnPosition>,
+
+    /// 出力する元の列 (`,` 区切りか繰り返しで指定)。指定した順に並べ、その後ろに追加する列を置く
+    #[arg(long, value_delimiter = ',', conflicts_with_all = ["drop_columns", "mesh_column_position"])]
+    keep_columns: Vec<String>,
+
+    /// 出力しない元の列 (`,` 区切りか繰り返しで指定)
+    #[arg(long, value_delimiter = ',')]
+    drop_columns: Vec<String>,
+
+    /// メッシュコードがこの接頭辞 (`5339` など) で始まる行だけを出力する (複数指定するといずれかで始まる行)
+    ///
+    /// レベルや座標列の組が複数ある場合は、最初のメッシュコード列で判定する。メッシュコードが空欄の行は出力しない。
+    #[arg(long, value_parser = parse_mesh_prefix)]
+    filter_mesh: Vec<String>,
+
+    /// メッシュコードの表記
+    #[arg(long, default_value = "plain")]
+    mesh_format: MeshFormat,
+
+    /// 同じメッシュコードの行は最初の1行だけを出力する
+    ///
+    /// レベルや座標列の組が複数ある場合は、最初のメッシュコード列で判定する。空欄のメッシュコードも1つの値として扱う。
+    /// 出力したメッシュコードは出力先ごとにメモリに持つ。
+    #[arg(long)]
+    unique_mesh: bool,
+
+    /// `--unique-mesh` で出力する列
+    #[arg(long, default_value = "row", requires = "unique_mesh")]
+    unique_output: UniqueOutput,
+
+    /// `--unique-mesh` で出力する行の並び順
+    #[arg(long, default_value = "input", requires = "unique_mesh")]
+    unique_order: UniqueOrder,
+
+    /// メッシュ矩形のジオメトリを mesh_geometry 列に出力する (レベルは --level に準ずる)
+    #[arg(long)]
+    geometry: Option<GeometryFormat>,
+
+    /// ジオメトリの頂点を並べる向き
+    #[arg(long, default_value = "ccw", requires = "geometry")]
+    geometry_orientation: Orientation,
+
+    /// ジオメトリの座標の小数点以下の桁数 (四捨五入。指定しない場合は --precision に従う)
+    #[arg(long, requires = "geometry")]
+    geometry_precision: Option<usize>,
+
+    /// メッシュの概算面積 (km²) を mesh_area_km2 列に出力する (レベルは --level に準ずる)
+    #[arg(long)]
+    add_area: bool,
+
+    /// メッシュの中心座標の GeoHash を geohash 列に出力する (レベルは --level に準ずる)
+    ///
+    /// GeoHash のセルはメッシュの矩形と境界がそろわないため、矩形全体ではなく中心の1点を表す。
+    #[arg(long)]
+    add_geohash: bool,
+
+    /// GeoHash の桁数 (1〜12。7桁で約150m四方)
+    #[arg(long, default_value_t = 7, value_parser = clap::value_parser!(u8).range(1..=12), requires = "add_geohash")]
+    geohash_precision: u8,
+
+    /// メッシュの中心座標を mesh_center_lat・mesh_center_lon 列に出力する (レベルは --level に準ずる)
+    #[arg(long)]
+    add_center: bool,
+
+    /// 中心座標の小数点以下の桁数 (四捨五入。指定しない場合は --precision に従う)
+    #[arg(long, requires = "add_center")]
+    center_precision: Option<usize>,
+
+    /// 点の座標とメッシュの中心のハバーサイン距離 (m) を mesh_center_distance_m 列に出力する (レベルは --level に準ずる)
+    ///
+    /// `--datum jgs` などの入力では、メッシュコードと同じく世界測地系に変換した座標で求める。
+    #[arg(long)]
+    add_center_distance: bool,
+
+    /// 点の座標をWebメルカトル (EPSG:3857) に変換した X・Y (m) を webmercator_x・webmercator_y 列に出力する
+    ///
+    /// メッシュコードとは独立に、世界測地系に変換した点の座標から求める。
+    #[arg(long)]
+    add_webmercator: bool,
+
+    /// Webメルカトル座標の小数点以下の桁数 (四捨五入。指定しない場合は --precision に従う)
+    #[arg(long, requires = "add_webmercator")]
+    webmercator_precision: Option<usize>,
+
+    /// メッシュコードの1次メッシュと範囲が重なる都道府県を prefecture 列に出力する
+    ///
+    /// 都道府県のおおよその範囲との重なりから求める概算で、行政界との照合ではない。
+    /// 1次メッシュが複数の都道府県にまたがる場合は、候補をすべて都道府県コード順に `;` 区切りで出力する。
+    /// 市区町村は判定しない。
+    #[arg(long)]
+    add_prefecture: bool,
+
+    /// 都道府県の出力形式
+    #[arg(long, default_value = "name", requires = "add_prefecture")]
+    prefecture_format: PrefectureFormat,
+
+    /// 中心座標・面積・距離・ジオメトリ・Webメルカトルの座標の小数点以下の桁数 (四捨五入。指定しない場合は丸めない)
+    #[arg(long)]
+    precision: Option<usize>,
+
+    /// 中心座標を出力する測地系
+    #[arg(long, default_value = "wgs", requires = "add_center")]
+    center_datum: CenterDatum,
+
+    /// メッシュコード列と同名の列が入力に既にある場合はその列を、出力先のファイルが既にある場合はそのファイルを上書きする
+    #[arg(long)]
+    force: bool,
+
+    /// 座標が日本の範囲外 (緯度20〜46度、経度122〜154度) の行の扱い
+    #[arg(long, default_value = "skip")]
+    out_of_range: OutOfRange,
+
+    /// 緯度・経度の値が空欄 (前後の空白を除いて空) の行の扱い。値が不正な行とは別に扱う
+    #[arg(long, default_value = "skip")]
+    empty_as: EmptyAs,
+
+    /// メッシュ計算に使うスレッド数 (指定しない場合はCPUのコア数)
+    #[arg(long)]
+    threads: Option<usize>,
+
+    /// まとめて読み込み、1つのワーカーで計算する行数 (メモリ使用量はおよそ この行数 × スレッド数 × 2 行分)
+    #[arg(long, default_value_t = DEFAULT_CHUNK_SIZE, value_parser = parse_chunk_size)]
+    chunk_size: usize,
+
+    /// 同じ座標の測地系の変換とメッシュ計算の結果を再利用する (同じ施設の複数のレコードなど、同じ座標が多い入力向け)
+    ///
+    /// キャッシュのキーにする座標の表し方を指定する。ヒット率は `-v` で表示する。
+    #[arg(long)]
+    coord_cache: Option<CacheKey>,
+
+    /// `--coord-cache rounded` で座標を丸める小数点以下の桁数
+    #[arg(long, default_value_t = 6, requires = "coord_cache")]
+    coord_cache_digits: u32,
+
+    /// N行書き出すごとに出力先へ送る (異常終了時に失う行を減らしたい場合に指定する。指定しない場合は最後にまとめて送る)
+    ///
+    /// Parquetは行グループ単位でしか書き出せず、SQLiteと集計 (`aggregate` サブコマンド) は最後にまとめて書き出すため、
+    /// これらの出力では効果がない。
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    flush_interval: Option<u64>,
+
+    /// 進捗バーを表示しない (標準出力へ書き出す場合やstderrが端末でない場合は常に表示しない)
+    #[arg(long)]
+    no_progress: bool,
+
+    /// 不正な座標や範囲外の座標の行をスキップせず、その時点でエラーとして終了する
+    ///
+    /// 書きかけの出力ファイルは削除する。
+    #[arg(long, conflicts_with = "out_of_range")]
+    strict: bool,
+
+    /// 座標などが不正な行をスキップせず、警告を出してメッシュコードを空欄にして出力する (入力と出力の行数をそろえる場合に使う)
+    ///
+    /// `--out-of-range skip` (既定) で範囲外の座標の行も同様に残す。
+    #[arg(long, conflicts_with = "strict")]
+    keep_invalid: bool,
+
+    /// 列の確認・座標の解析・メッシュコードの計算だけを行い、出力は書き出さずにサマリを表示する
+    ///
+    /// 出力ファイルは作成しないため、既存のファイルが上書きされることもない。
+    #[arg(long)]
+    dry_run: bool,
+
+    /// 各入力の先頭からこの行数 (ヘッダーを除く) を読み飛ばす。読み飛ばした行は読み込み行数に数えない
+    #[arg(long, default_value_t = 0)]
+    skip: usize,
+
+    /// 各入力で処理する行数の上限 (`--skip` の指定時は読み飛ばした後の行数)。動作確認のために先頭だけ処理する場合などに使う
+    #[arg(long)]
+    limit: Option<usize>,
+
+    /// 既存の出力に追記し、入力のこの行番号 (ヘッダーを1行目としたファイル上の行番号) の行から処理を再開する
+    ///
+    /// 中断などで途中までになった出力の続きを書き出すために使う。追記時は見出し行を書き出さず、既存の見出し行と
+    /// 出力する列が一致するか、出力済みの行数が再開位置より前の入力の行数と矛盾しないかを書き出す前に確かめる
+    /// (`--keep-invalid` で `--filter-mesh` も無い場合は、すべての行を出力するため一致まで確かめる)。
+    /// 入力は1つ、出力は圧縮しないCSVのファイルに限る。`--error-output` などの他の出力先には追記しない。
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), conflicts_with_all = ["skip", "unique_mesh"])]
+    resume_from: Option<u64>,
+
+    /// `--resume-from` で追記する既存の出力 (コマンドラインからは指定しない)
+    #[arg(skip)]
+    existing_output: Option<ExistingOutput>,
+
+    /// 各入力で想定する行数の上限 (`--skip` の指定時は読み飛ばした後の行数)。誤って巨大なファイルを処理しないための安全弁で、超えた場合の扱いは `--on-max-rows` で選ぶ
+    #[arg(long)]
+    max_rows: Option<usize>,
+
+    /// 行数が `--max-rows` を超えた場合の扱い
+    #[arg(long, default_value = "stop", requires = "max_rows")]
+    on_max_rows: OnMaxRows,
+
+    /// aggregate サブコマンドで集計する場合の集計方法 (コマンドラインからは指定しない)
+    #[arg(skip)]
+    aggregate: Option<Aggregation>,
+
+    /// スキップした行を、元の列にファイル上の行番号 (skip_line)・データの行番号 (skip_row)・理由 (skip_reason) の列を加えてこのCSVに書き出す (`-` で標準出力)
+    #[arg(long)]
+    error_output: Option<PathBuf>,
+
+    /// 処理結果の統計 (行数・スキップの内訳・処理時間・終了状態) をこのJSONファイルに書き出す
+    ///
+    /// 処理の途中でエラーになった場合や中断した場合も、そこまでの統計を書き出す。引数の誤りなど、
+    /// 入力を読み始める前のエラーでは書き出さない。`--dry-run` では出力と同様に書き出さない。
+    #[arg(long)]
+    stats_json: Option<PathBuf>,
+
+    /// 1行でもスキップした場合、出力を終えた後に非ゼロの終了コードで終了する
+    #[arg(long)]
+    fail_on_skip: bool,
+
+    /// 入力CSVファイルのパス (複数指定やglobパターンも可。`-` を指定すると標準入力から読み込む)
+    #[arg(required = true)]
+    input_file: Vec<PathBuf>,
+}
+
+impl EncodeArgs {
+    /// 出力形式。指定が無い場合は入力形式に合わせる
+    fn output_format(&self) -> OutputFormat {
+        match (self.format, self.format_in) {
+            (Some(format), _) => format,
+            (None, InputFormat::Csv) => OutputFormat::Csv,
+            (None, InputFormat::Ndjson) => OutputFormat::Ndjson,
+            (None, InputFormat::Geojson) => OutputFormat::Geojson,
+        }
+    }
+}
+
+/// 入力形式
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+enum InputFormat {
+    /// CSV (区切り文字は `--delimiter`)
+    Csv,
+    /// 1行に1つのJSONオブジェクト。`--lat`・`--lon` にはキーを指定し、`location.lat` のようにドット区切りでネストしたキーも指定できる
+    Ndjson,
+    /// GeoJSON の FeatureCollection。座標はPointのジオメトリから読み、`properties` のキーを列にする
+    Geojson,
+}
+
+/// 緯度経度の値の表記
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+enum CoordFormat {
+    /// 十進度 (35.6895)
+    Decimal,
+    /// 度分秒 (35°41'22.2"N、北緯35度41分22.2秒 など。十進度もそのまま読める)
+    Dms,
+}
+
+/// 緯度または経度の値を指定の表記として解析する
+fn parse_coord(s: &str, format: CoordFormat) -> Option<f64> {
+    match format {
+        CoordFormat::Decimal => s.trim().parse().ok(),
+        CoordFormat::Dms => parse_dms(s),
+    }
+}
+
+/// 緯度または経度の値を解析する。`--decimal-comma` 指定時は、小数点のカンマをピリオドにしてから解析する
+fn parse_coord_field(s: &str, args: &EncodeArgs) -> Option<f64> {
+    if args.decimal_comma {
+        parse_coord(&s.replace(',', "."), args.coord_format)
+    } else {
+        parse_coord(s, args.coord_format)
+    }
+}
+
+/// Z値を数値として読む (`--decimal-comma` の場合は小数点をカンマとして読む)
+fn parse_z(s: &str, args: &EncodeArgs) -> Option<f64> {
+    let s = s.trim();
+    let value = if args.decimal_comma {
+        s.replace(',', ".").parse::<f64>()
+    } else {
+        s.parse::<f64>()
+    };
+    value.ok().filter(|z| z.is_finite())
+}
+
+/// メッシュの中心座標を出力する測地系
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+enum CenterDatum {
+    /// 世界測地系 (WGS84)。メッシュコードの計算に使った座標系
+    Wgs,
+    /// 入力座標の測地系 (`--datum` または `--source-crs`) に戻す (`same` とも指定できる)
+    #[value(alias = "same")]
+    Input,
+}
+
+/// 緯度経度を読み取る列の位置
+#[derive(Copy, Clone, Debug)]
+enum CoordColumns {
+    /// 緯度列と経度列が別々にある
+    Separate(usize, usize),
+    /// 1つの列に緯度と経度が区切り文字でまとめて入っている
+    Combined(usize),
+}
+
+/// 追加する列を入れる位置 (`--mesh-column-position`)
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum ColumnPosition {
+    /// 0始まりの列の位置 (`first` は0)
+    Index(usize),
+    /// 指定した列の直後
+    After(String),
+}
+
+/// 座標が日本の範囲外だった行の扱い
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+enum OutOfRange {
+    /// 警告を出してスキップする
+    Skip,
+    /// 警告を出し、メッシュコードを空欄にして出力する
+    Keep,
+    /// エラーとして処理を中断する
+    Error,
+}
+
+/// メッシュコードの表記
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+enum MeshFormat {
+    /// 各レベルの桁を連結する (`5339359912`)
+    Plain,
+    /// 各レベルの桁の境界を `-` で区切る (`5339-35-99-1-2`)。decode はどちらの表記も受け付ける
+    Hyphenated,
+}
+
+/// `--add-prefecture` の都道府県の出力形式
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+enum PrefectureFormat {
+    /// 都道府県名 (`東京都`)
+    Name,
+    /// JIS X 0401 の都道府県コード (`13`)
+    Code,
+}
+
+/// 行数が `--max-rows` を超えた場合の扱い
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+enum OnMaxRows {
+    /// エラーとして処理を中断する
+    Stop,
+    /// 警告を出し、上限までの行で処理を終える
+    Truncate,
+}
+
+/// `--z` の列の値が数値でなかった行の扱い
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+enum InvalidZ {
+    /// 値をそのまま出力する
+    Keep,
+    /// 警告を出してスキップする (`--strict` の場合はエラーにする)
+    Skip,
+    /// エラーとして処理を中断する
+    Error,
+}
+
+/// 緯度・経度が空欄だった行の扱い
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+enum EmptyAs {
+    /// 警告を出してスキップする (`--strict` の場合はエラーにする)
+    Skip,
+    /// エラーとして処理を中断する
+    Error,
+    /// メッシュコードを空欄にして出力する
+    Keep,
+}
+
+/// 指定した列名の列が入力に複数あった場合の扱い
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+enum DuplicateHeader {
+    /// 警告を出し、最初の列を使う
+    First,
+    /// エラーとして処理を中断する (列の位置での指定を促す)
+    Error,
+}
+
+/// メッシュコードから中心座標を求める際の引数
+#[derive(clap::Args, Debug)]
+struct DecodeArgs {
+    #[command(flatten)]
+    column: CodeColumnArgs,
+
+    /// 指定したレベルの親メッシュのコードを parent_mesh_code 列に追加する (集計の粒度をそろえる場合など)
+    #[arg(long)]
+    parent: Option<MeshLevel>,
+
+    /// メッシュが覆う矩形を bbox 列に追加する (`--bbox` と同じ 最小緯度,最小経度,最大緯度,最大経度 の形式)
+    #[arg(long)]
+    bbox: bool,
+
+    /// 出力先のファイルパス (`-` で標準出力。指定しない場合は、<入力ファイル名>_center.csv に出力。標準入力からの場合は標準出力)
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// 出力先のファイルが既にある場合に上書きする
+    #[arg(long)]
+    force: bool,
 
-    /// 入力CSVファイルのパス
+    /// 入力CSVファイルのパス (`-` を指定すると標準入力から読み込む)
     #[arg()]
     input_file: PathBuf,
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let args = Args::parse();
-    let mut reader = csv::Reader::from_path(&args.input_file)?;
+/// decode・validate でメッシュコードが含まれる列の指定
+#[derive(clap::Args, Debug)]
+struct CodeColumnArgs {
+    /// メッシュコードが含まれる列名
+    #[arg(long, required_unless_present = "column_index")]
+    column: Option<String>,
 
-    // 出力ファイルパスの決定
-    let output_path = args.output.unwrap_or_else(|| {
-        let mut input_path = args.input_file.clone();
-        let file_stem = input_path.file_stem().unwrap().to_string_lossy();
-        input_path.set_file_name(format!("{}_mesh.csv", file_stem));
-        input_path
-    });
+    /// メッシュコードが含まれる列の位置 (0始まり。`--column` とは排他。`--no-header` の場合はこちらで指定する)
+    #[arg(long, conflicts_with = "column")]
+    column_index: Option<usize>,
+}
+
+impl CodeColumnArgs {
+    /// メッシュコード列の位置を求める。`--no-header` の場合、`headers` は1行目のデータで、列名では探せない
+    fn position(
+        &self,
+        headers: &csv::StringRecord,
+        has_headers: bool,
+    ) -> Result<usize, Box<dyn Error>> {
+        if let Some(index) = self.column_index {
+            if index >= headers.len() {
+                return Err(format!(
+                    "--column-index に指定された {} が列数 ({}) を超えています (列の位置は0始まりです)",
+                    index,
+                    headers.len()
+                )
+                .into());
+            }
+            return Ok(index);
+        }
+        if !has_headers {
+            return Err(
+                "--no-header 指定時は列名で列を指定できません (--column-index で列の位置を指定してください)"
+                    .into(),
+            );
+        }
+        let column = self
+            .column
+            .as_deref()
+            .expect("--column か --column-index のどちらかは必ず指定される");
+        Ok(headers
+            .iter()
+            .position(|h| h == column)
+            .ok_or_else(|| column_not_found(headers, "メッシュコード列", column))?)
+    }
+}
 
-    let mut writer = csv::Writer::from_writer(File::create(output_path)?);
+/// メッシュコードを検証する際の引数
+#[derive(clap::Args, Debug)]
+struct ValidateArgs {
+    #[command(flatten)]
+    column: CodeColumnArgs,
 
-    let headers = reader.headers()?.clone();
-    let lat_idx = headers
-        .iter()
-        .position(|h| h == args.lat)
-        .ok_or("緯度列が見つかりません")?;
-    let lon_idx = headers
-        .iter()
-        .position(|h| h == args.lon)
-        .ok_or("経度列が見つかりません")?;
+    /// 入力CSVファイルのパス (`-` を指定すると標準入力から読み込む)
+    #[arg()]
+    input_file: PathBuf,
+}
 
-    let mut new_headers = headers.iter().map(String::from).collect::<Vec<String>>();
-    new_headers.push("mesh_code".to_string());
-    writer.write_record(&new_headers)?;
+/// メッシュごとの点数を集計する際の引数
+///
+/// 座標列の指定などはメッシュコードを付与する場合と共通。出力は `<メッシュコード列名>,count` の形式
+/// (`--weight` 指定時は `<メッシュコード列名>,weight_sum`) で、出力先を指定しない場合は <入力ファイル名>_aggregate.csv に出力する。
+#[derive(clap::Args, Debug)]
+struct AggregateArgs {
+    #[command(flatten)]
+    encode: EncodeArgs,
 
-    // 日本測地系 (Tokyo Datum, EPSG:4301) → 世界測地系 (WGS84, EPSG:4326)
-    let proj = Proj::new_known_crs("EPSG:4301", "EPSG:4326", None)?;
+    #[command(flatten)]
+    aggregation: Aggregation,
+}
 
-    for result in reader.records() {
-        let mut record = result?;
+/// 1点のメッシュコードを求める際の引数
+#[derive(clap::Args, Debug)]
+struct PointArgs {
+    /// 緯度
+    #[arg(long, allow_hyphen_values = true)]
+    lat: String,
 
-        // readerから現在の行番号を取得する
-        let line_number = record.position().map(|p| p.line()).unwrap_or(0);
+    /// 経度
+    #[arg(long, allow_hyphen_values = true)]
+    lon: String,
 
-        let lat_str = &record[lat_idx];
-        let lat: f64 = match lat_str.trim().parse() {
-            Ok(val) => val,
-            Err(_) => {
-                // パース失敗時に警告を出し、この行の処理をスキップする
-                eprintln!("[警告] {}行目: 緯度の値「{}」が不正なため、この行をスキップします。", line_number, lat_str);
-                continue;
-            }
-        };
+    /// 緯度経度の値の表記
+    #[arg(long, default_value = "decimal")]
+    coord_format: CoordFormat,
 
-        let lon_str = &record[lon_idx];
-        let lon: f64 = match lon_str.trim().parse() {
-            Ok(val) => val,
-            Err(_) => {
-                eprintln!("[警告] {}行目: 経度の値「{}」が不正なため、この行をスキップします。", line_number, lon_str);
-                continue;
-            }
-        };
+    /// 計算するメッシュのレベル (複数指定すると、指定順に1行に1つずつ表示する)
+    #[arg(short, long, default_value = "standard")]
+    level: Vec<MeshLevel>,
 
-        let (wgs_lat, wgs_lon) = match args.datum {
-            Datum::JGS => {
-                // PROJは (経度, 緯度) の順
-                let (converted_lon, converted_lat) = proj.convert((lon, lat))?;
-                (converted_lat, converted_lon)
-            }
-            Datum::WGS => (lat, lon),
-        };
+    /// 座標の測地系
+    #[arg(short, long, default_value = "wgs")]
+    datum: Datum,
 
-        let mesh_code = get_mesh_code(wgs_lat, wgs_lon, args.level);
+    /// 座標のCRSをEPSGコードなどで指定する (`--datum` とは排他)
+    #[arg(long, conflicts_with = "datum", value_parser = parse_source_crs)]
+    source_crs: Option<String>,
 
-        record.push_field(&mesh_code);
-        writer.write_record(&record)?;
+    /// メッシュコードの各桁の内訳 (1次メッシュの p・u から8分の1地域メッシュの分割番号 o まで) を標準エラー出力に表示する
+    #[arg(long)]
+    explain: bool,
+}
+
+/// 矩形領域のメッシュ一覧を作る際の引数
+#[derive(clap::Args, Debug)]
+struct GridArgs {
+    /// 対象の矩形領域 (最小緯度,最小経度,最大緯度,最大経度)
+    #[arg(long, value_parser = parse_bbox, allow_hyphen_values = true)]
+    bbox: Bbox,
+
+    /// 出力するメッシュのレベル
+    #[arg(short, long, default_value = "standard")]
+    level: MeshLevel,
+
+    /// 出力するメッシュ数の上限。これを超える場合はエラーにする
+    #[arg(long, default_value_t = 1_000_000)]
+    max_meshes: usize,
+
+    /// 出力形式
+    #[arg(long, default_value = "csv")]
+    format: GridFormat,
+
+    /// 出力先のファイルパス (指定しない場合や `-` の場合は標準出力)
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// 出力先のファイルが既にある場合に上書きする
+    #[arg(long)]
+    force: bool,
+}
+
+/// メッシュ一覧の出力形式
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+enum GridFormat {
+    /// メッシュコードと中心座標のCSV
+    Csv,
+    /// 各メッシュの矩形をPolygonジオメトリにし、`properties` にメッシュコードと中心座標を入れたGeoJSON FeatureCollection
+    Geojson,
+}
+
+/// GeoJSONで出力するメッシュ数がこれを超える場合に警告する (地図ソフトで開きにくい大きさになるため)
+const GRID_GEOJSON_WARN_MESHES: usize = 100_000;
+
+/// 緯度経度の矩形領域
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Bbox {
+    min_lat: f64,
+    min_lon: f64,
+    max_lat: f64,
+    max_lon: f64,
+}
+
+/// `最小緯度,最小経度,最大緯度,最大経度` の形式の矩形領域を解析する
+fn parse_bbox(s: &str) -> Result<Bbox, String> {
+    let values = s
+        .split(',')
+        .map(|v| v.trim().parse::<f64>())
+        .collect::<Result<Vec<f64>, _>>()
+        .map_err(|_| format!("矩形領域の値「{}」に数値以外が含まれています", s))?;
+    let [min_lat, min_lon, max_lat, max_lon] = values[..] else {
+        return Err(format!(
+            "矩形領域は 最小緯度,最小経度,最大緯度,最大経度 の4つの値で指定してください: 「{}」",
+            s
+        ));
+    };
+    if min_lat > max_lat || min_lon > max_lon {
+        return Err(format!("矩形領域の最小値が最大値を超えています: 「{}」", s));
     }
+    Ok(Bbox {
+        min_lat,
+        min_lon,
+        max_lat,
+        max_lon,
+    })
+}
 
-    writer.flush()?;
-    Ok(())
+/// CSVの出力でフィールドを引用符で囲む方法
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+enum QuoteStyle {
+    /// すべてのフィールドを囲む (Excelなどで値を文字列として読ませる場合)
+    Always,
+    /// 区切り文字・引用符・改行を含むフィールドだけを囲む
+    Necessary,
+    /// 囲まない。区切り文字や改行を含むフィールドがあると、CSVとして読み戻せなくなる
+    Never,
+    /// 数値として読めないフィールドを囲む
+    NonNumeric,
 }
 
-/// 世界測地系の緯度経度から地域メッシュコードを計算
-fn get_mesh_code(lat: f64, lon: f64, level: MeshLevel) -> String {
-    // --- 基準地域メッシュ（3次メッシュ）の計算 ---
-    let lat_min = lat * 60.0;
-    let (p, a_rem) = ((lat_min / 40.0).floor(), lat_min % 40.0);
+impl QuoteStyle {
+    fn csv(self) -> csv::QuoteStyle {
+        match self {
+            QuoteStyle::Always => csv::QuoteStyle::Always,
+            QuoteStyle::Necessary => csv::QuoteStyle::Necessary,
+            QuoteStyle::Never => csv::QuoteStyle::Never,
+            QuoteStyle::NonNumeric => csv::QuoteStyle::NonNumeric,
+        }
+    }
+}
+
+/// CSVの出力の改行コード
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+enum LineEnding {
+    /// `\n` (Unix系)
+    Lf,
+    /// `\r\n` (Windows向け)
+    Crlf,
+}
 
-    let (q, b_rem) = ((a_rem / 5.0).floor(), a_rem % 5.0);
+impl LineEnding {
+    fn csv(self) -> csv::Terminator {
+        match self {
+            LineEnding::Lf => csv::Terminator::Any(b'\n'),
+            LineEnding::Crlf => csv::Terminator::CRLF,
+        }
+    }
+}
 
-    let lat_sec_in_b = b_rem * 60.0;
-    let (r, c_rem) = ((lat_sec_in_b / 30.0).floor(), lat_sec_in_b % 30.0);
+/// CSVの読み書きに関する引数 (すべてのサブコマンドで共通)
+#[derive(clap::Args, Debug)]
+struct CsvArgs {
+    /// 入力の区切り文字 (1文字。`\t` でタブ区切り)
+    #[arg(long, global = true, default_value = ",", value_parser = parse_delimiter)]
+    delimiter: u8,
 
-    let lon_deg_rem = lon - lon.floor();
-    let u = lon.floor() - 100.0;
+    /// 出力の区切り文字 (指定しない場合は入力と同じ)
+    #[arg(long, global = true, value_parser = parse_delimiter)]
+    output_delimiter: Option<u8>,
 
-    let lon_min_rem = lon_deg_rem * 60.0;
-    let (v, g_rem) = ((lon_min_rem / 7.5).floor(), lon_min_rem % 7.5);
+    /// 入力でフィールドを囲む引用符の文字 (1文字)
+    #[arg(long, global = true, default_value = "\"", value_parser = parse_quote_char)]
+    quote_char: u8,
 
-    let lon_sec_in_g = g_rem * 60.0;
-    let (w, h_rem) = ((lon_sec_in_g / 45.0).floor(), lon_sec_in_g % 45.0);
+    /// 出力のフィールドを引用符で囲む方法
+    #[arg(long, global = true, default_value = "necessary")]
+    quote_style: QuoteStyle,
 
-    // まず、変更しないベースとなるコードを mutable な String として作成
-    let mut code = format!(
-        "{}{}{}{}{}{}",
-        p as u32, u as u32, q as u32, v as u32, r as u32, w as u32
-    );
+    /// 出力の改行コード (入力の改行コードは LF・CRLF のどちらも自動で読む)
+    ///
+    /// 実行する環境によらず同じ出力になるよう、既定はプラットフォームによらずLFとする。
+    #[arg(long, global = true, default_value = "lf")]
+    line_ending: LineEnding,
+
+    /// 入力の文字エンコーディング (utf-8, sjis など。指定しない場合はUTF-8)
+    ///
+    /// 先頭にBOMがある入力は、この指定よりBOMの示すエンコーディングを優先し、BOMを取り除いて読む。
+    #[arg(long, global = true, value_parser = parse_encoding)]
+    encoding: Option<&'static Encoding>,
+
+    /// 出力の文字エンコーディング (utf-8, sjis など。指定しない場合はUTF-8)
+    #[arg(long, global = true, value_parser = parse_encoding)]
+    output_encoding: Option<&'static Encoding>,
+
+    /// UTF-8で出力する際に先頭にBOMを付ける (Excelでの文字化け対策)
+    #[arg(long, global = true)]
+    bom: bool,
+
+    /// 入力の1行目をヘッダーとして扱わない (座標列は --lat-index・--lon-index で指定し、出力にもヘッダーを書かない)
+    #[arg(long, global = true)]
+    no_header: bool,
+
+    /// 入力が .xlsx のファイルの場合に読むシートの名前 (指定しない場合は最初のシート。`--all-sheets` とは併用できない)
+    ///
+    /// xlsxの入力では、値のあるセルの範囲の最初の行をヘッダーとし、`--delimiter`・`--encoding` は使わない。
+    #[arg(long, global = true)]
+    sheet: Option<String>,
+
+    /// 入力が .xlsx のファイルの場合に、すべてのシートを順につなげて1つの入力として読む
+    ///
+    /// シート名を sheet 列として最後に加える。ヘッダーが最初のシートと一致しないシートは、警告を出して読み飛ばす
+    /// (`--no-header` の場合は列の数で比べる)。特定のシートだけを読む `--sheet` とは併用できない。
+    /// 行番号はシートを順につないだ行番号になり、Excelの行番号とは一致しない。
+    #[arg(long, global = true, conflicts_with = "sheet")]
+    all_sheets: bool,
+
+    /// 出力エンコーディングで表現できない文字の扱い
+    #[arg(long, global = true, default_value = "replace")]
+    on_unmappable: Unmappable,
+
+    /// 出力をgzipで圧縮する (出力パスが .gz で終わる場合は指定しなくても圧縮する。入力は中身から自動で判定する)
+    #[arg(long, global = true)]
+    gzip: bool,
+
+    /// gzip圧縮のレベル (0〜9)
+    #[arg(long, global = true, default_value_t = 6, value_parser = clap::value_parser!(u32).range(0..=9))]
+    compression_level: u32,
 
-    // 目的のレベルに達していない場合は、計算を続行する
-    if let MeshLevel::Standard = level {
-        return code;
+    /// 出力の書き込みバッファのバイト数 (`64K`・`1M` のように K・M を付けてもよい)
+    #[arg(long, global = true, default_value = "64K", value_parser = parse_buffer_size)]
+    buffer_size: usize,
+}
+
+impl CsvArgs {
+    fn reader(&self, input: Box<dyn Read>) -> io::Result<csv::Reader<Box<dyn Read>>> {
+        Ok(csv::ReaderBuilder::new()
+            .delimiter(self.delimiter)
+            .quote(self.quote_char)
+            .has_headers(!self.no_header)
+            .from_reader(self.decoded_input(input)?))
+    }
+
+    /// 入力をCSVとして読む。パスの拡張子が `.xlsx` の場合は、Excelのシートの内容をCSVとして読む
+    fn open_reader(
+        &self,
+        path: &Path,
+        input: Box<dyn Read>,
+    ) -> Result<csv::Reader<Box<dyn Read>>, Box<dyn Error>> {
+        if !excel::is_xlsx_path(path) {
+            return Ok(self.reader(input)?);
+        }
+        let csv = if self.all_sheets {
+            excel::all_sheets_to_csv(input, !self.no_header)
+        } else {
+            excel::sheet_to_csv(input, self.sheet.as_deref())
+        }
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(csv::ReaderBuilder::new()
+            .has_headers(!self.no_header)
+            .from_reader(Box::new(io::Cursor::new(csv))))
     }
 
-    // --- 2分の1地域メッシュの計算 ---
-    let (s, d_rem) = ((c_rem / 15.0).floor(), c_rem % 15.0);
-    let (x, i_rem) = ((h_rem / 22.5).floor(), h_rem % 22.5);
-    let m = (s * 2.0) + x + 1.0;
-    code.push_str(&(m as u32).to_string()); // 計算結果を追記
+    /// 入力を展開し、UTF-8にデコードして読めるようにする
+    fn decoded_input(&self, input: Box<dyn Read>) -> io::Result<Box<dyn Read>> {
+        let input = compression::decompress(input)?;
+        // UTF-8以外の入力は、csvクレートなどに渡す前にUTF-8へデコードする。先頭にBOMがあれば、
+        // --encoding の指定よりBOMの示すエンコーディングを優先し、BOMは取り除く
+        match self.encoding {
+            Some(encoding) if encoding != encoding_rs::UTF_8 => Ok(Box::new(
+                DecodeReaderBytesBuilder::new()
+                    .encoding(Some(encoding))
+                    .bom_override(true)
+                    .strip_bom(true)
+                    .build(input),
+            )),
+            _ => strip_utf8_bom(input),
+        }
+    }
 
-    if let MeshLevel::Half = level {
-        return code;
+    /// 出力先を開く。--gzip 指定時か出力パスが .gz で終わる場合は圧縮して書き出す
+    ///
+    /// 書き込みは `--buffer-size` のバッファにためてから出力先に送る (標準出力も行ごとには送らない)。
+    fn open_output(&self, path: Option<&Path>, force: bool) -> io::Result<Box<dyn Write>> {
+        let output = open_output(path, force)?;
+        let output = if self.gzip || path.is_some_and(compression::is_gzip_path) {
+            compression::compress(output, self.compression_level)
+        } else {
+            output
+        };
+        Ok(Box::new(BufWriter::with_capacity(self.buffer_size, output)))
     }
 
-    // --- 4分の1地域メッシュの計算 ---
-    let (t, e_rem) = ((d_rem / 7.5).floor(), d_rem % 7.5);
-    let (y, j_rem) = ((i_rem / 11.25).floor(), i_rem % 11.25);
-    let n = (t * 2.0) + y + 1.0;
-    code.push_str(&(n as u32).to_string()); // 計算結果を追記
+    /// 既存の出力ファイルに追記する (`--resume-from`)
+    fn open_append(&self, path: &Path) -> io::Result<Box<dyn Write>> {
+        let output = OpenOptions::new().append(true).open(path)?;
+        Ok(Box::new(BufWriter::with_capacity(self.buffer_size, output)))
+    }
 
-    if let MeshLevel::Quarter = level {
-        return code;
+    fn writer(&self, output: Box<dyn Write>) -> io::Result<csv::Writer<Box<dyn Write>>> {
+        self.csv_writer(output, self.bom)
     }
 
-    // --- 8分の1地域メッシュの計算 ---
-    let (t2, _) = ((e_rem / 3.75).floor(), e_rem % 3.75);
-    let (y2, _) = ((j_rem / 5.625).floor(), j_rem % 5.625);
-    let o = (t2 * 2.0) + y2 + 1.0;
-    code.push_str(&(o as u32).to_string()); // 計算結果を追記
+    /// 既存の出力に追記するCSVの書き出し先。先頭ではないため `--bom` を指定してもBOMは付けない
+    fn append_writer(&self, output: Box<dyn Write>) -> io::Result<csv::Writer<Box<dyn Write>>> {
+        self.csv_writer(output, false)
+    }
 
-    // Eighthが最後のレベルなので、そのまま返す
-    code
+    fn csv_writer(
+        &self,
+        mut output: Box<dyn Write>,
+        bom: bool,
+    ) -> io::Result<csv::Writer<Box<dyn Write>>> {
+        let output: Box<dyn Write> = match self.output_encoding {
+            Some(encoding) if encoding != encoding_rs::UTF_8 => {
+                if bom {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "--bom はUTF-8で出力する場合のみ指定できます",
+                    ));
+                }
+                Box::new(EncodingWriter::new(output, encoding, self.on_unmappable))
+            }
+            _ => {
+                if bom {
+                    output.write_all(b"\xEF\xBB\xBF")?;
+                }
+                output
+            }
+        };
+        Ok(csv::WriterBuilder::new()
+            .delimiter(self.output_delimiter.unwrap_or(self.delimiter))
+            .quote_style(self.quote_style.csv())
+            .terminator(self.line_ending.csv())
+            .from_writer(output))
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*; // main.rs内の関数やenumをテストコードで使えるようにする
+/// 区切り文字の指定を解析する。`\t` などのエスケープ表記も受け付ける
+fn parse_delimiter(s: &str) -> Result<u8, String> {
+    let c = match s {
+        "\\t" => '\t',
+        _ => {
+            let mut chars = s.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => c,
+                _ => return Err(format!("区切り文字は1文字で指定してください: 「{}」", s)),
+            }
+        }
+    };
+    u8::try_from(c)
+        .ok()
+        .filter(u8::is_ascii)
+        .ok_or_else(|| format!("区切り文字にはASCII文字を指定してください: 「{}」", s))
+}
 
-    /// 度分秒 (DMS) を 十進数度 (Decimal Degrees) に変換するヘルパー関数
-    fn dms_to_dd(d: f64, m: f64, s: f64) -> f64 {
-        d + m / 60.0 + s / 3600.0
+/// 引用符の文字の指定を解析する (ASCIIの1文字)
+fn parse_quote_char(s: &str) -> Result<u8, String> {
+    match s.as_bytes() {
+        &[c] if c.is_ascii() => Ok(c),
+        _ => Err(format!("引用符はASCIIの1文字で指定してください: 「{}」", s)),
     }
+}
 
-    #[test]
-    fn test_sapporo_standard() {
-        // 資料 p.14 表7 北海道札幌市
-        let lat = dms_to_dd(43.0, 3.0, 30.0);
-        let lon = dms_to_dd(141.0, 20.0, 15.0);
-        let expected = "64414277";
-        assert_eq!(get_mesh_code(lat, lon, MeshLevel::Standard), expected);
+/// `--chunk-size` の行数を解析する (1以上)
+fn parse_chunk_size(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(size) if size > 0 => Ok(size),
+        _ => Err(format!(
+            "チャンクの行数「{}」は1以上の整数で指定してください",
+            s
+        )),
     }
+}
 
-    #[test]
-    fn test_tokyo_standard() {
-        // 資料 p.14 表7 東京都新宿区
-        let lat = dms_to_dd(35.0, 41.0, 0.0);
-        let lon = dms_to_dd(139.0, 41.0, 15.0);
-        let expected = "53394525";
-        assert_eq!(get_mesh_code(lat, lon, MeshLevel::Standard), expected);
+/// `--buffer-size` のバイト数を解析する。`K` (1024倍)・`M` (1024×1024倍) の接尾辞も受け付ける
+fn parse_buffer_size(s: &str) -> Result<usize, String> {
+    let (digits, unit) = match s.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&s[..i], 1 << 10),
+        Some((i, 'M' | 'm')) => (&s[..i], 1 << 20),
+        _ => (s, 1),
+    };
+    match digits
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit))
+    {
+        Some(size) if size > 0 => Ok(size),
+        _ => Err(format!(
+            "バッファのサイズ「{}」は1以上のバイト数で指定してください",
+            s
+        )),
     }
+}
 
-    #[test]
-    fn test_naha_standard() {
-        // 資料 p.14 表7 沖縄県那覇市
-        let lat = dms_to_dd(26.0, 12.0, 30.0);
-        let lon = dms_to_dd(127.0, 40.0, 30.0);
-        let expected = "39272554";
-        assert_eq!(get_mesh_code(lat, lon, MeshLevel::Standard), expected);
+/// `--mesh-column-position` の位置の指定を解析する
+fn parse_column_position(s: &str) -> Result<ColumnPosition, String> {
+    if s == "first" {
+        return Ok(ColumnPosition::Index(0));
+    }
+    if let Some(name) = s.strip_prefix("after:") {
+        return Ok(ColumnPosition::After(name.to_string()));
+    }
+    s.parse::<usize>().map(ColumnPosition::Index).map_err(|_| {
+        format!(
+            "列の位置「{}」は first・0始まりの列の位置・after:<列名> のいずれかで指定してください",
+            s
+        )
+    })
+}
+
+/// `--filter-mesh` のメッシュコードの接頭辞を解析する
+fn parse_mesh_prefix(s: &str) -> Result<String, String> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(format!(
+            "メッシュコードの接頭辞「{}」は数字で指定してください",
+            s
+        ));
     }
+    Ok(s.to_string())
+}
 
-    #[test]
-    fn test_tokyo_all_levels() {
-        // 東京都新宿区の座標で、すべてのメッシュレベルをテスト
-        let lat = dms_to_dd(35.0, 41.0, 0.0);
-        let lon = dms_to_dd(139.0, 41.0, 15.0);
+/// 列名に一致させる正規表現を解析する
+fn parse_regex(s: &str) -> Result<Regex, String> {
+    Regex::new(s).map_err(|e| format!("正規表現として解析できません: {}", e))
+}
+
+/// 入力座標のCRSを解析する。`plane:<系番号>` は平面直角座標系、`utm:<ゾーン>` はUTMのEPSGコードに置き換える
+///
+/// PROJの変換を二度作らないよう、PROJが解釈できるCRSかどうかはここでは確かめない。解釈できないCRSは、
+/// 行を読む前に変換器を作る際 (`input_converter`・`point_mesh_codes`) にエラーになる。
+fn parse_source_crs(s: &str) -> Result<String, String> {
+    let crs = if let Some(zone) = s.strip_prefix("plane:") {
+        plane::parse_zone(zone).map(plane::zone_crs).ok_or_else(|| {
+            format!(
+                "平面直角座標系の系番号「{}」が不正です (I〜XIX または 1〜19 で指定してください)",
+                zone
+            )
+        })?
+    } else if let Some(zone) = s.strip_prefix("utm:") {
+        utm::parse_zone(zone).map(utm::zone_crs).ok_or_else(|| {
+            format!(
+                "UTMのゾーン「{}」が不正です (54N・53S のように 1〜60 の番号と N・S で指定してください)",
+                zone
+            )
+        })?
+    } else {
+        s.to_string()
+    };
+    Ok(crs)
+}
 
-        // 各レベルで期待されるコードを検証
-        assert_eq!(get_mesh_code(lat, lon, MeshLevel::Standard), "53394525");
-        assert_eq!(get_mesh_code(lat, lon, MeshLevel::Half), "533945251");
-        assert_eq!(get_mesh_code(lat, lon, MeshLevel::Quarter), "5339452511");
-        assert_eq!(get_mesh_code(lat, lon, MeshLevel::Eighth), "53394525111");
+/// `緯度,経度` の概算の位置から、平面直角座標系の系番号を推定する
+fn parse_plane_auto(s: &str) -> Result<u8, String> {
+    let (lat, lon) = s
+        .split_once(',')
+        .and_then(|(lat, lon)| {
+            Some((
+                lat.trim().parse::<f64>().ok()?,
+                lon.trim().parse::<f64>().ok()?,
+            ))
+        })
+        .ok_or_else(|| format!("位置は 緯度,経度 の形式で指定してください: 「{}」", s))?;
+    if !is_within_japan(lat, lon) {
+        return Err(format!("位置 ({}, {}) が日本の範囲外です", lat, lon));
     }
+    Ok(plane::estimate_zone(lat, lon))
 }
 
+fn main() -> ExitCode {
+    // エラーは Debug 表記ではなく、そのまま読める日本語のメッセージで表示する
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("[エラー] {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let args = config::args_with_config(Cli::command(), std::env::args_os().collect())?;
+    let cli = Cli::parse_from(args);
+    logging::init(&cli.log);
+    // 行を書き出す処理では、Ctrl-C で書きかけの出力が壊れないよう、処理済みの行を書き出してから終了する
+    if matches!(cli.command, None | Some(Command::Aggregate(_))) {
+        interrupt::install()?;
+    }
+    match (cli.command, cli.encode) {
+        (Some(Command::Decode(args)), _) => run_decode(args, &cli.csv),
+        (Some(Command::Validate(args)), _) => run_validate(args, &cli.csv),
+        (Some(Command::Grid(args)), _) => run_grid(args, &cli.csv),
+        (Some(Command::Aggregate(args)), _) => run_aggregate(args, &cli.csv),
+        (Some(Command::Point(args)), _) => run_point(args),
+        (None, Some(args)) => run_encode(args, &cli.csv),
+        (None, None) => unreachable!("サブコマンドが無い場合、clapが必須引数を検証する"),
+    }
+}
+
+/// パスが標準入出力を表す `-` かどうか
+fn is_stdio(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// 入力を開く (`-` の場合は標準入力)
+///
+/// 開けない場合は、ファイルが無い・ディレクトリである・権限が無いなどの原因が分かるメッセージのエラーにする。
+fn open_input(path: &Path) -> io::Result<Box<dyn Read>> {
+    if is_stdio(path) {
+        return Ok(Box::new(io::stdin().lock()));
+    }
+    let input_error = |e: io::Error| {
+        let message = match e.kind() {
+            io::ErrorKind::NotFound => format!("入力ファイルが見つかりません: {}", path.display()),
+            io::ErrorKind::PermissionDenied => {
+                format!("入力ファイルを読み取る権限がありません: {}", path.display())
+            }
+            _ => format!("入力ファイル「{}」を開けません: {}", path.display(), e),
+        };
+        io::Error::new(e.kind(), message)
+    };
+    // ディレクトリは開けても読み込む時点で失敗するため、先に確かめる
+    if fs::metadata(path).map_err(input_error)?.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("入力にディレクトリが指定されています: {}", path.display()),
+        ));
+    }
+    Ok(Box::new(File::open(path).map_err(input_error)?))
+}
+
+/// 出力先を決定する。`None` は標準出力を表す
+///
+/// `-` が指定された場合は標準出力に書き出す。
+/// 出力先の指定が無い場合は、入力ファイル名の末尾に接尾辞を付け、拡張子を `extension` にしたパスにする。
+/// 入力が `.gz` の場合は出力にも `.gz` を付ける。
+/// 入力が標準入力の場合はファイル名を導出できないため、標準出力に書き出す。
+fn resolve_output_path(
+    output: Option<PathBuf>,
+    input_file: &Path,
+    suffix: &str,
+    extension: &str,
+) -> Option<PathBuf> {
+    if let Some(path) = output {
+        return if is_stdio(&path) { None } else { Some(path) };
+    }
+    if is_stdio(input_file) {
+        return None;
+    }
+    // 圧縮された入力 (`points.csv.gz`) からは、圧縮した出力 (`points_mesh.csv.gz`) を作る
+    let (path, gz) = if compression::is_gzip_path(input_file) {
+        (input_file.with_extension(""), ".gz")
+    } else {
+        (input_file.to_path_buf(), "")
+    };
+    let file_stem = path.file_stem().unwrap().to_string_lossy();
+    Some(path.with_file_name(format!("{}_{}.{}{}", file_stem, suffix, extension, gz)))
+}
+
+/// 出力先を開く (`None` の場合は標準出力)
+///
+/// 元データなどを誤って消さないよう、既存のファイルは `force` の場合のみ上書きし、それ以外はエラーにする。
+/// 標準出力はロックしたまま書き込むため、CSV本体だけが流れる。警告は従来通り標準エラー出力に出す。
+/// バッファは書き込み側 (`CsvArgs::open_output` の `BufWriter` など) が持つので、処理の最後に必ず `flush` すること。
+fn open_output(path: Option<&Path>, force: bool) -> io::Result<Box<dyn Write>> {
+    match path {
+        Some(path) if force => Ok(Box::new(File::create(path)?)),
+        Some(path) => match File::create_new(path) {
+            Ok(file) => Ok(Box::new(file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                Err(io::Error::new(e.kind(), already_exists_message(path)))
+            }
+            Err(e) => Err(e),
+        },
+        None => Ok(Box::new(io::stdout().lock())),
+    }
+}
+
+fn already_exists_message(path: &Path) -> String {
+    format!(
+        "出力先「{}」は既に存在します (上書きする場合は --force を指定してください)",
+        path.display()
+    )
+}
+
+fn run_aggregate(args: Box<AggregateArgs>, csv_args: &CsvArgs) -> Result<(), Box<dyn Error>> {
+    let AggregateArgs {
+        mut encode,
+        aggregation,
+    } = *args;
+    if unique_levels(&encode.level).len() > 1 {
+        return Err("集計するメッシュのレベルは1つだけ指定できます".into());
+    }
+    if encode.lat.len() > 1 {
+        return Err("集計する座標列の組 (--lat・--lon) は1つだけ指定できます".into());
+    }
+    if encode.format_in != InputFormat::Csv {
+        return Err("aggregate の入力はCSVのみに対応しています".into());
+    }
+    // 集計結果には元の行が残らないため、行ごとの列を追加する指定は意味を持たない
+    if encode
+        .format
+        .is_some_and(|format| format != OutputFormat::Csv)
+        || encode.geometry.is_some()
+        || encode.add_area
+        || encode.add_geohash
+        || encode.add_center
+        || encode.add_center_distance
+        || encode.add_webmercator
+        || encode.add_prefecture
+        || !encode.keep_columns.is_empty()
+        || !encode.drop_columns.is_empty()
+    {
+        return Err(
+            "aggregate では --format・--geometry・--add-area・--add-geohash・--add-center・--add-center-distance・--add-webmercator・--add-prefecture・--keep-columns・--drop-columns は指定できません"
+                .into(),
+        );
+    }
+    if encode.unique_mesh {
+        return Err(
+            "集計結果はメッシュごとに1行のため、aggregate では --unique-mesh は指定できません"
+                .into(),
+        );
+    }
+    encode.aggregate = Some(aggregation);
+    run_encode(encode, csv_args)
+}
+
+fn run_encode(mut args: EncodeArgs, csv_args: &CsvArgs) -> Result<(), Box<dyn Error>> {
+    let started = Instant::now();
+    check_format(&args, csv_args)?;
+    if args.add_prefecture {
+        log::warn!(
+            "prefecture 列は、都道府県のおおよその範囲と1次メッシュの重なりから求めた概算です。行政界とは照合していないため、候補に実際には含まれない都道府県が入ることがあります。"
+        );
+    }
+    let inputs = expand_inputs(&args.input_file)?;
+    // 一部の入力を処理した後で止まらないよう、すべての入力を読めるか先に確かめる
+    for input in inputs.iter().filter(|input| !is_stdio(input)) {
+        open_input(input)?;
+    }
+
+    // 出力ファイルパスの決定。--output 指定時は全入力を1ファイルに結合し、それ以外は入力ごとに出力する
+    let (suffix, extension) = match (&args.aggregate, args.output_format()) {
+        (Some(_), _) => ("aggregate", "csv"),
+        (None, OutputFormat::Csv) => ("mesh", "csv"),
+        (None, OutputFormat::Geojson) => ("mesh", "geojson"),
+        (None, OutputFormat::Ndjson) => ("mesh", "ndjson"),
+        (None, OutputFormat::Parquet) => ("mesh", "parquet"),
+        (None, OutputFormat::Sqlite) => ("mesh", "db"),
+    };
+    let jobs: Vec<(Option<PathBuf>, &[PathBuf])> = match &args.output {
+        Some(_) => vec![(
+            resolve_output_path(args.output.clone(), &inputs[0], suffix, extension),
+            &inputs[..],
+        )],
+        None => inputs
+            .chunks(1)
+            .map(|input| {
+                (
+                    resolve_output_path(None, &input[0], suffix, extension),
+                    input,
+                )
+            })
+            .collect(),
+    };
+
+    if args.resume_from.is_some() {
+        args.existing_output = Some(read_resume_output(&args, csv_args, &jobs)?);
+    }
+
+    let to_stdout = !args.dry_run && jobs.iter().any(|(output_path, _)| output_path.is_none());
+    // 一部の入力を処理した後で止まらないよう、既存の出力先は処理を始める前に確かめる
+    if !args.dry_run && !args.force {
+        let existing = jobs
+            .iter()
+            .filter(|_| args.resume_from.is_none())
+            .filter_map(|(output_path, _)| output_path.as_deref())
+            .chain(args.error_output.as_deref().filter(|path| !is_stdio(path)))
+            .chain(args.stats_json.as_deref())
+            .find(|path| path.exists());
+        if let Some(path) = existing {
+            return Err(already_exists_message(path).into());
+        }
+    }
+    // --quiet でサマリ (info) を表示しない場合は、進捗も表示しない
+    let quiet = !log::log_enabled!(log::Level::Info);
+    let mut progress = if quiet || args.no_progress || to_stdout || !io::stderr().is_terminal() {
+        Progress::hidden()
+    } else if inputs.iter().any(|input| is_stdio(input)) {
+        Progress::new(None)
+    } else {
+        let mut total = 0;
+        for input in &inputs {
+            total += fs::metadata(input)?.len();
+        }
+        Progress::new(Some(total))
+    };
+
+    let mut builder = rayon::ThreadPoolBuilder::new();
+    if let Some(threads) = args.threads {
+        builder = builder.num_threads(threads);
+    }
+    let pool = builder.build()?;
+
+    let mut report = Report::default();
+    if let Some(path) = &args.error_output {
+        if to_stdout && is_stdio(path) {
+            return Err("出力と --error-output の両方を標準出力にはできません".into());
+        }
+        // --dry-run では出力と同様に書き出さない
+        let output: Box<dyn Write> = if args.dry_run {
+            Box::new(io::sink())
+        } else {
+            csv_args.open_output((!is_stdio(path)).then_some(path), args.force)?
+        };
+        report.errors = Some(ErrorOutput::new(csv_args.writer(output)?));
+    }
+    let result = jobs.into_iter().try_for_each(|(output_path, inputs)| {
+        encode_files(
+            inputs,
+            output_path.as_deref(),
+            &args,
+            csv_args,
+            &pool,
+            &mut progress,
+            &mut report,
+        )
+    });
+    progress.finish();
+    let Report {
+        summary, errors, ..
+    } = report;
+    if let Some(mut errors) = errors {
+        errors.finish()?;
+    }
+    if args.dry_run {
+        log::info!("{} (--dry-run のため出力は書き出していません)", summary);
+    } else {
+        log::info!("{}", summary);
+    }
+    let outcome = result.and_then(|()| {
+        if interrupt::is_interrupted() {
+            return Err("中断されたため、処理済みの行までを出力して終了しました".into());
+        }
+        // 自動処理で不正なデータの混入に気付けるよう、スキップがあれば失敗として終了する
+        if args.fail_on_skip && summary.skipped() > 0 {
+            return Err(
+                format!("{}行をスキップしました (--fail-on-skip)", summary.skipped()).into(),
+            );
+        }
+        Ok(())
+    });
+    if let Some(path) = args.stats_json.as_deref().filter(|_| !args.dry_run) {
+        write_stats_json(path, &summary, started.elapsed(), &outcome, args.force)?;
+    }
+    outcome
+}
+
+/// `--resume-from` の追記先を確かめ、既存の出力を読む
+fn read_resume_output(
+    args: &EncodeArgs,
+    csv_args: &CsvArgs,
+    jobs: &[(Option<PathBuf>, &[PathBuf])],
+) -> Result<ExistingOutput, Box<dyn Error>> {
+    if args.format_in != InputFormat::Csv
+        || args.output_format() != OutputFormat::Csv
+        || args.aggregate.is_some()
+    {
+        return Err(
+            "--resume-from はCSVの入力からCSVへの出力 (aggregate を除く) にのみ対応しています"
+                .into(),
+        );
+    }
+    let [(output_path, [_])] = jobs else {
+        return Err("--resume-from では入力を1つだけ指定してください".into());
+    };
+    let Some(path) = output_path else {
+        return Err(
+            "--resume-from では追記先の出力ファイルが必要です (標準出力には追記できません)".into(),
+        );
+    };
+    if csv_args.gzip || compression::is_gzip_path(path) {
+        return Err("--resume-from ではgzipで圧縮した出力には追記できません".into());
+    }
+    if !path.exists() {
+        return Err(format!("追記先の出力「{}」がありません", path.display()).into());
+    }
+    ExistingOutput::read(
+        path,
+        csv_args.output_delimiter.unwrap_or(csv_args.delimiter),
+        !csv_args.no_header,
+        csv_args.output_encoding.unwrap_or(encoding_rs::UTF_8),
+    )
+    .map_err(|e| format!("{}: {}", path.display(), e).into())
+}
+
+/// `--stats-json` の統計を書き出す
+///
+/// `status` は正常終了が `ok`、Ctrl-C での中断が `interrupted`、それ以外のエラー (`--fail-on-skip` を含む) が
+/// `error` で、エラーの場合は `error` にメッセージを入れる。
+fn write_stats_json(
+    path: &Path,
+    summary: &Summary,
+    elapsed: Duration,
+    outcome: &Result<(), Box<dyn Error>>,
+    force: bool,
+) -> Result<(), Box<dyn Error>> {
+    let (status, error) = match outcome {
+        Ok(()) => ("ok", None),
+        Err(e) if interrupt::is_interrupted() => ("interrupted", Some(e.to_string())),
+        Err(e) => ("error", Some(e.to_string())),
+    };
+    let mut stats = summary.to_json();
+    stats["status"] = Value::from(status);
+    stats["error"] = Value::from(error);
+    stats["elapsed_seconds"] = Value::from(elapsed.as_secs_f64());
+
+    let mut output = BufWriter::new(open_output(Some(path), force)?);
+    serde_json::to_writer_pretty(&mut output, &stats)?;
+    writeln!(output)?;
+    output.flush()?;
+    Ok(())
+}
+
+/// 入力形式と出力形式の組み合わせと、形式に対応しないオプションの指定を確かめる
+///
+/// 標準出力に書きかけの出力を残さないよう、出力先を開く前に呼ぶ。
+fn check_format(args: &EncodeArgs, csv_args: &CsvArgs) -> Result<(), Box<dyn Error>> {
+    match (args.format_in, args.output_format()) {
+        (InputFormat::Csv, OutputFormat::Ndjson) => {
+            Err("--format ndjson は --format-in ndjson の場合のみ指定できます".into())
+        }
+        (
+            InputFormat::Ndjson,
+            OutputFormat::Geojson | OutputFormat::Parquet | OutputFormat::Sqlite,
+        ) => {
+            Err("NDJSONの入力は ndjson か csv の形式でのみ出力できます".into())
+        }
+        (
+            InputFormat::Geojson,
+            OutputFormat::Ndjson | OutputFormat::Parquet | OutputFormat::Sqlite,
+        ) => {
+            Err("GeoJSONの入力は geojson か csv の形式でのみ出力できます".into())
+        }
+        (InputFormat::Geojson, _)
+            if !args.lat.is_empty()
+                || args.latlon.is_some()
+                || args.lat_index.is_some()
+                || args.lat_regex.is_some() =>
+        {
+            Err(
+                "--format-in geojson では座標をジオメトリから読むため、--lat・--lon・--latlon・--lat-index・--lon-index・--lat-regex・--lon-regex は指定できません"
+                    .into(),
+            )
+        }
+        (InputFormat::Csv | InputFormat::Ndjson, _)
+            if args.lat.is_empty()
+                && args.latlon.is_none()
+                && args.lat_index.is_none()
+                && args.lat_regex.is_none() =>
+        {
+            Err("座標の列を --lat・--lon か --latlon で指定してください".into())
+        }
+        (InputFormat::Ndjson, _) if args.lat_regex.is_some() => Err(
+            "--format-in ndjson では --lat-regex・--lon-regex は指定できません (キーを --lat・--lon で指定してください)"
+                .into(),
+        ),
+        (InputFormat::Ndjson, _) if args.lat_index.is_some() => Err(
+            "--format-in ndjson では --lat-index・--lon-index は指定できません (キーを --lat・--lon で指定してください)"
+                .into(),
+        ),
+        (InputFormat::Ndjson | InputFormat::Geojson, _) if args.unique_mesh => {
+            Err("--format-in ndjson・geojson では --unique-mesh は指定できません".into())
+        }
+        (InputFormat::Ndjson | InputFormat::Geojson, _) if args.mesh_column_position.is_some() => {
+            Err(
+                "--format-in ndjson・geojson では --mesh-column-position は指定できません".into(),
+            )
+        }
+        (InputFormat::Ndjson | InputFormat::Geojson, _) if args.z.is_some() => {
+            Err("--format-in ndjson・geojson では --z は指定できません".into())
+        }
+        (InputFormat::Ndjson | InputFormat::Geojson, _) if args.error_output.is_some() => {
+            Err("--format-in ndjson・geojson では --error-output は指定できません".into())
+        }
+        (InputFormat::Ndjson | InputFormat::Geojson, _)
+            if !args.keep_columns.is_empty() || !args.drop_columns.is_empty() =>
+        {
+            Err(
+                "--format-in ndjson・geojson では --keep-columns・--drop-columns は指定できません"
+                    .into(),
+            )
+        }
+        // Parquetの文字列はUTF-8と決まっていて、圧縮も形式自体で行う
+        (_, OutputFormat::Parquet)
+            if csv_args
+                .output_encoding
+                .is_some_and(|e| e != encoding_rs::UTF_8)
+                || csv_args.bom =>
+        {
+            Err("Parquetには --output-encoding・--bom は指定できません".into())
+        }
+        (_, OutputFormat::Parquet) if csv_args.gzip => {
+            Err("Parquetは列ごとに圧縮するため、--gzip とは併用できません".into())
+        }
+        (_, OutputFormat::Parquet) if csv_args.no_header => {
+            Err("Parquetの列名にヘッダーを使うため、--no-header とは併用できません".into())
+        }
+        (_, OutputFormat::Sqlite)
+            if csv_args
+                .output_encoding
+                .is_some_and(|e| e != encoding_rs::UTF_8)
+                || csv_args.bom
+                || csv_args.gzip =>
+        {
+            Err("SQLiteには --output-encoding・--bom・--gzip は指定できません".into())
+        }
+        (_, OutputFormat::Sqlite) if csv_args.no_header => {
+            Err("SQLiteの列名にヘッダーを使うため、--no-header とは併用できません".into())
+        }
+        // 小数点のカンマと区切りのカンマを区別できない
+        (InputFormat::Csv, _) if args.decimal_comma && csv_args.delimiter == b',' => Err(
+            "--decimal-comma では区切り文字にカンマを使えないため、--delimiter ';' などを指定してください"
+                .into(),
+        ),
+        _ if args.decimal_comma && args.latlon.is_some() && args.latlon_separator.contains(',') => {
+            Err(
+                "--decimal-comma では緯度と経度の区切りにカンマを使えないため、--latlon-separator ' ' などを指定してください"
+                    .into(),
+            )
+        }
+        _ => Ok(()),
+    }
+}
+
+/// 出力をJSONにする場合に、出力のエンコーディングの指定を確かめる (JSONはBOM無しのUTF-8と決まっている)
+fn check_json_output(csv_args: &CsvArgs, format: &str) -> Result<(), Box<dyn Error>> {
+    let is_utf8 = csv_args
+        .output_encoding
+        .is_none_or(|e| e == encoding_rs::UTF_8);
+    if !is_utf8 || csv_args.bom {
+        return Err(format!("{}はBOM無しのUTF-8でのみ出力できます", format).into());
+    }
+    Ok(())
+}
+
+/// 入力パスに含まれるワイルドカード (`*` `?` `[`) を展開する
+///
+/// シェルが展開しない環境 (Windowsなど) や、引用符で囲んで渡されたパターンのために自前で展開する。
+/// 同じ名前のファイルが実在する場合や標準入力 (`-`) はそのまま使う。
+fn expand_inputs(patterns: &[PathBuf]) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut inputs = Vec::new();
+    for pattern in patterns {
+        let pattern_str = pattern.to_string_lossy();
+        if is_stdio(pattern) || pattern.exists() || !pattern_str.contains(['*', '?', '[']) {
+            inputs.push(pattern.clone());
+            continue;
+        }
+        let matched = glob::glob(&pattern_str)?.collect::<Result<Vec<PathBuf>, _>>()?;
+        if matched.is_empty() {
+            return Err(format!("「{}」に一致するファイルがありません", pattern_str).into());
+        }
+        inputs.extend(matched);
+    }
+    Ok(inputs)
+}
+
+/// 1つ以上の入力ファイルを処理し、1つの出力先に結合して書き出す
+///
+/// ヘッダーは最初のファイルのものだけを書き出す。列の対応がずれないよう、全ファイルのヘッダーが一致しなければエラーにする。
+/// 途中でエラーになった場合、書きかけの出力ファイルは削除する。
+fn encode_files(
+    inputs: &[PathBuf],
+    output_path: Option<&Path>,
+    args: &EncodeArgs,
+    csv_args: &CsvArgs,
+    pool: &rayon::ThreadPool,
+    progress: &mut Progress,
+    report: &mut Report,
+) -> Result<(), Box<dyn Error>> {
+    if args.dry_run {
+        // 出力先を開かないので、既存のファイルには触れない
+        let output = Box::new(io::sink());
+        return write_files(output, inputs, args, csv_args, pool, progress, report);
+    }
+    let output = match (&args.existing_output, output_path) {
+        (Some(_), Some(path)) => csv_args.open_append(path)?,
+        _ => csv_args.open_output(output_path, args.force)?,
+    };
+    let result = write_files(output, inputs, args, csv_args, pool, progress, report);
+    // 追記した場合は、前回までの出力を残すため消さない
+    if result.is_err()
+        && args.existing_output.is_none()
+        && let Some(path) = output_path
+    {
+        // 削除に失敗しても、元のエラーの方が原因を示しているのでそちらを返す
+        let _ = fs::remove_file(path);
+    }
+    result
+}
+
+/// `encode_files` の本体。`output` は戻る時点で閉じられる
+fn write_files(
+    output: Box<dyn Write>,
+    inputs: &[PathBuf],
+    args: &EncodeArgs,
+    csv_args: &CsvArgs,
+    pool: &rayon::ThreadPool,
+    progress: &mut Progress,
+    report: &mut Report,
+) -> Result<(), Box<dyn Error>> {
+    if args.format_in != InputFormat::Csv {
+        return write_object_files(output, inputs, args, csv_args, progress, report);
+    }
+    let mut writer: Box<dyn RecordWriter> = match (&args.aggregate, args.output_format()) {
+        (Some(aggregation), _) => Box::new(MeshCounter::new(
+            csv_args.writer(output)?,
+            aggregation,
+            &args.mesh_column,
+        )),
+        (None, OutputFormat::Csv) => match &args.existing_output {
+            Some(existing) => Box::new(AppendWriter::new(
+                csv_args.append_writer(output)?,
+                existing.headers.clone(),
+            )),
+            None => Box::new(csv_args.writer(output)?),
+        },
+        (None, OutputFormat::Geojson) => {
+            // GeoJSON (RFC 7946) はBOM無しのUTF-8と決まっている
+            check_json_output(csv_args, "GeoJSON")?;
+            if csv_args.no_header {
+                return Err(
+                    "GeoJSONのプロパティ名にヘッダーを使うため、--no-header とは併用できません"
+                        .into(),
+                );
+            }
+            Box::new(GeoJsonWriter::new(output))
+        }
+        (None, OutputFormat::Parquet) => Box::new(ParquetWriter::new(output, args.parquet_schema)),
+        (None, OutputFormat::Sqlite) => {
+            let index_columns = if args.sqlite_index {
+                let levels = unique_levels(&args.level);
+                pair_suffixes(args)?
+                    .iter()
+                    .flat_map(|suffix| {
+                        mesh_column_names(&format!("{}{}", args.mesh_column, suffix), &levels)
+                    })
+                    .collect()
+            } else {
+                Vec::new()
+            };
+            Box::new(SqliteWriter::new(
+                output,
+                args.sqlite_schema,
+                &args.table,
+                index_columns,
+            )?)
+        }
+        (None, OutputFormat::Ndjson) => unreachable!("check_format でCSVの入力からは除いている"),
+    };
+    // --unique-mesh の出力済みのメッシュコードは、出力先ごとに数え直す
+    report.unique_meshes = None;
+    if args.unique_mesh && args.unique_order == UniqueOrder::Code {
+        writer = Box::new(SortedWriter::new(writer));
+    }
+
+    let mut first_headers: Option<csv::StringRecord> = None;
+    for input in inputs {
+        if interrupt::is_interrupted() {
+            break;
+        }
+        let mut reader = csv_args.open_reader(input, progress.wrap_reader(open_input(input)?))?;
+        let headers = reader.headers()?.clone();
+        if is_empty_input(&headers, reader.has_headers())
+            .map_err(|e| format!("{}: {}", input.display(), e))?
+        {
+            continue;
+        }
+        let write_headers = match &first_headers {
+            // ヘッダーが無い場合は1行目同士の比較になるため確かめない
+            Some(first) if reader.has_headers() && *first != headers => {
+                return Err(format!(
+                    "{} のヘッダーが {} と一致しないため、結合できません",
+                    input.display(),
+                    inputs[0].display()
+                )
+                .into());
+            }
+            Some(_) => false,
+            None => {
+                first_headers = Some(headers);
+                true
+            }
+        };
+        encode(
+            &mut reader,
+            writer.as_mut(),
+            args,
+            pool,
+            progress,
+            report,
+            write_headers,
+        )?;
+        if let Some(line) = report.interrupted {
+            log::warn!(
+                "{} の{}行目まで処理したところで中断しました (出力先がファイルの場合は --resume-from {} で続きから再開できます)",
+                input.display(),
+                line,
+                line + 1
+            );
+            break;
+        }
+    }
+
+    writer.finish()?;
+    Ok(())
+}
+
+/// `write_files` のNDJSON・GeoJSONの入力の場合。出力形式は `check_format` で入力と同じ形式か csv に限っている
+fn write_object_files(
+    output: Box<dyn Write>,
+    inputs: &[PathBuf],
+    args: &EncodeArgs,
+    csv_args: &CsvArgs,
+    progress: &mut Progress,
+    report: &mut Report,
+) -> Result<(), Box<dyn Error>> {
+    let mut writer = match args.output_format() {
+        OutputFormat::Ndjson => {
+            check_json_output(csv_args, "NDJSON")?;
+            ObjectWriter::ndjson(output)
+        }
+        OutputFormat::Geojson => {
+            check_json_output(csv_args, "GeoJSON")?;
+            ObjectWriter::geojson(output)
+        }
+        _ => ObjectWriter::csv(csv_args.writer(output)?),
+    };
+    for input in inputs {
+        if interrupt::is_interrupted() {
+            break;
+        }
+        let reader =
+            BufReader::new(csv_args.decoded_input(progress.wrap_reader(open_input(input)?))?);
+        if args.format_in == InputFormat::Geojson {
+            encode_geojson(reader, &mut writer, args, progress, report)?;
+        } else {
+            encode_ndjson(reader, &mut writer, args, progress, report)?;
+        }
+        if let Some(line) = report.interrupted {
+            log::warn!(
+                "{} の{}行目まで処理したところで中断しました",
+                input.display(),
+                line
+            );
+            break;
+        }
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+/// 処理した行の記録。行数を数え、`--error-output` 指定時はスキップした行を書き出す
+#[derive(Default)]
+struct Report {
+    summary: Summary,
+    errors: Option<ErrorOutput<Box<dyn Write>>>,
+    /// Ctrl-C で中断した場合、入力の最後に処理した行の行番号
+    interrupted: Option<u64>,
+    /// `--unique-mesh` 指定時に、出力先に出力済みのメッシュコード (出力先ごとに `write_files` で空にする)
+    unique_meshes: Option<HashSet<String>>,
+}
+
+/// 入力の行の位置
+///
+/// 「○行目」の行番号は、ヘッダーを1行目としたファイル上の行番号 (`line`) にそろえる。複数行にまたがる値を
+/// 含む行は、その最初の行の番号になる。行番号だけではデータの何件目か分かりにくいため、スキップなどの警告と
+/// `--error-output` にはヘッダーを除いたデータの行番号 (`row`、1始まり) も示す。
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct RowPosition {
+    line: u64,
+    row: u64,
+}
+
+impl RowPosition {
+    /// CSVのレコードの位置。csvクレートのレコード番号はヘッダーも0番目として数えるため、ヘッダーが無い場合は1を足す
+    fn of(record: &csv::StringRecord, has_headers: bool) -> Self {
+        let position = record.position();
+        RowPosition {
+            line: position.map_or(0, |p| p.line()),
+            row: position.map_or(0, |p| p.record() + u64::from(!has_headers)),
+        }
+    }
+}
+
+/// `--flush-interval` 指定時に、書き出した行数が間隔の倍数になったか
+fn flush_due(args: &EncodeArgs, report: &Report) -> bool {
+    args.flush_interval
+        .is_some_and(|interval| report.summary.written.is_multiple_of(interval))
+}
+
+/// 入力が完全に空 (ヘッダー行も無い) かを確かめる
+///
+/// ヘッダー行だけの入力は空ではなく、追加する列のヘッダーだけを書き出す。完全に空の入力は、
+/// `--no-header` の場合は0行の入力として扱い、ヘッダーが必要な場合は列を探せないためエラーにする。
+fn is_empty_input(headers: &csv::StringRecord, has_headers: bool) -> Result<bool, Box<dyn Error>> {
+    if !headers.is_empty() {
+        return Ok(false);
+    }
+    if has_headers {
+        return Err("入力が空です (ヘッダー行がありません)".into());
+    }
+    Ok(true)
+}
+
+/// `--chunk-size` の既定値。1チャンクの行数
+const DEFAULT_CHUNK_SIZE: usize = 10_000;
+
+/// CSVの各行にメッシュコードを付与して書き出す
+///
+/// 行はチャンク単位で読み込み、測地系の選択・座標のパースと変換・範囲の判定からメッシュコードの計算までを
+/// スレッドプールのワーカーに渡す。PROJの変換 (`ThreadLocalProj`) と座標のキャッシュ (`CoordCache`) は
+/// ワーカーのスレッドごとに持つ。ワーカーは行ごとの結果 (`ConvertedRow`) を返すだけで、不正な行の警告・
+/// `--error-output`・`--strict` のエラー・集計は、`Pipeline` からチャンクの順に受け取って書き出す際に行う。
+/// そのため出力も警告も、スレッド数やチャンクの行数によらず入力の順になる。中心座標とWebメルカトル座標の
+/// 変換は、出力しない行の分まで行わないよう、書き出す直前に行う。
+/// 複数の入力を1つの出力に結合する場合、2つ目以降は `write_headers` を偽にしてヘッダーを書き出さない。
+/// 書き残しの出力 (`RecordWriter::finish`) は呼び出し側で行う。行数は `report` に加算する。
+fn encode<R: Read>(
+    reader: &mut csv::Reader<R>,
+    writer: &mut dyn RecordWriter,
+    args: &EncodeArgs,
+    pool: &rayon::ThreadPool,
+    progress: &mut Progress,
+    report: &mut Report,
+    write_headers: bool,
+) -> Result<(), Box<dyn Error>> {
+    // ヘッダーが無い場合、headers() は1行目のデータを返すが、列数を知るためにはそのまま使える
+    let headers = reader.headers()?.clone();
+    let has_headers = reader.has_headers();
+    if is_empty_input(&headers, has_headers)? {
+        return Ok(());
+    }
+    let find_column = |name: &str, label: &'static str| -> Result<usize, Box<dyn Error>> {
+        if !has_headers {
+            return Err(
+                "--no-header 指定時は列名で列を指定できません (--lat-index・--lon-index で列の位置を指定してください)"
+                    .into(),
+            );
+        }
+        let idx = find_header(
+            &headers,
+            name,
+            args.ignore_case_headers,
+            args.on_duplicate_header,
+        )?
+        .ok_or_else(|| column_not_found(&headers, label, name))?;
+        Ok(idx)
+    };
+    let check_index = |index: usize, option: &str| {
+        if index < headers.len() {
+            Ok(index)
+        } else {
+            Err(format!(
+                "{} に指定された {} が列数 ({}) を超えています (列の位置は0始まりです)",
+                option,
+                index,
+                headers.len()
+            ))
+        }
+    };
+    let coord_pairs = if let (Some(lat_index), Some(lon_index)) = (args.lat_index, args.lon_index) {
+        vec![CoordColumns::Separate(
+            check_index(lat_index, "--lat-index")?,
+            check_index(lon_index, "--lon-index")?,
+        )]
+    } else if let (Some(lat_regex), Some(lon_regex)) = (&args.lat_regex, &args.lon_regex) {
+        if !has_headers {
+            return Err(
+                "--no-header 指定時は列名で列を指定できません (--lat-index・--lon-index で列の位置を指定してください)"
+                    .into(),
+            );
+        }
+        vec![CoordColumns::Separate(
+            find_header_regex(&headers, lat_regex, "緯度列")?,
+            find_header_regex(&headers, lon_regex, "経度列")?,
+        )]
+    } else if let Some(latlon) = &args.latlon {
+        vec![CoordColumns::Combined(find_column(latlon, "緯度経度列")?)]
+    } else {
+        if args.lat.len() != args.lon.len() {
+            return Err("--lat と --lon は同じ数だけ指定してください".into());
+        }
+        let mut pairs = Vec::with_capacity(args.lat.len());
+        for (lat, lon) in args.lat.iter().zip(&args.lon) {
+            pairs.push(CoordColumns::Separate(
+                find_column(lat, "緯度列")?,
+                find_column(lon, "経度列")?,
+            ));
+        }
+        pairs
+    };
+
+    // 重みの値は、不正な行を他の理由と同じように警告してスキップできるよう、ここで検証する
+    let weight_column = match args.aggregate.as_ref().and_then(|a| a.weight.as_ref()) {
+        Some(weight) => Some(find_column(weight, "重み列")?),
+        None => None,
+    };
+
+    let datum_column = match &args.datum_column {
+        Some(datum) => Some(find_column(datum, "測地系列")?),
+        None => None,
+    };
+
+    // Z値はメッシュコードの計算には使わないが、値の検証のために列を確かめておく
+    let z_column = match &args.z {
+        Some(z) => Some(find_column(z, "Z値列")?),
+        None => None,
+    };
+
+    let levels = unique_levels(&args.level);
+    let (column_names, pair_width) = added_columns(args, &levels)?;
+
+    // 同名の列が既にある場合は、--force 指定時のみその列に上書きする
+    let mut mesh_indices = Vec::with_capacity(column_names.len());
+    for name in &column_names {
+        let idx = if has_headers {
+            headers.iter().position(|h| h == name)
+        } else {
+            None
+        };
+        if idx.is_some() && !args.force {
+            return Err(format!(
+                "メッシュコード列名「{}」は入力に既に存在します (--mesh-column で別名を指定するか、--force で上書きしてください)",
+                name
+            )
+            .into());
+        }
+        mesh_indices.push(idx);
+    }
+
+    // 新しく追加する列は、既存の列の間のこの位置にまとめて入れる
+    let insert_at = match &args.mesh_column_position {
+        None => headers.len(),
+        Some(ColumnPosition::Index(index)) if *index <= headers.len() => *index,
+        Some(ColumnPosition::Index(index)) => {
+            return Err(format!(
+                "--mesh-column-position に指定された {} が列数 ({}) を超えています (0〜{} の列の位置で指定してください)",
+                index,
+                headers.len(),
+                headers.len()
+            )
+            .into());
+        }
+        Some(ColumnPosition::After(name)) => find_column(name, "挿入位置の列")? + 1,
+    };
+
+    let mut new_headers = headers.iter().map(String::from).collect::<Vec<String>>();
+    let added_names = column_names
+        .iter()
+        .zip(&mesh_indices)
+        .filter(|(_, idx)| idx.is_none())
+        .map(|(name, _)| name.clone());
+    new_headers.splice(insert_at..insert_at, added_names);
+    let selected = selected_columns(args, find_column, headers.len(), &mesh_indices, insert_at)?;
+    if let Some(selected) = &selected {
+        new_headers = selected.iter().map(|&i| new_headers[i].clone()).collect();
+    }
+    if let Some(errors) = &mut report.errors {
+        errors.start_input(&headers, has_headers)?;
+    }
+    if write_headers && has_headers {
+        match (args.unique_mesh, args.unique_output) {
+            (true, UniqueOutput::Code) => writer.write_headers(&column_names[..1])?,
+            _ => writer.write_headers(&new_headers)?,
+        }
+    }
+
+    let converter = input_converter(args)?;
+    log::debug!("座標列: {:?}、メッシュのレベル: {:?}", coord_pairs, levels);
+    if args.unique_mesh {
+        report.unique_meshes.get_or_insert_with(HashSet::new);
+    }
+
+    // --skip・--limit の対象はデータ行のみ。行番号は読み飛ばした行も含めた入力の行番号のまま
+    // --max-rows の上限を超えたことが分かるよう、上限より1行多くまで読む
+    let max_rows = args
+        .max_rows
+        .map_or(usize::MAX, |max| max.saturating_add(1));
+    let mut records = reader.records().skip(args.skip).peekable();
+    // --resume-from の行より前の行は読み飛ばし、出力済みの行数と矛盾しないかを書き出す前に確かめる
+    if let (Some(line), Some(existing)) = (args.resume_from, &args.existing_output) {
+        let mut skipped = 0;
+        while records
+            .next_if(|result| {
+                result
+                    .as_ref()
+                    .is_ok_and(|record| RowPosition::of(record, has_headers).line < line)
+            })
+            .is_some()
+        {
+            skipped += 1;
+        }
+        existing.check_rows(skipped, args.keep_invalid && args.filter_mesh.is_empty())?;
+        log::info!(
+            "{}行目から再開します (読み飛ばした行: {}行、出力済みの行: {}行)",
+            line,
+            skipped,
+            existing.rows
+        );
+    }
+    let mut records = records.take(args.limit.unwrap_or(usize::MAX).min(max_rows));
+
+    let webmercator = args.add_webmercator.then(WebMercator::new).transpose()?;
+
+    // 検証を終えた1行を、追加列とともに書き出す
+    let levels = &levels;
+    let write_row = |record: &csv::StringRecord,
+                     coords: &RowCoords,
+                     mut codes: Vec<String>,
+                     converter: &DatumConverter,
+                     writer: &mut dyn RecordWriter,
+                     report: &mut Report|
+     -> Result<(), Box<dyn Error>> {
+        if !matches_filter(&codes[0], args) {
+            report.summary.filtered += 1;
+            return Ok(());
+        }
+        if let Some(seen) = &mut report.unique_meshes {
+            if seen.contains(&codes[0]) {
+                report.summary.duplicates += 1;
+                return Ok(());
+            }
+            seen.insert(codes[0].clone());
+            if args.unique_output == UniqueOutput::Code {
+                writer.write_row(&[&codes[0]], &codes[0])?;
+                report.summary.written += 1;
+                return Ok(());
+            }
+        }
+        if args.add_center {
+            push_centers(
+                &mut codes,
+                coords,
+                levels.len(),
+                pair_width,
+                converter,
+                args,
+            )?;
+        }
+        if let Some(webmercator) = &webmercator {
+            push_webmercator(&mut codes, coords, webmercator, args)?;
+        }
+
+        let mut fields: Vec<&str> = record.iter().collect();
+        let mut added = Vec::with_capacity(codes.len());
+        for (idx, code) in mesh_indices.iter().zip(codes.iter()) {
+            match idx {
+                Some(i) => fields[*i] = code,
+                None => added.push(code.as_str()),
+            }
+        }
+        fields.splice(insert_at..insert_at, added);
+        if let Some(selected) = &selected {
+            fields = selected.iter().map(|&i| fields[i]).collect();
+        }
+        writer.write_row(&fields, &codes[0])?;
+        report.summary.written += 1;
+        if flush_due(args, report) {
+            writer.flush()?;
+        }
+        Ok(())
+    };
+
+    // ワーカーが座標を変換したチャンクの行を、入力の順に検証して書き出す。不正な行の警告・`--error-output`・
+    // `--strict` のエラー・集計はここで行うため、スレッド数やチャンクの行数によらず入力の順になる
+    let blank_fields = vec![String::new(); pair_width * coord_pairs.len()];
+    let write_chunk = |chunk: ComputedChunk,
+                       writer: &mut dyn RecordWriter,
+                       report: &mut Report|
+     -> Result<(), Box<dyn Error>> {
+        for row in chunk {
+            let ComputedRow {
+                record,
+                position,
+                converted,
+                fields,
+            } = row;
+            let line_number = position.line;
+            let (pairs, row_converter) = match converted? {
+                ConvertedRow::InvalidDatum(problem) => {
+                    if reject_row(
+                        SkipReason::InvalidDatum,
+                        problem,
+                        &record,
+                        position,
+                        args,
+                        report,
+                    )? {
+                        write_row(
+                            &record,
+                            &vec![None; coord_pairs.len()],
+                            blank_fields.clone(),
+                            converter.fallback(),
+                            writer,
+                            report,
+                        )?;
+                    }
+                    continue;
+                }
+                ConvertedRow::Pairs(pairs, row_converter) => (pairs, row_converter),
+            };
+            warn_kept_pairs(&pairs, position);
+
+            // 座標を求められない行は、重みより座標の不正として扱う
+            let rejected = pairs
+                .iter()
+                .all(|pair| matches!(pair, PairCoord::Rejected(..)));
+            if !rejected
+                && let Some(idx) = weight_column
+                && parse_weight(&record[idx]).is_none()
+            {
+                let problem = MeshifyError::ParseError {
+                    line: line_number,
+                    field: "重み",
+                    value: record[idx].to_string(),
+                };
+                if reject_row(
+                    SkipReason::InvalidWeight,
+                    problem,
+                    &record,
+                    position,
+                    args,
+                    report,
+                )? {
+                    write_row(
+                        &record,
+                        &vec![None; coord_pairs.len()],
+                        blank_fields.clone(),
+                        row_converter,
+                        writer,
+                        report,
+                    )?;
+                }
+                continue;
+            }
+
+            if !rejected
+                && let Some(idx) = z_column
+                && args.invalid_z != InvalidZ::Keep
+                && !record[idx].trim().is_empty()
+                && parse_z(&record[idx], args).is_none()
+            {
+                let problem = MeshifyError::ParseError {
+                    line: line_number,
+                    field: "Z値",
+                    value: record[idx].to_string(),
+                };
+                if args.invalid_z == InvalidZ::Error {
+                    return Err(problem.into());
+                }
+                if reject_row(
+                    SkipReason::InvalidZ,
+                    problem,
+                    &record,
+                    position,
+                    args,
+                    report,
+                )? {
+                    write_row(
+                        &record,
+                        &vec![None; coord_pairs.len()],
+                        blank_fields.clone(),
+                        row_converter,
+                        writer,
+                        report,
+                    )?;
+                }
+                continue;
+            }
+
+            // 組の一部だけが不正な行も、ワーカーは求められた組のメッシュコードを計算済み
+            if let Some(coords) = row_coords(pairs, &record, position, args, report)? {
+                write_row(&record, &coords, fields, row_converter, writer, report)?;
+            }
+        }
+        Ok(())
+    };
+
+    // 読み込みと書き出しを止めずに計算させつつ、メモリに持つチャンクの数はスレッド数の2倍までにする
+    let max_in_flight = pool.current_num_threads() * 2;
+    // 座標のキャッシュはワーカーのスレッドごとに持ち、チャンクをまたいで使い回す
+    let caches = (0..pool.current_num_threads())
+        .map(|_| Mutex::new(CoordCache::new(args.coord_cache, args.coord_cache_digits)))
+        .collect::<Vec<Mutex<CoordCache>>>();
+    let result = pool.in_place_scope(|scope| -> Result<(), Box<dyn Error>> {
+        let (caches, coord_pairs, converter) = (&caches, &coord_pairs, &converter);
+        let mut pipeline = Pipeline::new();
+        let mut last_line = 0;
+        let mut data_rows = 0;
+        loop {
+            // 中断が要求された場合は、読み込み済みのチャンクまでで止める
+            if interrupt::is_interrupted() {
+                report.interrupted = Some(last_line);
+                break;
+            }
+            let mut chunk = Vec::with_capacity(args.chunk_size);
+            for result in records.by_ref().take(args.chunk_size) {
+                data_rows += 1;
+                if exceeds_max_rows(data_rows, args)? {
+                    break;
+                }
+                // エラーで途中終了した場合も統計に残るよう、チャンク単位ではなく1行ずつ数える
+                report.summary.read += 1;
+                let record = result?;
+                let position = RowPosition::of(&record, has_headers);
+                last_line = position.line;
+                chunk.push((record, position));
+            }
+            progress.inc_rows(chunk.len() as u64);
+            if chunk.is_empty() {
+                break;
+            }
+
+            // 測地系の変換からメッシュコードの計算までをワーカーで行う。PROJの変換はスレッドごとに作られる
+            pipeline.spawn(scope, move || {
+                let index = rayon::current_thread_index().unwrap_or(0) % caches.len();
+                let mut coord_cache = caches[index]
+                    .lock()
+                    .expect("キャッシュを使うワーカーはパニックしていない");
+                let mut field_cache = FieldCache::new(args.coord_cache);
+                chunk
+                    .into_iter()
+                    .map(|(record, position)| {
+                        let converted = convert_row(
+                            &record,
+                            position,
+                            coord_pairs,
+                            datum_column,
+                            converter,
+                            args,
+                            &mut coord_cache,
+                        );
+                        let fields = match &converted {
+                            Ok(ConvertedRow::Pairs(pairs, _)) => {
+                                let coords = pairs.iter().map(PairCoord::wgs).collect();
+                                row_fields(&coords, levels, pair_width, args, &mut field_cache)
+                            }
+                            _ => Vec::new(),
+                        };
+                        ComputedRow {
+                            record,
+                            position,
+                            converted,
+                            fields,
+                        }
+                    })
+                    .collect()
+            });
+            while pipeline.in_flight() >= max_in_flight {
+                let chunk = pipeline.recv().expect("処理中のチャンクがある");
+                write_chunk(chunk, writer, report)?;
+            }
+        }
+        while let Some(chunk) = pipeline.recv() {
+            write_chunk(chunk, writer, report)?;
+        }
+        Ok(())
+    });
+    let caches = caches
+        .into_iter()
+        .map(|cache| {
+            cache
+                .into_inner()
+                .expect("キャッシュを使うワーカーはパニックしていない")
+        })
+        .collect::<Vec<CoordCache>>();
+    CoordCache::log_stats(&caches);
+    result
+}
+
+/// ワーカーが座標を変換した1行
+struct ComputedRow<'a> {
+    record: csv::StringRecord,
+    position: RowPosition,
+    /// 変換の結果。`--out-of-range error` などで処理を中断する場合は、書き出す際にこの行でエラーにする
+    converted: Result<ConvertedRow<'a>, MeshifyError>,
+    /// 座標を求められた組の追加列の値 (求められなかった組は空欄)
+    fields: Vec<String>,
+}
+
+/// ワーカーが計算したチャンクの各行 (入力の順)
+type ComputedChunk<'a> = Vec<ComputedRow<'a>>;
+
+/// 1行の座標を変換した結果。不正な行の警告や集計は、書き出す際にこの結果から入力の順に行う
+enum ConvertedRow<'a> {
+    /// 測地系列の値が不正で、変換器を選べない
+    InvalidDatum(MeshifyError),
+    /// 座標列の組ごとの結果と、行の変換器 (書き出す際に、中心座標を入力の測地系に戻すために使う)
+    Pairs(Vec<PairCoord>, &'a DatumConverter),
+}
+
+/// 1行の測地系を選び、各組の座標を世界測地系に変換する (ワーカーで呼ぶ)
+fn convert_row<'a>(
+    record: &csv::StringRecord,
+    position: RowPosition,
+    coord_pairs: &[CoordColumns],
+    datum_column: Option<usize>,
+    converter: &'a InputConverter,
+    args: &EncodeArgs,
+    cache: &mut CoordCache,
+) -> Result<ConvertedRow<'a>, MeshifyError> {
+    let datum = datum_column.map(|idx| &record[idx]);
+    let Some(row_converter) = converter.select(datum)? else {
+        return Ok(ConvertedRow::InvalidDatum(MeshifyError::ParseError {
+            line: position.line,
+            field: "測地系",
+            value: datum.unwrap_or_default().to_string(),
+        }));
+    };
+    let pairs = coord_pairs
+        .iter()
+        .map(|&columns| read_pair(record, columns, position, args, row_converter, cache))
+        .collect::<Result<Vec<PairCoord>, MeshifyError>>()?;
+    Ok(ConvertedRow::Pairs(pairs, row_converter))
+}
+
+/// 1行分の追加列の値を、座標列の組ごとに並べる。メッシュコードを計算しない組は空欄にする
+fn row_fields(
+    coords: &RowCoords,
+    levels: &[MeshLevel],
+    pair_width: usize,
+    args: &EncodeArgs,
+    cache: &mut FieldCache,
+) -> Vec<String> {
+    coords
+        .iter()
+        .flat_map(|coord| match coord {
+            Some((lat, lon)) => {
+                cache.get_or_compute(*lat, *lon, || mesh_fields(*lat, *lon, levels, args))
+            }
+            None => vec![String::new(); pair_width],
+        })
+        .collect()
+}
+
+/// メッシュコードが `--filter-mesh` の接頭辞のいずれかで始まるか (指定が無ければ常に真)
+///
+/// 接頭辞は数字で指定するため、`--mesh-format hyphenated` のコードは区切りを除いて比べる。
+fn matches_filter(code: &str, args: &EncodeArgs) -> bool {
+    if args.filter_mesh.is_empty() {
+        return true;
+    }
+    let code = strip_mesh_code_separators(code);
+    args.filter_mesh
+        .iter()
+        .any(|prefix| code.starts_with(prefix.as_str()))
+}
+
+/// 行の座標列の組ごとの、世界測地系の座標。メッシュコードを計算しない組 (範囲外で残す組や不正な組) は `None`
+type RowCoords = Vec<Option<(f64, f64)>>;
+
+/// NDJSONの各行 (JSONオブジェクト) にメッシュコードなどのキーを追加して書き出す
+///
+/// 座標は `--lat`・`--lon` (または `--latlon`) に指定したキーから読む。行ごとにキーが異なってもよいため、
+/// CSVのように列の有無を先に確かめず、座標のキーが無い行は値が空欄の行として扱う。
+/// 追加する値は文字列 (空欄の場合は `null`) にする。行数が少ない前提で、メッシュ計算も直列に行う。
+/// JSONオブジェクトとして解釈できない行は、出力するオブジェクトが無いため `--keep-invalid` の指定時もスキップする。
+fn encode_ndjson<R: BufRead, W: Write>(
+    reader: R,
+    writer: &mut ObjectWriter<W>,
+    args: &EncodeArgs,
+    progress: &mut Progress,
+    report: &mut Report,
+) -> Result<(), Box<dyn Error>> {
+    // 座標の値を並べたレコードの、組ごとの位置
+    let (keys, coord_pairs) = match &args.latlon {
+        Some(latlon) => (vec![latlon.as_str()], vec![CoordColumns::Combined(0)]),
+        None => {
+            if args.lat.len() != args.lon.len() {
+                return Err("--lat と --lon は同じ数だけ指定してください".into());
+            }
+            let keys = args
+                .lat
+                .iter()
+                .zip(&args.lon)
+                .flat_map(|(lat, lon)| [lat.as_str(), lon.as_str()])
+                .collect::<Vec<&str>>();
+            let pairs = (0..args.lat.len())
+                .map(|i| CoordColumns::Separate(i * 2, i * 2 + 1))
+                .collect();
+            (keys, pairs)
+        }
+    };
+    let mut encoder = ObjectEncoder::new(args, coord_pairs)?;
+    log::debug!(
+        "座標のキー: {:?}、メッシュのレベル: {:?}",
+        keys,
+        encoder.levels
+    );
+
+    // --skip・--limit の対象は空行を除いた行
+    let mut data_rows = 0;
+    for (i, line) in reader.lines().enumerate() {
+        if interrupt::is_interrupted() {
+            report.interrupted = Some(i as u64);
+            break;
+        }
+        let line = line?;
+        let line_number = i as u64 + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        data_rows += 1;
+        if data_rows <= args.skip {
+            continue;
+        }
+        if args
+            .limit
+            .is_some_and(|limit| data_rows - args.skip > limit)
+            || exceeds_max_rows(data_rows - args.skip, args)?
+        {
+            break;
+        }
+        progress.inc_rows(1);
+        report.summary.read += 1;
+
+        let Ok(Value::Object(mut object)) = serde_json::from_str::<Value>(&line) else {
+            let problem = MeshifyError::ParseError {
+                line: line_number,
+                field: "JSONオブジェクト",
+                value: line,
+            };
+            if args.strict {
+                return Err(problem.into());
+            }
+            log::warn!(line = line_number, reason:% = SkipReason::InvalidJson; "{}。この行をスキップします。", problem);
+            report.summary.skip(SkipReason::InvalidJson);
+            continue;
+        };
+
+        let record = keys
+            .iter()
+            .map(|key| ndjson::lookup(&object, key).map_or(Cow::Borrowed(""), ndjson::field_text))
+            .collect::<csv::StringRecord>();
+        let position = RowPosition {
+            line: line_number,
+            row: data_rows as u64,
+        };
+        if encoder.encode(&mut object, &record, position, report)? {
+            writer.write(&object)?;
+            report.summary.written += 1;
+            if flush_due(args, report) {
+                writer.flush()?;
+            }
+        }
+    }
+    CoordCache::log_stats(std::slice::from_ref(&encoder.coord_cache));
+    Ok(())
+}
+
+/// GeoJSONの FeatureCollection の各Featureの `properties` に、メッシュコードなどのキーを追加して書き出す
+///
+/// 座標はPointのジオメトリ (経度, 緯度) から読み、Point以外のジオメトリは `--non-point` に従って
+/// スキップするか代表点を使う。ジオメトリが `null` のFeatureは座標が空欄の行として扱う。行番号には
+/// Featureの番号 (1始まり) を使う。その他の扱いはNDJSONの入力 (`encode_ndjson`) と同じ。
+fn encode_geojson<R: Read, W: Write>(
+    reader: R,
+    writer: &mut ObjectWriter<W>,
+    args: &EncodeArgs,
+    progress: &mut Progress,
+    report: &mut Report,
+) -> Result<(), Box<dyn Error>> {
+    let features = geojson_input::read_features(reader)?;
+    let mut encoder = ObjectEncoder::new(args, vec![CoordColumns::Separate(0, 1)])?;
+    log::debug!(
+        "Feature数: {}、メッシュのレベル: {:?}",
+        features.len(),
+        encoder.levels
+    );
+
+    for (i, feature) in features.into_iter().enumerate() {
+        if interrupt::is_interrupted() {
+            report.interrupted = Some(i as u64);
+            break;
+        }
+        let number = i + 1;
+        if number <= args.skip {
+            continue;
+        }
+        if args.limit.is_some_and(|limit| number - args.skip > limit)
+            || exceeds_max_rows(number - args.skip, args)?
+        {
+            break;
+        }
+        progress.inc_rows(1);
+        report.summary.read += 1;
+        let line_number = number as u64;
+
+        let text = feature.to_string();
+        let Some((feature, mut properties)) = geojson_input::split_feature(feature) else {
+            let problem = MeshifyError::ParseError {
+                line: line_number,
+                field: "Feature",
+                value: text,
+            };
+            if args.strict {
+                return Err(problem.into());
+            }
+            log::warn!(line = line_number, reason:% = SkipReason::InvalidJson; "{}。このFeatureをスキップします。", problem);
+            report.summary.skip(SkipReason::InvalidJson);
+            continue;
+        };
+        let geometry = feature.get("geometry").unwrap_or(&Value::Null);
+        let Some(values) = geojson_input::point_values(geometry, args.non_point) else {
+            let problem = MeshifyError::NonPointGeometry {
+                line: line_number,
+                geometry: geojson_input::geometry_type(geometry).to_string(),
+            };
+            if args.strict {
+                return Err(problem.into());
+            }
+            log::warn!(line = line_number, reason:% = SkipReason::NonPoint; "{}。このFeatureをスキップします (--non-point bbox-center で代表点を使えます)。", problem);
+            report.summary.skip(SkipReason::NonPoint);
+            continue;
+        };
+
+        let record = csv::StringRecord::from(values.to_vec());
+        let position = RowPosition {
+            line: line_number,
+            row: line_number,
+        };
+        if encoder.encode(&mut properties, &record, position, report)? {
+            writer.write_feature(feature, properties)?;
+            report.summary.written += 1;
+            if flush_due(args, report) {
+                writer.flush()?;
+            }
+        }
+    }
+    CoordCache::log_stats(std::slice::from_ref(&encoder.coord_cache));
+    Ok(())
+}
+
+/// NDJSON・GeoJSONの入力で、オブジェクトごとに座標を読んでメッシュコードなどのキーを追加する
+struct ObjectEncoder<'a> {
+    args: &'a EncodeArgs,
+    /// 座標の値を並べたレコードの、組ごとの位置
+    coord_pairs: Vec<CoordColumns>,
+    levels: Vec<MeshLevel>,
+    column_names: Vec<String>,
+    pair_width: usize,
+    converter: InputConverter,
+    webmercator: Option<WebMercator>,
+    coord_cache: CoordCache,
+    field_cache: FieldCache,
+}
+
+impl<'a> ObjectEncoder<'a> {
+    fn new(args: &'a EncodeArgs, coord_pairs: Vec<CoordColumns>) -> Result<Self, Box<dyn Error>> {
+        let levels = unique_levels(&args.level);
+        let (column_names, pair_width) = added_columns(args, &levels)?;
+        Ok(ObjectEncoder {
+            args,
+            coord_pairs,
+            levels,
+            column_names,
+            pair_width,
+            converter: input_converter(args)?,
+            webmercator: args.add_webmercator.then(WebMercator::new).transpose()?,
+            coord_cache: CoordCache::new(args.coord_cache, args.coord_cache_digits),
+            field_cache: FieldCache::new(args.coord_cache),
+        })
+    }
+
+    /// `record` の座標からメッシュコードなどを求め、`object` にキーとして加える
+    ///
+    /// スキップした場合と `--filter-mesh` で除外した場合は、出力しないため偽を返す。
+    fn encode(
+        &mut self,
+        object: &mut serde_json::Map<String, Value>,
+        record: &csv::StringRecord,
+        position: RowPosition,
+        report: &mut Report,
+    ) -> Result<bool, Box<dyn Error>> {
+        let args = self.args;
+        let datum = args
+            .datum_column
+            .as_ref()
+            .map(|key| ndjson::lookup(object, key).map_or(Cow::Borrowed(""), ndjson::field_text));
+        let Some(converter) = self.converter.select(datum.as_deref())? else {
+            let problem = MeshifyError::ParseError {
+                line: position.line,
+                field: "測地系",
+                value: datum.unwrap_or_default().into_owned(),
+            };
+            if !reject_row(
+                SkipReason::InvalidDatum,
+                problem,
+                record,
+                position,
+                args,
+                report,
+            )? {
+                return Ok(false);
+            }
+            let fields = vec![String::new(); self.column_names.len()];
+            insert_fields(object, &self.column_names, fields, position.line, args)?;
+            return Ok(true);
+        };
+
+        let mut pairs = Vec::with_capacity(self.coord_pairs.len());
+        for &columns in &self.coord_pairs {
+            pairs.push(read_pair(
+                record,
+                columns,
+                position,
+                args,
+                converter,
+                &mut self.coord_cache,
+            )?);
+        }
+        warn_kept_pairs(&pairs, position);
+        let Some(coords) = row_coords(pairs, record, position, args, report)? else {
+            return Ok(false);
+        };
+
+        let mut fields = row_fields(
+            &coords,
+            &self.levels,
+            self.pair_width,
+            args,
+            &mut self.field_cache,
+        );
+        if !matches_filter(&fields[0], args) {
+            report.summary.filtered += 1;
+            return Ok(false);
+        }
+        if args.add_center {
+            push_centers(
+                &mut fields,
+                &coords,
+                self.levels.len(),
+                self.pair_width,
+                converter,
+                args,
+            )?;
+        }
+        if let Some(webmercator) = &self.webmercator {
+            push_webmercator(&mut fields, &coords, webmercator, args)?;
+        }
+        insert_fields(object, &self.column_names, fields, position.line, args)?;
+        Ok(true)
+    }
+}
+
+/// NDJSONの1行のオブジェクトに、追加する値を文字列 (空欄の場合は `null`) で加える
+fn insert_fields(
+    object: &mut serde_json::Map<String, Value>,
+    column_names: &[String],
+    fields: Vec<String>,
+    line_number: u64,
+    args: &EncodeArgs,
+) -> Result<(), Box<dyn Error>> {
+    for (name, field) in column_names.iter().zip(fields) {
+        if object.contains_key(name) && !args.force {
+            return Err(format!(
+                "{}行目: 追加するキー「{}」は入力に既に存在します (--mesh-column で別名を指定するか、--force で上書きしてください)",
+                line_number, name
+            )
+            .into());
+        }
+        let value = if field.is_empty() {
+            Value::Null
+        } else {
+            Value::String(field)
+        };
+        object.insert(name.clone(), value);
+    }
+    Ok(())
+}
+
+/// 1組の座標列の値を解析し、世界測地系に変換した結果
+enum PairCoord {
+    /// メッシュコードを計算する世界測地系の座標
+    Wgs(f64, f64),
+    /// 範囲外のため、メッシュコードを空欄にして出力する (`--out-of-range keep`)
+    Blank {
+        lat: f64,
+        lon: f64,
+        problem: MeshifyError,
+    },
+    /// 値が空欄のため、メッシュコードを空欄にして出力する (`--empty-as keep`)
+    Empty(MeshifyError),
+    /// 値が不正、または範囲外のためメッシュコードを求められない
+    Rejected(SkipReason, MeshifyError),
+}
+
+impl PairCoord {
+    /// メッシュコードを計算する座標 (計算しない組は `None`)
+    fn wgs(&self) -> Option<(f64, f64)> {
+        match self {
+            PairCoord::Wgs(lat, lon) => Some((*lat, *lon)),
+            _ => None,
+        }
+    }
+}
+
+/// メッシュコードを空欄にして出力する組 (`--out-of-range keep`・`--empty-as keep`) を警告する
+fn warn_kept_pairs(pairs: &[PairCoord], position: RowPosition) {
+    for pair in pairs {
+        match pair {
+            PairCoord::Blank { lat, lon, problem } => {
+                log::warn!(line = position.line, row = position.row, lat = lat, lon = lon; "{} (データの{}行目)。メッシュコードを空欄にします。", problem, position.row);
+            }
+            PairCoord::Empty(problem) => {
+                log::warn!(line = position.line, row = position.row; "{} (データの{}行目)。メッシュコードを空欄にします。", problem, position.row);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// 1行から1組の座標列の値を読み、世界測地系の座標に変換する
+///
+/// `--out-of-range error` で範囲外の場合と、変換自体に失敗した場合はエラーを返す。ワーカーから呼べるよう警告は
+/// 出さず、空欄にして出力する組の警告は呼び出し側が `warn_kept_pairs` で出す。
+fn read_pair(
+    record: &csv::StringRecord,
+    columns: CoordColumns,
+    position: RowPosition,
+    args: &EncodeArgs,
+    converter: &DatumConverter,
+    cache: &mut CoordCache,
+) -> Result<PairCoord, MeshifyError> {
+    let (lat_str, lon_str) = match columns {
+        CoordColumns::Separate(lat_idx, lon_idx) => (&record[lat_idx], &record[lon_idx]),
+        CoordColumns::Combined(idx) => {
+            let latlon_str = &record[idx];
+            if latlon_str.trim().is_empty() {
+                return empty_coord("緯度経度", position, args);
+            }
+            let mut parts = latlon_str.split(args.latlon_separator.as_str());
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(lat_str), Some(lon_str), None) => (lat_str, lon_str),
+                _ => {
+                    let problem = MeshifyError::SplitError {
+                        line: position.line,
+                        value: latlon_str.to_string(),
+                    };
+                    return Ok(PairCoord::Rejected(SkipReason::InvalidLatLon, problem));
+                }
+            }
+        }
+    };
+
+    let (lat_str, lon_str) = if args.swap_xy {
+        (lon_str, lat_str)
+    } else {
+        (lat_str, lon_str)
+    };
+    if lat_str.trim().is_empty() {
+        return empty_coord("緯度", position, args);
+    }
+    if lon_str.trim().is_empty() {
+        return empty_coord("経度", position, args);
+    }
+
+    let lat: f64 = match parse_coord_field(lat_str, args) {
+        Some(val) => val,
+        None => {
+            let problem = MeshifyError::ParseError {
+                line: position.line,
+                field: "緯度",
+                value: lat_str.to_string(),
+            };
+            return Ok(PairCoord::Rejected(SkipReason::InvalidLat, problem));
+        }
+    };
+
+    let lon: f64 = match parse_coord_field(lon_str, args) {
+        Some(val) => val,
+        None => {
+            let problem = MeshifyError::ParseError {
+                line: position.line,
+                field: "経度",
+                value: lon_str.to_string(),
+            };
+            return Ok(PairCoord::Rejected(SkipReason::InvalidLon, problem));
+        }
+    };
+
+    let (wgs_lat, wgs_lon) = cache.convert(converter, lat, lon)?;
+    let line_number = position.line;
+    log::trace!(line = line_number, lat = lat, lon = lon, wgs_lat = wgs_lat, wgs_lon = wgs_lon; "{}行目: ({}, {}) を世界測地系の ({}, {}) に変換しました", line_number, lat, lon, wgs_lat, wgs_lon);
+
+    // 範囲外の座標ではメッシュコードが意味を持たないため、計算の手前で検出する
+    if is_within_japan(wgs_lat, wgs_lon) {
+        return Ok(PairCoord::Wgs(wgs_lat, wgs_lon));
+    }
+    let problem = MeshifyError::OutOfRange {
+        line: position.line,
+        lat,
+        lon,
+    };
+    match args.out_of_range {
+        OutOfRange::Skip => Ok(PairCoord::Rejected(SkipReason::OutOfRange, problem)),
+        OutOfRange::Keep => Ok(PairCoord::Blank { lat, lon, problem }),
+        OutOfRange::Error => Err(problem),
+    }
+}
+
+/// 座標の値が空欄だった組を `--empty-as` に従って扱う
+fn empty_coord(
+    field: &'static str,
+    position: RowPosition,
+    args: &EncodeArgs,
+) -> Result<PairCoord, MeshifyError> {
+    let problem = MeshifyError::EmptyCoord {
+        line: position.line,
+        field,
+    };
+    match args.empty_as {
+        EmptyAs::Skip => Ok(PairCoord::Rejected(SkipReason::EmptyCoord, problem)),
+        EmptyAs::Keep => Ok(PairCoord::Empty(problem)),
+        EmptyAs::Error => Err(problem),
+    }
+}
+
+/// 入力座標を世界測地系に変換する変換器
+enum InputConverter {
+    /// すべての行を同じ測地系 (`--datum` または `--source-crs`) から変換する
+    Single(DatumConverter),
+    /// 行ごとに `--datum-column` の値の測地系から変換する (`converters` は `Datum::value_variants` の順)
+    ///
+    /// 入力に現れない測地系のためにPROJの変換を作らないよう、変換器はその測地系の行が初めて現れた際に作る。
+    PerRow {
+        converters: Vec<OnceLock<DatumConverter>>,
+        default: Option<Datum>,
+    },
+}
+
+impl InputConverter {
+    /// 行の測地系列の値に対応する変換器を返す。値が不正で `--default-datum` も無い場合は `None`
+    fn select(&self, datum: Option<&str>) -> Result<Option<&DatumConverter>, MeshifyError> {
+        match self {
+            InputConverter::Single(converter) => Ok(Some(converter)),
+            InputConverter::PerRow {
+                converters,
+                default,
+            } => {
+                let Some(datum) = datum
+                    .and_then(|value| Datum::from_str(value.trim(), true).ok())
+                    .or(*default)
+                else {
+                    return Ok(None);
+                };
+                Self::per_row_converter(converters, datum).map(Some)
+            }
+        }
+    }
+
+    /// 座標を変換しない行 (メッシュコードを空欄にして出力する不正な行) に渡す変換器
+    fn fallback(&self) -> &DatumConverter {
+        match self {
+            InputConverter::Single(converter) => converter,
+            InputConverter::PerRow { converters, .. } => {
+                Self::per_row_converter(converters, Datum::WGS)
+                    .expect("世界測地系の変換器はPROJを使わずに作れる")
+            }
+        }
+    }
+
+    fn per_row_converter(
+        converters: &[OnceLock<DatumConverter>],
+        datum: Datum,
+    ) -> Result<&DatumConverter, MeshifyError> {
+        let cell = &converters[datum as usize];
+        match cell.get() {
+            Some(converter) => Ok(converter),
+            None => {
+                let created = DatumConverter::new(datum)?;
+                Ok(cell.get_or_init(|| created))
+            }
+        }
+    }
+}
+
+/// 入力座標を世界測地系に変換する変換器を作る
+fn input_converter(args: &EncodeArgs) -> Result<InputConverter, Box<dyn Error>> {
+    if let Some(column) = &args.datum_column {
+        log::debug!(
+            "入力座標を列「{}」の測地系から世界測地系に変換します (値が不正な場合: {:?})",
+            column,
+            args.default_datum
+        );
+        let converters = Datum::value_variants()
+            .iter()
+            .map(|_| OnceLock::new())
+            .collect();
+        return Ok(InputConverter::PerRow {
+            converters,
+            default: args.default_datum,
+        });
+    }
+    if let Some(zone) = args.plane_auto {
+        let crs = plane::zone_crs(zone);
+        log::info!(
+            "平面直角座標系の第{}系 ({}) と推定しました",
+            plane::zone_name(zone),
+            crs
+        );
+        return Ok(InputConverter::Single(DatumConverter::from_crs(&crs)?));
+    }
+    match &args.source_crs {
+        Some(source) => {
+            log::debug!("入力座標をCRS {} から世界測地系に変換します", source);
+            Ok(InputConverter::Single(DatumConverter::from_crs(source)?))
+        }
+        None => {
+            log::debug!(
+                "入力座標を測地系 {:?} から世界測地系に変換します",
+                args.datum
+            );
+            Ok(InputConverter::Single(DatumConverter::new(args.datum)?))
+        }
+    }
+}
+
+/// 1行の各組の座標から、メッシュコードを計算する座標を決める。行をスキップする場合は `None` を返す
+///
+/// どの組からもメッシュコードを求められない行は `reject_row` で扱い、理由は最初の組のものを数える。
+/// 一部の組だけが不正な場合は、その組のメッシュコードを空欄にして他の組は出力する。
+fn row_coords(
+    pairs: Vec<PairCoord>,
+    record: &csv::StringRecord,
+    position: RowPosition,
+    args: &EncodeArgs,
+    report: &mut Report,
+) -> Result<Option<RowCoords>, Box<dyn Error>> {
+    if pairs
+        .iter()
+        .all(|pair| matches!(pair, PairCoord::Rejected(..)))
+    {
+        let count = pairs.len();
+        let mut problems = pairs.into_iter().filter_map(|pair| match pair {
+            PairCoord::Rejected(reason, problem) => Some((reason, problem)),
+            _ => None,
+        });
+        let (reason, problem) = problems.next().expect("座標列の組は1つ以上ある");
+        let keep = reject_row(reason, problem, record, position, args, report)?;
+        for (_, problem) in problems {
+            log::warn!(line = problem.line(); "{}。", problem);
+        }
+        return Ok(keep.then(|| vec![None; count]));
+    }
+
+    let mut blank = false;
+    let mut empty = false;
+    let mut partial = false;
+    let mut coords = Vec::with_capacity(pairs.len());
+    for pair in pairs {
+        coords.push(match pair {
+            PairCoord::Wgs(lat, lon) => Some((lat, lon)),
+            PairCoord::Blank { .. } => {
+                blank = true;
+                None
+            }
+            PairCoord::Empty(_) => {
+                empty = true;
+                None
+            }
+            PairCoord::Rejected(_, problem) if args.strict => return Err(problem.into()),
+            PairCoord::Rejected(reason, problem) => {
+                log::warn!(line = position.line, row = position.row, reason:% = reason; "{} (データの{}行目)。この組のメッシュコードを空欄にします。", problem, position.row);
+                partial = true;
+                None
+            }
+        });
+    }
+    report.summary.blank += u64::from(blank);
+    report.summary.empty += u64::from(empty);
+    report.summary.partial += u64::from(partial);
+    Ok(Some(coords))
+}
+
+/// 追加する列名を、座標列の組ごとに (メッシュコード、ジオメトリ、面積) の順で並べ、中心座標の列を最後に加えて返す
+///
+/// 1組あたりの、中心座標を除く列の数も返す。列名が重複する場合はエラーにする。
+fn added_columns(
+    args: &EncodeArgs,
+    levels: &[MeshLevel],
+) -> Result<(Vec<String>, usize), Box<dyn Error>> {
+    let suffixes = pair_suffixes(args)?;
+    let mut column_names = Vec::new();
+    for suffix in &suffixes {
+        let base = |name: &str| format!("{}{}", name, suffix);
+        column_names.extend(mesh_column_names(&base(&args.mesh_column), levels));
+        if args.geometry.is_some() {
+            column_names.extend(mesh_column_names(&base("mesh_geometry"), levels));
+        }
+        if args.add_area {
+            column_names.extend(mesh_column_names(&base("mesh_area_km2"), levels));
+        }
+        if args.add_geohash {
+            column_names.extend(mesh_column_names(&base("geohash"), levels));
+        }
+        if args.add_center_distance {
+            column_names.extend(mesh_column_names(&base("mesh_center_distance_m"), levels));
+        }
+        // 都道府県は1次メッシュから求めるため、レベルによらず1列
+        if args.add_prefecture {
+            column_names.push(base("prefecture"));
+        }
+    }
+    let pair_width = column_names.len() / suffixes.len();
+    // 中心座標は、--filter-mesh などで出力しない行の分まで逆変換しないよう、書き出す直前に求める。
+    // そのため列は最後に置く
+    if args.add_center {
+        for suffix in &suffixes {
+            let lat_names = mesh_column_names(&format!("mesh_center_lat{}", suffix), levels);
+            let lon_names = mesh_column_names(&format!("mesh_center_lon{}", suffix), levels);
+            for (lat_name, lon_name) in lat_names.into_iter().zip(lon_names) {
+                column_names.push(lat_name);
+                column_names.push(lon_name);
+            }
+        }
+    }
+    // Webメルカトル座標も、中心座標と同じく書き出す直前に求める
+    if args.add_webmercator {
+        for suffix in &suffixes {
+            column_names.push(format!("webmercator_x{}", suffix));
+            column_names.push(format!("webmercator_y{}", suffix));
+        }
+    }
+    if let Some(name) = column_names
+        .iter()
+        .enumerate()
+        .find_map(|(i, name)| column_names[..i].contains(name).then_some(name))
+    {
+        return Err(format!(
+            "追加する列名「{}」が重複しています (--pair-suffix で座標列の組ごとに別の名前を指定してください)",
+            name
+        )
+        .into());
+    }
+    Ok((column_names, pair_width))
+}
+
+/// 座標列の組ごとの追加列の値 (`fields`) の後ろに、各組のメッシュの中心座標を加える
+fn push_centers(
+    fields: &mut Vec<String>,
+    coords: &RowCoords,
+    levels: usize,
+    pair_width: usize,
+    converter: &DatumConverter,
+    args: &EncodeArgs,
+) -> Result<(), Box<dyn Error>> {
+    let mut centers = Vec::with_capacity(coords.len() * levels * 2);
+    for (pair, coord) in coords.iter().enumerate() {
+        let start = pair * pair_width;
+        match coord {
+            Some(_) => centers.extend(mesh_centers(
+                &fields[start..start + levels],
+                converter,
+                args,
+            )?),
+            None => centers.extend(vec![String::new(); levels * 2]),
+        }
+    }
+    fields.extend(centers);
+    Ok(())
+}
+
+/// 追加列の値 (`fields`) の後ろに、座標列の組ごとの点のWebメルカトル座標を加える
+fn push_webmercator(
+    fields: &mut Vec<String>,
+    coords: &RowCoords,
+    webmercator: &WebMercator,
+    args: &EncodeArgs,
+) -> Result<(), Box<dyn Error>> {
+    let format = |value: f64| format_decimal(value, args.webmercator_precision.or(args.precision));
+    for coord in coords {
+        match coord {
+            Some((lat, lon)) => {
+                let (x, y) = webmercator.convert(*lat, *lon)?;
+                fields.push(format(x));
+                fields.push(format(y));
+            }
+            None => fields.extend([String::new(), String::new()]),
+        }
+    }
+    Ok(())
+}
+
+/// 座標列の組ごとに、追加する列名に付けるサフィックスを決める
+///
+/// 組が1つだけなら付けない。複数ある場合は `--pair-suffix` の指定か、緯度の列名を `_` に続けて付ける。
+fn pair_suffixes(args: &EncodeArgs) -> Result<Vec<String>, Box<dyn Error>> {
+    if args.lat.len() <= 1 {
+        return Ok(vec![String::new()]);
+    }
+    if args.pair_suffix.is_empty() {
+        return Ok(args.lat.iter().map(|lat| format!("_{}", lat)).collect());
+    }
+    if args.pair_suffix.len() != args.lat.len() {
+        return Err("--pair-suffix は --lat と同じ数だけ指定してください".into());
+    }
+    Ok(args
+        .pair_suffix
+        .iter()
+        .map(|suffix| format!("_{}", suffix))
+        .collect())
+}
+
+/// `--keep-columns`・`--drop-columns` の指定から、出力する列を追加列を入れた後の列の位置で返す (指定が無ければ `None`)
+///
+/// `--keep-columns` では指定の順に元の列を並べ、その後ろに追加する列を置く。`--force` で元の列に上書きする
+/// 追加列も、指定に無ければ後ろに置く。`find_column` で探すため、入力に無い列を指定するとエラーになる。
+fn selected_columns<F>(
+    args: &EncodeArgs,
+    find_column: F,
+    input_columns: usize,
+    mesh_indices: &[Option<usize>],
+    insert_at: usize,
+) -> Result<Option<Vec<usize>>, Box<dyn Error>>
+where
+    F: Fn(&str, &'static str) -> Result<usize, Box<dyn Error>>,
+{
+    let added = mesh_indices.iter().filter(|idx| idx.is_none()).count();
+    // 元の列の位置を、追加列を入れた後の位置にする
+    let shifted = |idx: usize| if idx < insert_at { idx } else { idx + added };
+    if !args.keep_columns.is_empty() {
+        let mut selected = Vec::new();
+        for name in &args.keep_columns {
+            let idx = shifted(find_column(name, "出力する列")?);
+            if !selected.contains(&idx) {
+                selected.push(idx);
+            }
+        }
+        let mut added_positions = insert_at..;
+        for idx in mesh_indices {
+            let idx = match idx {
+                Some(idx) => shifted(*idx),
+                None => added_positions.next().expect("範囲の終わりが無い"),
+            };
+            if !selected.contains(&idx) {
+                selected.push(idx);
+            }
+        }
+        return Ok(Some(selected));
+    }
+    if !args.drop_columns.is_empty() {
+        let dropped = args
+            .drop_columns
+            .iter()
+            .map(|name| find_column(name, "除外する列").map(shifted))
+            .collect::<Result<Vec<usize>, _>>()?;
+        let selected = (0..input_columns + added)
+            .filter(|idx| !dropped.contains(idx))
+            .collect();
+        return Ok(Some(selected));
+    }
+    Ok(None)
+}
+
+/// 列名に一致する列の位置を探す
+///
+/// `ignore_case` の場合は前後の空白を除き、大文字小文字を区別せずに比較する。
+/// その際に表記の異なる複数の列が一致すると、どちらを使うべきか決められないため候補を挙げてエラーにする。
+/// 同じ列名の列が複数ある場合は、`on_duplicate` に従って警告を出して最初の列を使うか、エラーにする。
+fn find_header(
+    headers: &csv::StringRecord,
+    name: &str,
+    ignore_case: bool,
+    on_duplicate: DuplicateHeader,
+) -> Result<Option<usize>, MeshifyError> {
+    let normalize = |s: &str| {
+        if ignore_case {
+            s.trim().to_lowercase()
+        } else {
+            s.to_string()
+        }
+    };
+    let target = normalize(name);
+    let matched = headers
+        .iter()
+        .enumerate()
+        .filter(|(_, h)| normalize(h) == target)
+        .collect::<Vec<(usize, &str)>>();
+    let Some(&(first, header)) = matched.first() else {
+        return Ok(None);
+    };
+    if matched.len() == 1 {
+        return Ok(Some(first));
+    }
+    if matched.iter().any(|&(_, h)| h != header) {
+        return Err(MeshifyError::AmbiguousColumn {
+            name: name.to_string(),
+            candidates: matched.iter().map(|(_, h)| h.to_string()).collect(),
+        });
+    }
+    let positions = matched.iter().map(|&(idx, _)| idx).collect::<Vec<usize>>();
+    match on_duplicate {
+        DuplicateHeader::First => {
+            log::warn!(
+                "列名「{}」の列が複数あります (列の位置: {})。最初の列を使います。",
+                name,
+                positions
+                    .iter()
+                    .map(usize::to_string)
+                    .collect::<Vec<String>>()
+                    .join("、")
+            );
+            Ok(Some(first))
+        }
+        DuplicateHeader::Error => Err(MeshifyError::DuplicateColumn {
+            name: name.to_string(),
+            positions,
+        }),
+    }
+}
+
+/// 列名が正規表現に一致する列の位置を探す (`--lat-regex` など)
+///
+/// 一致する列が複数ある場合は、どの列を使うべきか決められないため候補を挙げてエラーにする。
+fn find_header_regex(
+    headers: &csv::StringRecord,
+    pattern: &Regex,
+    label: &'static str,
+) -> Result<usize, MeshifyError> {
+    let matched = headers
+        .iter()
+        .enumerate()
+        .filter(|(_, h)| pattern.is_match(h))
+        .collect::<Vec<(usize, &str)>>();
+    match matched[..] {
+        [(idx, _)] => Ok(idx),
+        [] => Err(MeshifyError::NoColumnMatched {
+            label,
+            pattern: pattern.to_string(),
+            columns: headers.iter().map(String::from).collect(),
+        }),
+        _ => Err(MeshifyError::AmbiguousColumnPattern {
+            label,
+            pattern: pattern.to_string(),
+            candidates: matched.iter().map(|(_, h)| h.to_string()).collect(),
+        }),
+    }
+}
+
+/// 列が見つからない場合のエラー。指定の誤りに気付けるよう、入力にある列名と近い候補を添える
+fn column_not_found(headers: &csv::StringRecord, label: &'static str, name: &str) -> MeshifyError {
+    MeshifyError::ColumnNotFound {
+        label,
+        name: name.to_string(),
+        suggestion: suggest_column(headers, name).map(String::from),
+        columns: headers.iter().map(String::from).collect(),
+    }
+}
+
+/// 指定された列名に近い列名を探す
+///
+/// 大文字小文字と前後の空白は区別せず、一方が他方を含む (`lat` と `latitude`) か、
+/// 編集距離が短い (`lattitude` と `latitude`) 列名のうち、最も編集距離の短いものを返す。
+fn suggest_column<'a>(headers: &'a csv::StringRecord, name: &str) -> Option<&'a str> {
+    let name = name.trim().to_lowercase();
+    if name.is_empty() {
+        return None;
+    }
+    let max_distance = (name.chars().count() / 3).max(1);
+    headers
+        .iter()
+        .filter_map(|header| {
+            let lower = header.trim().to_lowercase();
+            if lower.is_empty() {
+                return None;
+            }
+            let distance = edit_distance(&name, &lower);
+            let similar =
+                lower.contains(&name) || name.contains(&lower) || distance <= max_distance;
+            similar.then_some((distance, header))
+        })
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, header)| header)
+}
+
+/// 2つの文字列のレーベンシュタイン距離 (文字単位)
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<char>>();
+    let mut previous = (0..=b.len()).collect::<Vec<usize>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// `--skip` で読み飛ばした後の `rows` 行目を処理する前に、行数が `--max-rows` を超えていないか確かめる
+///
+/// 超えている場合、`--on-max-rows stop` ならエラーを返し、`truncate` なら警告を出して `true` を返す
+/// (呼び出し側はその入力の読み込みを終える)。
+fn exceeds_max_rows(rows: usize, args: &EncodeArgs) -> Result<bool, Box<dyn Error>> {
+    let Some(max_rows) = args.max_rows else {
+        return Ok(false);
+    };
+    if rows <= max_rows {
+        return Ok(false);
+    }
+    match args.on_max_rows {
+        OnMaxRows::Stop => Err(format!(
+            "入力の行数が --max-rows の上限 ({} 行) を超えたため、処理を中断します (意図した入力であれば上限を変更してください)",
+            max_rows
+        )
+        .into()),
+        OnMaxRows::Truncate => {
+            log::warn!(
+                "入力の行数が --max-rows の上限 ({} 行) を超えたため、以降の行は処理しません。",
+                max_rows
+            );
+            Ok(true)
+        }
+    }
+}
+
+/// メッシュコードを求められない行を扱う
+///
+/// 通常は警告を出して行をスキップし、理由ごとに数える。`--error-output` 指定時は行をそのファイルに書き出す。
+/// `--strict` の場合は `problem` をエラーとして返し、処理を中断させる。
+/// `--keep-invalid` の場合は行を残すため `true` を返し、呼び出し側はメッシュコードを空欄にして出力する。
+fn reject_row(
+    reason: SkipReason,
+    problem: MeshifyError,
+    record: &csv::StringRecord,
+    position: RowPosition,
+    args: &EncodeArgs,
+    report: &mut Report,
+) -> Result<bool, Box<dyn Error>> {
+    if args.strict {
+        return Err(problem.into());
+    }
+    if args.keep_invalid {
+        log::warn!(line = position.line, row = position.row, reason:% = reason; "{} (データの{}行目)。メッシュコードを空欄にして出力します。", problem, position.row);
+        report.summary.invalid += 1;
+        return Ok(true);
+    }
+    log::warn!(line = position.line, row = position.row, reason:% = reason; "{} (データの{}行目)。この行をスキップします。", problem, position.row);
+    report.summary.skip(reason);
+    if let Some(errors) = &mut report.errors {
+        errors.write(record, position, reason)?;
+    }
+    Ok(false)
+}
+
+/// 1行分の追加列 (メッシュコード、ジオメトリ、面積、中心からの距離など) の値を、列名と同じ順序で作る
+fn mesh_fields(lat: f64, lon: f64, levels: &[MeshLevel], args: &EncodeArgs) -> Vec<String> {
+    let codes = levels
+        .iter()
+        .map(|&level| get_mesh_code(lat, lon, level))
+        .collect::<Vec<String>>();
+    let mut fields = codes.clone();
+    if let Some(format) = args.geometry {
+        fields.extend(codes.iter().map(|code| mesh_geometry(code, format, args)));
+    }
+    if args.add_area {
+        fields.extend(codes.iter().map(|code| {
+            let area =
+                mesh_area_km2(code).expect("get_mesh_code が返すメッシュコードは常に解釈できる");
+            format_decimal(area, args.precision)
+        }));
+    }
+    if args.add_geohash {
+        fields.extend(codes.iter().map(|code| {
+            mesh_to_geohash(code, usize::from(args.geohash_precision))
+                .expect("get_mesh_code が返すメッシュコードは常に解釈できる")
+        }));
+    }
+    if args.add_center_distance {
+        fields.extend(codes.iter().map(|code| {
+            let (center_lat, center_lon) = mesh_code_to_center(code)
+                .expect("get_mesh_code が返すメッシュコードは常に解釈できる");
+            let distance = haversine_distance_m(lat, lon, center_lat, center_lon);
+            format_decimal(distance, args.precision)
+        }));
+    }
+    if args.add_prefecture {
+        let prefectures = prefectures_of_mesh(&codes[0])
+            .expect("get_mesh_code が返すメッシュコードは常に解釈できる");
+        fields.push(
+            prefectures
+                .iter()
+                .map(|prefecture| match args.prefecture_format {
+                    PrefectureFormat::Name => prefecture.name,
+                    PrefectureFormat::Code => prefecture.code,
+                })
+                .collect::<Vec<&str>>()
+                .join(";"),
+        );
+    }
+    // 他の追加列はメッシュコードから求めるため、表記は最後に整える
+    if args.mesh_format == MeshFormat::Hyphenated {
+        for code in &mut fields[..levels.len()] {
+            *code = hyphenate_mesh_code(code);
+        }
+    }
+    fields
+}
+
+/// 各メッシュコードの中心座標を、緯度・経度の順に文字列で並べて返す
+fn mesh_centers(
+    codes: &[String],
+    converter: &DatumConverter,
+    args: &EncodeArgs,
+) -> Result<Vec<String>, Box<dyn Error>> {
+    let format = |value: f64| format_decimal(value, args.center_precision.or(args.precision));
+    let mut centers = Vec::with_capacity(codes.len() * 2);
+    for code in codes {
+        let (lat, lon) = mesh_code_to_center(&strip_mesh_code_separators(code))?;
+        let (lat, lon) = match args.center_datum {
+            CenterDatum::Wgs => (lat, lon),
+            CenterDatum::Input => converter.from_wgs(lat, lon)?,
+        };
+        centers.push(format(lat));
+        centers.push(format(lon));
+    }
+    Ok(centers)
+}
+
+/// メッシュコードから、指定の形式のジオメトリ文字列を作る
+fn mesh_geometry(code: &str, format: GeometryFormat, args: &EncodeArgs) -> String {
+    let ring = mesh_polygon(code, args.geometry_orientation)
+        .expect("get_mesh_code が返すメッシュコードは常に解釈できる");
+    match format {
+        GeometryFormat::Wkt => polygon_wkt(&ring, args.geometry_precision.or(args.precision)),
+    }
+}
+
+/// 指定順を保ったまま、重複して指定されたレベルを取り除く
+fn unique_levels(levels: &[MeshLevel]) -> Vec<MeshLevel> {
+    let mut unique = Vec::with_capacity(levels.len());
+    for &level in levels {
+        if !unique.contains(&level) {
+            unique.push(level);
+        }
+    }
+    unique
+}
+
+/// 追加するメッシュコード列の列名を決める
+///
+/// レベルが1つだけなら列名をそのまま使い、複数ある場合はレベル名 (`five-fold` は `five_fold`) を
+/// サフィックスとして付ける。
+fn mesh_column_names(base: &str, levels: &[MeshLevel]) -> Vec<String> {
+    if let [_] = levels {
+        return vec![base.to_string()];
+    }
+    levels
+        .iter()
+        .map(|level| {
+            let name = level
+                .to_possible_value()
+                .expect("MeshLevel に skip されたバリアントはない");
+            format!("{}_{}", base, name.get_name().replace('-', "_"))
+        })
+        .collect()
+}
+
+fn run_decode(args: DecodeArgs, csv_args: &CsvArgs) -> Result<(), Box<dyn Error>> {
+    let mut reader = csv_args.open_reader(&args.input_file, open_input(&args.input_file)?)?;
+
+    let output_path = resolve_output_path(args.output.clone(), &args.input_file, "center", "csv");
+
+    let mut writer = csv_args.writer(csv_args.open_output(output_path.as_deref(), args.force)?)?;
+
+    decode(&mut reader, &mut writer, &args)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// CSVの各行のメッシュコードから中心座標 (と親メッシュのコード・矩形) を求めて書き出す
+///
+/// メッシュのレベルは行ごとにコードの桁数から判定するため、レベルの異なるコードが混在していてもよい。
+/// `5339-35-99` のような `-` 区切りのコード (`--mesh-format hyphenated`) も受け付ける。
+fn decode<R: Read, W: Write>(
+    reader: &mut csv::Reader<R>,
+    writer: &mut csv::Writer<W>,
+    args: &DecodeArgs,
+) -> Result<(), Box<dyn Error>> {
+    let headers = reader.headers()?.clone();
+    let has_headers = reader.has_headers();
+    if is_empty_input(&headers, has_headers)? {
+        return Ok(());
+    }
+    let code_idx = args.column.position(&headers, has_headers)?;
+
+    let mut new_headers = headers.iter().map(String::from).collect::<Vec<String>>();
+    new_headers.push("center_lat".to_string());
+    new_headers.push("center_lon".to_string());
+    if args.parent.is_some() {
+        new_headers.push("parent_mesh_code".to_string());
+    }
+    if args.bbox {
+        new_headers.push("bbox".to_string());
+    }
+    // --no-header の場合は、入力と同じく出力にもヘッダーを書かない
+    if has_headers {
+        writer.write_record(&new_headers)?;
+    }
+
+    for result in reader.records() {
+        let mut record = result?;
+        let RowPosition { line, row } = RowPosition::of(&record, has_headers);
+
+        let code = &record[code_idx];
+        let plain = strip_mesh_code_separators(code.trim());
+        let (lat, lon) = match mesh_code_to_center(&plain) {
+            Ok(center) => center,
+            Err(e) => {
+                log::warn!(line = line, row = row, code = code; "{}行目 (データの{}行目): メッシュコード「{}」が不正なため、この行をスキップします。({})", line, row, code, e);
+                continue;
+            }
+        };
+
+        let parent = match args.parent.map(|level| parent_mesh(&plain, level)) {
+            Some(Ok(parent)) => Some(parent),
+            Some(Err(e)) => {
+                log::warn!(line = line, row = row, code = code; "{}行目 (データの{}行目): メッシュコード「{}」の親メッシュを求められないため、この行をスキップします。({})", line, row, code, e);
+                continue;
+            }
+            None => None,
+        };
+        let bbox = args.bbox.then(|| {
+            let (min_lon, min_lat, max_lon, max_lat) =
+                mesh_code_to_bounds(&plain).expect("中心座標を求められたコード");
+            format!("{},{},{},{}", min_lat, min_lon, max_lat, max_lon)
+        });
+
+        record.push_field(&lat.to_string());
+        record.push_field(&lon.to_string());
+        if let Some(parent) = parent {
+            record.push_field(&parent);
+        }
+        if let Some(bbox) = bbox {
+            record.push_field(&bbox);
+        }
+        writer.write_record(&record)?;
+    }
+
+    Ok(())
+}
+
+fn run_validate(args: ValidateArgs, csv_args: &CsvArgs) -> Result<(), Box<dyn Error>> {
+    let mut reader = csv_args.open_reader(&args.input_file, open_input(&args.input_file)?)?;
+    let (rows, invalid) = validate(&mut reader, &args.column, &mut io::stdout().lock())?;
+    if invalid > 0 {
+        return Err(format!("{}行中{}行のメッシュコードが不正です", rows, invalid).into());
+    }
+    log::info!("{}行すべてのメッシュコードが正しい形式です", rows);
+    Ok(())
+}
+
+/// メッシュコード列を検証し、不正な行を1行ずつ `report` に書き出す。戻り値は (総行数, 不正な行数)
+fn validate<R: Read, W: Write>(
+    reader: &mut csv::Reader<R>,
+    column: &CodeColumnArgs,
+    report: &mut W,
+) -> Result<(usize, usize), Box<dyn Error>> {
+    let has_headers = reader.has_headers();
+    let headers = reader.headers()?;
+    if is_empty_input(headers, has_headers)? {
+        return Ok((0, 0));
+    }
+    let code_idx = column.position(headers, has_headers)?;
+
+    let mut rows = 0;
+    let mut invalid = 0;
+    for result in reader.records() {
+        let record = result?;
+        rows += 1;
+        let RowPosition { line, row } = RowPosition::of(&record, has_headers);
+        let code = &record[code_idx];
+        if let Err(e) = validate_mesh_code(code.trim()) {
+            invalid += 1;
+            writeln!(
+                report,
+                "{}行目 (データの{}行目): メッシュコード「{}」: {}",
+                line, row, code, e
+            )?;
+        }
+    }
+    Ok((rows, invalid))
+}
+
+fn run_point(args: PointArgs) -> Result<(), Box<dyn Error>> {
+    let mut stdout = io::stdout().lock();
+    for code in point_mesh_codes(&args)? {
+        writeln!(stdout, "{}", code)?;
+    }
+    Ok(())
+}
+
+/// 1点の座標を世界測地系に変換し、指定の各レベルのメッシュコードを求める
+fn point_mesh_codes(args: &PointArgs) -> Result<Vec<String>, Box<dyn Error>> {
+    let parse = |value: &str, field: &str| {
+        parse_coord(value, args.coord_format)
+            .ok_or_else(|| format!("{}の値「{}」が不正です", field, value))
+    };
+    let lat = parse(&args.lat, "緯度")?;
+    let lon = parse(&args.lon, "経度")?;
+    let converter = match &args.source_crs {
+        Some(source) => DatumConverter::from_crs(source)?,
+        None => DatumConverter::new(args.datum)?,
+    };
+    let (wgs_lat, wgs_lon) = converter.to_wgs(lat, lon)?;
+    log::debug!(lat = lat, lon = lon, wgs_lat = wgs_lat, wgs_lon = wgs_lon; "({}, {}) を世界測地系の ({}, {}) に変換しました", lat, lon, wgs_lat, wgs_lon);
+    let components = try_mesh_components(wgs_lat, wgs_lon)
+        .map_err(|_| format!("座標 ({}, {}) が日本の範囲外です", lat, lon))?;
+    if args.explain {
+        eprint!("{}", explain(wgs_lat, wgs_lon, &components));
+    }
+    Ok(unique_levels(&args.level)
+        .into_iter()
+        .map(|level| {
+            let mut code = String::with_capacity(level.code_length());
+            components.write_code(&mut code, level);
+            code
+        })
+        .collect())
+}
+
+/// メッシュコードの各桁の内訳を、レベルごとに1行ずつの表示用の文字列にする
+fn explain(lat: f64, lon: f64, components: &MeshComponents) -> String {
+    let MeshComponents {
+        p,
+        u,
+        q,
+        v,
+        r,
+        w,
+        m,
+        n,
+        o,
+        five_fold,
+        two_fold: (two_fold_lat, two_fold_lon),
+    } = *components;
+    let mut text = format!("世界測地系の座標: ({}, {})\n", lat, lon);
+    for &level in MeshLevel::value_variants() {
+        let digits = match level {
+            MeshLevel::First => format!("p={} u={}", p, u),
+            MeshLevel::Second => format!("q={} v={}", q, v),
+            MeshLevel::FiveFold => format!("分割番号={}", five_fold),
+            MeshLevel::TwoFold => format!("緯度方向={} 経度方向={}", two_fold_lat, two_fold_lon),
+            MeshLevel::Standard => format!("r={} w={}", r, w),
+            MeshLevel::Half => format!("m={}", m),
+            MeshLevel::Quarter => format!("n={}", n),
+            MeshLevel::Eighth => format!("o={}", o),
+        };
+        let mut code = String::new();
+        components.write_code(&mut code, level);
+        text.push_str(&format!("{}: {} → {}\n", level, digits, code));
+    }
+    text
+}
+
+fn run_grid(args: GridArgs, csv_args: &CsvArgs) -> Result<(), Box<dyn Error>> {
+    let output_path = args.output.as_deref().filter(|path| !is_stdio(path));
+    if args.format == GridFormat::Geojson {
+        check_json_output(csv_args, "GeoJSON")?;
+    }
+    let output = csv_args.open_output(output_path, args.force)?;
+    let mut writer: Box<dyn RecordWriter> = match args.format {
+        GridFormat::Csv => Box::new(csv_args.writer(output)?),
+        GridFormat::Geojson => Box::new(GeoJsonWriter::new(output)),
+    };
+    grid(&args, writer.as_mut())?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// 矩形領域を覆うメッシュのコードと中心座標を書き出す
+///
+/// GeoJSONの場合は、各メッシュの矩形をPolygonジオメトリとするFeatureになる。
+fn grid(args: &GridArgs, writer: &mut dyn RecordWriter) -> Result<(), Box<dyn Error>> {
+    let Bbox {
+        min_lat,
+        min_lon,
+        max_lat,
+        max_lon,
+    } = args.bbox;
+    if !is_within_japan(min_lat, min_lon) || !is_within_japan(max_lat, max_lon) {
+        log::warn!("矩形領域に日本の範囲外 (緯度20〜46度、経度122〜154度) が含まれています。");
+    }
+
+    // 一覧を組み立てる前に件数を確かめ、膨大になる場合はメモリを確保する前に止める
+    let count = count_meshes_in_bbox(min_lat, min_lon, max_lat, max_lon, args.level);
+    if count == 0 {
+        return Err("矩形領域が日本の範囲と重なっていません".into());
+    }
+    if count > args.max_meshes {
+        return Err(format!(
+            "メッシュ数が {} 件になり、上限 ({} 件) を超えています (領域を狭めるか、--max-meshes で上限を変更してください)",
+            count, args.max_meshes
+        )
+        .into());
+    }
+    if args.format == GridFormat::Geojson && count > GRID_GEOJSON_WARN_MESHES {
+        log::warn!(
+            "メッシュ数が {} 件と多いため、GeoJSONが大きくなります (開けない場合は、領域を狭めるか上位のレベルを指定してください)。",
+            count
+        );
+    }
+
+    writer.write_headers(&["mesh_code", "center_lat", "center_lon"].map(String::from))?;
+    for code in meshes_in_bbox(min_lat, min_lon, max_lat, max_lon, args.level) {
+        let (lat, lon) = mesh_code_to_center(&code)?;
+        writer.write_row(&[&code, &lat.to_string(), &lon.to_string()], &code)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use meshify::mesh_components;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn csv_args(encoding: Option<&'static Encoding>) -> CsvArgs {
+        CsvArgs {
+            delimiter: b',',
+            output_delimiter: None,
+            quote_char: b'"',
+            quote_style: QuoteStyle::Necessary,
+            line_ending: LineEnding::Lf,
+            encoding,
+            output_encoding: None,
+            bom: false,
+            on_unmappable: Unmappable::Replace,
+            gzip: false,
+            compression_level: 6,
+            buffer_size: 64 * 1024,
+            no_header: false,
+            sheet: None,
+            all_sheets: false,
+        }
+    }
+
+    #[test]
+    fn test_csv_dialect() {
+        let mut args = csv_args(None);
+        let path =
+            std::env::temp_dir().join(format!("meshify_quote_style_{}.csv", std::process::id()));
+        let write = |args: &CsvArgs| {
+            let file = fs::File::create(&path).unwrap();
+            let mut writer = args.writer(Box::new(file)).unwrap();
+            writer.write_record(["id", "1.5", "a,b"]).unwrap();
+            writer.flush().unwrap();
+            fs::read_to_string(&path).unwrap()
+        };
+        assert_eq!(write(&args), "id,1.5,\"a,b\"\n");
+        args.quote_style = QuoteStyle::Always;
+        assert_eq!(write(&args), "\"id\",\"1.5\",\"a,b\"\n");
+        args.quote_style = QuoteStyle::NonNumeric;
+        assert_eq!(write(&args), "\"id\",1.5,\"a,b\"\n");
+        args.quote_style = QuoteStyle::Never;
+        assert_eq!(write(&args), "id,1.5,a,b\n");
+        args.line_ending = LineEnding::Crlf;
+        assert_eq!(write(&args), "id,1.5,a,b\r\n");
+        fs::remove_file(&path).unwrap();
+
+        // 入力の引用符を ' にすると、' で囲んだ区切り文字を値として読む (改行コードは自動で判別する)
+        args.quote_char = b'\'';
+        let mut reader = args
+            .reader(Box::new(io::Cursor::new("name,lat\r\n'a,b',35\r\n")))
+            .unwrap();
+        let record = reader.records().next().unwrap().unwrap();
+        assert_eq!(&record[0], "a,b");
+        assert_eq!(&record[1], "35");
+        assert_eq!(parse_quote_char("'"), Ok(b'\''));
+        assert!(parse_quote_char("''").is_err());
+        assert!(parse_quote_char("「").is_err());
+    }
+
+    #[test]
+    fn test_reader_decodes_sjis_headers() {
+        // 全角の列名を含むShift_JISのCSVを読み込み、列名と値がUTF-8で得られることを確認
+        let (bytes, _, _) =
+            encoding_rs::SHIFT_JIS.encode("地点名,緯度,経度\n新宿,35.6895,139.6917\n");
+        let mut reader = csv_args(Some(encoding_rs::SHIFT_JIS))
+            .reader(Box::new(io::Cursor::new(bytes.into_owned())))
+            .unwrap();
+
+        let headers = reader.headers().unwrap().clone();
+        assert_eq!(headers.iter().position(|h| h == "緯度"), Some(1));
+        assert_eq!(headers.iter().position(|h| h == "経度"), Some(2));
+
+        let record = reader.records().next().unwrap().unwrap();
+        assert_eq!(&record[0], "新宿");
+    }
+
+    #[test]
+    fn test_reader_strips_bom() {
+        // BOMの有無や --encoding の指定に関わらず、最初の列名が一致する
+        for encoding in [None, Some(encoding_rs::UTF_8), Some(encoding_rs::SHIFT_JIS)] {
+            for bom in [&b""[..], b"\xEF\xBB\xBF"] {
+                let input = [bom, b"lat,lon\n35.68,139.76\n"].concat();
+                let mut reader = csv_args(encoding)
+                    .reader(Box::new(io::Cursor::new(input)))
+                    .unwrap();
+                assert_eq!(reader.headers().unwrap(), vec!["lat", "lon"]);
+            }
+        }
+
+        // NDJSONの1行目もJSONとして読める
+        let mut text = String::new();
+        csv_args(None)
+            .decoded_input(Box::new(
+                &b"\xEF\xBB\xBF{\"lat\":35.68,\"lon\":139.76}\n"[..],
+            ))
+            .unwrap()
+            .read_to_string(&mut text)
+            .unwrap();
+        let args = encode_args(&["--format-in", "ndjson", "--lat", "lat", "--lon", "lon", "-"]);
+        let (output, _) = encode_ndjson_str(&text, &args, false).unwrap();
+        assert_eq!(
+            output,
+            "{\"lat\":35.68,\"lon\":139.76,\"mesh_code\":\"53394610\"}\n"
+        );
+    }
+
+    /// コマンドライン引数からエンコード用の引数を組み立てる
+    fn encode_args(args: &[&str]) -> EncodeArgs {
+        let cli = Cli::try_parse_from(["meshify"].iter().chain(args)).unwrap();
+        cli.encode.unwrap()
+    }
+
+    /// 入力CSV文字列を指定のスレッド数で処理し、出力CSVを文字列で返す
+    fn try_encode_str(
+        input: &str,
+        args: &EncodeArgs,
+        threads: usize,
+    ) -> Result<String, Box<dyn Error>> {
+        encode_str_with_summary(input, args, threads).map(|(output, _)| output)
+    }
+
+    fn encode_str_with_summary(
+        input: &str,
+        args: &EncodeArgs,
+        threads: usize,
+    ) -> Result<(String, Summary), Box<dyn Error>> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+        let mut reader = csv::Reader::from_reader(input.as_bytes());
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        let mut report = Report::default();
+        encode(
+            &mut reader,
+            &mut writer,
+            args,
+            &pool,
+            &mut Progress::hidden(),
+            &mut report,
+            true,
+        )?;
+        let output = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        Ok((output, report.summary))
+    }
+
+    fn run_encode_str(input: &str, args: &EncodeArgs, threads: usize) -> String {
+        try_encode_str(input, args, threads).unwrap()
+    }
+
+    thread_local! {
+        /// このスレッドで出た警告 (並列に走る他のテストの警告と混ざらないよう、スレッドごとに持つ)
+        static WARNINGS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    }
+
+    /// 警告を、それを出したスレッドの `WARNINGS` に記録するロガー
+    struct WarningRecorder;
+
+    impl log::Log for WarningRecorder {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.level() <= log::Level::Warn
+        }
+
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) {
+                WARNINGS.with_borrow_mut(|warnings| warnings.push(record.args().to_string()));
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    /// 処理を終えた後に中身を読めるよう、書き込んだ内容を共有する出力先
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// 入力CSV文字列を処理し、出力・このスレッドで出た警告・`--error-output` の内容・行数を返す
+    fn encode_str_with_warnings(
+        input: &str,
+        args: &EncodeArgs,
+        threads: usize,
+    ) -> (String, Vec<String>, String, Summary) {
+        // テストでほかにロガーを設定することはないため、最初に設定したものがそのまま使われる
+        let _ = log::set_logger(&WarningRecorder);
+        log::set_max_level(log::LevelFilter::Warn);
+        WARNINGS.with_borrow_mut(Vec::clear);
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+        let mut reader = csv::Reader::from_reader(input.as_bytes());
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        let errors = SharedBuffer::default();
+        let mut report = Report {
+            errors: Some(ErrorOutput::new(csv::Writer::from_writer(Box::new(
+                errors.clone(),
+            )))),
+            ..Report::default()
+        };
+        encode(
+            &mut reader,
+            &mut writer,
+            args,
+            &pool,
+            &mut Progress::hidden(),
+            &mut report,
+            true,
+        )
+        .unwrap();
+        report.errors.as_mut().unwrap().finish().unwrap();
+        let output = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        let errors = String::from_utf8(errors.0.take()).unwrap();
+        (output, WARNINGS.take(), errors, report.summary)
+    }
+
+    #[test]
+    fn test_parallel_preserves_order() {
+        // チャンクを複数にまたがる行数を、並列・直列の両方で処理して出力が一致することを確認
+        let mut input = String::from("id,lat,lon\n");
+        for i in 0..(DEFAULT_CHUNK_SIZE * 2 + 123) {
+            let lat = 30.0 + (i % 1000) as f64 * 0.01;
+            let lon = 130.0 + (i % 777) as f64 * 0.013;
+            input.push_str(&format!("{},{},{}\n", i, lat, lon));
+        }
+
+        let args = encode_args(&["--lat", "lat", "--lon", "lon", "-"]);
+        let serial = run_encode_str(&input, &args, 1);
+        let parallel = run_encode_str(&input, &args, 4);
+        assert_eq!(serial, parallel);
+
+        // チャンクの行数を変えても、1スレッドの場合と完全に同じ出力になる
+        let small = &input[..input.match_indices('\n').nth(300).unwrap().0 + 1];
+        for chunk_size in ["1", "7", "1000"] {
+            let args = encode_args(&[
+                "--lat",
+                "lat",
+                "--lon",
+                "lon",
+                "--add-center",
+                "--chunk-size",
+                chunk_size,
+                "-",
+            ]);
+            let expected = run_encode_str(small, &args, 1);
+            for threads in [2, 8] {
+                assert_eq!(run_encode_str(small, &args, threads), expected);
+            }
+        }
+
+        // 日本測地系の変換と範囲の判定をワーカーで行っても、警告・スキップした行・行数が入力の順のまま一致する。
+        // チャンクを小さくして、複数のワーカーがそれぞれPROJの変換を作るようにする
+        let mut jgs_input = String::from("id,lat,lon\n");
+        for i in 0..300 {
+            jgs_input.push_str(&match i % 50 {
+                10 => format!("{},abc,139.76\n", i),
+                30 => format!("{},40.71,-74.0\n", i),
+                _ => format!("{},{},139.5\n", i, 35.0 + i as f64 * 0.001),
+            });
+        }
+        let jgs_args = |extra: &[&str]| {
+            let base = [
+                "--lat",
+                "lat",
+                "--lon",
+                "lon",
+                "--datum",
+                "jgs",
+                "--add-center",
+                "--center-datum",
+                "input",
+                "--chunk-size",
+                "7",
+            ];
+            encode_args(&[&base[..], extra, &["-"]].concat())
+        };
+        let args = jgs_args(&[]);
+        let expected = encode_str_with_warnings(&jgs_input, &args, 1);
+        assert_eq!(encode_str_with_warnings(&jgs_input, &args, 8), expected);
+        let (output, warnings, errors, summary) = expected;
+        assert_eq!(output.lines().count(), 1 + 288);
+        assert_eq!(summary.read, 300);
+        assert_eq!(summary.written, 288);
+        assert_eq!(summary.skipped_by(SkipReason::InvalidLat), 6);
+        assert_eq!(summary.skipped_by(SkipReason::OutOfRange), 6);
+        assert_eq!(warnings.len(), 12);
+        assert!(warnings[0].contains("(データの11行目)"));
+        assert!(warnings[1].contains("(データの31行目)"));
+        assert!(warnings[11].contains("(データの281行目)"));
+        let skipped = errors.lines().skip(1).collect::<Vec<&str>>();
+        assert_eq!(skipped.len(), 12);
+        assert_eq!(skipped[0], "10,abc,139.76,12,11,緯度不正");
+        assert_eq!(skipped[1], "30,40.71,-74.0,32,31,範囲外");
+        assert_eq!(skipped[11], "280,40.71,-74.0,282,281,範囲外");
+
+        // --strict でも、並列に変換した後の最初の不正な行でエラーになる
+        let args = jgs_args(&["--strict"]);
+        let serial = try_encode_str(&jgs_input, &args, 1)
+            .unwrap_err()
+            .to_string();
+        assert!(serial.contains("12行目"));
+        assert_eq!(
+            try_encode_str(&jgs_input, &args, 8)
+                .unwrap_err()
+                .to_string(),
+            serial
+        );
+
+        // id列が入力順に並んでいる
+        let mut reader = csv::Reader::from_reader(parallel.as_bytes());
+        for (i, record) in reader.records().enumerate() {
+            assert_eq!(record.unwrap()[0], i.to_string());
+        }
+        assert!(
+            Cli::try_parse_from([
+                "meshify",
+                "--lat",
+                "a",
+                "--lon",
+                "b",
+                "--chunk-size",
+                "0",
+                "-"
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_coord_cache() {
+        // 同じ座標が繰り返し現れる入力で、キャッシュしない場合と出力が一致する
+        let mut input = String::from("id,lat,lon\n");
+        for i in 0..500 {
+            input.push_str(&format!(
+                "{},{},{}\n",
+                i,
+                35.0 + (i % 7) as f64 * 0.1,
+                139.5
+            ));
+        }
+        let base = ["--lat", "lat", "--lon", "lon", "--add-center", "-"];
+        let expected = run_encode_str(&input, &encode_args(&base), 2);
+        for key in ["bits", "rounded"] {
+            let args = encode_args(&[&base[..], &["--coord-cache", key]].concat());
+            assert_eq!(run_encode_str(&input, &args, 2), expected);
+        }
+        assert!(
+            Cli::try_parse_from([
+                "meshify",
+                "--lat",
+                "a",
+                "--lon",
+                "b",
+                "--coord-cache-digits",
+                "3",
+                "-"
+            ])
+            .is_err()
+        );
+    }
+
+    /// 書き込みと出力先へ送った回数を数える出力先
+    #[derive(Default)]
+    struct FlushCounter {
+        written: Vec<u8>,
+        flushed: usize,
+        flushes: usize,
+    }
+
+    impl Write for FlushCounter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushed = self.written.len();
+            self.flushes += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_flush_interval() {
+        let input = "id,lat,lon\n1,35.68,139.76\n2,35.68,139.76\n3,35.68,139.76\n4,35.68,139.76\n5,35.68,139.76\n";
+        let encode_counting = |extra: &[&str]| {
+            let args = encode_args(&[&["--lat", "lat", "--lon", "lon"], extra, &["-"]].concat());
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(1)
+                .build()
+                .unwrap();
+            let mut reader = csv::Reader::from_reader(input.as_bytes());
+            let mut writer = csv::Writer::from_writer(FlushCounter::default());
+            encode(
+                &mut reader,
+                &mut writer,
+                &args,
+                &pool,
+                &mut Progress::hidden(),
+                &mut Report::default(),
+                true,
+            )
+            .unwrap();
+            // into_inner は書き残しを送るため、送った内容は encode の直後に確かめる
+            let counter = writer.get_ref();
+            let flushed = String::from_utf8(counter.written[..counter.flushed].to_vec()).unwrap();
+            (counter.flushes, flushed)
+        };
+        // 指定しない場合は途中で送らない
+        assert_eq!(encode_counting(&[]).0, 0);
+        // 2行ごとに送る (5行目は最後の finish で送る)
+        let (flushes, flushed) = encode_counting(&["--flush-interval", "2"]);
+        assert_eq!(flushes, 2);
+        assert_eq!(flushed.lines().count(), 5);
+        assert!(flushed.ends_with("4,35.68,139.76,53394610\n"));
+
+        assert!(
+            Cli::try_parse_from([
+                "meshify",
+                "--lat",
+                "a",
+                "--lon",
+                "b",
+                "--flush-interval",
+                "0",
+                "-"
+            ])
+            .is_err()
+        );
+        assert_eq!(parse_buffer_size("8192"), Ok(8192));
+        assert_eq!(parse_buffer_size("64K"), Ok(64 * 1024));
+        assert_eq!(parse_buffer_size("2m"), Ok(2 * 1024 * 1024));
+        assert!(parse_buffer_size("0").is_err());
+        assert!(parse_buffer_size("K").is_err());
+        assert!(parse_buffer_size("1G").is_err());
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        let input = "id,lat,lon\n1,35.68,139.76\n2,-33.87,151.21\n3,40.71,-74.0\n";
+
+        let args = encode_args(&["--lat", "lat", "--lon", "lon", "-"]);
+        assert_eq!(
+            run_encode_str(input, &args, 1),
+            "id,lat,lon,mesh_code\n1,35.68,139.76,53394610\n"
+        );
+
+        let args = encode_args(&[
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "--out-of-range",
+            "keep",
+            "-",
+        ]);
+        assert_eq!(
+            run_encode_str(input, &args, 1),
+            "id,lat,lon,mesh_code\n1,35.68,139.76,53394610\n2,-33.87,151.21,\n3,40.71,-74.0,\n"
+        );
+
+        let args = encode_args(&[
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "--out-of-range",
+            "error",
+            "-",
+        ]);
+        let err = try_encode_str(input, &args, 1).unwrap_err();
+        assert!(err.to_string().contains("3行目"));
+    }
+
+    #[test]
+    fn test_empty_as() {
+        let input = "id,lat,lon\n1,35.68,139.76\n2, ,139.76\n3,abc,139.76\n";
+        let run = |empty_as: &str| {
+            let args = encode_args(&["--lat", "lat", "--lon", "lon", "--empty-as", empty_as, "-"]);
+            encode_str_with_summary(input, &args, 1)
+        };
+
+        // 空欄は値が不正な行とは別の理由で数える
+        let (output, summary) = run("skip").unwrap();
+        assert_eq!(output, "id,lat,lon,mesh_code\n1,35.68,139.76,53394610\n");
+        assert_eq!(summary.skipped_by(SkipReason::EmptyCoord), 1);
+        assert_eq!(summary.skipped_by(SkipReason::InvalidLat), 1);
+
+        let (output, summary) = run("keep").unwrap();
+        assert_eq!(
+            output,
+            "id,lat,lon,mesh_code\n1,35.68,139.76,53394610\n2, ,139.76,\n"
+        );
+        assert_eq!((summary.empty, summary.skipped()), (1, 1));
+
+        let err = run("error").unwrap_err();
+        assert_eq!(err.to_string(), "3行目: 緯度の値が空欄です");
+
+        let args = encode_args(&["--latlon", "pos", "-"]);
+        let (_, summary) = encode_str_with_summary("pos\n\"\"\n", &args, 1).unwrap();
+        assert_eq!(summary.skipped_by(SkipReason::EmptyCoord), 1);
+    }
+
+    #[test]
+    fn test_summary() {
+        let input = "id,lat,lon
+1,35.68,139.76
+2,abc,139.76
+3,35.68,
+4,40.71,-74.0
+5,35.0,139.0
+";
+        let args = encode_args(&["--lat", "lat", "--lon", "lon", "--quiet", "-"]);
+        let (_, summary) = encode_str_with_summary(input, &args, 1).unwrap();
+        assert_eq!(summary.read, 5);
+        assert_eq!(summary.written, 2);
+        assert_eq!(summary.skipped(), 3);
+        assert_eq!(summary.skipped_by(SkipReason::InvalidLat), 1);
+        assert_eq!(summary.skipped_by(SkipReason::EmptyCoord), 1);
+        assert_eq!(summary.skipped_by(SkipReason::OutOfRange), 1);
+
+        // 空欄で残した行は出力行数に含める
+        let args = encode_args(&[
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "--out-of-range",
+            "keep",
+            "-",
+        ]);
+        let (_, summary) = encode_str_with_summary(input, &args, 1).unwrap();
+        assert_eq!(summary.written, 3);
+        assert_eq!(summary.blank, 1);
+        assert_eq!(summary.skipped(), 2);
+    }
+
+    /// NDJSONの入力文字列を処理し、出力 (NDJSONか、`csv` の場合はCSV) を文字列で返す
+    fn encode_ndjson_str(
+        input: &str,
+        args: &EncodeArgs,
+        csv: bool,
+    ) -> Result<(String, Summary), Box<dyn Error>> {
+        let mut writer = if csv {
+            ObjectWriter::csv(csv::Writer::from_writer(Vec::new()))
+        } else {
+            ObjectWriter::ndjson(Vec::new())
+        };
+        let mut report = Report::default();
+        encode_ndjson(
+            input.as_bytes(),
+            &mut writer,
+            args,
+            &mut Progress::hidden(),
+            &mut report,
+        )?;
+        writer.finish()?;
+        let output = String::from_utf8(writer.into_inner()).unwrap();
+        Ok((output, report.summary))
+    }
+
+    #[test]
+    fn test_ndjson() {
+        let input = r#"{"id":1,"location":{"lat":35.68,"lon":139.76}}
+{"id":2,"location":{"lat":"35.6895","lon":"139.6917"},"tags":["a"]}
+
+not json
+{"id":3,"location":{"lat":40.71,"lon":-74.0}}
+{"id":4}
+"#;
+        let args = encode_args(&[
+            "--format-in",
+            "ndjson",
+            "--lat",
+            "location.lat",
+            "--lon",
+            "location.lon",
+            "-",
+        ]);
+        assert_eq!(args.output_format(), OutputFormat::Ndjson);
+        let (output, summary) = encode_ndjson_str(input, &args, false).unwrap();
+        assert_eq!(
+            output,
+            r#"{"id":1,"location":{"lat":35.68,"lon":139.76},"mesh_code":"53394610"}
+{"id":2,"location":{"lat":"35.6895","lon":"139.6917"},"tags":["a"],"mesh_code":"53394525"}
+"#
+        );
+        // 空行は数えない
+        assert_eq!(summary.read, 5);
+        assert_eq!(summary.written, 2);
+        assert_eq!(summary.skipped_by(SkipReason::InvalidJson), 1);
+        assert_eq!(summary.skipped_by(SkipReason::OutOfRange), 1);
+        assert_eq!(summary.skipped_by(SkipReason::EmptyCoord), 1);
+
+        // CSVでは最初の行のキーを列にし、ネストした値はJSONの表記で出力する
+        let (output, _) = encode_ndjson_str(input, &args, true).unwrap();
+        assert_eq!(
+            output,
+            "id,location,mesh_code\n1,\"{\"\"lat\"\":35.68,\"\"lon\"\":139.76}\",53394610\n2,\"{\"\"lat\"\":\"\"35.6895\"\",\"\"lon\"\":\"\"139.6917\"\"}\",53394525\n"
+        );
+
+        // 範囲外で残す行のメッシュコードは null にする
+        let args = encode_args(&[
+            "--format-in",
+            "ndjson",
+            "--latlon",
+            "pos",
+            "--out-of-range",
+            "keep",
+            "-",
+        ]);
+        let (output, _) = encode_ndjson_str("{\"pos\":\"40.71,-74.0\"}\n", &args, false).unwrap();
+        assert_eq!(output, "{\"pos\":\"40.71,-74.0\",\"mesh_code\":null}\n");
+
+        // 既にあるキーは --force 指定時のみ上書きする
+        let input = "{\"lat\":35.68,\"lon\":139.76,\"mesh_code\":\"x\"}\n";
+        let ndjson_args = ["--format-in", "ndjson", "--lat", "lat", "--lon", "lon"];
+        let args = encode_args(&[&ndjson_args[..], &["-"]].concat());
+        assert!(encode_ndjson_str(input, &args, false).is_err());
+        let args = encode_args(&[&ndjson_args[..], &["--force", "-"]].concat());
+        let (output, _) = encode_ndjson_str(input, &args, false).unwrap();
+        assert_eq!(
+            output,
+            "{\"lat\":35.68,\"lon\":139.76,\"mesh_code\":\"53394610\"}\n"
+        );
+
+        let args = encode_args(&[&ndjson_args[..], &["--strict", "-"]].concat());
+        assert!(encode_ndjson_str("[1]\n", &args, false).is_err());
+    }
+
+    /// GeoJSONの入力文字列を処理し、出力 (GeoJSONか、`csv` の場合はCSV) を文字列で返す
+    fn encode_geojson_str(
+        input: &str,
+        args: &EncodeArgs,
+        csv: bool,
+    ) -> Result<(String, Summary), Box<dyn Error>> {
+        let mut writer = if csv {
+            ObjectWriter::csv(csv::Writer::from_writer(Vec::new()))
+        } else {
+            ObjectWriter::geojson(Vec::new())
+        };
+        let mut report = Report::default();
+        encode_geojson(
+            input.as_bytes(),
+            &mut writer,
+            args,
+            &mut Progress::hidden(),
+            &mut report,
+        )?;
+        writer.finish()?;
+        let output = String::from_utf8(writer.into_inner()).unwrap();
+        Ok((output, report.summary))
+    }
+
+    #[test]
+    fn test_geojson_input() {
+        let input = r#"{"type":"FeatureCollection","features":[
+{"type":"Feature","id":1,"properties":{"name":"東京","tags":["a"]},"geometry":{"type":"Point","coordinates":[139.76,35.68]}},
+{"type":"Feature","properties":{"name":"線"},"geometry":{"type":"LineString","coordinates":[[139.0,35.0],[140.0,36.0]]}},
+{"type":"Feature","properties":null,"geometry":null},
+{"type":"Feature","properties":{"name":"NY"},"geometry":{"type":"Point","coordinates":[-74.0,40.71]}},
+[1]
+]}"#;
+        let args = encode_args(&["--format-in", "geojson", "-"]);
+        assert_eq!(args.output_format(), OutputFormat::Geojson);
+        assert!(check_format(&args, &csv_args(None)).is_ok());
+        let (output, summary) = encode_geojson_str(input, &args, false).unwrap();
+        assert_eq!(
+            output,
+            "{\"type\":\"FeatureCollection\",\"features\":[\n{\"type\":\"Feature\",\"id\":1,\"geometry\":{\"type\":\"Point\",\"coordinates\":[139.76,35.68]},\"properties\":{\"name\":\"東京\",\"tags\":[\"a\"],\"mesh_code\":\"53394610\"}}\n]}\n"
+        );
+        assert_eq!(summary.read, 5);
+        assert_eq!(summary.written, 1);
+        assert_eq!(summary.skipped_by(SkipReason::NonPoint), 1);
+        assert_eq!(summary.skipped_by(SkipReason::EmptyCoord), 1);
+        assert_eq!(summary.skipped_by(SkipReason::OutOfRange), 1);
+        assert_eq!(summary.skipped_by(SkipReason::InvalidJson), 1);
+
+        // CSVでは properties のキーを列にし、Point以外は代表点を使える
+        let args = encode_args(&[
+            "--format-in",
+            "geojson",
+            "--non-point",
+            "bbox-center",
+            "--format",
+            "csv",
+            "-",
+        ]);
+        let (output, summary) = encode_geojson_str(input, &args, true).unwrap();
+        assert_eq!(
+            output,
+            "name,tags,mesh_code\n東京,\"[\"\"a\"\"]\",53394610\n線,,53392400\n"
+        );
+        assert_eq!(summary.skipped_by(SkipReason::NonPoint), 0);
+
+        let args = encode_args(&["--format-in", "geojson", "--strict", "-"]);
+        assert!(encode_geojson_str(input, &args, false).is_err());
+        let args = encode_args(&["--format-in", "geojson", "-"]);
+        assert!(encode_geojson_str("{\"type\":\"Feature\"}", &args, false).is_err());
+
+        // 座標はジオメトリから読むため、列の指定はできない
+        let args = encode_args(&["--format-in", "geojson", "--lat", "a", "--lon", "b", "-"]);
+        assert!(check_format(&args, &csv_args(None)).is_err());
+        let args = encode_args(&["--format-in", "geojson", "--format", "ndjson", "-"]);
+        assert!(check_format(&args, &csv_args(None)).is_err());
+        let args = encode_args(&["--format-in", "ndjson", "-"]);
+        assert!(check_format(&args, &csv_args(None)).is_err());
+    }
+
+    #[test]
+    fn test_check_format() {
+        assert!(
+            check_format(
+                &encode_args(&["--lat", "a", "--lon", "b", "--format", "ndjson", "-"]),
+                &csv_args(None)
+            )
+            .is_err()
+        );
+        let ndjson = ["--format-in", "ndjson", "--lat", "a", "--lon", "b"];
+        assert!(
+            check_format(
+                &encode_args(&[&ndjson[..], &["-"]].concat()),
+                &csv_args(None)
+            )
+            .is_ok()
+        );
+        assert!(
+            check_format(
+                &encode_args(&[&ndjson[..], &["--format", "csv", "-"]].concat()),
+                &csv_args(None)
+            )
+            .is_ok()
+        );
+        assert!(
+            check_format(
+                &encode_args(&[&ndjson[..], &["--format", "geojson", "-"]].concat()),
+                &csv_args(None)
+            )
+            .is_err()
+        );
+        assert!(
+            check_format(
+                &encode_args(&[&ndjson[..], &["--error-output", "e.csv", "-"]].concat()),
+                &csv_args(None)
+            )
+            .is_err()
+        );
+
+        let parquet = ["--lat", "a", "--lon", "b", "--format", "parquet", "-"];
+        assert!(check_format(&encode_args(&parquet), &csv_args(None)).is_ok());
+        let mut gzip = csv_args(None);
+        gzip.gzip = true;
+        assert!(check_format(&encode_args(&parquet), &gzip).is_err());
+        assert!(
+            check_format(
+                &encode_args(&[&ndjson[..], &["--format", "parquet", "-"]].concat()),
+                &csv_args(None)
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_decimal_comma() {
+        let input = "id,lat,lon\n1,\"35,68\",\"139,76\"\n2,35.68,139.76\n3,\"35,6,8\",\"139,76\"\n";
+        let args = encode_args(&["--lat", "lat", "--lon", "lon", "--decimal-comma", "-"]);
+        let (output, summary) = encode_str_with_summary(input, &args, 1).unwrap();
+        assert_eq!(
+            output,
+            "id,lat,lon,mesh_code\n1,\"35,68\",\"139,76\",53394610\n2,35.68,139.76,53394610\n"
+        );
+        assert_eq!(summary.skipped_by(SkipReason::InvalidLat), 1);
+
+        // 通常はカンマを小数点として読まない
+        let args = encode_args(&["--lat", "lat", "--lon", "lon", "-"]);
+        let (_, summary) = encode_str_with_summary(input, &args, 1).unwrap();
+        assert_eq!(summary.written, 1);
+
+        // 区切り文字にカンマを使う場合は指定できない
+        let args = encode_args(&["--lat", "lat", "--lon", "lon", "--decimal-comma", "-"]);
+        let err = check_format(&args, &csv_args(None)).unwrap_err();
+        assert!(err.to_string().contains("--delimiter"));
+        let mut semicolon = csv_args(None);
+        semicolon.delimiter = b';';
+        assert!(check_format(&args, &semicolon).is_ok());
+        let args = encode_args(&["--latlon", "pos", "--decimal-comma", "-"]);
+        assert!(check_format(&args, &semicolon).is_err());
+        let args = encode_args(&[
+            "--latlon",
+            "pos",
+            "--latlon-separator",
+            " ",
+            "--decimal-comma",
+            "-",
+        ]);
+        assert!(check_format(&args, &semicolon).is_ok());
+    }
+
+    #[test]
+    fn test_point() {
+        let codes = |args: &[&str]| {
+            let cli = Cli::try_parse_from(["meshify", "point"].iter().chain(args)).unwrap();
+            let Some(Command::Point(args)) = cli.command else {
+                unreachable!()
+            };
+            point_mesh_codes(&args)
+        };
+        assert_eq!(
+            codes(&["--lat", "35.68", "--lon", "139.76"]).unwrap(),
+            ["53394610"]
+        );
+        assert_eq!(
+            codes(&[
+                "--lat", "35.68", "--lon", "139.76", "-l", "first", "-l", "half"
+            ])
+            .unwrap(),
+            ["5339", "533946104"]
+        );
+        assert_eq!(
+            codes(&[
+                "--lat",
+                "35°40'48\"N",
+                "--lon",
+                "139°45'36\"E",
+                "--coord-format",
+                "dms"
+            ])
+            .unwrap(),
+            ["53394610"]
+        );
+        assert!(codes(&["--lat", "-33.87", "--lon", "151.21"]).is_err());
+        assert!(codes(&["--lat", "abc", "--lon", "139.76"]).is_err());
+    }
+
+    #[test]
+    fn test_explain() {
+        assert_eq!(
+            explain(35.68, 139.76, &mesh_components(35.68, 139.76)),
+            "世界測地系の座標: (35.68, 139.76)
+1次メッシュ: p=53 u=39 → 5339
+2次メッシュ: q=4 v=6 → 533946
+5倍地域メッシュ: 分割番号=1 → 5339461
+2倍地域メッシュ: 緯度方向=0 経度方向=0 → 533946005
+基準地域メッシュ: r=1 w=0 → 53394610
+2分の1地域メッシュ: m=4 → 533946104
+4分の1地域メッシュ: n=2 → 5339461042
+8分の1地域メッシュ: o=1 → 53394610421
+"
+        );
+    }
+
+    #[test]
+    fn test_z_column() {
+        let input =
+            "id,lat,lon,height\n1,35.68,139.76,40.5\n2,35.68,139.76,unknown\n3,35.68,139.76,\n";
+        let z_args = |invalid_z: &str| {
+            encode_args(&[
+                "--lat",
+                "lat",
+                "--lon",
+                "lon",
+                "--z",
+                "height",
+                "--invalid-z",
+                invalid_z,
+                "-",
+            ])
+        };
+        // Z値は計算に使わず、そのまま出力する
+        assert_eq!(
+            run_encode_str(input, &z_args("keep"), 1),
+            "id,lat,lon,height,mesh_code\n1,35.68,139.76,40.5,53394610\n2,35.68,139.76,unknown,53394610\n3,35.68,139.76,,53394610\n"
+        );
+
+        // 数値でない行は指定に応じてスキップするかエラーにする (空欄はそのまま出力する)
+        let (output, summary) = encode_str_with_summary(input, &z_args("skip"), 1).unwrap();
+        assert_eq!(
+            output,
+            "id,lat,lon,height,mesh_code\n1,35.68,139.76,40.5,53394610\n3,35.68,139.76,,53394610\n"
+        );
+        assert_eq!(summary.skipped_by(SkipReason::InvalidZ), 1);
+        let err = try_encode_str(input, &z_args("error"), 1).unwrap_err();
+        assert_eq!(err.to_string(), "3行目: Z値の値「unknown」が不正です");
+
+        let args = encode_args(&["--lat", "lat", "--lon", "lon", "--z", "missing", "-"]);
+        assert!(try_encode_str(input, &args, 1).is_err());
+    }
+
+    #[test]
+    fn test_keep_invalid() {
+        let input = "id,lat,lon
+1,35.68,139.76
+2,abc,139.76
+3,40.71,-74.0
+";
+        // 不正な行や範囲外の行もメッシュコードを空欄にして残し、入力と出力の行数をそろえる
+        let args = encode_args(&["--lat", "lat", "--lon", "lon", "--keep-invalid", "-"]);
+        let (output, summary) = encode_str_with_summary(input, &args, 1).unwrap();
+        assert_eq!(
+            output,
+            "id,lat,lon,mesh_code\n1,35.68,139.76,53394610\n2,abc,139.76,\n3,40.71,-74.0,\n"
+        );
+        assert_eq!(summary.written, 3);
+        assert_eq!(summary.invalid, 2);
+        assert_eq!(summary.skipped(), 0);
+
+        // --out-of-range error は従来通りエラーにする
+        let args = encode_args(&[
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "--keep-invalid",
+            "--out-of-range",
+            "error",
+            "-",
+        ]);
+        assert!(try_encode_str(input, &args, 1).is_err());
+
+        assert!(
+            Cli::try_parse_from([
+                "meshify",
+                "--lat",
+                "lat",
+                "--lon",
+                "lon",
+                "--keep-invalid",
+                "--strict",
+                "-"
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_multiple_pairs() {
+        let input = "id,origin_lat,origin_lon,dest_lat,dest_lon
+1,35.68,139.76,35.0,139.0
+2,abc,139.76,35.0,139.0
+3,35.68,139.76,40.71,-74.0
+4,x,139.76,y,139.0
+";
+        let pair_args = [
+            "--lat",
+            "origin_lat",
+            "--lon",
+            "origin_lon",
+            "--lat",
+            "dest_lat",
+            "--lon",
+            "dest_lon",
+        ];
+        let args = encode_args(&[&pair_args[..], &["--quiet", "-"]].concat());
+        let (output, summary) = encode_str_with_summary(input, &args, 1).unwrap();
+        // 列名は緯度の列名から導出し、不正な組だけを空欄にする。どの組も不正な行はスキップする
+        assert_eq!(
+            output,
+            "id,origin_lat,origin_lon,dest_lat,dest_lon,mesh_code_origin_lat,mesh_code_dest_lat
+1,35.68,139.76,35.0,139.0,53394610,52394000
+2,abc,139.76,35.0,139.0,,52394000
+3,35.68,139.76,40.71,-74.0,53394610,
+"
+        );
+        assert_eq!(summary.written, 3);
+        assert_eq!(summary.partial, 2);
+        assert_eq!(summary.skipped_by(SkipReason::InvalidLat), 1);
+
+        // サフィックスを組ごとに指定でき、中心座標も組ごとに出力する
+        let args = encode_args(
+            &[
+                &pair_args[..],
+                &[
+                    "--pair-suffix",
+                    "o",
+                    "--pair-suffix",
+                    "d",
+                    "--add-center",
+                    "--center-precision",
+                    "3",
+                    "--quiet",
+                    "-",
+                ],
+            ]
+            .concat(),
+        );
+        let output = run_encode_str(
+            "id,origin_lat,origin_lon,dest_lat,dest_lon\n1,35.68,139.76,abc,139.0\n",
+            &args,
+            1,
+        );
+        assert_eq!(
+            output.lines().collect::<Vec<&str>>(),
+            [
+                "id,origin_lat,origin_lon,dest_lat,dest_lon,mesh_code_o,mesh_code_d,mesh_center_lat_o,mesh_center_lon_o,mesh_center_lat_d,mesh_center_lon_d",
+                "1,35.68,139.76,abc,139.0,53394610,,35.679,139.756,,"
+            ]
+        );
+
+        // --strict の場合は一部の組が不正でもエラーにする
+        let args = encode_args(&[&pair_args[..], &["--strict", "-"]].concat());
+        assert!(try_encode_str(input, &args, 1).is_err());
+
+        let args = encode_args(&[&pair_args[..], &["--pair-suffix", "o", "-"]].concat());
+        assert!(try_encode_str(input, &args, 1).is_err());
+        let args = encode_args(&[
+            "--lat",
+            "origin_lat",
+            "--lat",
+            "dest_lat",
+            "--lon",
+            "origin_lon",
+            "-",
+        ]);
+        assert!(try_encode_str(input, &args, 1).is_err());
+    }
+
+    #[test]
+    fn test_mesh_column() {
+        let input = "id,lat,lon,mesh_code\n1,35.68,139.76,old\n";
+
+        // 列名に空白や全角を含んでも扱える
+        let args = encode_args(&[
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "--mesh-column",
+            "メッシュ コード",
+            "-",
+        ]);
+        assert_eq!(
+            run_encode_str(input, &args, 1),
+            "id,lat,lon,mesh_code,メッシュ コード\n1,35.68,139.76,old,53394610\n"
+        );
+
+        // 既存の列名と重複する場合は --force で上書きする
+        let args = encode_args(&["--lat", "lat", "--lon", "lon", "--force", "-"]);
+        assert_eq!(
+            run_encode_str(input, &args, 1),
+            "id,lat,lon,mesh_code\n1,35.68,139.76,53394610\n"
+        );
+
+        let args = encode_args(&["--lat", "lat", "--lon", "lon", "-"]);
+        let err = try_encode_str(input, &args, 1).unwrap_err();
+        assert!(err.to_string().contains("mesh_code"));
+    }
+
+    #[test]
+    fn test_mesh_column_position() {
+        let input = "id,lat,lon,name\n1,35.68,139.76,a\n";
+        let encode_at = |position: &str| {
+            let args = encode_args(&[
+                "--lat",
+                "lat",
+                "--lon",
+                "lon",
+                "--level",
+                "standard",
+                "--level",
+                "first",
+                "--mesh-column-position",
+                position,
+                "-",
+            ]);
+            try_encode_str(input, &args, 1)
+        };
+        assert_eq!(
+            encode_at("after:lon").unwrap(),
+            "id,lat,lon,mesh_code_standard,mesh_code_first,name\n1,35.68,139.76,53394610,5339,a\n"
+        );
+        assert_eq!(
+            encode_at("first").unwrap(),
+            "mesh_code_standard,mesh_code_first,id,lat,lon,name\n53394610,5339,1,35.68,139.76,a\n"
+        );
+        assert_eq!(
+            encode_at("4").unwrap(),
+            "id,lat,lon,name,mesh_code_standard,mesh_code_first\n1,35.68,139.76,a,53394610,5339\n"
+        );
+        let err = encode_at("5").unwrap_err().to_string();
+        assert!(err.contains("列数 (4) を超えています"), "{}", err);
+        let err = encode_at("after:latitude").unwrap_err().to_string();
+        assert!(err.contains("latitude"), "{}", err);
+        assert!(parse_column_position("last").is_err());
+
+        // 上書きする既存の列は元の位置のまま
+        let args = encode_args(&[
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "--force",
+            "--add-center",
+            "--mesh-column-position",
+            "first",
+            "-",
+        ]);
+        assert_eq!(
+            run_encode_str("lat,mesh_code,lon\n35.68,x,139.76\n", &args, 1),
+            "mesh_center_lat,mesh_center_lon,lat,mesh_code,lon\n35.679166666666674,139.75625,35.68,53394610,139.76\n"
+        );
+    }
+
+    #[test]
+    fn test_multiple_levels() {
+        let input = "id,lat,lon\n1,35.68,139.76\n";
+
+        // 重複して指定したレベルは無視する
+        let args = encode_args(&[
+            "--lat", "lat", "--lon", "lon", "--level", "standard", "--level", "half", "--level",
+            "standard", "-",
+        ]);
+        assert_eq!(
+            run_encode_str(input, &args, 1),
+            "id,lat,lon,mesh_code_standard,mesh_code_half\n1,35.68,139.76,53394610,533946104\n"
+        );
+
+        assert_eq!(
+            mesh_column_names("m", &[MeshLevel::FiveFold, MeshLevel::TwoFold]),
+            vec!["m_five_fold", "m_two_fold"]
+        );
+        assert_eq!(
+            mesh_column_names("m", &unique_levels(&[MeshLevel::Half, MeshLevel::Half])),
+            vec!["m"]
+        );
+    }
+
+    #[test]
+    fn test_geometry_column() {
+        let input = "id,lat,lon\n1,35.68,139.76\n";
+        let args = encode_args(&[
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "--level",
+            "first",
+            "--geometry",
+            "wkt",
+            "--geometry-precision",
+            "2",
+            "-",
+        ]);
+        assert_eq!(
+            run_encode_str(input, &args, 1),
+            "id,lat,lon,mesh_code,mesh_geometry\n1,35.68,139.76,5339,\"POLYGON((139.00 35.33, 140.00 35.33, 140.00 36.00, 139.00 36.00, 139.00 35.33))\"\n"
+        );
+
+        let args = encode_args(&[
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "--level",
+            "first",
+            "--level",
+            "second",
+            "--geometry",
+            "wkt",
+            "-",
+        ]);
+        let output = run_encode_str(input, &args, 1);
+        assert!(output.starts_with(
+            "id,lat,lon,mesh_code_first,mesh_code_second,mesh_geometry_first,mesh_geometry_second\n"
+        ));
+    }
+
+    #[test]
+    fn test_column_index() {
+        let input = "a,b,c\n1,139.76,35.68\n";
+        let args = encode_args(&["--lat-index", "2", "--lon-index", "1", "-"]);
+        assert_eq!(
+            run_encode_str(input, &args, 1),
+            "a,b,c,mesh_code\n1,139.76,35.68,53394610\n"
+        );
+
+        let args = encode_args(&["--lat-index", "3", "--lon-index", "1", "-"]);
+        let err = try_encode_str(input, &args, 1).unwrap_err();
+        assert!(err.to_string().contains("--lat-index"));
+
+        let parse = |args: &[&str]| Cli::try_parse_from(["meshify"].iter().chain(args));
+        assert!(parse(&["--lat-index", "0", "-"]).is_err());
+        assert!(
+            parse(&[
+                "--lat-index",
+                "0",
+                "--lon-index",
+                "1",
+                "--lat",
+                "a",
+                "--lon",
+                "b",
+                "-"
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_source_crs() {
+        assert!(parse_source_crs("EPSG:2451").is_ok());
+        // PROJが解釈できないCRSは、変換器を作る際にエラーになる
+        let args = encode_args(&["--lat", "y", "--lon", "x", "--source-crs", "EPSG:abc", "-"]);
+        assert!(input_converter(&args).is_err());
+
+        let parse = |args: &[&str]| Cli::try_parse_from(["meshify"].iter().chain(args));
+        assert!(
+            parse(&[
+                "--lat",
+                "x",
+                "--lon",
+                "y",
+                "--source-crs",
+                "EPSG:2451",
+                "--datum",
+                "jgs",
+                "-"
+            ])
+            .is_err()
+        );
+
+        // --swap-xy では --lat 列の値を経度、--lon 列の値を緯度として読む
+        let input = "x,y\n139.76,35.68\n";
+        let args = encode_args(&["--lat", "x", "--lon", "y", "--swap-xy", "-"]);
+        assert_eq!(
+            run_encode_str(input, &args, 1),
+            "x,y,mesh_code\n139.76,35.68,53394610\n"
+        );
+    }
+
+    #[test]
+    fn test_datum_column() {
+        let parse = |args: &[&str]| Cli::try_parse_from(["meshify"].iter().chain(args));
+        let base = ["--lat", "lat", "--lon", "lon", "--datum-column", "datum"];
+        assert!(parse(&[&base[..], &["--datum", "jgs", "-"]].concat()).is_err());
+        assert!(
+            parse(&[
+                "--lat",
+                "lat",
+                "--lon",
+                "lon",
+                "--default-datum",
+                "wgs",
+                "-"
+            ])
+            .is_err()
+        );
+
+        // 値が不正な行は、--default-datum が無ければスキップする
+        let input = "id,lat,lon,datum\n1,35.68,139.76,wgs\n2,35.68,139.76, JGS \n3,35.68,139.76,\n";
+        let args = encode_args(&[&base[..], &["-"]].concat());
+        let (output, summary) = encode_str_with_summary(input, &args, 1).unwrap();
+        assert_eq!(
+            output,
+            "id,lat,lon,datum,mesh_code\n1,35.68,139.76,wgs,53394610\n2,35.68,139.76, JGS ,53394610\n"
+        );
+        assert_eq!(summary.skipped_by(SkipReason::InvalidDatum), 1);
+
+        let args = encode_args(&[&base[..], &["--default-datum", "wgs", "-"]].concat());
+        let output = run_encode_str(input, &args, 1);
+        assert!(output.ends_with("3,35.68,139.76,,53394610\n"));
+
+        let err = try_encode_str(
+            input,
+            &encode_args(&["--lat", "lat", "--lon", "lon", "--datum-column", "crs", "-"]),
+            1,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("crs"));
+
+        // 変換器は、その測地系の行が初めて現れた際に作る
+        let converter = input_converter(&encode_args(&[&base[..], &["-"]].concat())).unwrap();
+        let created = || match &converter {
+            InputConverter::PerRow { converters, .. } => converters
+                .iter()
+                .map(|cell| cell.get().is_some())
+                .collect::<Vec<bool>>(),
+            InputConverter::Single(_) => unreachable!(),
+        };
+        assert_eq!(created(), [false, false, false]);
+        assert!(converter.select(Some("wgs")).unwrap().is_some());
+        assert!(converter.select(Some("unknown")).unwrap().is_none());
+        assert_eq!(created(), [true, false, false]);
+        assert!(converter.select(Some("jgs")).unwrap().is_some());
+        assert_eq!(created(), [true, true, false]);
+    }
+
+    #[test]
+    fn test_plane_crs() {
+        assert_eq!(parse_source_crs("plane:VIII").unwrap(), "EPSG:6676");
+        assert_eq!(parse_source_crs("plane:19").unwrap(), "EPSG:6687");
+        assert!(parse_source_crs("plane:XX").is_err());
+        assert_eq!(parse_plane_auto("35.68, 139.76"), Ok(9));
+        assert!(parse_plane_auto("35.68").is_err());
+        assert!(parse_plane_auto("0,0").is_err());
+
+        let args = encode_args(&[
+            "--lat",
+            "x",
+            "--lon",
+            "y",
+            "--plane-auto",
+            "35.68,139.76",
+            "-",
+        ]);
+        assert_eq!(args.plane_auto, Some(9));
+        let parse = |args: &[&str]| Cli::try_parse_from(["meshify"].iter().chain(args));
+        assert!(
+            parse(&[
+                "--lat",
+                "x",
+                "--lon",
+                "y",
+                "--plane-auto",
+                "35,139",
+                "--source-crs",
+                "plane:IX",
+                "-"
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_utm_crs() {
+        // 本州をまたぐ 53N (西日本) と 54N (東日本) のどちらも指定できる
+        assert_eq!(parse_source_crs("utm:53N").unwrap(), "EPSG:32653");
+        assert_eq!(parse_source_crs("utm:54").unwrap(), "EPSG:32654");
+        assert_eq!(parse_source_crs("EPSG:32654").unwrap(), "EPSG:32654");
+        assert!(parse_source_crs("utm:61N").is_err());
+        assert!(parse_source_crs("utm:").is_err());
+
+        // 北距 (Y) を --lat、東距 (X) を --lon の列に指定する
+        let args = encode_args(&[
+            "--lat",
+            "northing",
+            "--lon",
+            "easting",
+            "--source-crs",
+            "utm:54N",
+            "-",
+        ]);
+        assert_eq!(args.source_crs.as_deref(), Some("EPSG:32654"));
+    }
+
+    #[test]
+    fn test_webmercator_columns() {
+        let input = "id,lat,lon\n1,35.68,139.76\n2,abc,139.76\n";
+        let args = encode_args(&[
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "--add-webmercator",
+            "--webmercator-precision",
+            "2",
+            "--keep-invalid",
+            "-",
+        ]);
+        let output = run_encode_str(input, &args, 1);
+        let mut reader = csv::Reader::from_reader(output.as_bytes());
+        assert_eq!(
+            reader.headers().unwrap(),
+            vec![
+                "id",
+                "lat",
+                "lon",
+                "mesh_code",
+                "webmercator_x",
+                "webmercator_y"
+            ]
+        );
+        let records = reader.records().collect::<Result<Vec<_>, _>>().unwrap();
+        // 桁数の指定どおりに丸める
+        assert_eq!(records[0][4].split_once('.').unwrap().1.len(), 2);
+        assert!(records[0][5].parse::<f64>().is_ok());
+        // 座標を求められない行は空欄にする
+        assert_eq!(&records[1][4], "");
+        assert_eq!(&records[1][5], "");
+    }
+
+    #[test]
+    fn test_prefecture_column() {
+        let input = "id,lat,lon\n1,43.06,141.35\n2,24.34,124.16\n3,abc,139.76\n";
+        let args = encode_args(&[
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "--add-prefecture",
+            "--keep-invalid",
+            "-",
+        ]);
+        assert_eq!(
+            run_encode_str(input, &args, 1),
+            "id,lat,lon,mesh_code,prefecture\n1,43.06,141.35,64414278,北海道\n2,24.34,124.16,36244102,沖縄県\n3,abc,139.76,,\n"
+        );
+
+        // 1次メッシュが複数の都道府県にまたがる場合は、候補を都道府県コード順に並べる
+        let args = encode_args(&[
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "--level",
+            "first",
+            "--add-prefecture",
+            "--prefecture-format",
+            "code",
+            "-",
+        ]);
+        let output = run_encode_str("lat,lon\n35.68,139.76\n", &args, 1);
+        let codes = output.lines().nth(1).unwrap().rsplit(',').next().unwrap();
+        let codes = codes.split(';').collect::<Vec<&str>>();
+        assert!(codes.contains(&"13") && codes.contains(&"14"));
+        assert!(codes.is_sorted());
+
+        assert!(Cli::try_parse_from(["meshify", "--prefecture-format", "code"]).is_err());
+    }
+
+    #[test]
+    fn test_dms_coord_format() {
+        let input = "id,lat,lon\n1,35°40'48\"N,139°45'36\"E\n2,北緯35度,abc\n";
+        let args = encode_args(&["--lat", "lat", "--lon", "lon", "--coord-format", "dms", "-"]);
+        assert_eq!(
+            run_encode_str(input, &args, 1),
+            "id,lat,lon,mesh_code\n1,\"35°40'48\"\"N\",\"139°45'36\"\"E\",53394610\n"
+        );
+    }
+
+    #[test]
+    fn test_area_column() {
+        let input = "id,lat,lon\n1,35.68,139.76\n";
+        let args = encode_args(&["--lat", "lat", "--lon", "lon", "--add-area", "-"]);
+        let output = run_encode_str(input, &args, 1);
+        let mut reader = csv::Reader::from_reader(output.as_bytes());
+        assert_eq!(
+            reader.headers().unwrap(),
+            vec!["id", "lat", "lon", "mesh_code", "mesh_area_km2"]
+        );
+        let record = reader.records().next().unwrap().unwrap();
+        let area: f64 = record[4].parse().unwrap();
+        assert!((area - mesh_area_km2("53394610").unwrap()).abs() < 1e-12);
+
+        // --precision は面積にも四捨五入で適用する
+        let args = encode_args(&[
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "--add-area",
+            "--precision",
+            "3",
+            "-",
+        ]);
+        let output = run_encode_str("lat,lon\n35.68,139.76\n", &args, 1);
+        let area = format!("{:.3}", mesh_area_km2("53394610").unwrap());
+        assert_eq!(
+            output,
+            format!(
+                "lat,lon,mesh_code,mesh_area_km2\n35.68,139.76,53394610,{}\n",
+                area
+            )
+        );
+    }
+
+    #[test]
+    fn test_keep_and_drop_columns() {
+        let input = "id,name,lat,lon,memo\n1,東京,35.68,139.76,x\n";
+        let coords = ["--lat", "lat", "--lon", "lon"];
+        let run = |options: &[&str]| {
+            let args = encode_args(&[&coords[..], options, &["-"]].concat());
+            try_encode_str(input, &args, 1)
+        };
+        // 指定の順に並べ、追加する列は後ろに置く
+        assert_eq!(
+            run(&[
+                "--keep-columns",
+                "name,id",
+                "--add-area",
+                "--precision",
+                "1"
+            ])
+            .unwrap(),
+            "name,id,mesh_code,mesh_area_km2\n東京,1,53394610,1.0\n"
+        );
+        assert_eq!(
+            run(&["--keep-columns", "lon", "--keep-columns", "lat"]).unwrap(),
+            "lon,lat,mesh_code\n139.76,35.68,53394610\n"
+        );
+        assert_eq!(
+            run(&["--drop-columns", "memo,name", "--mesh-column-position", "1"]).unwrap(),
+            "id,mesh_code,lat,lon\n1,53394610,35.68,139.76\n"
+        );
+
+        // --force で上書きする列は、--keep-columns に無くても残す
+        let input = "id,mesh_code,lat,lon\n1,x,35.68,139.76\n";
+        let args = encode_args(&[&coords[..], &["--keep-columns", "id", "--force", "-"]].concat());
+        assert_eq!(
+            run_encode_str(input, &args, 1),
+            "id,mesh_code\n1,53394610\n"
+        );
+
+        let err = run(&["--keep-columns", "id,missing"]).unwrap_err();
+        assert!(err.to_string().contains("「missing」"), "{}", err);
+        assert!(run(&["--drop-columns", "missing"]).is_err());
+        assert!(
+            Cli::try_parse_from([
+                "meshify",
+                "--lat",
+                "a",
+                "--lon",
+                "b",
+                "--keep-columns",
+                "a",
+                "--drop-columns",
+                "b",
+                "-"
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_center_distance_column() {
+        let input = "id,lat,lon\n1,35.68,139.76\n2,35.6792,139.7563\n";
+        let args = encode_args(&[
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "--level",
+            "standard",
+            "--level",
+            "first",
+            "--add-center-distance",
+            "-",
+        ]);
+        let output = run_encode_str(input, &args, 1);
+        let mut reader = csv::Reader::from_reader(output.as_bytes());
+        assert_eq!(
+            reader.headers().unwrap(),
+            vec![
+                "id",
+                "lat",
+                "lon",
+                "mesh_code_standard",
+                "mesh_code_first",
+                "mesh_center_distance_m_standard",
+                "mesh_center_distance_m_first"
+            ]
+        );
+        let records = reader.records().map(Result::unwrap).collect::<Vec<_>>();
+        for (record, (lat, lon)) in records.iter().zip([(35.68, 139.76), (35.6792, 139.7563)]) {
+            for (code, distance) in [(&record[3], &record[5]), (&record[4], &record[6])] {
+                let (center_lat, center_lon) = mesh_code_to_center(code).unwrap();
+                let expected = haversine_distance_m(lat, lon, center_lat, center_lon);
+                assert!((distance.parse::<f64>().unwrap() - expected).abs() < 1e-6);
+            }
+        }
+        // 基準地域メッシュの中心からの距離は、対角線の半分 (約600m) を超えない
+        let distance: f64 = records[0][5].parse().unwrap();
+        assert!((0.0..600.0).contains(&distance), "{}", distance);
+        let distance: f64 = records[1][5].parse().unwrap();
+        assert!(distance < 10.0, "{}", distance);
+
+        // 日本測地系の入力は、世界測地系に変換した座標からの距離にする
+        let args = encode_args(&[
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "--datum",
+            "jgs",
+            "--add-center-distance",
+            "--precision",
+            "3",
+            "-",
+        ]);
+        let (lat, lon) = DatumConverter::new(Datum::JGS)
+            .unwrap()
+            .to_wgs(35.68, 139.76)
+            .unwrap();
+        let code = get_mesh_code(lat, lon, MeshLevel::Standard);
+        let (center_lat, center_lon) = mesh_code_to_center(&code).unwrap();
+        assert_eq!(
+            run_encode_str("lat,lon\n35.68,139.76\n", &args, 1),
+            format!(
+                "lat,lon,mesh_code,mesh_center_distance_m\n35.68,139.76,{},{}\n",
+                code,
+                format_decimal(
+                    haversine_distance_m(lat, lon, center_lat, center_lon),
+                    Some(3)
+                )
+            )
+        );
+    }
+
+    #[test]
+    fn test_geohash_column() {
+        let input = "id,lat,lon\n1,35.68,139.76\n";
+        let args = encode_args(&["--lat", "lat", "--lon", "lon", "--add-geohash", "-"]);
+        assert_eq!(
+            run_encode_str(input, &args, 1),
+            format!(
+                "id,lat,lon,mesh_code,geohash\n1,35.68,139.76,53394610,{}\n",
+                mesh_to_geohash("53394610", 7).unwrap()
+            )
+        );
+        let args = encode_args(&[
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "--add-geohash",
+            "--geohash-precision",
+            "4",
+            "-",
+        ]);
+        assert_eq!(
+            run_encode_str(input, &args, 1),
+            "id,lat,lon,mesh_code,geohash\n1,35.68,139.76,53394610,xn76\n"
+        );
+        for precision in ["0", "13"] {
+            assert!(
+                Cli::try_parse_from([
+                    "meshify",
+                    "--lat",
+                    "a",
+                    "--lon",
+                    "b",
+                    "--add-geohash",
+                    "--geohash-precision",
+                    precision,
+                    "-"
+                ])
+                .is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn test_center_columns() {
+        let input = "id,lat,lon\n1,35.68,139.76\n2,-33.87,151.21\n";
+        let args = encode_args(&[
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "--add-center",
+            "--center-precision",
+            "4",
+            "--out-of-range",
+            "keep",
+            "-",
+        ]);
+        assert_eq!(
+            run_encode_str(input, &args, 1),
+            "id,lat,lon,mesh_code,mesh_center_lat,mesh_center_lon\n1,35.68,139.76,53394610,35.6792,139.7563\n2,-33.87,151.21,,,\n"
+        );
+
+        let args = encode_args(&[
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "--level",
+            "first",
+            "--level",
+            "second",
+            "--add-center",
+            "--center-datum",
+            "input",
+            "-",
+        ]);
+        let output = run_encode_str(input, &args, 1);
+        let mut reader = csv::Reader::from_reader(output.as_bytes());
+        assert_eq!(
+            reader.headers().unwrap(),
+            vec![
+                "id",
+                "lat",
+                "lon",
+                "mesh_code_first",
+                "mesh_code_second",
+                "mesh_center_lat_first",
+                "mesh_center_lon_first",
+                "mesh_center_lat_second",
+                "mesh_center_lon_second"
+            ]
+        );
+        let record = reader.records().next().unwrap().unwrap();
+        let center_lat: f64 = record[5].parse().unwrap();
+        let center_lon: f64 = record[6].parse().unwrap();
+        assert!((center_lat - (35.0 + 40.0 / 60.0)).abs() < 1e-9);
+        assert!((center_lon - 139.5).abs() < 1e-9);
+
+        // same は input と同じ
+        let same = encode_args(&[
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "--add-center",
+            "--center-datum",
+            "same",
+            "-",
+        ]);
+        assert_eq!(same.center_datum, CenterDatum::Input);
+    }
+
+    #[test]
+    fn test_latlon_column() {
+        let input = "id,pos\n1,\"35.68,139.76\"\n2,35.68\n3,\"35.68,139.76,0\"\n";
+        let args = encode_args(&["--latlon", "pos", "-"]);
+        assert_eq!(
+            run_encode_str(input, &args, 1),
+            "id,pos,mesh_code\n1,\"35.68,139.76\",53394610\n"
+        );
+
+        let input = "id,pos\n1,35.68 / 139.76\n";
+        let args = encode_args(&["--latlon", "pos", "--latlon-separator", "/", "-"]);
+        assert_eq!(
+            run_encode_str(input, &args, 1),
+            "id,pos,mesh_code\n1,35.68 / 139.76,53394610\n"
+        );
+
+        // --latlon と --lat/--lon は排他で、どちらか一方が必須
+        let parse = |args: &[&str]| Cli::try_parse_from(["meshify"].iter().chain(args));
+        assert!(parse(&["--latlon", "pos", "--lat", "lat", "--lon", "lon", "-"]).is_err());
+        assert!(parse(&["--lat", "lat", "-"]).is_err());
+        assert!(parse(&["-"]).is_err());
+    }
+
+    #[test]
+    fn test_combine_inputs() {
+        let dir = std::env::temp_dir().join(format!("meshify_combine_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("2024-01.csv"), "id,lat,lon\n1,35.68,139.76\n").unwrap();
+        fs::write(dir.join("2024-02.csv"), "id,lat,lon\n2,35.68,139.76\n").unwrap();
+
+        let pool = rayon::ThreadPoolBuilder::new().build().unwrap();
+        let csv_args = csv_args(None);
+        let output = dir.join("out.csv");
+        let args = encode_args(&["--lat", "lat", "--lon", "lon", "--force", "-"]);
+
+        // globで展開した入力のヘッダーは先頭の1回だけ出力する
+        let inputs = expand_inputs(&[dir.join("2024-*.csv")]).unwrap();
+        assert_eq!(inputs.len(), 2);
+        encode_files(
+            &inputs,
+            Some(&output),
+            &args,
+            &csv_args,
+            &pool,
+            &mut Progress::hidden(),
+            &mut Report::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(&output).unwrap(),
+            "id,lat,lon,mesh_code\n1,35.68,139.76,53394610\n2,35.68,139.76,53394610\n"
+        );
+
+        // --unique-mesh は結合した入力をまたいで重複を除き、--unique-order code で並べ替える
+        fs::write(
+            dir.join("2024-02.csv"),
+            "id,lat,lon\n2,35.0,139.0\n3,35.68,139.76\n",
+        )
+        .unwrap();
+        let unique_args = encode_args(&[
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "--force",
+            "--unique-mesh",
+            "--unique-order",
+            "code",
+            "-",
+        ]);
+        let mut report = Report::default();
+        encode_files(
+            &inputs,
+            Some(&output),
+            &unique_args,
+            &csv_args,
+            &pool,
+            &mut Progress::hidden(),
+            &mut report,
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(&output).unwrap(),
+            "id,lat,lon,mesh_code\n2,35.0,139.0,52394000\n1,35.68,139.76,53394610\n"
+        );
+        assert_eq!(report.summary.duplicates, 1);
+
+        // ヘッダーが一致しないファイルは結合できない
+        fs::write(dir.join("2024-03.csv"), "id,lon,lat\n3,139.76,35.68\n").unwrap();
+        let inputs = expand_inputs(&[dir.join("2024-*.csv")]).unwrap();
+        let err = encode_files(
+            &inputs,
+            Some(&output),
+            &args,
+            &csv_args,
+            &pool,
+            &mut Progress::hidden(),
+            &mut Report::default(),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("2024-03.csv"));
+
+        assert!(expand_inputs(&[dir.join("1999-*.csv")]).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_open_input_error() {
+        let dir = std::env::temp_dir().join(format!("meshify_open_input_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let message = |path: &Path| match open_input(path) {
+            Ok(_) => panic!("{} を開けてしまった", path.display()),
+            Err(e) => e.to_string(),
+        };
+        assert!(message(&dir.join("missing.csv")).starts_with("入力ファイルが見つかりません: "));
+        assert!(message(&dir).starts_with("入力にディレクトリが指定されています: "));
+
+        // 処理を始める前に、すべての入力を確かめる
+        let input = dir.join("points.csv");
+        fs::write(&input, "id,lat,lon\n1,35.68,139.76\n").unwrap();
+        let missing = dir.join("missing.csv");
+        let args = encode_args(&[
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "-q",
+            input.to_str().unwrap(),
+            missing.to_str().unwrap(),
+        ]);
+        let err = run_encode(args, &csv_args(None)).unwrap_err();
+        assert!(err.to_string().contains("missing.csv"));
+        assert!(!dir.join("points_mesh.csv").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_overwrite_protection() {
+        let dir = std::env::temp_dir().join(format!("meshify_overwrite_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("points.csv");
+        fs::write(&input, "id,lat,lon\n1,35.68,139.76\n").unwrap();
+        let input = input.to_str().unwrap();
+        let output = dir.join("points_mesh.csv");
+        fs::write(&output, "important\n").unwrap();
+
+        // 既存の出力先 (自動で決めた出力先を含む) は上書きせず、処理を始める前に止める
+        let args = encode_args(&["--lat", "lat", "--lon", "lon", "-q", input]);
+        let err = run_encode(args, &csv_args(None)).unwrap_err();
+        assert!(err.to_string().contains("--force"));
+        assert_eq!(fs::read_to_string(&output).unwrap(), "important\n");
+
+        let args = encode_args(&["--lat", "lat", "--lon", "lon", "-q", "--force", input]);
+        run_encode(args, &csv_args(None)).unwrap();
+        assert_eq!(
+            fs::read_to_string(&output).unwrap(),
+            "id,lat,lon,mesh_code\n1,35.68,139.76,53394610\n"
+        );
+
+        // 出力を開く時点でも確かめる
+        assert!(open_output(Some(&output), false).is_err());
+        assert!(open_output(Some(&output), true).is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_resume_from() {
+        let dir = std::env::temp_dir().join(format!("meshify_resume_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("points.csv");
+        fs::write(
+            &input,
+            "id,lat,lon\n1,35.68,139.76\n2,abc,139.76\n3,35.68,139.77\n",
+        )
+        .unwrap();
+        let input = input.to_str().unwrap();
+        let output = dir.join("points_mesh.csv");
+        let resume = |line: &str, extra: &[&str]| {
+            let mut options = vec!["--lat", "lat", "--lon", "lon", "-q", "--resume-from", line];
+            options.extend(extra);
+            options.push(input);
+            run_encode(encode_args(&options), &csv_args(None))
+        };
+
+        // 4行目から再開し、見出し行を書かずに続きを追記する
+        fs::write(&output, "id,lat,lon,mesh_code\n1,35.68,139.76,53394610\n").unwrap();
+        resume("4", &[]).unwrap();
+        assert_eq!(
+            fs::read_to_string(&output).unwrap(),
+            "id,lat,lon,mesh_code\n1,35.68,139.76,53394610\n3,35.68,139.77,53394611\n"
+        );
+
+        // すべての行を出力する場合は、出力済みの行数が再開位置と一致するかを確かめる
+        fs::write(&output, "id,lat,lon,mesh_code\n1,35.68,139.76,53394610\n").unwrap();
+        let err = resume("4", &["--keep-invalid"]).unwrap_err();
+        assert!(err.to_string().contains("出力済みの行数"));
+        let err = resume("2", &[]).unwrap_err();
+        assert!(err.to_string().contains("出力済みの行数"));
+        // 列が前回と異なる場合も追記しない
+        let err = resume("4", &["--add-area"]).unwrap_err();
+        assert!(err.to_string().contains("見出し行"));
+        assert_eq!(
+            fs::read_to_string(&output).unwrap(),
+            "id,lat,lon,mesh_code\n1,35.68,139.76,53394610\n"
+        );
+
+        // 追記先が無い場合や、書きかけの行が残っている場合
+        fs::remove_file(&output).unwrap();
+        assert!(resume("4", &[]).is_err());
+        fs::write(&output, "id,lat,lon,mesh_code\n1,35.68,13").unwrap();
+        let err = resume("4", &[]).unwrap_err();
+        assert!(err.to_string().contains("改行"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(
+            Cli::try_parse_from(["meshify", "--resume-from", "3", "--skip", "1", "in.csv"])
+                .is_err()
+        );
+        assert!(Cli::try_parse_from(["meshify", "--resume-from", "0", "in.csv"]).is_err());
+    }
+
+    #[test]
+    fn test_fail_on_skip() {
+        let dir = std::env::temp_dir().join(format!("meshify_fail_on_skip_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("points.csv");
+        fs::write(&input, "id,lat,lon\n1,35.68,139.76\n2,abc,139.76\n").unwrap();
+        let input = input.to_str().unwrap();
+        let output = dir.join("out.csv");
+        let output = output.to_str().unwrap();
+
+        let args = encode_args(&["--lat", "lat", "--lon", "lon", "-q", "-o", output, input]);
+        assert!(run_encode(args, &csv_args(None)).is_ok());
+
+        let args = encode_args(&[
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "-q",
+            "--fail-on-skip",
+            "--force",
+            "-o",
+            output,
+            input,
+        ]);
+        let err = run_encode(args, &csv_args(None)).unwrap_err();
+        assert!(err.to_string().contains("1行をスキップ"));
+        // スキップしなかった行は出力されている
+        assert_eq!(
+            fs::read_to_string(output).unwrap(),
+            "id,lat,lon,mesh_code\n1,35.68,139.76,53394610\n"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_xlsx_input() {
+        let dir = std::env::temp_dir().join(format!("meshify_xlsx_input_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("points.xlsx");
+        let mut workbook = rust_xlsxwriter::Workbook::new();
+        let sheet = workbook.add_worksheet();
+        for (col, name) in ["id", "lat", "lon"].into_iter().enumerate() {
+            sheet.write_string(0, col as u16, name).unwrap();
+        }
+        // 緯度経度の列に数値のセルと文字列のセルが混在していても読める
+        sheet.write_number(1, 0, 1).unwrap();
+        sheet.write_number(1, 1, 35.68).unwrap();
+        sheet.write_number(1, 2, 139.76).unwrap();
+        sheet.write_string(2, 0, "2").unwrap();
+        sheet.write_string(2, 1, "35.68").unwrap();
+        sheet.write_number(2, 2, 139.76).unwrap();
+        sheet.write_number(3, 0, 3).unwrap();
+        sheet.write_string(3, 1, "abc").unwrap();
+        sheet.write_number(3, 2, 139.76).unwrap();
+        let other = workbook.add_worksheet().set_name("その他").unwrap();
+        other.write_string(0, 0, "lat").unwrap();
+        other.write_string(0, 1, "lon").unwrap();
+        other.write_number(1, 0, 35.0).unwrap();
+        other.write_number(1, 1, 139.0).unwrap();
+        workbook.save(&input).unwrap();
+        let input = input.to_str().unwrap();
+        let output = dir.join("out.csv");
+        let output = output.to_str().unwrap();
+
+        let args = encode_args(&[
+            "--lat", "lat", "--lon", "lon", "-q", "--strict", "-o", output, input,
+        ]);
+        // エラーの行番号はExcelの行番号と一致する
+        let err = run_encode(args, &csv_args(None)).unwrap_err();
+        assert_eq!(err.to_string(), "4行目: 緯度の値「abc」が不正です");
+
+        let args = encode_args(&[
+            "--lat", "lat", "--lon", "lon", "-q", "--force", "-o", output, input,
+        ]);
+        run_encode(args, &csv_args(None)).unwrap();
+        assert_eq!(
+            fs::read_to_string(output).unwrap(),
+            "id,lat,lon,mesh_code\n1,35.68,139.76,53394610\n2,35.68,139.76,53394610\n"
+        );
+
+        let mut sheet_args = csv_args(None);
+        sheet_args.sheet = Some("その他".to_string());
+        let args = encode_args(&[
+            "--lat", "lat", "--lon", "lon", "-q", "--force", "-o", output, input,
+        ]);
+        run_encode(args, &sheet_args).unwrap();
+        assert_eq!(
+            fs::read_to_string(output).unwrap(),
+            "lat,lon,mesh_code\n35,139,52394000\n"
+        );
+
+        sheet_args.sheet = Some("missing".to_string());
+        let args = encode_args(&[
+            "--lat", "lat", "--lon", "lon", "-q", "--force", "-o", output, input,
+        ]);
+        let err = run_encode(args, &sheet_args).unwrap_err();
+        assert!(err.to_string().contains("シート「missing」がありません"));
+
+        // ヘッダーが最初のシートと異なる「その他」は読み飛ばす
+        let mut all_sheets = csv_args(None);
+        all_sheets.all_sheets = true;
+        let args = encode_args(&[
+            "--lat", "lat", "--lon", "lon", "-q", "--force", "-o", output, input,
+        ]);
+        run_encode(args, &all_sheets).unwrap();
+        assert_eq!(
+            fs::read_to_string(output).unwrap(),
+            "id,lat,lon,sheet,mesh_code\n1,35.68,139.76,Sheet1,53394610\n2,35.68,139.76,Sheet1,53394610\n"
+        );
+        assert!(
+            Cli::try_parse_from(["meshify", "--all-sheets", "--sheet", "その他", input]).is_err()
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_stats_json() {
+        let dir = std::env::temp_dir().join(format!("meshify_stats_json_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("points.csv");
+        fs::write(&input, "id,lat,lon\n1,35.68,139.76\n2,abc,139.76\n").unwrap();
+        let input = input.to_str().unwrap();
+        let output = dir.join("out.csv");
+        let output = output.to_str().unwrap();
+        let stats = dir.join("stats.json");
+        let stats = stats.to_str().unwrap();
+        let read_stats =
+            || -> Value { serde_json::from_str(&fs::read_to_string(stats).unwrap()).unwrap() };
+
+        let args = encode_args(&[
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "-q",
+            "-o",
+            output,
+            "--stats-json",
+            stats,
+            input,
+        ]);
+        run_encode(args, &csv_args(None)).unwrap();
+        let json = read_stats();
+        assert_eq!(json["status"], "ok");
+        assert_eq!(json["error"], Value::Null);
+        assert_eq!(
+            (json["read"].as_u64(), json["written"].as_u64()),
+            (Some(2), Some(1))
+        );
+        assert_eq!(json["skipped_by_reason"]["invalid_lat"], 1);
+        assert!(json["elapsed_seconds"].as_f64().unwrap() >= 0.0);
+
+        // 既存の統計ファイルは --force の場合のみ上書きする
+        let args = encode_args(&[
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "-q",
+            "-o",
+            &format!("{}2", output),
+            "--stats-json",
+            stats,
+            input,
+        ]);
+        assert!(run_encode(args, &csv_args(None)).is_err());
+
+        // エラーで終了した場合も、そこまでの統計を書き出す
+        let args = encode_args(&[
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "-q",
+            "--strict",
+            "--force",
+            "-o",
+            output,
+            "--stats-json",
+            stats,
+            input,
+        ]);
+        let err = run_encode(args, &csv_args(None)).unwrap_err();
+        let json = read_stats();
+        assert_eq!(json["status"], "error");
+        assert_eq!(json["error"], err.to_string());
+        assert_eq!(json["read"], 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_row_position() {
+        // 2件目の値は3行にまたがるため、3件目以降はファイル上の行番号とデータの行番号の差が広がる
+        let input =
+            "id,note,lat,lon\n1,x,35.68,139.76\n2,\"a\nb\nc\",35.68,139.76\n3,y,abc,139.76\n";
+        let positions = |has_headers: bool| {
+            csv::ReaderBuilder::new()
+                .has_headers(has_headers)
+                .from_reader(input.as_bytes())
+                .records()
+                .map(|record| {
+                    let RowPosition { line, row } = RowPosition::of(&record.unwrap(), has_headers);
+                    (line, row)
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(positions(true), [(2, 1), (3, 2), (6, 3)]);
+        assert_eq!(positions(false), [(1, 1), (2, 2), (3, 3), (6, 4)]);
+
+        // エラーの「○行目」は、値が複数行にまたがる行の後でもファイル上の行番号と一致する
+        let args = encode_args(&["--lat", "lat", "--lon", "lon", "--strict", "-"]);
+        let err = try_encode_str(input, &args, 1).unwrap_err();
+        assert_eq!(err.to_string(), "6行目: 緯度の値「abc」が不正です");
+    }
+
+    #[test]
+    fn test_strict() {
+        let input = "id,lat,lon\n1,35.68,139.76\n2,abc,139.76\n3,40.71,-74.0\n";
+        let args = encode_args(&["--lat", "lat", "--lon", "lon", "--strict", "-"]);
+        let err = try_encode_str(input, &args, 1).unwrap_err();
+        assert_eq!(err.to_string(), "3行目: 緯度の値「abc」が不正です");
+        // 呼び出し側はエラーの種類で分岐できる
+        assert!(matches!(
+            err.downcast_ref::<MeshifyError>(),
+            Some(MeshifyError::ParseError {
+                line: 3,
+                field: "緯度",
+                ..
+            })
+        ));
+
+        let input = "id,lat,lon\n1,35.68,139.76\n3,40.71,-74.0\n";
+        let err = try_encode_str(input, &args, 1).unwrap_err();
+        assert!(err.to_string().contains("日本の範囲外"));
+
+        // 範囲外の扱いは --strict と同時に指定できない
+        assert!(
+            Cli::try_parse_from([
+                "meshify",
+                "--lat",
+                "lat",
+                "--lon",
+                "lon",
+                "--strict",
+                "--out-of-range",
+                "keep",
+                "-"
+            ])
+            .is_err()
+        );
+
+        // 書きかけの出力ファイルは残さない
+        let dir = std::env::temp_dir().join(format!("meshify_strict_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input_path = dir.join("points.csv");
+        fs::write(&input_path, input).unwrap();
+        let output = dir.join("out.csv");
+        let args = encode_args(&[
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "--strict",
+            "-o",
+            output.to_str().unwrap(),
+            input_path.to_str().unwrap(),
+        ]);
+        assert!(run_encode(args, &csv_args(None)).is_err());
+        assert!(!output.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_dry_run() {
+        let dir = std::env::temp_dir().join(format!("meshify_dry_run_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("points.csv");
+        fs::write(&input, "id,lat,lon\n1,35.68,139.76\n2,abc,139.76\n").unwrap();
+        let input = input.to_str().unwrap();
+
+        // 出力ファイルは作成しない
+        let args = encode_args(&["--lat", "lat", "--lon", "lon", "-q", "--dry-run", input]);
+        run_encode(args, &csv_args(None)).unwrap();
+        assert!(!dir.join("points_mesh.csv").exists());
+
+        // 既存のファイルも上書きしない
+        let output = dir.join("out.csv");
+        fs::write(&output, "existing\n").unwrap();
+        let args = encode_args(&[
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "-q",
+            "--dry-run",
+            "-o",
+            output.to_str().unwrap(),
+            input,
+        ]);
+        run_encode(args, &csv_args(None)).unwrap();
+        assert_eq!(fs::read_to_string(&output).unwrap(), "existing\n");
+
+        // 列の確認は通常通り行う
+        let args = encode_args(&[
+            "--lat",
+            "latitude",
+            "--lon",
+            "lon",
+            "-q",
+            "--dry-run",
+            input,
+        ]);
+        assert!(run_encode(args, &csv_args(None)).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_error_output() {
+        let dir = std::env::temp_dir().join(format!("meshify_error_output_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("points.csv");
+        fs::write(
+            &input,
+            "id,lat,lon\n1,35.68,139.76\n2,abc,139.76\n3,40.71,-74.0\n",
+        )
+        .unwrap();
+        let errors = dir.join("errors.csv");
+
+        // 出力には正常な行だけ、エラー出力にはスキップした行だけを書き出す
+        let args = encode_args(&[
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "-q",
+            "--error-output",
+            errors.to_str().unwrap(),
+            input.to_str().unwrap(),
+        ]);
+        run_encode(args, &csv_args(None)).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("points_mesh.csv")).unwrap(),
+            "id,lat,lon,mesh_code\n1,35.68,139.76,53394610\n"
+        );
+        assert_eq!(
+            fs::read_to_string(&errors).unwrap(),
+            "id,lat,lon,skip_line,skip_row,skip_reason\n2,abc,139.76,3,2,緯度不正\n3,40.71,-74.0,4,3,範囲外\n"
+        );
+
+        // 出力とエラー出力の両方を標準出力にはできない
+        let args = encode_args(&[
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "-q",
+            "--error-output",
+            "-",
+            "-o",
+            "-",
+            input.to_str().unwrap(),
+        ]);
+        assert!(run_encode(args, &csv_args(None)).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let dir = std::env::temp_dir().join(format!("meshify_aggregate_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("points.csv");
+        fs::write(
+            &input,
+            "id,lat,lon\n1,35.68,139.76\n2,35.681,139.761\n3,35.0,139.0\n4,abc,139.0\n",
+        )
+        .unwrap();
+
+        let parse =
+            |args: &[&str]| match Cli::try_parse_from([&["meshify", "aggregate"], args].concat()) {
+                Ok(Cli {
+                    command: Some(Command::Aggregate(args)),
+                    ..
+                }) => *args,
+                other => panic!("aggregate として解釈できない: {:?}", other),
+            };
+        let args = parse(&[
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "-q",
+            "--sort",
+            "count",
+            input.to_str().unwrap(),
+        ]);
+        run_aggregate(Box::new(args), &csv_args(None)).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("points_aggregate.csv")).unwrap(),
+            "mesh_code,count\n53394610,2\n52394000,1\n"
+        );
+
+        // 重みが数値でない行はスキップする
+        fs::write(
+            &input,
+            "id,lat,lon,population\n1,35.68,139.76,120\n2,35.681,139.761,x\n3,35.0,139.0,8.5\n",
+        )
+        .unwrap();
+        let args = parse(&[
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "-q",
+            "--weight",
+            "population",
+            "--with-count",
+            "--force",
+            input.to_str().unwrap(),
+        ]);
+        run_aggregate(Box::new(args), &csv_args(None)).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("points_aggregate.csv")).unwrap(),
+            "mesh_code,count,weight_sum\n52394000,1,8.5\n53394610,1,120\n"
+        );
+
+        let args = parse(&[
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "--weight",
+            "weight",
+            input.to_str().unwrap(),
+        ]);
+        assert!(run_aggregate(Box::new(args), &csv_args(None)).is_err());
+
+        let args = parse(&[
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "-l",
+            "first",
+            "-l",
+            "second",
+            input.to_str().unwrap(),
+        ]);
+        assert!(run_aggregate(Box::new(args), &csv_args(None)).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_no_header() {
+        let pool = rayon::ThreadPoolBuilder::new().build().unwrap();
+        let mut csv_args = csv_args(None);
+        csv_args.no_header = true;
+        let run = |args: &EncodeArgs| {
+            let mut reader = csv_args
+                .reader(Box::new(io::Cursor::new(
+                    b"1,35.68,139.76\n2,35.0,139.0\n".to_vec(),
+                )))
+                .unwrap();
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            encode(
+                &mut reader,
+                &mut writer,
+                args,
+                &pool,
+                &mut Progress::hidden(),
+                &mut Report::default(),
+                true,
+            )
+            .map(|_| String::from_utf8(writer.into_inner().unwrap()).unwrap())
+        };
+
+        // 1行目もデータとして扱い、ヘッダーは書き出さない
+        let args = encode_args(&["--lat-index", "1", "--lon-index", "2", "-"]);
+        assert_eq!(
+            run(&args).unwrap(),
+            "1,35.68,139.76,53394610\n2,35.0,139.0,52394000\n"
+        );
+
+        let args = encode_args(&["--lat", "lat", "--lon", "lon", "-"]);
+        let err = run(&args).unwrap_err();
+        assert!(err.to_string().contains("--lat-index"));
+    }
+
+    #[test]
+    fn test_mesh_format() {
+        let input = "id,lat,lon\n1,35.68,139.76\n2,35.0,139.0\n";
+        let args = encode_args(&[
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "--level",
+            "quarter",
+            "--mesh-format",
+            "hyphenated",
+            "--filter-mesh",
+            "533946",
+            "--add-center",
+            "-",
+        ]);
+        assert_eq!(
+            run_encode_str(input, &args, 1),
+            "id,lat,lon,mesh_code,mesh_center_lat,mesh_center_lon\n1,35.68,139.76,5339-46-10-4-2,35.68020833333334,139.7609375\n"
+        );
+
+        // ジオメトリは区切りを除いたコードから作る
+        let args = encode_args(&[
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "--mesh-format",
+            "hyphenated",
+            "-",
+        ]);
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        let mut writer = GeoJsonWriter::new(Vec::new());
+        encode(
+            &mut csv::Reader::from_reader(input.as_bytes()),
+            &mut writer,
+            &args,
+            &pool,
+            &mut Progress::hidden(),
+            &mut Report::default(),
+            true,
+        )
+        .unwrap();
+        writer.finish().unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&writer.into_inner()).unwrap();
+        assert_eq!(
+            value["features"][0]["properties"]["mesh_code"],
+            "5339-46-10"
+        );
+        assert_eq!(value["features"][0]["geometry"]["type"], "Polygon");
+    }
+
+    #[test]
+    fn test_filter_mesh() {
+        let input = "id,lat,lon\n1,35.68,139.76\n2,35.0,139.0\n3,36.0,140.0\n4,10.0,100.0\n";
+        let args = encode_args(&[
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "--filter-mesh",
+            "5339",
+            "--filter-mesh",
+            "5440",
+            "--out-of-range",
+            "keep",
+            "-",
+        ]);
+        let (output, summary) = encode_str_with_summary(input, &args, 1).unwrap();
+        assert_eq!(
+            output,
+            "id,lat,lon,mesh_code\n1,35.68,139.76,53394610\n3,36.0,140.0,54400000\n"
+        );
+        assert_eq!((summary.written, summary.filtered), (2, 2));
+
+        let parse = |prefix: &str| {
+            Cli::try_parse_from([
+                "meshify",
+                "--lat",
+                "a",
+                "--lon",
+                "b",
+                "--filter-mesh",
+                prefix,
+                "-",
+            ])
+        };
+        assert!(parse("53a9").is_err());
+        assert!(parse("").is_err());
+    }
+
+    #[test]
+    fn test_unique_mesh() {
+        let input = "id,lat,lon\n1,35.68,139.76\n2,35.0,139.0\n3,35.681,139.761\n4,abc,139.0\n5,35.0,139.0\n";
+        let encode_unique = |extra: &[&str]| {
+            let args = encode_args(
+                &[
+                    &["--lat", "lat", "--lon", "lon", "--keep-invalid"],
+                    extra,
+                    &["-"],
+                ]
+                .concat(),
+            );
+            encode_str_with_summary(input, &args, 2).unwrap()
+        };
+        // 元の列を残し、最初に現れた行だけを出力する (空欄のメッシュコードも1つの値として扱う)
+        let (output, summary) = encode_unique(&["--unique-mesh"]);
+        assert_eq!(
+            output,
+            "id,lat,lon,mesh_code\n1,35.68,139.76,53394610\n2,35.0,139.0,52394000\n4,abc,139.0,\n"
+        );
+        assert_eq!((summary.written, summary.duplicates), (3, 2));
+
+        // メッシュコード列だけを出力する
+        let (output, _) = encode_unique(&[
+            "--unique-mesh",
+            "--unique-output",
+            "code",
+            "--level",
+            "first",
+        ]);
+        assert_eq!(output, "mesh_code\n5339\n5239\n\"\"\n");
+    }
+
+    #[test]
+    fn test_skip_limit() {
+        let input = "id,lat,lon\n1,35.68,139.76\n2,abc,139.76\n3,35.0,139.0\n4,35.68,139.76\n";
+        let run = |options: &[&str]| {
+            let args = encode_args(&[&["--lat", "lat", "--lon", "lon"], options, &["-"]].concat());
+            encode_str_with_summary(input, &args, 1).unwrap()
+        };
+
+        let (output, summary) = run(&["--limit", "2"]);
+        assert_eq!(output, "id,lat,lon,mesh_code\n1,35.68,139.76,53394610\n");
+        assert_eq!((summary.read, summary.skipped()), (2, 1));
+
+        // 読み飛ばした後の行数を制限する
+        let (output, summary) = run(&["--skip", "2", "--limit", "1"]);
+        assert_eq!(output, "id,lat,lon,mesh_code\n3,35.0,139.0,52394000\n");
+        assert_eq!(summary.read, 1);
+
+        let (output, _) = run(&["--skip", "10"]);
+        assert_eq!(output, "id,lat,lon,mesh_code\n");
+
+        let args = encode_args(&[
+            "--format-in",
+            "ndjson",
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "--skip",
+            "1",
+            "--limit",
+            "1",
+            "-",
+        ]);
+        let (output, summary) = encode_ndjson_str(
+            "{\"lat\":35.0,\"lon\":139.0}\n\n{\"lat\":35.68,\"lon\":139.76}\n{\"lat\":35.0,\"lon\":139.0}\n",
+            &args,
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            output,
+            "{\"lat\":35.68,\"lon\":139.76,\"mesh_code\":\"53394610\"}\n"
+        );
+        assert_eq!(summary.read, 1);
+    }
+
+    #[test]
+    fn test_max_rows() {
+        let input = "id,lat,lon\n1,35.68,139.76\n2,35.0,139.0\n3,35.68,139.76\n";
+        let args = |options: &[&str]| {
+            encode_args(&[&["--lat", "lat", "--lon", "lon"], options, &["-"]].concat())
+        };
+
+        // 上限ちょうどの行数は、そのまま処理する
+        assert_eq!(
+            run_encode_str(input, &args(&["--max-rows", "3"]), 1),
+            "id,lat,lon,mesh_code\n1,35.68,139.76,53394610\n2,35.0,139.0,52394000\n3,35.68,139.76,53394610\n"
+        );
+        let err = try_encode_str(input, &args(&["--max-rows", "2"]), 1).unwrap_err();
+        assert!(err.to_string().contains("--max-rows の上限 (2 行)"));
+
+        let (output, summary) = encode_str_with_summary(
+            input,
+            &args(&["--max-rows", "2", "--on-max-rows", "truncate"]),
+            1,
+        )
+        .unwrap();
+        assert_eq!(
+            output,
+            "id,lat,lon,mesh_code\n1,35.68,139.76,53394610\n2,35.0,139.0,52394000\n"
+        );
+        assert_eq!(summary.read, 2);
+
+        // --skip で読み飛ばした行や、--limit で処理しない行は数えない
+        let (_, summary) =
+            encode_str_with_summary(input, &args(&["--skip", "1", "--max-rows", "2"]), 1).unwrap();
+        assert_eq!(summary.read, 2);
+        let (_, summary) =
+            encode_str_with_summary(input, &args(&["--limit", "2", "--max-rows", "2"]), 1).unwrap();
+        assert_eq!(summary.read, 2);
+
+        let ndjson = encode_args(&[
+            "--format-in",
+            "ndjson",
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "--max-rows",
+            "1",
+            "-",
+        ]);
+        let input = "{\"lat\":35.0,\"lon\":139.0}\n{\"lat\":35.68,\"lon\":139.76}\n";
+        assert!(encode_ndjson_str(input, &ndjson, false).is_err());
+    }
+
+    #[test]
+    fn test_empty_input() {
+        // ヘッダーだけの入力は、追加する列を含むヘッダーだけを書き出す
+        let args = encode_args(&["--lat", "lat", "--lon", "lon", "--add-center", "-"]);
+        let (output, summary) = encode_str_with_summary("id,lat,lon\n", &args, 1).unwrap();
+        assert_eq!(
+            output,
+            "id,lat,lon,mesh_code,mesh_center_lat,mesh_center_lon\n"
+        );
+        assert_eq!(summary.read, 0);
+
+        let err = try_encode_str("", &args, 1).unwrap_err();
+        assert_eq!(err.to_string(), "入力が空です (ヘッダー行がありません)");
+
+        // ヘッダーが無い前提なら、空の入力は0行として扱う
+        let pool = rayon::ThreadPoolBuilder::new().build().unwrap();
+        let mut csv_args = csv_args(None);
+        csv_args.no_header = true;
+        let mut reader = csv_args.reader(Box::new(io::empty())).unwrap();
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        let args = encode_args(&["--lat-index", "1", "--lon-index", "2", "-"]);
+        encode(
+            &mut reader,
+            &mut writer,
+            &args,
+            &pool,
+            &mut Progress::hidden(),
+            &mut Report::default(),
+            true,
+        )
+        .unwrap();
+        assert!(writer.into_inner().unwrap().is_empty());
+
+        let cli = Cli::try_parse_from(["meshify", "decode", "--column", "code", "-"]).unwrap();
+        let Some(Command::Decode(args)) = cli.command else {
+            panic!("decode として解析されない");
+        };
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        decode(
+            &mut csv::Reader::from_reader("code\n".as_bytes()),
+            &mut writer,
+            &args,
+        )
+        .unwrap();
+        assert_eq!(
+            writer.into_inner().unwrap(),
+            b"code,center_lat,center_lon\n"
+        );
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        assert!(
+            decode(
+                &mut csv::Reader::from_reader("".as_bytes()),
+                &mut writer,
+                &args
+            )
+            .is_err()
+        );
+        assert!(
+            validate(
+                &mut csv::Reader::from_reader("".as_bytes()),
+                &args.column,
+                &mut Vec::new()
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_find_header() {
+        let headers = csv::StringRecord::from(vec!["ID", " Latitude ", "Longitude", "lon", "LON"]);
+        let find = |name: &str, ignore_case: bool| {
+            find_header(&headers, name, ignore_case, DuplicateHeader::First).unwrap()
+        };
+        assert_eq!(find("Longitude", false), Some(2));
+        assert_eq!(find("latitude", false), None);
+        assert_eq!(find("latitude", true), Some(1));
+        assert_eq!(find(" id", true), Some(0));
+        assert_eq!(find("lon", false), Some(3));
+
+        let err = find_header(&headers, "lon", true, DuplicateHeader::First).unwrap_err();
+        assert!(
+            matches!(&err, MeshifyError::AmbiguousColumn { candidates, .. } if candidates.len() == 2)
+        );
+        assert!(err.to_string().contains("「lon」、「LON」"));
+
+        let input = "ID, Latitude ,LONGITUDE\n1,35.68,139.76\n";
+        let args = encode_args(&["--lat", "latitude", "--lon", "longitude", "-"]);
+        assert!(try_encode_str(input, &args, 1).is_err());
+        let args = encode_args(&[
+            "--lat",
+            "latitude",
+            "--lon",
+            "longitude",
+            "--ignore-case-headers",
+            "-",
+        ]);
+        assert_eq!(
+            run_encode_str(input, &args, 1),
+            "ID, Latitude ,LONGITUDE,mesh_code\n1,35.68,139.76,53394610\n"
+        );
+    }
+
+    #[test]
+    fn test_find_header_regex() {
+        let input = "id,lat_deg_2023,lon_deg_2023,lat_note\n1,35.68,139.76,x\n";
+        let args = encode_args(&["--lat-regex", "^lat_deg_", "--lon-regex", "^lon_deg_", "-"]);
+        assert_eq!(
+            run_encode_str(input, &args, 1),
+            "id,lat_deg_2023,lon_deg_2023,lat_note,mesh_code\n1,35.68,139.76,x,53394610\n"
+        );
+
+        let headers = csv::StringRecord::from(vec!["id", "lat_deg_2023", "lat_note"]);
+        let err =
+            find_header_regex(&headers, &parse_regex("^lat_").unwrap(), "緯度列").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "緯度列の正規表現「^lat_」に一致する列が複数あります: 「lat_deg_2023」、「lat_note」"
+        );
+        let err = find_header_regex(&headers, &parse_regex("^lon").unwrap(), "経度列").unwrap_err();
+        assert!(matches!(err, MeshifyError::NoColumnMatched { .. }));
+        assert!(parse_regex("lat(").is_err());
+
+        // 列名での指定とは排他
+        assert!(
+            Cli::try_parse_from([
+                "meshify",
+                "--lat",
+                "lat",
+                "--lat-regex",
+                "^lat",
+                "--lon-regex",
+                "^lon",
+                "-"
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_duplicate_header() {
+        let headers = csv::StringRecord::from(vec!["value", "lat", "value", " Value "]);
+        assert_eq!(
+            find_header(&headers, "value", false, DuplicateHeader::First).unwrap(),
+            Some(0)
+        );
+        let err = find_header(&headers, "value", false, DuplicateHeader::Error).unwrap_err();
+        assert!(
+            matches!(&err, MeshifyError::DuplicateColumn { positions, .. } if positions == &[0, 2])
+        );
+        assert!(err.to_string().contains("列の位置: 0、2"));
+        // 表記の異なる列も一致する場合は、重複ではなく曖昧な指定としてエラーにする
+        assert!(matches!(
+            find_header(&headers, "value", true, DuplicateHeader::First),
+            Err(MeshifyError::AmbiguousColumn { .. })
+        ));
+
+        let input = "lat,lon,lat\n35.68,139.76,0\n";
+        let args = encode_args(&["--lat", "lat", "--lon", "lon", "-"]);
+        assert_eq!(
+            run_encode_str(input, &args, 1),
+            "lat,lon,lat,mesh_code\n35.68,139.76,0,53394610\n"
+        );
+        let args = encode_args(&[
+            "--lat",
+            "lat",
+            "--lon",
+            "lon",
+            "--on-duplicate-header",
+            "error",
+            "-",
+        ]);
+        assert!(try_encode_str(input, &args, 1).is_err());
+        let args = encode_args(&[
+            "--lat-index",
+            "0",
+            "--lon-index",
+            "1",
+            "--on-duplicate-header",
+            "error",
+            "-",
+        ]);
+        assert_eq!(
+            run_encode_str(input, &args, 1),
+            "lat,lon,lat,mesh_code\n35.68,139.76,0,53394610\n"
+        );
+    }
+
+    #[test]
+    fn test_column_not_found() {
+        assert_eq!(edit_distance("lng", "lon"), 2);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "緯度"), 2);
+
+        let headers = csv::StringRecord::from(vec!["id", "Latitude", "longitude", "name"]);
+        assert_eq!(suggest_column(&headers, "lat"), Some("Latitude"));
+        assert_eq!(suggest_column(&headers, "longitud"), Some("longitude"));
+        assert_eq!(suggest_column(&headers, "nane"), Some("name"));
+        assert_eq!(suggest_column(&headers, "緯度"), None);
+
+        let input = "id,latitude,longitude\n1,35.68,139.76\n";
+        let args = encode_args(&["--lat", "lat", "--lon", "longitude", "-"]);
+        assert_eq!(
+            try_encode_str(input, &args, 1).unwrap_err().to_string(),
+            "緯度列「lat」が見つかりません (「latitude」の誤りではありませんか?)。入力にある列: 「id」、「latitude」、「longitude」"
+        );
+        let args = encode_args(&["--lat", "latitude", "--lon", "経度", "-"]);
+        assert_eq!(
+            try_encode_str(input, &args, 1).unwrap_err().to_string(),
+            "経度列「経度」が見つかりません。入力にある列: 「id」、「latitude」、「longitude」"
+        );
+    }
+
+    #[test]
+    fn test_resolve_output_path() {
+        let resolve = |input: &str, extension: &str| {
+            resolve_output_path(None, Path::new(input), "mesh", extension)
+        };
+        assert_eq!(
+            resolve("data/points.csv", "csv"),
+            Some(PathBuf::from("data/points_mesh.csv"))
+        );
+        assert_eq!(
+            resolve("data/points.csv.gz", "geojson"),
+            Some(PathBuf::from("data/points_mesh.geojson.gz"))
+        );
+        assert_eq!(resolve("-", "csv"), None);
+        assert_eq!(
+            resolve_output_path(Some(PathBuf::from("-")), Path::new("a.csv"), "mesh", "csv"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_decode_parent() {
+        let cli = Cli::try_parse_from([
+            "meshify", "decode", "--column", "code", "--parent", "standard", "-",
+        ])
+        .unwrap();
+        let Some(Command::Decode(args)) = cli.command else {
+            panic!("decode として解析されない");
+        };
+
+        let input = "id,code\n1,533945251\n2,5339452\n3,53394525\n4,5339-45-25-1\n";
+        let mut reader = csv::Reader::from_reader(input.as_bytes());
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        decode(&mut reader, &mut writer, &args).unwrap();
+        let output = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+
+        // 5倍地域メッシュは基準地域メッシュの階層に無いためスキップする
+        let lines = output.lines().collect::<Vec<&str>>();
+        assert_eq!(lines[0], "id,code,center_lat,center_lon,parent_mesh_code");
+        assert_eq!(lines.len(), 4);
+        assert!(lines[1].starts_with("1,533945251,") && lines[1].ends_with(",53394525"));
+        assert!(lines[2].starts_with("3,53394525,") && lines[2].ends_with(",53394525"));
+        // - 区切りのコードも受け付ける
+        assert_eq!(
+            lines[3]
+                .split_once(',')
+                .unwrap()
+                .1
+                .split_once(',')
+                .unwrap()
+                .1,
+            lines[1]
+                .split_once(',')
+                .unwrap()
+                .1
+                .split_once(',')
+                .unwrap()
+                .1
+        );
+    }
+
+    #[test]
+    fn test_decode_bbox() {
+        let cli =
+            Cli::try_parse_from(["meshify", "decode", "--column", "code", "--bbox", "-"]).unwrap();
+        let Some(Command::Decode(args)) = cli.command else {
+            panic!("decode として解析されない");
+        };
+
+        // レベルの異なるコードが混在していても、行ごとに桁数から判定する
+        let input = "id,code\n1,5339\n2,abc\n3,52394000\n";
+        let mut reader = csv::Reader::from_reader(input.as_bytes());
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        decode(&mut reader, &mut writer, &args).unwrap();
+        let output = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+
+        let lines = output.lines().collect::<Vec<&str>>();
+        assert_eq!(lines[0], "id,code,center_lat,center_lon,bbox");
+        assert_eq!(lines.len(), 3);
+        assert!(
+            lines[1].starts_with("1,5339,35.666")
+                && lines[1].ends_with(",\"35.333333333333336,139,36,140\"")
+        );
+        assert!(lines[2].starts_with("3,52394000,35.004"));
+    }
+
+    #[test]
+    fn test_validate() {
+        let column = CodeColumnArgs {
+            column: Some("code".to_string()),
+            column_index: None,
+        };
+        let input = "id,code\n1,53394525\n2,5339852\n3,abc\n4,5339452\n";
+        let mut reader = csv::Reader::from_reader(input.as_bytes());
+        let mut report = Vec::new();
+        assert_eq!(validate(&mut reader, &column, &mut report).unwrap(), (4, 2));
+        let report = String::from_utf8(report).unwrap();
+        let lines = report.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("3行目 (データの2行目): メッシュコード「5339852」"));
+        assert!(lines[0].contains("5桁目"));
+        assert!(lines[1].starts_with("4行目 (データの3行目): メッシュコード「abc」"));
+
+        // 複数行にまたがる値があると、ファイル上の行番号とデータの行番号がずれる
+        let input = "id,note,code\n1,\"a\nb\",abc\n2,x,5339852\n";
+        let mut reader = csv::Reader::from_reader(input.as_bytes());
+        let mut report = Vec::new();
+        assert_eq!(validate(&mut reader, &column, &mut report).unwrap(), (2, 2));
+        let report = String::from_utf8(report).unwrap();
+        let lines = report.lines().collect::<Vec<&str>>();
+        assert!(lines[0].starts_with("2行目 (データの1行目): メッシュコード「abc」"));
+        assert!(lines[1].starts_with("4行目 (データの2行目): メッシュコード「5339852」"));
+    }
+
+    #[test]
+    fn test_decode_validate_no_header() {
+        let input = "5339,a\n5340,b\nabc,c\n";
+        let reader = || {
+            csv::ReaderBuilder::new()
+                .has_headers(false)
+                .from_reader(input.as_bytes())
+        };
+        let decode_args =
+            |args: &[&str]| match Cli::try_parse_from(["meshify", "decode"].iter().chain(args)) {
+                Ok(Cli {
+                    command: Some(Command::Decode(args)),
+                    ..
+                }) => args,
+                other => panic!("{:?}", other),
+            };
+
+        // ヘッダーが無い場合は、出力にもヘッダーを書かない
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        decode(
+            &mut reader(),
+            &mut writer,
+            &decode_args(&["--column-index", "0", "-"]),
+        )
+        .unwrap();
+        let output = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        let lines = output.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("5339,a,35.666"));
+        assert!(lines[1].starts_with("5340,b,35.666"));
+
+        // 1行目のデータを列名として探さない
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        let err = decode(
+            &mut reader(),
+            &mut writer,
+            &decode_args(&["--column", "5339", "-"]),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("--column-index"));
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        assert!(
+            decode(
+                &mut reader(),
+                &mut writer,
+                &decode_args(&["--column-index", "2", "-"])
+            )
+            .is_err()
+        );
+        assert!(Cli::try_parse_from(["meshify", "decode", "-"]).is_err());
+        assert!(
+            Cli::try_parse_from([
+                "meshify",
+                "decode",
+                "--column",
+                "code",
+                "--column-index",
+                "0",
+                "-"
+            ])
+            .is_err()
+        );
+
+        let by_index = CodeColumnArgs {
+            column: None,
+            column_index: Some(0),
+        };
+        let mut report = Vec::new();
+        assert_eq!(
+            validate(&mut reader(), &by_index, &mut report).unwrap(),
+            (3, 1)
+        );
+        assert!(
+            String::from_utf8(report)
+                .unwrap()
+                .starts_with("3行目 (データの3行目)")
+        );
+        let by_name = CodeColumnArgs {
+            column: Some("5339".to_string()),
+            column_index: None,
+        };
+        let err = validate(&mut reader(), &by_name, &mut Vec::new()).unwrap_err();
+        assert!(err.to_string().contains("--column-index"));
+    }
+
+    #[test]
+    fn test_grid() {
+        let grid_args =
+            |args: &[&str]| match Cli::try_parse_from(["meshify", "grid"].iter().chain(args)) {
+                Ok(Cli {
+                    command: Some(Command::Grid(args)),
+                    ..
+                }) => args,
+                other => panic!("{:?}", other),
+            };
+
+        let args = grid_args(&["--bbox", "35.0,139.0,35.4,140.5", "--level", "first"]);
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        grid(&args, &mut writer).unwrap();
+        let output = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        let codes = output
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').next().unwrap())
+            .collect::<Vec<&str>>();
+        assert_eq!(codes, ["5239", "5240", "5339", "5340"]);
+
+        // 上限を超える場合は出力しない
+        let args = grid_args(&["--bbox", "35.0,139.0,36.0,140.0", "--max-meshes", "100"]);
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        let err = grid(&args, &mut writer).unwrap_err();
+        assert!(err.to_string().contains("--max-meshes"));
+        assert!(writer.into_inner().unwrap().is_empty());
+
+        // 日本の範囲と重ならない領域はエラー
+        let args = grid_args(&["--bbox", "10,100,11,101", "--level", "first"]);
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        assert!(grid(&args, &mut writer).is_err());
+
+        // GeoJSONでは各メッシュの矩形をPolygonにする
+        let args = grid_args(&[
+            "--bbox",
+            "35.0,139.0,35.4,140.5",
+            "--level",
+            "first",
+            "--format",
+            "geojson",
+        ]);
+        let mut writer = GeoJsonWriter::new(Vec::new());
+        grid(&args, &mut writer).unwrap();
+        writer.finish().unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&writer.into_inner()).unwrap();
+        let features = value["features"].as_array().unwrap();
+        assert_eq!(features.len(), 4);
+        assert_eq!(features[0]["properties"]["mesh_code"], "5239");
+        assert_eq!(features[0]["properties"]["center_lat"], "35");
+        assert_eq!(features[0]["geometry"]["type"], "Polygon");
+        assert_eq!(
+            features[0]["geometry"]["coordinates"][0]
+                .as_array()
+                .unwrap()
+                .len(),
+            5
+        );
+    }
+
+    #[test]
+    fn test_parse_bbox() {
+        assert_eq!(
+            parse_bbox("35.0, 139.0, 36.0, 140.0"),
+            Ok(Bbox {
+                min_lat: 35.0,
+                min_lon: 139.0,
+                max_lat: 36.0,
+                max_lon: 140.0
+            })
+        );
+        assert!(parse_bbox("35.0,139.0,36.0").is_err());
+        assert!(parse_bbox("35.0,139.0,36.0,abc").is_err());
+        assert!(parse_bbox("36.0,139.0,35.0,140.0").is_err());
+    }
+
+    #[test]
+    fn test_parse_delimiter() {
+        assert_eq!(parse_delimiter(","), Ok(b','));
+        assert_eq!(parse_delimiter(";"), Ok(b';'));
+        assert_eq!(parse_delimiter("\\t"), Ok(b'\t'));
+        assert_eq!(parse_delimiter("\t"), Ok(b'\t'));
+        assert!(parse_delimiter("").is_err());
+        assert!(parse_delimiter(",,").is_err());
+        assert!(parse_delimiter("、").is_err());
+    }
+}
@@ -0,0 +1,1788 @@
+//! 地域メッシュコードの計算
+
+use clap::ValueEnum;
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt::{self, Write};
+use std::ops::RangeInclusive;
+
+/// 地域メッシュを計算できる緯度の範囲（日本周辺のおおよその範囲）
+pub const LAT_RANGE: RangeInclusive<f64> = 20.0..=46.0;
+
+/// 地域メッシュを計算できる経度の範囲（日本周辺のおおよその範囲）
+pub const LON_RANGE: RangeInclusive<f64> = 122.0..=154.0;
+
+/// 面積や距離の概算に使う地球の平均半径 (km)
+const EARTH_RADIUS_KM: f64 = 6371.0088;
+
+/// 基準地域メッシュを縦横2等分していく分割地域メッシュの段数 (2分の1・4分の1・8分の1)
+///
+/// より細かい分割 (16分の1など) を加える場合は、この段数を増やし、`MeshLevel` にレベルを加える。
+const SUBDIVISION_DEPTH: usize = 3;
+
+/// 基準地域メッシュの1辺に並ぶ、最も細かい分割地域メッシュの数
+const STANDARD_UNITS: f64 = (1u32 << SUBDIVISION_DEPTH) as f64;
+
+/// 1次メッシュの緯度方向・経度方向の番号の最大値 (2桁)
+const MAX_FIRST_DIGIT: u32 = 99;
+
+/// 計算するメッシュのレベル
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum MeshLevel {
+    /// 1次メッシュ（約80km四方）
+    First,
+    /// 2次メッシュ（約10km四方）
+    Second,
+    /// 5倍地域メッシュ（約5km四方）
+    FiveFold,
+    /// 2倍地域メッシュ（約2km四方）
+    TwoFold,
+    /// 基準地域メッシュ（3次メッシュ、約1km四方）
+    Standard,
+    /// 2分の1地域メッシュ（約500m四方）
+    Half,
+    /// 4分の1地域メッシュ（約250m四方）
+    Quarter,
+    /// 8分の1地域メッシュ（約125m四方）
+    Eighth,
+}
+
+impl MeshLevel {
+    /// このレベルのメッシュコードの桁数
+    pub fn code_length(self) -> usize {
+        match self {
+            MeshLevel::First => 4,
+            MeshLevel::Second => 6,
+            MeshLevel::FiveFold => 7,
+            MeshLevel::Standard => 8,
+            MeshLevel::TwoFold | MeshLevel::Half => 9,
+            MeshLevel::Quarter => 10,
+            MeshLevel::Eighth => 11,
+        }
+    }
+
+    /// 1段階上位のレベル。1次メッシュには上位が無いため `None`
+    ///
+    /// 5倍・2倍地域メッシュは2次メッシュを分割したもので、基準地域メッシュとは系統が異なるため、上位は2次メッシュになる。
+    pub fn parent(self) -> Option<MeshLevel> {
+        match self {
+            MeshLevel::First => None,
+            MeshLevel::Second => Some(MeshLevel::First),
+            MeshLevel::FiveFold | MeshLevel::TwoFold | MeshLevel::Standard => {
+                Some(MeshLevel::Second)
+            }
+            MeshLevel::Half => Some(MeshLevel::Standard),
+            MeshLevel::Quarter => Some(MeshLevel::Half),
+            MeshLevel::Eighth => Some(MeshLevel::Quarter),
+        }
+    }
+
+    /// 1段階下位のレベル。8分の1地域メッシュと、別系統の5倍・2倍地域メッシュには下位が無いため `None`
+    pub fn child(self) -> Option<MeshLevel> {
+        match self {
+            MeshLevel::First => Some(MeshLevel::Second),
+            MeshLevel::Second => Some(MeshLevel::Standard),
+            MeshLevel::Standard => Some(MeshLevel::Half),
+            MeshLevel::Half => Some(MeshLevel::Quarter),
+            MeshLevel::Quarter => Some(MeshLevel::Eighth),
+            MeshLevel::FiveFold | MeshLevel::TwoFold | MeshLevel::Eighth => None,
+        }
+    }
+
+    /// 基準地域メッシュを何段分割したレベルか (基準地域メッシュより大きいレベルは0)
+    fn subdivision_depth(self) -> usize {
+        match self {
+            MeshLevel::Half => 1,
+            MeshLevel::Quarter => 2,
+            MeshLevel::Eighth => 3,
+            _ => 0,
+        }
+    }
+}
+
+impl fmt::Display for MeshLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            MeshLevel::First => "1次メッシュ",
+            MeshLevel::Second => "2次メッシュ",
+            MeshLevel::FiveFold => "5倍地域メッシュ",
+            MeshLevel::TwoFold => "2倍地域メッシュ",
+            MeshLevel::Standard => "基準地域メッシュ",
+            MeshLevel::Half => "2分の1地域メッシュ",
+            MeshLevel::Quarter => "4分の1地域メッシュ",
+            MeshLevel::Eighth => "8分の1地域メッシュ",
+        };
+        f.write_str(name)
+    }
+}
+
+/// メッシュコードの解析時に発生するエラー
+#[derive(Debug, Clone, PartialEq)]
+pub enum MeshError {
+    /// 桁数がどのレベルにも一致しない
+    InvalidLength(usize),
+    /// 数字以外の文字が含まれている
+    InvalidCharacter(char),
+    /// 桁の値がその桁の取り得る範囲外 (`position` は先頭を1とする桁の位置)
+    OutOfRange {
+        name: &'static str,
+        position: usize,
+        value: u32,
+    },
+    /// 親メッシュとして指定したレベルが、元のメッシュの上位の階層にない
+    NotAncestor { level: MeshLevel, target: MeshLevel },
+    /// これより下位の階層が無いレベルの子メッシュを求めようとした
+    NoChildren(MeshLevel),
+    /// GeoHash の桁数が範囲 (1〜12) 外
+    InvalidPrecision(usize),
+    /// 緯度経度が地域メッシュを計算できる範囲 ([`LAT_RANGE`]・[`LON_RANGE`]) 外か NaN
+    InvalidCoordinate { lat: f64, lon: f64 },
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeshError::InvalidLength(len) => {
+                write!(
+                    f,
+                    "メッシュコードの桁数 {} はどのレベルにも該当しません",
+                    len
+                )
+            }
+            MeshError::InvalidCharacter(c) => {
+                write!(f, "メッシュコードに数字以外の文字「{}」が含まれています", c)
+            }
+            MeshError::OutOfRange {
+                name,
+                position,
+                value,
+            } => {
+                write!(
+                    f,
+                    "メッシュコードの{}桁目 ({}) の値 {} が範囲外です",
+                    position, name, value
+                )
+            }
+            MeshError::NotAncestor { level, target } => {
+                write!(f, "{}の上位の階層に{}はありません", level, target)
+            }
+            MeshError::NoChildren(level) => {
+                write!(f, "{}より下位の階層のメッシュはありません", level)
+            }
+            MeshError::InvalidPrecision(precision) => {
+                write!(f, "GeoHashの桁数 {} は1〜12で指定してください", precision)
+            }
+            MeshError::InvalidCoordinate { lat, lon } => {
+                write!(
+                    f,
+                    "座標 ({}, {}) は地域メッシュを計算できる範囲 (緯度{}〜{}度、経度{}〜{}度) の外です",
+                    lat,
+                    lon,
+                    LAT_RANGE.start(),
+                    LAT_RANGE.end(),
+                    LON_RANGE.start(),
+                    LON_RANGE.end()
+                )
+            }
+        }
+    }
+}
+
+impl Error for MeshError {}
+
+/// 緯度経度が地域メッシュの計算対象となる日本周辺の範囲内かどうか
+///
+/// 範囲外の座標を [`get_mesh_code`] に渡すと、意味のないコードが返る。範囲外をエラーにする場合は
+/// [`try_get_mesh_code`] を使う。
+///
+/// ```
+/// assert!(meshify::is_within_japan(35.68, 139.76));
+/// assert!(!meshify::is_within_japan(-33.87, 151.21));
+/// ```
+pub fn is_within_japan(lat: f64, lon: f64) -> bool {
+    LAT_RANGE.contains(&lat) && LON_RANGE.contains(&lon)
+}
+
+/// 世界測地系の緯度経度から地域メッシュコードを計算
+///
+/// ```
+/// use meshify::MeshLevel;
+///
+/// assert_eq!(meshify::get_mesh_code(35.0, 139.0, MeshLevel::Standard), "52394000");
+/// assert_eq!(meshify::get_mesh_code(35.0, 139.0, MeshLevel::Half), "523940001");
+/// ```
+pub fn get_mesh_code(lat: f64, lon: f64, level: MeshLevel) -> String {
+    let mut code = String::with_capacity(level.code_length());
+    write_mesh_code(&mut code, lat, lon, level);
+    code
+}
+
+/// 世界測地系の緯度経度から地域メッシュコードを計算する。座標が [`is_within_japan`] の範囲外か NaN の場合はエラーを返す
+///
+/// [`get_mesh_code`] は範囲外の座標でもパニックせずにコードを返すが、そのコードは意味を持たない。
+/// 座標を確かめていない入力から計算する場合はこちらを使う。
+///
+/// ```
+/// use meshify::MeshLevel;
+///
+/// assert_eq!(meshify::try_get_mesh_code(35.0, 139.0, MeshLevel::Standard).unwrap(), "52394000");
+/// assert!(meshify::try_get_mesh_code(35.0, 99.5, MeshLevel::Standard).is_err());
+/// assert!(meshify::try_get_mesh_code(f64::NAN, 139.0, MeshLevel::Standard).is_err());
+/// ```
+pub fn try_get_mesh_code(lat: f64, lon: f64, level: MeshLevel) -> Result<String, MeshError> {
+    let mut code = String::with_capacity(level.code_length());
+    try_mesh_components(lat, lon)?.write_code(&mut code, level);
+    Ok(code)
+}
+
+/// 世界測地系の緯度経度から地域メッシュコードを計算し、`code` の末尾に書き足す
+///
+/// 大量の行を処理する場合に、1つのバッファを使い回して行ごとの `String` の確保を避けるために使う。
+/// `code` の既存の内容は消さないため、使い回す場合は呼び出し側で `clear` すること。
+///
+/// ```
+/// use meshify::MeshLevel;
+///
+/// let mut code = String::new();
+/// for (lat, lon) in [(35.0, 139.0), (35.68, 139.76)] {
+///     code.clear();
+///     meshify::write_mesh_code(&mut code, lat, lon, MeshLevel::Standard);
+///     assert_eq!(code, meshify::get_mesh_code(lat, lon, MeshLevel::Standard));
+/// }
+/// ```
+pub fn write_mesh_code(code: &mut String, lat: f64, lon: f64, level: MeshLevel) {
+    mesh_components(lat, lon).write_code(code, level);
+}
+
+/// 緯度経度から求めた、メッシュコードの各桁の値
+///
+/// 1次〜基準地域メッシュの桁は、一般的な計算式の記号にならって緯度方向を p・q・r、経度方向を u・v・w とし、
+/// 2分の1・4分の1・8分の1地域メッシュの分割番号を m・n・o とする。
+/// 分割番号は、メッシュを縦横2等分して南西から 1,2 (南側) 3,4 (北側) と付けた番号。
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct MeshComponents {
+    /// 1次メッシュの緯度方向の番号 (緯度を1.5倍した整数部、2桁)
+    pub p: u32,
+    /// 1次メッシュの経度方向の番号 (経度から100を引いた整数部、2桁)
+    pub u: u32,
+    /// 2次メッシュの緯度方向の番号 (0〜7)
+    pub q: u32,
+    /// 2次メッシュの経度方向の番号 (0〜7)
+    pub v: u32,
+    /// 基準地域メッシュの緯度方向の番号 (0〜9)
+    pub r: u32,
+    /// 基準地域メッシュの経度方向の番号 (0〜9)
+    pub w: u32,
+    /// 2分の1地域メッシュの分割番号 (1〜4)
+    pub m: u32,
+    /// 4分の1地域メッシュの分割番号 (1〜4)
+    pub n: u32,
+    /// 8分の1地域メッシュの分割番号 (1〜4)
+    pub o: u32,
+    /// 5倍地域メッシュの、2次メッシュ内の分割番号 (1〜4)
+    pub five_fold: u32,
+    /// 2倍地域メッシュの、2次メッシュ内の南西端の緯度方向・経度方向の位置 (0,2,4,6,8)
+    pub two_fold: (u32, u32),
+}
+
+/// 世界測地系の緯度経度から、メッシュコードの各桁の値を求める
+///
+/// [`get_mesh_code`] はこの値を桁として並べたもの。各桁が何を表すかを確かめる場合に使う。
+///
+/// ```
+/// use meshify::MeshLevel;
+///
+/// let components = meshify::mesh_components(35.68, 139.76);
+/// assert_eq!((components.p, components.u), (53, 39));
+/// assert_eq!((components.q, components.v, components.r, components.w), (4, 6, 1, 0));
+/// assert_eq!(components.m, 4);
+///
+/// let mut code = String::new();
+/// components.write_code(&mut code, MeshLevel::Half);
+/// assert_eq!(code, "533946104");
+/// ```
+///
+/// 範囲外の座標でも桁の数は変わらない。1次メッシュの番号は0〜99に丸める (経度100度未満や南半球では0、
+/// 緯度約66.7度・経度200度以上では99) ため、コードとして解釈はできるが位置は表さない。
+pub fn mesh_components(lat: f64, lon: f64) -> MeshComponents {
+    // 最も細かい分割地域メッシュ (8分の1地域メッシュは緯度3.75秒・経度5.625秒) を単位にした位置。
+    // 各桁はこの整数値の割り算で求める
+    let lat_units = grid_units(lat, 3600.0 / 30.0 * STANDARD_UNITS);
+    let lon_units = grid_units(lon - 100.0, 3600.0 / 45.0 * STANDARD_UNITS);
+    // 1次メッシュは緯度40分・経度1度、2次メッシュは緯度5分・経度7分30秒、基準地域メッシュは緯度30秒・経度45秒
+    let div = |units: f64, size: f64| ((units / size).floor(), units.rem_euclid(size));
+
+    // --- 1次・2次・基準地域メッシュ（3次メッシュ）の各桁の計算 ---
+    let (p, a_rem) = div(lat_units, 80.0 * STANDARD_UNITS);
+    let (q, b_rem) = div(a_rem, 10.0 * STANDARD_UNITS);
+    let (r, c_rem) = div(b_rem, STANDARD_UNITS);
+
+    let (u, g_rem) = div(lon_units, 80.0 * STANDARD_UNITS);
+    let (v, h_rem) = div(g_rem, 10.0 * STANDARD_UNITS);
+    let (w, i_rem) = div(h_rem, STANDARD_UNITS);
+
+    // --- 分割地域メッシュの計算: 基準地域メッシュ内の位置を1段ずつ縦横2等分していく ---
+    let mut divisions = [0; SUBDIVISION_DEPTH];
+    let (mut lat_rem, mut lon_rem) = (c_rem / STANDARD_UNITS, i_rem / STANDARD_UNITS);
+    for division in &mut divisions {
+        (*division, lat_rem, lon_rem) = subdivide(lat_rem, lon_rem);
+    }
+    let [m, n, o] = divisions;
+
+    MeshComponents {
+        p: clamp_digit(p, MAX_FIRST_DIGIT),
+        u: clamp_digit(u, MAX_FIRST_DIGIT),
+        q: clamp_digit(q, 7),
+        v: clamp_digit(v, 7),
+        r: clamp_digit(r, 9),
+        w: clamp_digit(w, 9),
+        m,
+        n,
+        o,
+        // 5倍・2倍地域メッシュは2次メッシュを分割したもので、基準地域メッシュ以下とは系統が異なる
+        five_fold: quadrant(
+            (b_rem / (5.0 * STANDARD_UNITS)).floor(),
+            (h_rem / (5.0 * STANDARD_UNITS)).floor(),
+        ),
+        two_fold: (
+            clamp_digit((b_rem / (2.0 * STANDARD_UNITS)).floor(), 4) * 2,
+            clamp_digit((h_rem / (2.0 * STANDARD_UNITS)).floor(), 4) * 2,
+        ),
+    }
+}
+
+/// 世界測地系の緯度経度から、メッシュコードの各桁の値を求める。座標が [`is_within_japan`] の範囲外か NaN の場合はエラーを返す
+///
+/// ```
+/// let components = meshify::try_mesh_components(35.68, 139.76).unwrap();
+/// assert_eq!((components.p, components.u), (53, 39));
+/// assert!(meshify::try_mesh_components(-33.87, 151.21).is_err());
+/// ```
+pub fn try_mesh_components(lat: f64, lon: f64) -> Result<MeshComponents, MeshError> {
+    if !is_within_japan(lat, lon) {
+        return Err(MeshError::InvalidCoordinate { lat, lon });
+    }
+    Ok(mesh_components(lat, lon))
+}
+
+/// 切り捨て済みの桁の値を、0〜`max` の整数にする
+///
+/// 範囲外の座標では、1次メッシュの番号が負 (経度100度未満など) や極端に大きな値になり、NaN や無限大の
+/// 座標では剰余から求める2次以下の桁も NaN になる。これらを負の値と NaN は0、`max` を超える値は `max` として扱う。
+fn clamp_digit(value: f64, max: u32) -> u32 {
+    if value.is_nan() || value <= 0.0 {
+        0
+    } else if value >= f64::from(max) {
+        max
+    } else {
+        // 0以上 max 未満に収まっていることを確かめたので、切り捨てても桁あふれしない
+        value as u32
+    }
+}
+
+/// メッシュを縦横2等分し、位置が含まれる分割番号 (1〜4) と、分割したメッシュ内での位置を求める
+///
+/// 位置はメッシュの南西端からの、緯度方向・経度方向それぞれの割合 (0以上1未満)。最も細かいメッシュの
+/// 単位の整数を2の累乗で割った値なので、2倍しても誤差は出ない。
+fn subdivide(lat_rem: f64, lon_rem: f64) -> (u32, f64, f64) {
+    let (lat, lon) = (lat_rem * 2.0, lon_rem * 2.0);
+    (quadrant(lat.floor(), lon.floor()), lat.fract(), lon.fract())
+}
+
+/// 縦横2等分したメッシュの南北 (0: 南、1: 北)・東西 (0: 西、1: 東) の位置から分割番号を求める
+///
+/// 2次メッシュ以下の桁は剰余から求めるため、範囲外の座標でも桁の範囲に収まる。NaN や無限大の座標で
+/// 位置が NaN になった場合は南・西 (0) として扱い、分割番号は常に1〜4になる。
+fn quadrant(south_north: f64, west_east: f64) -> u32 {
+    clamp_digit(south_north, 1) * 2 + clamp_digit(west_east, 1) + 1
+}
+
+impl MeshComponents {
+    /// 指定したレベルのメッシュコードを、`code` の末尾に書き足す
+    pub fn write_code(&self, code: &mut String, level: MeshLevel) {
+        // 1次メッシュの番号は0〜99に丸めてあるが、フィールドを直接作った場合に備えて fmt::Write で書く。
+        // String への書き込みは失敗しない
+        let _ = write!(code, "{:02}{:02}", self.p, self.u);
+
+        // 目的のレベルに達していない場合は、計算を続行する
+        if let MeshLevel::First = level {
+            return;
+        }
+
+        // --- 2次メッシュ ---
+        push_digit(code, self.q);
+        push_digit(code, self.v);
+
+        match level {
+            MeshLevel::Second => return,
+            MeshLevel::FiveFold => {
+                push_digit(code, self.five_fold);
+                return;
+            }
+            MeshLevel::TwoFold => {
+                // 2倍地域メッシュは2次メッシュ内の位置の2桁の末尾に5を付ける
+                push_digit(code, self.two_fold.0);
+                push_digit(code, self.two_fold.1);
+                code.push('5');
+                return;
+            }
+            _ => {}
+        }
+
+        // --- 基準地域メッシュ ---
+        push_digit(code, self.r);
+        push_digit(code, self.w);
+
+        // --- 分割地域メッシュ: レベルの段数分の分割番号 ---
+        for &division in &[self.m, self.n, self.o][..level.subdivision_depth()] {
+            push_digit(code, division);
+        }
+    }
+}
+
+/// 0〜9の値を1桁の数字として書き足す
+fn push_digit(code: &mut String, digit: u32) {
+    code.push(char::from_digit(digit, 10).expect("メッシュコードの2次以下の桁は0〜9に収まる"));
+}
+
+/// 度で表した値を、1度あたり `units_per_degree` の単位に換算して切り捨てる
+///
+/// メッシュの境界はすべて8分の1地域メッシュの大きさの整数倍なので、その単位に直せば以降は誤差なく割り算できる。
+/// ただし境界ちょうどの座標 ([`mesh_code_to_bounds`] の南西端など) は、度で表した時点の誤差で
+/// 境界のわずかに手前の値になることがあるため、整数との差がごく小さい (約1マイクロメートル未満) 場合は境界上とみなす。
+fn grid_units(degrees: f64, units_per_degree: f64) -> f64 {
+    let units = degrees * units_per_degree;
+    let nearest = units.round();
+    if (units - nearest).abs() < 1e-8 {
+        nearest
+    } else {
+        units.floor()
+    }
+}
+
+/// 世界測地系の緯度経度から地域メッシュコードを計算し、整数で返す
+///
+/// データベースの整数型の列に格納する場合などに使う。日本の範囲内ではメッシュコードの先頭が0になることはないため、
+/// 値は [`get_mesh_code`] の文字列をそのまま整数にしたものと一致する。ただし桁数はレベルによって異なり、
+/// 整数だけからはレベル (9桁の2倍地域メッシュと2分の1地域メッシュなど) を区別しにくいため、レベルと合わせて保存するとよい。
+///
+/// ```
+/// use meshify::MeshLevel;
+///
+/// assert_eq!(meshify::get_mesh_code_u64(35.0, 139.0, MeshLevel::Standard), 52394000);
+/// assert_eq!(meshify::get_mesh_code_u64(35.0, 139.0, MeshLevel::Eighth), 52394000111);
+/// ```
+pub fn get_mesh_code_u64(lat: f64, lon: f64, level: MeshLevel) -> u64 {
+    get_mesh_code(lat, lon, level)
+        .parse()
+        .expect("メッシュコードは数字のみで、u64に収まる桁数である")
+}
+
+/// 整数のメッシュコードを、指定したレベルの桁数の文字列に戻す
+///
+/// 桁数が足りない場合は先頭を0で埋める。桁数が多すぎる場合や、各桁の値が範囲外の場合はエラーを返す。
+///
+/// ```
+/// use meshify::MeshLevel;
+///
+/// assert_eq!(meshify::mesh_code_from_u64(53394525, MeshLevel::Standard).unwrap(), "53394525");
+/// assert!(meshify::mesh_code_from_u64(53394525, MeshLevel::Second).is_err());
+/// ```
+pub fn mesh_code_from_u64(code: u64, level: MeshLevel) -> Result<String, MeshError> {
+    let code = format!("{:0width$}", code, width = level.code_length());
+    if code.len() != level.code_length() {
+        return Err(MeshError::InvalidLength(code.len()));
+    }
+    validate_mesh_code(&code)?;
+    Ok(code)
+}
+
+/// メッシュコードが表す矩形の南西端と大きさ（いずれも度単位）
+struct MeshCell {
+    level: MeshLevel,
+    south: f64,
+    west: f64,
+    lat_size: f64,
+    lon_size: f64,
+}
+
+/// メッシュコードを桁ごとに分解し、そのメッシュの矩形を求める
+fn parse_mesh_code(code: &str) -> Result<MeshCell, MeshError> {
+    let level = level_of(code)?;
+    let digits: Vec<u32> = code.bytes().map(|b| u32::from(b - b'0')).collect();
+
+    let check = |name: &'static str, position: usize, min: u32, max: u32| {
+        let value = digits[position - 1];
+        if (min..=max).contains(&value) {
+            Ok(value)
+        } else {
+            Err(MeshError::OutOfRange {
+                name,
+                position,
+                value,
+            })
+        }
+    };
+
+    // --- 1次メッシュ: 緯度40分×経度1度 ---
+    let p = digits[0] * 10 + digits[1];
+    let u = digits[2] * 10 + digits[3];
+    let mut cell = MeshCell {
+        level: MeshLevel::First,
+        south: p as f64 * 40.0 / 60.0,
+        west: u as f64 + 100.0,
+        lat_size: 40.0 / 60.0,
+        lon_size: 1.0,
+    };
+
+    // --- 2次メッシュ: 1次メッシュを縦横8等分 ---
+    if digits.len() >= 6 {
+        let q = check("q", 5, 0, 7)?;
+        let v = check("v", 6, 0, 7)?;
+        cell.level = MeshLevel::Second;
+        cell.lat_size /= 8.0;
+        cell.lon_size /= 8.0;
+        cell.south += q as f64 * cell.lat_size;
+        cell.west += v as f64 * cell.lon_size;
+    }
+
+    // --- 5倍地域メッシュ: 2次メッシュを縦横2等分 ---
+    if digits.len() == 7 {
+        let number = check("5倍地域メッシュの区画番号", 7, 1, 4)?;
+        cell.level = MeshLevel::FiveFold;
+        cell.lat_size /= 2.0;
+        cell.lon_size /= 2.0;
+        let index = number - 1;
+        cell.south += (index / 2) as f64 * cell.lat_size;
+        cell.west += (index % 2) as f64 * cell.lon_size;
+        return Ok(cell);
+    }
+
+    // --- 2倍地域メッシュ: 2次メッシュを縦横5等分 ---
+    if level == MeshLevel::TwoFold {
+        cell.level = MeshLevel::TwoFold;
+        cell.lat_size /= 5.0;
+        cell.lon_size /= 5.0;
+        cell.south += (digits[6] / 2) as f64 * cell.lat_size;
+        cell.west += (digits[7] / 2) as f64 * cell.lon_size;
+        return Ok(cell);
+    }
+
+    // --- 基準地域メッシュ（3次メッシュ）: 2次メッシュを縦横10等分 ---
+    if digits.len() >= 8 {
+        let r = digits[6];
+        let w = digits[7];
+        cell.level = MeshLevel::Standard;
+        cell.lat_size /= 10.0;
+        cell.lon_size /= 10.0;
+        cell.south += r as f64 * cell.lat_size;
+        cell.west += w as f64 * cell.lon_size;
+    }
+
+    // --- 分割地域メッシュ: 分割番号ごとに矩形を4等分していく ---
+    let divisions = [
+        ("m", MeshLevel::Half),
+        ("n", MeshLevel::Quarter),
+        ("o", MeshLevel::Eighth),
+    ];
+    for (position, (name, level)) in (9..=digits.len()).zip(divisions) {
+        let number = check(name, position, 1, 4)?;
+        cell.level = level;
+        cell.lat_size /= 2.0;
+        cell.lon_size /= 2.0;
+        let index = number - 1;
+        cell.south += (index / 2) as f64 * cell.lat_size;
+        cell.west += (index % 2) as f64 * cell.lon_size;
+    }
+
+    Ok(cell)
+}
+
+/// メッシュコードの桁数から、そのレベルを判定する
+///
+/// 1次は4桁、2次は6桁、5倍は7桁、基準は8桁、1/2は9桁、1/4は10桁、1/8は11桁。9桁のうち末尾が5で
+/// 7・8桁目が偶数のものは2倍地域メッシュとする (1/2地域メッシュの分割番号は1〜4のため区別できる)。
+/// 各桁の値が範囲に収まっているかは確かめないため、検証には [`validate_mesh_code`] を使う。
+///
+/// ```
+/// use meshify::{MeshError, MeshLevel, level_of};
+///
+/// assert_eq!(level_of("5339"), Ok(MeshLevel::First));
+/// assert_eq!(level_of("533945"), Ok(MeshLevel::Second));
+/// assert_eq!(level_of("53394525"), Ok(MeshLevel::Standard));
+/// assert_eq!(level_of("533945244"), Ok(MeshLevel::Half));
+/// assert_eq!(level_of("533945245"), Ok(MeshLevel::TwoFold));
+/// assert_eq!(level_of("53394"), Err(MeshError::InvalidLength(5)));
+/// ```
+pub fn level_of(code: &str) -> Result<MeshLevel, MeshError> {
+    if let Some(c) = code.chars().find(|c| !c.is_ascii_digit()) {
+        return Err(MeshError::InvalidCharacter(c));
+    }
+    let digits = code.as_bytes();
+    Ok(match digits.len() {
+        4 => MeshLevel::First,
+        6 => MeshLevel::Second,
+        7 => MeshLevel::FiveFold,
+        8 => MeshLevel::Standard,
+        9 if digits[8] == b'5'
+            && (digits[6] - b'0').is_multiple_of(2)
+            && (digits[7] - b'0').is_multiple_of(2) =>
+        {
+            MeshLevel::TwoFold
+        }
+        9 => MeshLevel::Half,
+        10 => MeshLevel::Quarter,
+        11 => MeshLevel::Eighth,
+        len => return Err(MeshError::InvalidLength(len)),
+    })
+}
+
+/// `-` 区切りのメッシュコードで区切りを入れる位置 (1次・2次・基準・1/2・1/4 の各レベルの桁の後)
+const SEPARATOR_POSITIONS: [usize; 5] = [4, 6, 8, 9, 10];
+
+/// メッシュコードを、レベルごとの桁の境界で `-` 区切りにする (`5339359912` → `5339-35-99-1-2`)
+///
+/// 桁数だけを見て区切るため、コードの検証はしない。5倍地域メッシュは `5339-35-2`、2倍地域メッシュは
+/// `5339-35-24-5` になる。
+///
+/// ```
+/// assert_eq!(meshify::hyphenate_mesh_code("5339"), "5339");
+/// assert_eq!(meshify::hyphenate_mesh_code("53393599"), "5339-35-99");
+/// assert_eq!(meshify::hyphenate_mesh_code("53393599123"), "5339-35-99-1-2-3");
+/// assert_eq!(meshify::hyphenate_mesh_code("5339352"), "5339-35-2");
+/// ```
+pub fn hyphenate_mesh_code(code: &str) -> String {
+    let mut hyphenated = String::with_capacity(code.len() + SEPARATOR_POSITIONS.len());
+    for (i, c) in code.chars().enumerate() {
+        if SEPARATOR_POSITIONS.contains(&i) {
+            hyphenated.push('-');
+        }
+        hyphenated.push(c);
+    }
+    hyphenated
+}
+
+/// `-` 区切りのメッシュコード ([`hyphenate_mesh_code`]) から区切りを除き、連結したコードに戻す
+///
+/// 区切りの無いコードは、そのまま借用して返す。
+///
+/// ```
+/// assert_eq!(meshify::strip_mesh_code_separators("5339-35-99-1-2"), "5339359912");
+/// assert_eq!(meshify::strip_mesh_code_separators("53393599"), "53393599");
+/// ```
+pub fn strip_mesh_code_separators(code: &str) -> Cow<'_, str> {
+    if code.contains('-') {
+        Cow::Owned(code.replace('-', ""))
+    } else {
+        Cow::Borrowed(code)
+    }
+}
+
+/// メッシュコードを検証し、桁数から判定したレベルを返す
+///
+/// 各桁がその桁の取り得る範囲 (2次メッシュの q・v は0〜7、分割番号は1〜4 など) に収まっているかを確かめる。
+/// 不正な場合は、問題のある桁の位置と値をエラーで返す。
+///
+/// ```
+/// use meshify::{MeshError, MeshLevel, validate_mesh_code};
+///
+/// assert_eq!(validate_mesh_code("5339452"), Ok(MeshLevel::FiveFold));
+/// assert_eq!(validate_mesh_code("533945244"), Ok(MeshLevel::Half));
+/// assert!(matches!(
+///     validate_mesh_code("53398525"),
+///     Err(MeshError::OutOfRange { position: 5, value: 8, .. })
+/// ));
+/// ```
+pub fn validate_mesh_code(code: &str) -> Result<MeshLevel, MeshError> {
+    parse_mesh_code(code).map(|cell| cell.level)
+}
+
+/// メッシュコードから、そのメッシュが覆う矩形を (最小経度, 最小緯度, 最大経度, 最大緯度) で返す
+///
+/// 矩形は半開区間 [min, max) として扱う。南端・西端の線上の点はこのメッシュに含まれ、
+/// 北端・東端の線上の点は隣のメッシュに属する。
+///
+/// ```
+/// let (min_lon, min_lat, max_lon, max_lat) = meshify::mesh_code_to_bounds("52394000").unwrap();
+/// assert!((min_lat - 35.0).abs() < 1e-9 && (min_lon - 139.0).abs() < 1e-9);
+/// assert!((max_lat - 35.0 - 30.0 / 3600.0).abs() < 1e-9);
+/// assert!((max_lon - 139.0 - 45.0 / 3600.0).abs() < 1e-9);
+/// ```
+pub fn mesh_code_to_bounds(code: &str) -> Result<(f64, f64, f64, f64), MeshError> {
+    let cell = parse_mesh_code(code)?;
+    Ok((
+        cell.west,
+        cell.south,
+        cell.west + cell.lon_size,
+        cell.south + cell.lat_size,
+    ))
+}
+
+/// メッシュコードから、そのメッシュの中心の緯度経度（世界測地系）を計算
+///
+/// レベルはコードの桁数から判定する（1次: 4桁、2次: 6桁、5倍: 7桁、標準: 8桁、1/2・2倍: 9桁、1/4: 10桁、1/8: 11桁）。
+///
+/// ```
+/// let (lat, lon) = meshify::mesh_code_to_center("52394000").unwrap();
+/// assert!((lat - (35.0 + 15.0 / 3600.0)).abs() < 1e-9);
+/// assert!((lon - (139.0 + 22.5 / 3600.0)).abs() < 1e-9);
+/// ```
+pub fn mesh_code_to_center(code: &str) -> Result<(f64, f64), MeshError> {
+    let (min_lon, min_lat, max_lon, max_lat) = mesh_code_to_bounds(code)?;
+    Ok(((min_lat + max_lat) / 2.0, (min_lon + max_lon) / 2.0))
+}
+
+/// メッシュコードを、指定した上位レベルの親メッシュのコードに丸める
+///
+/// 分割地域メッシュの分割番号や5倍・2倍地域メッシュの区画番号を含めて階層をたどり、
+/// 親のレベルの桁数で切り落とす。`target` は元のメッシュの上位の階層 ([`MeshLevel::parent`] をたどって
+/// 到達できるレベル) か同じレベルでなければならず、より詳細なレベルや別系統のレベルはエラーになる。
+///
+/// ```
+/// use meshify::MeshLevel;
+///
+/// assert_eq!(meshify::parent_mesh("53394525123", MeshLevel::Half).unwrap(), "533945251");
+/// assert_eq!(meshify::parent_mesh("533945245", MeshLevel::Second).unwrap(), "533945");
+/// assert!(meshify::parent_mesh("53394525", MeshLevel::Half).is_err());
+/// ```
+pub fn parent_mesh(code: &str, target: MeshLevel) -> Result<String, MeshError> {
+    let level = validate_mesh_code(code)?;
+    let mut ancestor = Some(level);
+    while let Some(current) = ancestor {
+        if current == target {
+            return Ok(code[..target.code_length()].to_string());
+        }
+        ancestor = current.parent();
+    }
+    Err(MeshError::NotAncestor { level, target })
+}
+
+/// メッシュの概算面積 (km²) を計算する
+///
+/// 地球を球とみなし、南北方向の長さと、中心緯度の cos を掛けた東西方向の長さの積で近似する。
+/// 同じレベルのメッシュでも、高緯度ほど東西方向が縮むため面積は小さくなる。
+///
+/// ```
+/// let area = meshify::mesh_area_km2("53394525").unwrap();
+/// assert!((area - 1.0).abs() < 0.1);
+/// ```
+pub fn mesh_area_km2(code: &str) -> Result<f64, MeshError> {
+    let cell = parse_mesh_code(code)?;
+    let center_lat = (cell.south + cell.lat_size / 2.0).to_radians();
+    let height = EARTH_RADIUS_KM * cell.lat_size.to_radians();
+    let width = EARTH_RADIUS_KM * center_lat.cos() * cell.lon_size.to_radians();
+    Ok(height * width)
+}
+
+/// 2点間の大円距離 (m) をハバーサインの公式で計算する
+///
+/// 地球を平均半径の球とみなすため、楕円体上の距離とは最大で0.5%程度異なる。メッシュ内の位置の偏りを
+/// 比べる用途などで使う。
+///
+/// ```
+/// // 赤道上の経度1度はおよそ111km
+/// let distance = meshify::haversine_distance_m(0.0, 139.0, 0.0, 140.0);
+/// assert!((distance - 111_195.0).abs() < 1.0);
+/// ```
+pub fn haversine_distance_m(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (phi1, phi2) = (lat1.to_radians(), lat2.to_radians());
+    let d_phi = phi2 - phi1;
+    let d_lambda = (lon2 - lon1).to_radians();
+    let a = (d_phi / 2.0).sin().powi(2) + phi1.cos() * phi2.cos() * (d_lambda / 2.0).sin().powi(2);
+    // 丸め誤差で a が1をわずかに超えても NaN にならないようにする
+    2.0 * EARTH_RADIUS_KM * 1000.0 * a.sqrt().min(1.0).asin()
+}
+
+/// 1段階下位の子メッシュのコードを、南西から順にすべて返す
+///
+/// レベルはコードの桁数から判定する。1次メッシュからは2次メッシュ64個、2次メッシュからは基準地域メッシュ100個、
+/// 基準地域メッシュ以下からは分割地域メッシュ4個が得られる。8分の1地域メッシュと、別系統の5倍・2倍地域メッシュはエラーになる。
+///
+/// ```
+/// let children = meshify::child_meshes("53394525").unwrap();
+/// assert_eq!(children, ["533945251", "533945252", "533945253", "533945254"]);
+/// ```
+pub fn child_meshes(code: &str) -> Result<Vec<String>, MeshError> {
+    let level = validate_mesh_code(code)?;
+    let child = level.child().ok_or(MeshError::NoChildren(level))?;
+    let children = match child {
+        // 緯度方向 (q・r) ごとに、経度方向 (v・w) を並べる
+        MeshLevel::Second => (0..8)
+            .flat_map(|q| (0..8).map(move |v| format!("{}{}{}", code, q, v)))
+            .collect(),
+        MeshLevel::Standard => (0..10)
+            .flat_map(|r| (0..10).map(move |w| format!("{}{}{}", code, r, w)))
+            .collect(),
+        _ => (1..=4)
+            .map(|number| format!("{}{}", code, number))
+            .collect(),
+    };
+    Ok(children)
+}
+
+/// 指定した下位レベルまで子メッシュを再帰的に展開し、そのレベルのメッシュコードをすべて返す
+///
+/// 集計用に、ある区画に含まれる最下層 (8分の1地域メッシュ) までのコードを列挙する場合などに使う。
+/// `target` が元のメッシュと同じレベルならそのコードだけを返す。下位の階層に無いレベルはエラーになる。
+///
+/// ```
+/// use meshify::MeshLevel;
+///
+/// let eighths = meshify::descendant_meshes("53394525", MeshLevel::Eighth).unwrap();
+/// assert_eq!(eighths.len(), 64);
+/// ```
+pub fn descendant_meshes(code: &str, target: MeshLevel) -> Result<Vec<String>, MeshError> {
+    let level = validate_mesh_code(code)?;
+    // 下位に展開できるかは、target から親をたどって元のレベルに届くかで判定する
+    let mut ancestor = Some(target);
+    while ancestor.is_some_and(|current| current != level) {
+        ancestor = ancestor.and_then(MeshLevel::parent);
+    }
+    if ancestor.is_none() {
+        return Err(MeshError::NotAncestor {
+            level: target,
+            target: level,
+        });
+    }
+
+    let mut codes = vec![code.to_string()];
+    let mut current = level;
+    while current != target {
+        codes = codes
+            .iter()
+            .map(|code| child_meshes(code))
+            .collect::<Result<Vec<Vec<String>>, MeshError>>()?
+            .concat();
+        current = current.child().ok_or(MeshError::NoChildren(current))?;
+    }
+    Ok(codes)
+}
+
+/// 周囲8方向の隣接メッシュコードを、北 (N) から時計回りに N, NE, E, SE, S, SW, W, NW の順で返す
+///
+/// 隣接メッシュは元のメッシュと同じレベルで求める。隣の区画の中心座標からコードを計算し直すため、
+/// 2次メッシュの q・v (0〜7) や基準地域メッシュの r・w (0〜9) の繰り上がり・繰り下がりは
+/// 上位の桁 (1次メッシュの p・u など) に正しく伝わる。
+///
+/// ```
+/// let neighbors = meshify::mesh_neighbors("53394509").unwrap();
+/// // 東隣は w が 9 → 0 に繰り上がり、2次メッシュの v が +1 される
+/// assert_eq!(neighbors[2], "53394600");
+/// ```
+pub fn mesh_neighbors(code: &str) -> Result<[String; 8], MeshError> {
+    let cell = parse_mesh_code(code)?;
+    let center_lat = cell.south + cell.lat_size / 2.0;
+    let center_lon = cell.west + cell.lon_size / 2.0;
+    // (緯度方向, 経度方向) のずれ
+    let offsets = [
+        (1.0, 0.0),
+        (1.0, 1.0),
+        (0.0, 1.0),
+        (-1.0, 1.0),
+        (-1.0, 0.0),
+        (-1.0, -1.0),
+        (0.0, -1.0),
+        (1.0, -1.0),
+    ];
+    Ok(offsets.map(|(d_lat, d_lon)| {
+        get_mesh_code(
+            center_lat + d_lat * cell.lat_size,
+            center_lon + d_lon * cell.lon_size,
+            cell.level,
+        )
+    }))
+}
+
+/// 矩形領域を覆うメッシュの格子 (南西端のメッシュ、行数、列数)
+///
+/// メッシュコードを計算できるのは日本の範囲内だけのため、領域はその範囲に切り詰める。
+/// 領域が日本の範囲と重ならない場合は `None` を返す。
+fn bbox_grid(
+    min_lat: f64,
+    min_lon: f64,
+    max_lat: f64,
+    max_lon: f64,
+    level: MeshLevel,
+) -> Option<(MeshCell, usize, usize)> {
+    let (min_lat, max_lat) = (
+        min_lat.max(*LAT_RANGE.start()),
+        max_lat.min(*LAT_RANGE.end()),
+    );
+    let (min_lon, max_lon) = (
+        min_lon.max(*LON_RANGE.start()),
+        max_lon.min(*LON_RANGE.end()),
+    );
+    if min_lat > max_lat || min_lon > max_lon {
+        return None;
+    }
+    let cell = parse_mesh_code(&get_mesh_code(min_lat, min_lon, level))
+        .expect("get_mesh_code が返すメッシュコードは常に解釈できる");
+    // 北端・東端の線はメッシュに含まれない (半開区間) ため、ちょうど境界に重なる列は数えない。
+    // 浮動小数点の誤差で境界上の max が隣の列に食い込まないよう、わずかな余裕を持たせる。
+    // 領域が線や点の場合でも、その位置のメッシュは1つ返す
+    let count =
+        |max: f64, origin: f64, size: f64| (((max - origin) / size - 1e-9).ceil() as usize).max(1);
+    let rows = count(max_lat, cell.south, cell.lat_size);
+    let cols = count(max_lon, cell.west, cell.lon_size);
+    Some((cell, rows, cols))
+}
+
+/// 矩形領域を覆うメッシュの数を、コードを生成せずに求める
+///
+/// [`meshes_in_bbox`] で生成する前に、件数が膨大にならないかを確かめるのに使う。
+pub fn count_meshes_in_bbox(
+    min_lat: f64,
+    min_lon: f64,
+    max_lat: f64,
+    max_lon: f64,
+    level: MeshLevel,
+) -> usize {
+    match bbox_grid(min_lat, min_lon, max_lat, max_lon, level) {
+        Some((_, rows, cols)) => rows.saturating_mul(cols),
+        None => 0,
+    }
+}
+
+/// 矩形領域を覆う、指定レベルのすべてのメッシュコードを南西から順に返す
+///
+/// 領域と一部でも重なるメッシュを含める。日本の範囲外の部分は含めず、領域全体が範囲外の場合は
+/// 空の一覧を返す。広い領域を細かいレベルで指定すると件数が膨大になるため、
+/// 必要に応じて [`count_meshes_in_bbox`] で件数を確かめてから呼ぶこと。
+///
+/// ```
+/// use meshify::MeshLevel;
+///
+/// let codes = meshify::meshes_in_bbox(35.0, 139.0, 35.7, 140.5, MeshLevel::First);
+/// assert_eq!(codes, ["5239", "5240", "5339", "5340"]);
+/// ```
+pub fn meshes_in_bbox(
+    min_lat: f64,
+    min_lon: f64,
+    max_lat: f64,
+    max_lon: f64,
+    level: MeshLevel,
+) -> Vec<String> {
+    let Some((cell, rows, cols)) = bbox_grid(min_lat, min_lon, max_lat, max_lon, level) else {
+        return Vec::new();
+    };
+    let mut codes = Vec::with_capacity(rows * cols);
+    for row in 0..rows {
+        // 誤差が積み重ならないよう、足し合わせずに行・列番号から各メッシュの中心を求める
+        let lat = cell.south + (row as f64 + 0.5) * cell.lat_size;
+        for col in 0..cols {
+            let lon = cell.west + (col as f64 + 0.5) * cell.lon_size;
+            codes.push(get_mesh_code(lat, lon, level));
+        }
+    }
+    codes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 度分秒 (DMS) を 十進数度 (Decimal Degrees) に変換するヘルパー関数
+    fn dms_to_dd(d: f64, m: f64, s: f64) -> f64 {
+        d + m / 60.0 + s / 3600.0
+    }
+
+    #[test]
+    fn test_sapporo_standard() {
+        // 資料 p.14 表7 北海道札幌市
+        let lat = dms_to_dd(43.0, 3.0, 30.0);
+        let lon = dms_to_dd(141.0, 20.0, 15.0);
+        let expected = "64414277";
+        assert_eq!(get_mesh_code(lat, lon, MeshLevel::Standard), expected);
+    }
+
+    #[test]
+    fn test_tokyo_standard() {
+        // 資料 p.14 表7 東京都新宿区
+        let lat = dms_to_dd(35.0, 41.0, 0.0);
+        let lon = dms_to_dd(139.0, 41.0, 15.0);
+        let expected = "53394525";
+        assert_eq!(get_mesh_code(lat, lon, MeshLevel::Standard), expected);
+    }
+
+    #[test]
+    fn test_naha_standard() {
+        // 資料 p.14 表7 沖縄県那覇市
+        let lat = dms_to_dd(26.0, 12.0, 30.0);
+        let lon = dms_to_dd(127.0, 40.0, 30.0);
+        let expected = "39272554";
+        assert_eq!(get_mesh_code(lat, lon, MeshLevel::Standard), expected);
+    }
+
+    #[test]
+    fn test_tokyo_all_levels() {
+        // 東京都新宿区の座標で、すべてのメッシュレベルをテスト
+        let lat = dms_to_dd(35.0, 41.0, 0.0);
+        let lon = dms_to_dd(139.0, 41.0, 15.0);
+
+        // 各レベルで期待されるコードを検証
+        assert_eq!(get_mesh_code(lat, lon, MeshLevel::Standard), "53394525");
+        assert_eq!(get_mesh_code(lat, lon, MeshLevel::Half), "533945251");
+        assert_eq!(get_mesh_code(lat, lon, MeshLevel::Quarter), "5339452511");
+        assert_eq!(get_mesh_code(lat, lon, MeshLevel::Eighth), "53394525111");
+    }
+
+    #[test]
+    fn test_tokyo_first_and_second() {
+        // 基準地域メッシュ 53393599 の中にある点で、1次・2次メッシュのコードを検証
+        let (lat, lon) = mesh_code_to_center("53393599").unwrap();
+        assert_eq!(get_mesh_code(lat, lon, MeshLevel::Standard), "53393599");
+        assert_eq!(get_mesh_code(lat, lon, MeshLevel::First), "5339");
+        assert_eq!(get_mesh_code(lat, lon, MeshLevel::Second), "533935");
+    }
+
+    #[test]
+    fn test_first_and_second_bounds() {
+        // 1次メッシュ 5339 は北緯35度20分〜36度、東経139度〜140度
+        let (min_lon, min_lat, max_lon, max_lat) = mesh_code_to_bounds("5339").unwrap();
+        assert!((min_lat - dms_to_dd(35.0, 20.0, 0.0)).abs() < 1e-9);
+        assert!((max_lat - 36.0).abs() < 1e-9);
+        assert!((min_lon - 139.0).abs() < 1e-9);
+        assert!((max_lon - 140.0).abs() < 1e-9);
+
+        // 2次メッシュ 533935 は緯度5分×経度7.5分
+        let (min_lon, min_lat, max_lon, max_lat) = mesh_code_to_bounds("533935").unwrap();
+        assert!((min_lat - dms_to_dd(35.0, 35.0, 0.0)).abs() < 1e-9);
+        assert!((min_lon - dms_to_dd(139.0, 37.0, 30.0)).abs() < 1e-9);
+        assert!((max_lat - min_lat - 5.0 / 60.0).abs() < 1e-9);
+        assert!((max_lon - min_lon - 7.5 / 60.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_five_fold() {
+        // 2次メッシュ 533945 を縦横2等分した区画: 南西1, 南東2, 北西3, 北東4
+        let south = dms_to_dd(35.0, 40.0, 0.0);
+        let west = dms_to_dd(139.0, 37.0, 30.0);
+        let cases = [
+            (1.0, 1.0, "5339451"),
+            (1.0, 5.0, "5339452"),
+            (4.0, 1.0, "5339453"),
+            (4.0, 5.0, "5339454"),
+        ];
+        for (lat_min, lon_min, expected) in cases {
+            let lat = south + lat_min / 60.0;
+            let lon = west + lon_min / 60.0;
+            assert_eq!(get_mesh_code(lat, lon, MeshLevel::FiveFold), expected);
+        }
+    }
+
+    #[test]
+    fn test_two_fold() {
+        // 2次メッシュ 533945 を縦横5等分した区画: 南西端の位置を偶数2桁で表し末尾に5を付ける
+        let south = dms_to_dd(35.0, 40.0, 0.0);
+        let west = dms_to_dd(139.0, 37.0, 30.0);
+        let cases = [
+            (0.5, 0.75, "533945005"),
+            (1.5, 4.0, "533945245"),
+            (4.5, 7.0, "533945885"),
+            (2.5, 0.75, "533945405"),
+        ];
+        for (lat_min, lon_min, expected) in cases {
+            let lat = south + lat_min / 60.0;
+            let lon = west + lon_min / 60.0;
+            assert_eq!(get_mesh_code(lat, lon, MeshLevel::TwoFold), expected);
+        }
+    }
+
+    #[test]
+    fn test_five_and_two_fold_bounds() {
+        // 5倍地域メッシュは緯度2.5分×経度3.75分
+        let (min_lon, min_lat, max_lon, max_lat) = mesh_code_to_bounds("5339454").unwrap();
+        assert!((min_lat - dms_to_dd(35.0, 42.0, 30.0)).abs() < 1e-9);
+        assert!((min_lon - dms_to_dd(139.0, 41.0, 15.0)).abs() < 1e-9);
+        assert!((max_lat - min_lat - 2.5 / 60.0).abs() < 1e-9);
+        assert!((max_lon - min_lon - 3.75 / 60.0).abs() < 1e-9);
+
+        // 2倍地域メッシュは緯度1分×経度1.5分
+        let (min_lon, min_lat, max_lon, max_lat) = mesh_code_to_bounds("533945245").unwrap();
+        assert!((min_lat - dms_to_dd(35.0, 41.0, 0.0)).abs() < 1e-9);
+        assert!((min_lon - dms_to_dd(139.0, 40.0, 30.0)).abs() < 1e-9);
+        assert!((max_lat - min_lat - 1.0 / 60.0).abs() < 1e-9);
+        assert!((max_lon - min_lon - 1.5 / 60.0).abs() < 1e-9);
+
+        // 区画番号の範囲外
+        assert_eq!(
+            mesh_code_to_bounds("5339455"),
+            Err(MeshError::OutOfRange {
+                name: "5倍地域メッシュの区画番号",
+                position: 7,
+                value: 5
+            })
+        );
+    }
+
+    #[test]
+    fn test_mesh_code_u64() {
+        let points = [
+            (43.058, 141.337),
+            (35.683, 139.76),
+            (26.208, 127.687),
+            (35.0, 139.0),
+        ];
+        let levels = [
+            MeshLevel::First,
+            MeshLevel::Second,
+            MeshLevel::FiveFold,
+            MeshLevel::TwoFold,
+            MeshLevel::Standard,
+            MeshLevel::Half,
+            MeshLevel::Quarter,
+            MeshLevel::Eighth,
+        ];
+        for (lat, lon) in points {
+            for level in levels {
+                let code = get_mesh_code(lat, lon, level);
+                let numeric = get_mesh_code_u64(lat, lon, level);
+                assert_eq!(numeric.to_string(), code, "{:?} ({}, {})", level, lat, lon);
+                assert_eq!(mesh_code_from_u64(numeric, level), Ok(code));
+            }
+        }
+
+        // 先頭を0で埋めた "005339" は2次メッシュとして不正
+        assert_eq!(
+            mesh_code_from_u64(5339, MeshLevel::Second),
+            Err(MeshError::OutOfRange {
+                name: "v",
+                position: 6,
+                value: 9
+            })
+        );
+        assert_eq!(
+            mesh_code_from_u64(533945250, MeshLevel::Standard),
+            Err(MeshError::InvalidLength(9))
+        );
+    }
+
+    #[test]
+    fn test_validate_mesh_code() {
+        let cases = [
+            ("5339", MeshLevel::First),
+            ("533945", MeshLevel::Second),
+            ("5339451", MeshLevel::FiveFold),
+            ("53394525", MeshLevel::Standard),
+            ("533945245", MeshLevel::TwoFold),
+            ("533945251", MeshLevel::Half),
+            ("5339452512", MeshLevel::Quarter),
+            ("53394525123", MeshLevel::Eighth),
+        ];
+        for (code, level) in cases {
+            assert_eq!(validate_mesh_code(code), Ok(level), "{}", code);
+        }
+
+        // どのレベルのコードでも、生成したものは検証を通る
+        for level in MeshLevel::value_variants() {
+            let code = get_mesh_code(35.6895, 139.6917, *level);
+            assert_eq!(validate_mesh_code(&code), Ok(*level), "{}", code);
+        }
+
+        assert_eq!(
+            validate_mesh_code("5339452510"),
+            Err(MeshError::OutOfRange {
+                name: "n",
+                position: 10,
+                value: 0
+            })
+        );
+        assert_eq!(
+            validate_mesh_code("5339485"),
+            Err(MeshError::OutOfRange {
+                name: "v",
+                position: 6,
+                value: 8
+            })
+        );
+        assert_eq!(validate_mesh_code(""), Err(MeshError::InvalidLength(0)));
+    }
+
+    #[test]
+    fn test_level_of() {
+        // どのレベルのコードでも、桁数から生成したレベルが分かる
+        for level in MeshLevel::value_variants() {
+            let code = get_mesh_code(35.6895, 139.6917, *level);
+            assert_eq!(level_of(&code), Ok(*level), "{}", code);
+            assert_eq!(level.code_length(), code.len());
+        }
+
+        // 各桁の範囲は確かめない
+        assert_eq!(level_of("53398525"), Ok(MeshLevel::Standard));
+        assert_eq!(level_of("533945255"), Ok(MeshLevel::Half));
+        for len in [0, 1, 2, 3, 5, 12] {
+            assert_eq!(
+                level_of(&"5".repeat(len)),
+                Err(MeshError::InvalidLength(len))
+            );
+        }
+        assert_eq!(level_of("5339-45"), Err(MeshError::InvalidCharacter('-')));
+    }
+
+    #[test]
+    fn test_neighbors() {
+        // 基準地域メッシュの内部
+        assert_eq!(
+            mesh_neighbors("53394525").unwrap(),
+            [
+                "53394535", "53394536", "53394526", "53394516", "53394515", "53394514", "53394524",
+                "53394534"
+            ]
+        );
+
+        // 1次メッシュの南西の角: 南・西へは p・u が繰り下がる
+        assert_eq!(
+            mesh_neighbors("53390000").unwrap(),
+            [
+                "53390010", "53390011", "53390001", "52397091", "52397090", "52387799", "53380709",
+                "53380719"
+            ]
+        );
+
+        // 2次メッシュの北東の角: v=7 の東は u を+1して v=0、q=7 の北は p を+1して q=0
+        assert_eq!(
+            mesh_neighbors("533977").unwrap(),
+            [
+                "543907", "544000", "534070", "534060", "533967", "533966", "533976", "543906"
+            ]
+        );
+
+        // 1次メッシュ
+        assert_eq!(
+            mesh_neighbors("5339").unwrap(),
+            [
+                "5439", "5440", "5340", "5240", "5239", "5238", "5338", "5438"
+            ]
+        );
+
+        // 分割地域メッシュ・5倍・2倍でも同じレベルの隣接メッシュを返す
+        assert_eq!(mesh_neighbors("533945254").unwrap()[6], "533945253");
+        assert_eq!(mesh_neighbors("5339452").unwrap()[4], "5339354");
+        assert_eq!(mesh_neighbors("533945885").unwrap()[2], "533946805");
+
+        assert_eq!(mesh_neighbors("53394"), Err(MeshError::InvalidLength(5)));
+    }
+
+    #[test]
+    fn test_meshes_in_bbox() {
+        // 基準地域メッシュの境界ちょうどの矩形は、北端・東端のメッシュを含まない
+        let codes = meshes_in_bbox(
+            35.0,
+            139.0,
+            35.0 + 60.0 / 3600.0,
+            139.0 + 90.0 / 3600.0,
+            MeshLevel::Standard,
+        );
+        assert_eq!(codes, ["52394000", "52394001", "52394010", "52394011"]);
+
+        // 境界をわずかに越えると次の列まで含める
+        let codes = meshes_in_bbox(
+            35.0,
+            139.0,
+            35.0 + 60.0 / 3600.0,
+            139.0 + 90.0 / 3600.0 + 1e-6,
+            MeshLevel::Standard,
+        );
+        assert_eq!(
+            codes,
+            [
+                "52394000", "52394001", "52394002", "52394010", "52394011", "52394012"
+            ]
+        );
+
+        // 点を指定した場合はその点を含むメッシュ
+        assert_eq!(
+            meshes_in_bbox(35.68, 139.76, 35.68, 139.76, MeshLevel::Standard),
+            ["53394610"]
+        );
+
+        // 1次メッシュ1つ分を基準地域メッシュで覆うと 80×80 個になり、重複しない
+        let (min_lon, min_lat, max_lon, max_lat) = mesh_code_to_bounds("5339").unwrap();
+        let codes = meshes_in_bbox(min_lat, min_lon, max_lat, max_lon, MeshLevel::Standard);
+        assert_eq!(codes.len(), 6400);
+        assert_eq!(
+            count_meshes_in_bbox(min_lat, min_lon, max_lat, max_lon, MeshLevel::Standard),
+            6400
+        );
+        let mut unique = codes.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), 6400);
+        assert!(codes.iter().all(|code| code.starts_with("5339")));
+    }
+
+    #[test]
+    fn test_meshes_in_bbox_outside_japan() {
+        // 日本の範囲外の部分は含めず、範囲と重ならない領域は空になる
+        assert_eq!(
+            meshes_in_bbox(10.0, 100.0, 20.5, 122.5, MeshLevel::First),
+            ["3022"]
+        );
+        assert!(meshes_in_bbox(10.0, 100.0, 11.0, 101.0, MeshLevel::First).is_empty());
+        assert_eq!(
+            count_meshes_in_bbox(10.0, 100.0, 11.0, 101.0, MeshLevel::First),
+            0
+        );
+    }
+
+    #[test]
+    fn test_parent_mesh() {
+        let code = "53394525123";
+        assert_eq!(parent_mesh(code, MeshLevel::Eighth).unwrap(), code);
+        assert_eq!(parent_mesh(code, MeshLevel::Quarter).unwrap(), "5339452512");
+        assert_eq!(parent_mesh(code, MeshLevel::Half).unwrap(), "533945251");
+        assert_eq!(parent_mesh(code, MeshLevel::Standard).unwrap(), "53394525");
+        assert_eq!(parent_mesh(code, MeshLevel::Second).unwrap(), "533945");
+        assert_eq!(parent_mesh(code, MeshLevel::First).unwrap(), "5339");
+
+        // 親メッシュは元の座標から直接求めたコードと一致する
+        for level in MeshLevel::value_variants() {
+            let eighth = get_mesh_code(35.6895, 139.6917, MeshLevel::Eighth);
+            if let Ok(parent) = parent_mesh(&eighth, *level) {
+                assert_eq!(parent, get_mesh_code(35.6895, 139.6917, *level));
+            }
+        }
+
+        // 2倍地域メッシュの上位は2次メッシュ
+        assert_eq!(
+            parent_mesh("533945245", MeshLevel::Second).unwrap(),
+            "533945"
+        );
+        assert_eq!(parent_mesh("5339452", MeshLevel::First).unwrap(), "5339");
+
+        // より詳細なレベルや別系統のレベルは指定できない
+        assert_eq!(
+            parent_mesh("53394525", MeshLevel::Half),
+            Err(MeshError::NotAncestor {
+                level: MeshLevel::Standard,
+                target: MeshLevel::Half
+            })
+        );
+        assert!(parent_mesh("533945251", MeshLevel::FiveFold).is_err());
+        assert!(parent_mesh("533945245", MeshLevel::Standard).is_err());
+        assert!(parent_mesh("5339452a", MeshLevel::First).is_err());
+    }
+
+    #[test]
+    fn test_child_meshes() {
+        let children = child_meshes("5339").unwrap();
+        assert_eq!(children.len(), 64);
+        assert_eq!(children[0], "533900");
+        assert_eq!(children[1], "533901");
+        assert_eq!(children[63], "533977");
+
+        let children = child_meshes("533945").unwrap();
+        assert_eq!(children.len(), 100);
+        assert_eq!(children[10], "53394510");
+
+        assert_eq!(
+            child_meshes("5339452512").unwrap(),
+            ["53394525121", "53394525122", "53394525123", "53394525124"]
+        );
+
+        // 子メッシュはすべて親メッシュに含まれる
+        for child in child_meshes("53394525").unwrap() {
+            assert_eq!(
+                parent_mesh(&child, MeshLevel::Standard).unwrap(),
+                "53394525"
+            );
+        }
+
+        assert_eq!(
+            child_meshes("53394525123"),
+            Err(MeshError::NoChildren(MeshLevel::Eighth))
+        );
+        assert_eq!(
+            child_meshes("5339452"),
+            Err(MeshError::NoChildren(MeshLevel::FiveFold))
+        );
+    }
+
+    #[test]
+    fn test_descendant_meshes() {
+        assert_eq!(
+            descendant_meshes("53394525", MeshLevel::Standard).unwrap(),
+            ["53394525"]
+        );
+        let quarters = descendant_meshes("53394525", MeshLevel::Quarter).unwrap();
+        assert_eq!(quarters.len(), 16);
+        assert_eq!(quarters[0], "5339452511");
+        assert_eq!(quarters[15], "5339452544");
+        assert_eq!(
+            descendant_meshes("5339", MeshLevel::Standard)
+                .unwrap()
+                .len(),
+            6400
+        );
+
+        assert!(descendant_meshes("533945251", MeshLevel::Standard).is_err());
+        assert!(descendant_meshes("5339", MeshLevel::FiveFold).is_err());
+    }
+
+    #[test]
+    fn test_mesh_area() {
+        // 緯度35度付近の基準地域メッシュはおよそ1km²
+        let area = mesh_area_km2(&get_mesh_code(35.0, 139.0, MeshLevel::Standard)).unwrap();
+        assert!((1.0..1.1).contains(&area), "{}", area);
+
+        // 子メッシュの面積の合計は親メッシュの面積とほぼ一致する
+        let total: f64 = child_meshes("53394525")
+            .unwrap()
+            .iter()
+            .map(|code| mesh_area_km2(code).unwrap())
+            .sum();
+        assert!((total - mesh_area_km2("53394525").unwrap()).abs() < 1e-3);
+
+        // 高緯度ほど小さい
+        let north = mesh_area_km2(&get_mesh_code(45.0, 142.0, MeshLevel::Standard)).unwrap();
+        let south = mesh_area_km2(&get_mesh_code(26.0, 127.0, MeshLevel::Standard)).unwrap();
+        assert!(north < area && area < south);
+
+        assert!(mesh_area_km2("53394").is_err());
+    }
+
+    #[test]
+    fn test_haversine_distance() {
+        assert_eq!(haversine_distance_m(35.68, 139.76, 35.68, 139.76), 0.0);
+        // 緯度1度はどこでもおよそ111km、経度1度は cos(緯度) 倍に縮む
+        let north = haversine_distance_m(35.0, 139.0, 36.0, 139.0);
+        assert!((north - 111_195.0).abs() < 1.0, "{}", north);
+        let east = haversine_distance_m(35.0, 139.0, 35.0, 140.0);
+        assert!(
+            (east - 111_195.0 * 35f64.to_radians().cos()).abs() < 100.0,
+            "{}",
+            east
+        );
+        // 向きによらず同じ距離になり、対蹠点でも NaN にならない
+        assert_eq!(
+            haversine_distance_m(35.0, 139.0, 36.0, 140.0),
+            haversine_distance_m(36.0, 140.0, 35.0, 139.0)
+        );
+        let antipode = haversine_distance_m(35.0, 139.0, -35.0, -41.0);
+        assert!((antipode - std::f64::consts::PI * EARTH_RADIUS_KM * 1000.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_within_japan() {
+        assert!(is_within_japan(43.0, 141.0));
+        assert!(is_within_japan(26.2, 127.7));
+        assert!(!is_within_japan(19.9, 135.0));
+        assert!(!is_within_japan(35.0, 121.9));
+        assert!(!is_within_japan(f64::NAN, 139.0));
+    }
+
+    #[test]
+    fn test_outside_japan_does_not_panic() {
+        // 南半球・西経の座標でもパニックやオーバーフローが起きず、桁数もレベルどおりになる
+        for (lat, lon) in [
+            (-33.87, 151.21),
+            (40.71, -74.0),
+            (-90.0, -180.0),
+            (90.0, 180.0),
+            (35.0, 99.5),
+            (1e300, -1e300),
+            (f64::NAN, 139.0),
+            (35.0, f64::INFINITY),
+            (f64::NEG_INFINITY, f64::NAN),
+        ] {
+            for &level in MeshLevel::value_variants() {
+                let code = get_mesh_code(lat, lon, level);
+                assert_eq!(code.len(), level.code_length(), "({}, {})", lat, lon);
+                assert!(validate_mesh_code(&code).is_ok(), "{}", code);
+                get_mesh_code_u64(lat, lon, level);
+                assert!(try_get_mesh_code(lat, lon, level).is_err());
+            }
+        }
+        // 経度100度未満では1次メッシュの経度方向の番号が負になるため、0に丸める (u32 として桁あふれしない)
+        assert_eq!(get_mesh_code(35.0, 99.5, MeshLevel::First), "5200");
+        assert_eq!(mesh_components(-10.0, 139.0).p, 0);
+        assert_eq!(mesh_components(80.0, 250.0).p, MAX_FIRST_DIGIT);
+        assert_eq!(mesh_components(80.0, 250.0).u, MAX_FIRST_DIGIT);
+    }
+
+    #[test]
+    fn test_try_get_mesh_code() {
+        // 範囲の端はどちらも範囲内
+        for (lat, lon) in [(20.0, 122.0), (46.0, 154.0), (35.68, 139.76)] {
+            for &level in MeshLevel::value_variants() {
+                assert_eq!(
+                    try_get_mesh_code(lat, lon, level).unwrap(),
+                    get_mesh_code(lat, lon, level)
+                );
+            }
+        }
+        assert_eq!(
+            try_get_mesh_code(35.0, 154.0001, MeshLevel::Standard),
+            Err(MeshError::InvalidCoordinate {
+                lat: 35.0,
+                lon: 154.0001
+            })
+        );
+        assert!(try_get_mesh_code(19.99, 139.0, MeshLevel::First).is_err());
+        assert!(try_mesh_components(35.0, f64::NAN).is_err());
+        assert_eq!(
+            try_get_mesh_code(35.0, 99.5, MeshLevel::First)
+                .unwrap_err()
+                .to_string(),
+            "座標 (35, 99.5) は地域メッシュを計算できる範囲 (緯度20〜46度、経度122〜154度) の外です"
+        );
+    }
+
+    #[test]
+    fn test_tokyo_center_all_levels() {
+        // 各レベルの中心座標を再度エンコードすると元のコードに戻ることを検証
+        for code in [
+            "5339",
+            "533945",
+            "5339452",
+            "533945245",
+            "53394525",
+            "533945251",
+            "5339452511",
+            "53394525111",
+        ] {
+            let (lat, lon) = mesh_code_to_center(code).unwrap();
+            // 9桁の2倍地域メッシュと2分の1地域メッシュも level_of で区別する
+            let level = level_of(code).unwrap();
+            assert_eq!(get_mesh_code(lat, lon, level), code);
+        }
+    }
+
+    #[test]
+    fn test_subdivide() {
+        // 南西・南東・北西・北東の順に1〜4
+        assert_eq!(subdivide(0.25, 0.25), (1, 0.5, 0.5));
+        assert_eq!(subdivide(0.25, 0.75), (2, 0.5, 0.5));
+        assert_eq!(subdivide(0.5, 0.0), (3, 0.0, 0.0));
+        assert_eq!(subdivide(0.875, 0.625), (4, 0.75, 0.25));
+        // NaN の位置は南西とみなし、分割番号の範囲 (1〜4) に収める
+        assert_eq!(subdivide(f64::NAN, 0.5).0, 2);
+    }
+
+    #[test]
+    fn test_standard_center() {
+        // 53394525 の南西端は 北緯35度41分0秒 東経139度41分15秒
+        let (lat, lon) = mesh_code_to_center("53394525").unwrap();
+        assert!((lat - dms_to_dd(35.0, 41.0, 15.0)).abs() < 1e-9);
+        assert!((lon - dms_to_dd(139.0, 41.0, 37.5)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_bounds_size() {
+        // 標準メッシュは緯度30秒×経度45秒（約1km四方）
+        let (min_lon, min_lat, max_lon, max_lat) = mesh_code_to_bounds("53394525").unwrap();
+        assert!((min_lat - dms_to_dd(35.0, 41.0, 0.0)).abs() < 1e-9);
+        assert!((min_lon - dms_to_dd(139.0, 41.0, 15.0)).abs() < 1e-9);
+        assert!((max_lat - min_lat - 30.0 / 3600.0).abs() < 1e-9);
+        assert!((max_lon - min_lon - 45.0 / 3600.0).abs() < 1e-9);
+
+        // 1/8メッシュは緯度3.75秒×経度5.625秒（約125m四方）
+        let (min_lon, min_lat, max_lon, max_lat) = mesh_code_to_bounds("53394525111").unwrap();
+        assert!((max_lat - min_lat - 3.75 / 3600.0).abs() < 1e-9);
+        assert!((max_lon - min_lon - 5.625 / 3600.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_exact_boundaries() {
+        // 境界ちょうどの座標は北側・東側のメッシュに属し、誤差で1つ手前のメッシュにならない
+        // 経度139度30分は2次メッシュの境界 (7分30秒の倍数)
+        assert_eq!(get_mesh_code(35.5, 139.5, MeshLevel::Standard), "53392400");
+        assert_eq!(
+            get_mesh_code(35.5, 139.5 - 1e-9, MeshLevel::Standard),
+            "53392309"
+        );
+        // 1次メッシュの端 (緯度40分・経度1度の倍数)
+        assert_eq!(
+            get_mesh_code(dms_to_dd(35.0, 20.0, 0.0), 139.0, MeshLevel::Standard),
+            "53390000"
+        );
+        assert_eq!(
+            get_mesh_code(36.0 - 1e-9, 140.0 - 1e-9, MeshLevel::Standard),
+            "53397799"
+        );
+        assert_eq!(get_mesh_code(36.0, 140.0, MeshLevel::Standard), "54400000");
+
+        // 基準地域メッシュの境界 (緯度30秒・経度45秒の倍数) を、度への換算の仕方を変えてすべて確かめる
+        let digits = |step: u32| (step / 80, step % 80 / 10, step % 10);
+        let lon = 139.76;
+        for lat_step in 20 * 120..46 * 120 {
+            let (p, q, r) = digits(lat_step);
+            let expected = format!("{}{}{}{}{}{}", p, 39, q, 6, r, 0);
+            let seconds = f64::from(lat_step * 30);
+            for lat in [
+                f64::from(lat_step) / 120.0,
+                f64::from(lat_step) * (30.0 / 3600.0),
+                dms_to_dd(
+                    (seconds / 3600.0).floor(),
+                    (seconds % 3600.0 / 60.0).floor(),
+                    seconds % 60.0,
+                ),
+            ] {
+                assert_eq!(
+                    get_mesh_code(lat, lon, MeshLevel::Standard),
+                    expected,
+                    "{}",
+                    lat
+                );
+            }
+        }
+        let lat = 35.68;
+        for lon_step in 22 * 80..54 * 80 {
+            let (u, v, w) = digits(lon_step);
+            let expected = format!("{}{}{}{}{}{}", 53, u, 4, v, 1, w);
+            let seconds = f64::from(lon_step * 45);
+            // 45秒は0.0125度なので、10進の表記で書いた値も境界ちょうどを表す
+            let text = format!("{}.{:04}", 100 + lon_step / 80, lon_step % 80 * 125);
+            for lon in [
+                100.0 + f64::from(lon_step) / 80.0,
+                100.0 + f64::from(lon_step) * 0.0125,
+                dms_to_dd(
+                    100.0 + (seconds / 3600.0).floor(),
+                    (seconds % 3600.0 / 60.0).floor(),
+                    seconds % 60.0,
+                ),
+                text.parse().unwrap(),
+            ] {
+                assert_eq!(
+                    get_mesh_code(lat, lon, MeshLevel::Standard),
+                    expected,
+                    "{}",
+                    lon
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_bounds_half_open() {
+        // 矩形は [min, max) の半開区間: 南西端は自身に、北端・東端は隣のメッシュに属する
+        let code = "53394525";
+        let (min_lon, min_lat, max_lon, max_lat) = mesh_code_to_bounds(code).unwrap();
+        let eps = 1e-9;
+
+        assert_eq!(get_mesh_code(min_lat, min_lon, MeshLevel::Standard), code);
+        assert_eq!(
+            get_mesh_code(max_lat - eps, max_lon - eps, MeshLevel::Standard),
+            code
+        );
+
+        // 北端を越えると北隣 (r + 1)、東端を越えると東隣 (w + 1)
+        assert_eq!(
+            get_mesh_code(max_lat + eps, min_lon, MeshLevel::Standard),
+            "53394535"
+        );
+        assert_eq!(
+            get_mesh_code(min_lat, max_lon + eps, MeshLevel::Standard),
+            "53394526"
+        );
+    }
+
+    #[test]
+    fn test_center_invalid_length() {
+        assert_eq!(
+            mesh_code_to_center("533945251111"),
+            Err(MeshError::InvalidLength(12))
+        );
+        assert_eq!(
+            mesh_code_to_center("53394"),
+            Err(MeshError::InvalidLength(5))
+        );
+        assert_eq!(
+            mesh_code_to_center("5339452511111"),
+            Err(MeshError::InvalidLength(13))
+        );
+    }
+
+    #[test]
+    fn test_center_out_of_range() {
+        assert_eq!(
+            mesh_code_to_center("533945255"),
+            Err(MeshError::OutOfRange {
+                name: "m",
+                position: 9,
+                value: 5
+            })
+        );
+        assert_eq!(
+            mesh_code_to_center("53398525"),
+            Err(MeshError::OutOfRange {
+                name: "q",
+                position: 5,
+                value: 8
+            })
+        );
+        assert_eq!(
+            mesh_code_to_center("5339452a"),
+            Err(MeshError::InvalidCharacter('a'))
+        );
+    }
+
+    /// 日本の範囲内のランダムな座標についての性質
+    mod roundtrip {
+        use super::*;
+        use proptest::prelude::*;
+
+        fn level() -> impl Strategy<Value = MeshLevel> {
+            prop::sample::select(MeshLevel::value_variants())
+        }
+
+        proptest! {
+            /// 座標→コード→中心座標→コードで元のコードに戻り、元の座標はそのメッシュの矩形に含まれる
+            #[test]
+            fn center_roundtrip(lat in 20.0f64..46.0, lon in 122.0f64..154.0, level in level()) {
+                let code = get_mesh_code(lat, lon, level);
+                prop_assert_eq!(validate_mesh_code(&code), Ok(level));
+                let (center_lat, center_lon) = mesh_code_to_center(&code).unwrap();
+                prop_assert_eq!(get_mesh_code(center_lat, center_lon, level), code.clone());
+
+                let (min_lon, min_lat, max_lon, max_lat) = mesh_code_to_bounds(&code).unwrap();
+                let eps = 1e-9;
+                prop_assert!(min_lat - eps <= lat && lat < max_lat + eps);
+                prop_assert!(min_lon - eps <= lon && lon < max_lon + eps);
+            }
+
+            /// 矩形の四隅の内側では、浮動小数点の誤差があっても同じコードになる
+            ///
+            /// 境界上とみなす幅 (約1e-11度) より十分大きく、最小のメッシュ (緯度3.75秒) より十分小さい距離だけ
+            /// 内側の点を調べる。
+            #[test]
+            fn near_boundary(lat in 20.0f64..46.0, lon in 122.0f64..154.0, level in level()) {
+                let code = get_mesh_code(lat, lon, level);
+                let (min_lon, min_lat, max_lon, max_lat) = mesh_code_to_bounds(&code).unwrap();
+                let eps = 1e-9;
+                for (corner_lat, corner_lon) in [
+                    (min_lat + eps, min_lon + eps),
+                    (min_lat + eps, max_lon - eps),
+                    (max_lat - eps, min_lon + eps),
+                    (max_lat - eps, max_lon - eps),
+                ] {
+                    prop_assert_eq!(get_mesh_code(corner_lat, corner_lon, level), code.clone());
+                }
+                // 北端・東端を越えると隣のメッシュになる
+                prop_assert_ne!(get_mesh_code(max_lat + eps, min_lon + eps, level), code.clone());
+                prop_assert_ne!(get_mesh_code(min_lat + eps, max_lon + eps, level), code.clone());
+            }
+
+            /// 分割地域メッシュの分割番号が、8分の1地域メッシュの単位の整数の位置から直接求めた値と一致する
+            #[test]
+            fn subdivisions_match_units(lat in 20.0f64..46.0, lon in 122.0f64..154.0) {
+                let lat_units = grid_units(lat, 960.0) as u32 % 8;
+                let lon_units = grid_units(lon - 100.0, 640.0) as u32 % 8;
+                let number = |bit: u32| (lat_units >> bit & 1) * 2 + (lon_units >> bit & 1) + 1;
+                let components = mesh_components(lat, lon);
+                prop_assert_eq!((components.m, components.n, components.o), (number(2), number(1), number(0)));
+                let code = get_mesh_code(lat, lon, MeshLevel::Eighth);
+                prop_assert!(code.starts_with(&get_mesh_code(lat, lon, MeshLevel::Quarter)));
+                prop_assert!(code.starts_with(&get_mesh_code(lat, lon, MeshLevel::Half)));
+            }
+
+            /// 矩形の南西端ちょうどの座標は、そのメッシュ自身に属する
+            #[test]
+            fn south_west_corner(lat in 20.0f64..46.0, lon in 122.0f64..154.0, level in level()) {
+                let code = get_mesh_code(lat, lon, level);
+                let (min_lon, min_lat, _, _) = mesh_code_to_bounds(&code).unwrap();
+                prop_assert_eq!(get_mesh_code(min_lat, min_lon, level), code);
+            }
+        }
+    }
+}
@@ -0,0 +1,246 @@
+//! NDJSON (1行に1つのJSONオブジェクト) の値の読み取りと書き出し
+
+use serde_json::{Map, Value};
+use std::borrow::Cow;
+use std::io::{self, BufWriter, Write};
+
+/// ドット区切りのキーで、ネストしたオブジェクトの値を取り出す
+///
+/// `location.lat` は `{"location": {"lat": ...}}` の値を指す。キー自体にドットを含む場合に備え、
+/// 同名のキーがあればそちらを優先する。
+pub fn lookup<'a>(object: &'a Map<String, Value>, path: &str) -> Option<&'a Value> {
+    if let Some(value) = object.get(path) {
+        return Some(value);
+    }
+    let (key, rest) = path.split_once('.')?;
+    match object.get(key)? {
+        Value::Object(inner) => lookup(inner, rest),
+        _ => None,
+    }
+}
+
+/// JSONの値を、座標やCSVの列として読む文字列にする
+///
+/// 文字列はそのまま、`null` は空欄にし、それ以外 (数値やネストしたオブジェクトなど) はJSONの表記にする。
+pub fn field_text(value: &Value) -> Cow<'_, str> {
+    match value {
+        Value::String(s) => Cow::Borrowed(s),
+        Value::Null => Cow::Borrowed(""),
+        _ => Cow::Owned(value.to_string()),
+    }
+}
+
+/// メッシュコードのキーを追加したJSONオブジェクトの書き出し先
+pub enum ObjectWriter<W: Write> {
+    /// 1行に1オブジェクトのNDJSON
+    Ndjson(BufWriter<W>),
+    /// 最初のオブジェクトのキーを列にしたCSV
+    ///
+    /// 以降のオブジェクトに無いキーの列は空欄にし、最初のオブジェクトに無いキーは出力しない。
+    Csv {
+        writer: Box<csv::Writer<W>>,
+        headers: Option<Vec<String>>,
+    },
+    /// オブジェクトを `properties` にしたFeatureを並べた GeoJSON FeatureCollection
+    Geojson {
+        writer: BufWriter<W>,
+        features: usize,
+    },
+}
+
+/// GeoJSONの出力の、最初のFeatureより前の部分
+const FEATURE_COLLECTION_START: &[u8] = b"{\"type\":\"FeatureCollection\",\"features\":[";
+
+impl<W: Write> ObjectWriter<W> {
+    pub fn ndjson(writer: W) -> Self {
+        ObjectWriter::Ndjson(BufWriter::new(writer))
+    }
+
+    pub fn csv(writer: csv::Writer<W>) -> Self {
+        ObjectWriter::Csv {
+            writer: Box::new(writer),
+            headers: None,
+        }
+    }
+
+    pub fn geojson(writer: W) -> Self {
+        ObjectWriter::Geojson {
+            writer: BufWriter::new(writer),
+            features: 0,
+        }
+    }
+
+    /// 1オブジェクト分を書き出す。GeoJSONではジオメトリを持たないFeatureにする
+    pub fn write(&mut self, object: &Map<String, Value>) -> io::Result<()> {
+        match self {
+            ObjectWriter::Geojson { .. } => {
+                let mut feature = Map::new();
+                feature.insert("type".to_string(), Value::from("Feature"));
+                feature.insert("geometry".to_string(), Value::Null);
+                self.write_feature(feature, object.clone())
+            }
+            ObjectWriter::Ndjson(writer) => {
+                serde_json::to_writer(&mut *writer, object)?;
+                writer.write_all(b"\n")
+            }
+            ObjectWriter::Csv { writer, headers } => {
+                let headers = match headers {
+                    Some(headers) => headers,
+                    None => {
+                        let keys = object.keys().cloned().collect::<Vec<String>>();
+                        writer.write_record(&keys)?;
+                        headers.insert(keys)
+                    }
+                };
+                let fields = headers
+                    .iter()
+                    .map(|key| object.get(key).map_or(Cow::Borrowed(""), field_text));
+                Ok(writer.write_record(fields.map(|field| field.into_owned()))?)
+            }
+        }
+    }
+
+    /// GeoJSONの入力の1 Feature分を書き出す
+    ///
+    /// GeoJSONでは `properties` を除いた入力のFeature (ジオメトリなど) に `properties` を戻して書き出し、
+    /// それ以外の形式では `properties` をオブジェクトとして書き出す。
+    pub fn write_feature(
+        &mut self,
+        mut feature: Map<String, Value>,
+        properties: Map<String, Value>,
+    ) -> io::Result<()> {
+        let ObjectWriter::Geojson { writer, features } = self else {
+            return self.write(&properties);
+        };
+        feature.insert("properties".to_string(), Value::Object(properties));
+        writer.write_all(if *features == 0 {
+            FEATURE_COLLECTION_START
+        } else {
+            b","
+        })?;
+        writer.write_all(b"\n")?;
+        serde_json::to_writer(&mut *writer, &feature)?;
+        *features += 1;
+        Ok(())
+    }
+
+    /// ここまでに書き出したオブジェクトを出力先に送る (`--flush-interval`)
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ObjectWriter::Ndjson(writer) | ObjectWriter::Geojson { writer, .. } => writer.flush(),
+            ObjectWriter::Csv { writer, .. } => writer.flush(),
+        }
+    }
+
+    /// 書き残しを出力して書き出しを終える
+    pub fn finish(&mut self) -> io::Result<()> {
+        if let ObjectWriter::Geojson { writer, features } = self {
+            if *features == 0 {
+                writer.write_all(FEATURE_COLLECTION_START)?;
+            }
+            writer.write_all(b"\n]}\n")?;
+        }
+        self.flush()
+    }
+
+    #[cfg(test)]
+    pub fn into_inner(self) -> W {
+        match self {
+            ObjectWriter::Ndjson(writer) | ObjectWriter::Geojson { writer, .. } => {
+                writer.into_inner().ok().unwrap()
+            }
+            ObjectWriter::Csv { writer, .. } => writer.into_inner().ok().unwrap(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn object(value: Value) -> Map<String, Value> {
+        match value {
+            Value::Object(object) => object,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_lookup() {
+        let record = object(json!({
+            "id": 1,
+            "location": { "lat": 35.68, "lon": "139.76" },
+            "geo.lat": 36.0,
+            "tags": ["a"],
+        }));
+        assert_eq!(lookup(&record, "id"), Some(&json!(1)));
+        assert_eq!(lookup(&record, "location.lat"), Some(&json!(35.68)));
+        assert_eq!(lookup(&record, "location.lon"), Some(&json!("139.76")));
+        // ドットを含むキーはそのまま優先する
+        assert_eq!(lookup(&record, "geo.lat"), Some(&json!(36.0)));
+        assert_eq!(lookup(&record, "location.alt"), None);
+        assert_eq!(lookup(&record, "tags.0"), None);
+        assert_eq!(lookup(&record, "lat"), None);
+    }
+
+    #[test]
+    fn test_field_text() {
+        assert_eq!(field_text(&json!("35.68")), "35.68");
+        assert_eq!(field_text(&json!(35.68)), "35.68");
+        assert_eq!(field_text(&json!(null)), "");
+        assert_eq!(field_text(&json!(true)), "true");
+        assert_eq!(field_text(&json!({ "a": [1, 2] })), "{\"a\":[1,2]}");
+    }
+
+    #[test]
+    fn test_object_writer() {
+        let rows = [
+            object(json!({ "id": 1, "name": "新宿", "mesh_code": "53394611" })),
+            object(json!({ "id": 2, "extra": true, "mesh_code": null })),
+        ];
+
+        let mut writer = ObjectWriter::ndjson(Vec::new());
+        for row in &rows {
+            writer.write(row).unwrap();
+        }
+        writer.finish().unwrap();
+        assert_eq!(
+            String::from_utf8(writer.into_inner()).unwrap(),
+            "{\"id\":1,\"name\":\"新宿\",\"mesh_code\":\"53394611\"}\n{\"id\":2,\"extra\":true,\"mesh_code\":null}\n"
+        );
+
+        // 列は最初のオブジェクトのキーで決まる
+        let mut writer = ObjectWriter::csv(csv::Writer::from_writer(Vec::new()));
+        for row in &rows {
+            writer.write(row).unwrap();
+        }
+        writer.finish().unwrap();
+        assert_eq!(
+            String::from_utf8(writer.into_inner()).unwrap(),
+            "id,name,mesh_code\n1,新宿,53394611\n2,,\n"
+        );
+
+        // GeoJSONでは入力のFeatureのジオメトリを残す
+        let mut writer = ObjectWriter::geojson(Vec::new());
+        let feature = object(json!({
+            "type": "Feature",
+            "id": "a",
+            "geometry": { "type": "Point", "coordinates": [139.76, 35.68] },
+        }));
+        writer.write_feature(feature, rows[0].clone()).unwrap();
+        writer.write(&rows[1]).unwrap();
+        writer.finish().unwrap();
+        assert_eq!(
+            String::from_utf8(writer.into_inner()).unwrap(),
+            "{\"type\":\"FeatureCollection\",\"features\":[\n{\"type\":\"Feature\",\"id\":\"a\",\"geometry\":{\"type\":\"Point\",\"coordinates\":[139.76,35.68]},\"properties\":{\"id\":1,\"name\":\"新宿\",\"mesh_code\":\"53394611\"}},\n{\"type\":\"Feature\",\"geometry\":null,\"properties\":{\"id\":2,\"extra\":true,\"mesh_code\":null}}\n]}\n"
+        );
+
+        let mut writer = ObjectWriter::geojson(Vec::new());
+        writer.finish().unwrap();
+        assert_eq!(
+            String::from_utf8(writer.into_inner()).unwrap(),
+            "{\"type\":\"FeatureCollection\",\"features\":[\n]}\n"
+        );
+    }
+}
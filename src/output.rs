@@ -0,0 +1,196 @@
+//! メッシュコードを付与した行の書き出し先 (CSV / GeoJSON)
+//!
+//! Parquetの書き出しは `parquet_output` モジュール、SQLiteの書き出しは `sqlite_output` モジュールで行う。
+//!
+//! NDJSONの入力の書き出しは、元のオブジェクトを保つため `ndjson` モジュールで行う。
+
+use crate::geometry::{Orientation, mesh_polygon};
+use clap::ValueEnum;
+use serde_json::{Map, Value, json};
+use std::io::{self, Write};
+
+/// 出力形式
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// 入力と同じ列に、メッシュコード列を追加したCSV
+    Csv,
+    /// 各行をFeatureとし、メッシュ矩形をPolygonジオメトリにしたGeoJSON FeatureCollection
+    ///
+    /// `--format-in geojson` の場合は、入力のFeatureのジオメトリをそのまま残し、`properties` にメッシュコードを加える。
+    Geojson,
+    /// 入力のJSONオブジェクトにメッシュコードのキーを追加したNDJSON (`--format-in ndjson` の場合のみ)
+    Ndjson,
+    /// 入力と同じ列に、メッシュコード列を追加したApache Parquet (列の型は `--parquet-schema` で決める)
+    Parquet,
+    /// 入力と同じ列に、メッシュコード列を追加したテーブル (`--table`) を持つSQLiteのデータベース (列の型は `--sqlite-schema` で決める)
+    Sqlite,
+}
+
+/// メッシュコードを付与した行を書き出す
+pub trait RecordWriter {
+    /// 見出し行を書き出す
+    fn write_headers(&mut self, headers: &[String]) -> io::Result<()>;
+
+    /// 1行分を書き出す。`mesh_code` はジオメトリの元にするメッシュコードで、空欄の場合はジオメトリを持たない
+    fn write_row(&mut self, fields: &[&str], mesh_code: &str) -> io::Result<()>;
+
+    /// ここまでに書き出した行を出力先に送る (`--flush-interval`)。行をまとめてから書き出す形式では何もしない
+    fn flush(&mut self) -> io::Result<()>;
+
+    /// 書き残しを出力して書き出しを終える
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+impl<R: RecordWriter + ?Sized> RecordWriter for Box<R> {
+    fn write_headers(&mut self, headers: &[String]) -> io::Result<()> {
+        (**self).write_headers(headers)
+    }
+
+    fn write_row(&mut self, fields: &[&str], mesh_code: &str) -> io::Result<()> {
+        (**self).write_row(fields, mesh_code)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (**self).flush()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        (**self).finish()
+    }
+}
+
+impl<W: Write> RecordWriter for csv::Writer<W> {
+    fn write_headers(&mut self, headers: &[String]) -> io::Result<()> {
+        Ok(self.write_record(headers)?)
+    }
+
+    fn write_row(&mut self, fields: &[&str], _mesh_code: &str) -> io::Result<()> {
+        Ok(self.write_record(fields)?)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        csv::Writer::flush(self)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        csv::Writer::flush(self)
+    }
+}
+
+/// GeoJSON FeatureCollection を1 Featureずつ書き出す
+///
+/// 全体をメモリに組み立てず、Featureごとにシリアライズして書き出すため、巨大な入力でもメモリ使用量は一定になる。
+/// 元の列はすべて文字列として `properties` に入れる。座標はWGS84の (経度, 緯度) 順で出力する。
+pub struct GeoJsonWriter<W: Write> {
+    writer: W,
+    headers: Vec<String>,
+    features: usize,
+}
+
+impl<W: Write> GeoJsonWriter<W> {
+    pub fn new(writer: W) -> Self {
+        GeoJsonWriter {
+            writer,
+            headers: Vec::new(),
+            features: 0,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> RecordWriter for GeoJsonWriter<W> {
+    fn write_headers(&mut self, headers: &[String]) -> io::Result<()> {
+        self.headers = headers.to_vec();
+        self.writer
+            .write_all(b"{\"type\":\"FeatureCollection\",\"features\":[")
+    }
+
+    fn write_row(&mut self, fields: &[&str], mesh_code: &str) -> io::Result<()> {
+        let properties = self
+            .headers
+            .iter()
+            .zip(fields)
+            .map(|(name, &value)| (name.clone(), Value::from(value)))
+            .collect::<Map<String, Value>>();
+        let geometry = if mesh_code.is_empty() {
+            Value::Null
+        } else {
+            // RFC 7946 に従い、外周リングは左回りにする
+            let ring = mesh_polygon(mesh_code, Orientation::Ccw)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            let coordinates = ring
+                .iter()
+                .map(|&(lon, lat)| json!([lon, lat]))
+                .collect::<Vec<Value>>();
+            json!({ "type": "Polygon", "coordinates": [coordinates] })
+        };
+        let feature = json!({
+            "type": "Feature",
+            "properties": properties,
+            "geometry": geometry,
+        });
+
+        if self.features > 0 {
+            self.writer.write_all(b",")?;
+        }
+        self.writer.write_all(b"\n")?;
+        serde_json::to_writer(&mut self.writer, &feature)?;
+        self.features += 1;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.write_all(b"\n]}\n")?;
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_geojson_writer() {
+        let mut writer = GeoJsonWriter::new(Vec::new());
+        let headers = ["id".to_string(), "mesh_code".to_string()];
+        writer.write_headers(&headers).unwrap();
+        writer.write_row(&["1", "5339"], "5339").unwrap();
+        writer.write_row(&["2", ""], "").unwrap();
+        writer.finish().unwrap();
+
+        let collection: Value = serde_json::from_slice(&writer.into_inner()).unwrap();
+        assert_eq!(collection["type"], "FeatureCollection");
+        let features = collection["features"].as_array().unwrap();
+        assert_eq!(features.len(), 2);
+
+        assert_eq!(
+            features[0]["properties"],
+            json!({"id": "1", "mesh_code": "5339"})
+        );
+        let ring = features[0]["geometry"]["coordinates"][0]
+            .as_array()
+            .unwrap();
+        assert_eq!(ring.len(), 5);
+        assert_eq!(ring[0], ring[4]);
+        assert_eq!(ring[2], json!([140.0, 36.0]));
+
+        assert!(features[1]["geometry"].is_null());
+    }
+
+    #[test]
+    fn test_geojson_writer_empty() {
+        let mut writer = GeoJsonWriter::new(Vec::new());
+        writer.write_headers(&["id".to_string()]).unwrap();
+        writer.finish().unwrap();
+        let collection: Value = serde_json::from_slice(&writer.into_inner()).unwrap();
+        assert_eq!(collection["features"], json!([]));
+    }
+}
@@ -0,0 +1,351 @@
+//! メッシュコードを付与した行の Apache Parquet での書き出し
+
+use crate::output::RecordWriter;
+use clap::ValueEnum;
+use parquet::basic::{Compression, LogicalType, Repetition, Type as PhysicalType};
+use parquet::data_type::{ByteArray, ByteArrayType, DoubleType, Int64Type};
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::types::Type;
+use std::io::{self, Write};
+use std::sync::Arc;
+
+/// 1つの行グループにまとめる行数。書き出すまでこの行数分の値をメモリに持つ
+const ROW_GROUP_ROWS: usize = 100_000;
+
+/// Parquetの列の型の決め方
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ParquetSchema {
+    /// すべての列を文字列にする
+    String,
+    /// 最初の行グループ (10万行) の値から、列ごとに整数・小数・文字列のいずれかに決める (空欄は null にする)
+    ///
+    /// 先頭に0が付く値 (郵便番号など) は、数値にすると元の表記が失われるため文字列として扱う。
+    Infer,
+}
+
+/// Parquetの列の型 (SQLiteの出力でも、列の型の推論に使う)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColumnType {
+    Utf8,
+    Int64,
+    Double,
+}
+
+impl ColumnType {
+    /// 列のすべての値を書き出せる型を推論する。値がすべて空欄の列は文字列にする
+    pub fn infer(values: &[String]) -> ColumnType {
+        let mut values = values.iter().filter(|v| !v.is_empty()).peekable();
+        if values.peek().is_none() {
+            return ColumnType::Utf8;
+        }
+        let mut column_type = ColumnType::Int64;
+        for value in values {
+            if column_type == ColumnType::Int64 && !is_integer(value) {
+                column_type = ColumnType::Double;
+            }
+            if column_type == ColumnType::Double && !is_decimal(value) {
+                return ColumnType::Utf8;
+            }
+        }
+        column_type
+    }
+
+    /// 値をこの型で表記を変えずに書き出せるか
+    pub fn accepts(self, value: &str) -> bool {
+        match self {
+            ColumnType::Utf8 => true,
+            ColumnType::Int64 => is_integer(value),
+            ColumnType::Double => is_decimal(value),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ColumnType::Utf8 => "文字列",
+            ColumnType::Int64 => "整数",
+            ColumnType::Double => "小数",
+        }
+    }
+}
+
+/// 整数として書き出しても表記が変わらない値か
+fn is_integer(s: &str) -> bool {
+    s.parse::<i64>().is_ok_and(|n| n.to_string() == s)
+}
+
+/// 小数として書き出せる値か。先頭に余分な0や空白が付く値は除く
+fn is_decimal(s: &str) -> bool {
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    let leading_zero = digits.len() > 1 && digits.starts_with('0') && !digits[1..].starts_with('.');
+    !leading_zero && s.trim() == s && s.parse::<f64>().is_ok_and(f64::is_finite)
+}
+
+/// メッシュコードを付与した行をParquetファイルとして書き出す
+///
+/// 行は列ごとに行グループの行数分ためてから書き出す。Parquetの書き出しは途中の行グループを
+/// メモリ上のバッファに書き、行グループごとに出力先へ移すため、出力先はシークできなくてよい。
+/// 圧縮は Snappy を使う。
+pub struct ParquetWriter<W: Write> {
+    output: W,
+    schema: ParquetSchema,
+    headers: Vec<String>,
+    /// 書き出し前の値 (列ごと)
+    columns: Vec<Vec<String>>,
+    rows: usize,
+    /// 最初の行グループを書き出す際に型を決めて作る
+    writer: Option<(SerializedFileWriter<Vec<u8>>, Vec<ColumnType>)>,
+}
+
+impl<W: Write> ParquetWriter<W> {
+    pub fn new(output: W, schema: ParquetSchema) -> Self {
+        ParquetWriter {
+            output,
+            schema,
+            headers: Vec::new(),
+            columns: Vec::new(),
+            rows: 0,
+            writer: None,
+        }
+    }
+
+    #[cfg(test)]
+    pub fn into_inner(self) -> W {
+        self.output
+    }
+
+    /// ためた行を1つの行グループとして書き出す
+    fn write_row_group(&mut self) -> io::Result<()> {
+        let (writer, types) = match &mut self.writer {
+            Some(writer) => writer,
+            None => {
+                let types = match self.schema {
+                    ParquetSchema::String => vec![ColumnType::Utf8; self.headers.len()],
+                    ParquetSchema::Infer => self
+                        .columns
+                        .iter()
+                        .map(|values| ColumnType::infer(values))
+                        .collect(),
+                };
+                let writer = file_writer(&self.headers, &types, self.schema)?;
+                self.writer.insert((writer, types))
+            }
+        };
+        let nullable = self.schema == ParquetSchema::Infer;
+
+        let mut row_group = writer.next_row_group().map_err(io::Error::other)?;
+        for ((name, values), &column_type) in self.headers.iter().zip(&self.columns).zip(&*types) {
+            let mut column = row_group
+                .next_column()
+                .map_err(io::Error::other)?
+                .expect("スキーマの列の数だけ書き出す");
+            let mismatch = |value: &str| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "列「{}」の値「{}」を、最初の{}行から推論した型 ({}) で書き出せません (--parquet-schema string を指定してください)",
+                        name,
+                        value,
+                        ROW_GROUP_ROWS,
+                        column_type.name()
+                    ),
+                )
+            };
+            // 推論する場合は空欄を null にするため、定義レベル (0 が null) を付けて値の無い行を除く
+            let present = values.iter().filter(|v| !nullable || !v.is_empty());
+            let def_levels = nullable.then(|| {
+                values
+                    .iter()
+                    .map(|v| i16::from(!v.is_empty()))
+                    .collect::<Vec<i16>>()
+            });
+            let result = match column_type {
+                ColumnType::Utf8 => {
+                    let values = present
+                        .map(|v| ByteArray::from(v.as_str()))
+                        .collect::<Vec<ByteArray>>();
+                    column.typed::<ByteArrayType>().write_batch(
+                        &values,
+                        def_levels.as_deref(),
+                        None,
+                    )
+                }
+                ColumnType::Int64 => {
+                    let values = present
+                        .map(|v| v.parse::<i64>().map_err(|_| mismatch(v)))
+                        .collect::<io::Result<Vec<i64>>>()?;
+                    column
+                        .typed::<Int64Type>()
+                        .write_batch(&values, def_levels.as_deref(), None)
+                }
+                ColumnType::Double => {
+                    let values = present
+                        .map(|v| {
+                            v.parse::<f64>()
+                                .ok()
+                                .filter(|x| x.is_finite())
+                                .ok_or_else(|| mismatch(v))
+                        })
+                        .collect::<io::Result<Vec<f64>>>()?;
+                    column
+                        .typed::<DoubleType>()
+                        .write_batch(&values, def_levels.as_deref(), None)
+                }
+            };
+            result.map_err(io::Error::other)?;
+            column.close().map_err(io::Error::other)?;
+        }
+        row_group.close().map_err(io::Error::other)?;
+
+        // 書き終えた行グループの分を出力先に移し、バッファを空にする
+        writer.flush()?;
+        self.output.write_all(&std::mem::take(writer.inner_mut()))?;
+        for values in &mut self.columns {
+            values.clear();
+        }
+        self.rows = 0;
+        Ok(())
+    }
+}
+
+/// 列名と型からParquetのスキーマを作り、メモリ上のバッファに書き出すライターを作る
+fn file_writer(
+    headers: &[String],
+    types: &[ColumnType],
+    schema: ParquetSchema,
+) -> io::Result<SerializedFileWriter<Vec<u8>>> {
+    let repetition = match schema {
+        ParquetSchema::String => Repetition::REQUIRED,
+        ParquetSchema::Infer => Repetition::OPTIONAL,
+    };
+    let fields = headers
+        .iter()
+        .zip(types)
+        .map(|(name, column_type)| {
+            let builder = match column_type {
+                ColumnType::Utf8 => Type::primitive_type_builder(name, PhysicalType::BYTE_ARRAY)
+                    .with_logical_type(Some(LogicalType::String)),
+                ColumnType::Int64 => Type::primitive_type_builder(name, PhysicalType::INT64),
+                ColumnType::Double => Type::primitive_type_builder(name, PhysicalType::DOUBLE),
+            };
+            builder.with_repetition(repetition).build().map(Arc::new)
+        })
+        .collect::<Result<Vec<_>, ParquetError>>()
+        .map_err(io::Error::other)?;
+    let schema = Type::group_type_builder("schema")
+        .with_fields(fields)
+        .build()
+        .map_err(io::Error::other)?;
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .build();
+    SerializedFileWriter::new(Vec::new(), Arc::new(schema), Arc::new(properties))
+        .map_err(io::Error::other)
+}
+
+impl<W: Write> RecordWriter for ParquetWriter<W> {
+    fn write_headers(&mut self, headers: &[String]) -> io::Result<()> {
+        self.headers = headers.to_vec();
+        self.columns = vec![Vec::with_capacity(ROW_GROUP_ROWS); headers.len()];
+        Ok(())
+    }
+
+    fn write_row(&mut self, fields: &[&str], _mesh_code: &str) -> io::Result<()> {
+        for (values, field) in self.columns.iter_mut().zip(fields) {
+            values.push(field.to_string());
+        }
+        self.rows += 1;
+        if self.rows == ROW_GROUP_ROWS {
+            self.write_row_group()?;
+        }
+        Ok(())
+    }
+
+    /// 行グループ単位でしか書き出せないため、書き出し済みの行グループ以外は送らない
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        // 行が無い場合も、スキーマだけのファイルを作る
+        if self.rows > 0 || self.writer.is_none() {
+            self.write_row_group()?;
+        }
+        let (writer, _) = self.writer.take().expect("行グループを書き出し済み");
+        let footer = writer.into_inner().map_err(io::Error::other)?;
+        self.output.write_all(&footer)?;
+        self.output.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use std::fs::{self, File};
+
+    /// 行を書き出したParquetファイルを読み戻し、各行を表示形式の文字列で返す
+    fn roundtrip(schema: ParquetSchema, rows: &[[&str; 3]]) -> io::Result<Vec<String>> {
+        let mut writer = ParquetWriter::new(Vec::new(), schema);
+        let headers = ["id", "zip", "mesh_code"].map(String::from);
+        writer.write_headers(&headers)?;
+        for row in rows {
+            writer.write_row(row, row[2])?;
+        }
+        writer.finish()?;
+
+        let path = std::env::temp_dir().join(format!(
+            "meshify_parquet_{:?}_{}.parquet",
+            schema,
+            std::process::id()
+        ));
+        fs::write(&path, writer.into_inner())?;
+        let reader = SerializedFileReader::new(File::open(&path)?).unwrap();
+        let rows = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| row.unwrap().to_string())
+            .collect();
+        fs::remove_file(&path)?;
+        Ok(rows)
+    }
+
+    const ROWS: [[&str; 3]; 2] = [["1", "0010001", "53394611"], ["2.5", "", "53394610"]];
+
+    #[test]
+    fn test_parquet_writer() {
+        assert_eq!(
+            roundtrip(ParquetSchema::String, &ROWS).unwrap(),
+            [
+                "{id: \"1\", zip: \"0010001\", mesh_code: \"53394611\"}",
+                "{id: \"2.5\", zip: \"\", mesh_code: \"53394610\"}"
+            ]
+        );
+        assert_eq!(
+            roundtrip(ParquetSchema::Infer, &ROWS).unwrap(),
+            [
+                "{id: 1.0, zip: \"0010001\", mesh_code: 53394611}",
+                "{id: 2.5, zip: null, mesh_code: 53394610}"
+            ]
+        );
+        assert_eq!(
+            roundtrip(ParquetSchema::Infer, &[]).unwrap(),
+            [] as [&str; 0]
+        );
+    }
+
+    #[test]
+    fn test_infer() {
+        let infer = |values: &[&str]| {
+            ColumnType::infer(&values.iter().map(|v| v.to_string()).collect::<Vec<_>>())
+        };
+        assert_eq!(infer(&["1", "-20", ""]), ColumnType::Int64);
+        assert_eq!(infer(&["1", "0.5", "-3e2"]), ColumnType::Double);
+        assert_eq!(infer(&["1", "abc"]), ColumnType::Utf8);
+        assert_eq!(infer(&["007"]), ColumnType::Utf8);
+        assert_eq!(infer(&[" 1"]), ColumnType::Utf8);
+        assert_eq!(infer(&["NaN"]), ColumnType::Utf8);
+        assert_eq!(infer(&["", ""]), ColumnType::Utf8);
+    }
+}
@@ -0,0 +1,108 @@
+//! チャンク単位の並列処理と、その結果の入力順での受け取り
+//!
+//! 読み込み側はチャンクに通し番号を付けてスレッドプールで処理させ、ワーカーは結果を番号付きで
+//! チャネルに送る。結果は終わった順に届くため、受け取り側で番号順に並べ直してから返す。
+//! これにより、スレッド数やチャンクの行数によらず、出力は1スレッドで処理した場合と同じ順序になる。
+
+use crossbeam_channel::{Receiver, Sender};
+use std::collections::BTreeMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::thread;
+
+/// 処理中のチャンクの結果を、処理を始めた順に受け取る
+pub struct Pipeline<T> {
+    sender: Sender<(usize, thread::Result<T>)>,
+    receiver: Receiver<(usize, thread::Result<T>)>,
+    /// 先に届いた、まだ返せない結果 (通し番号順)
+    pending: BTreeMap<usize, thread::Result<T>>,
+    /// 処理を始めたチャンクの数
+    spawned: usize,
+    /// 結果を返したチャンクの数 (次に返す通し番号)
+    received: usize,
+}
+
+impl<T: Send> Pipeline<T> {
+    pub fn new() -> Self {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        Pipeline {
+            sender,
+            receiver,
+            pending: BTreeMap::new(),
+            spawned: 0,
+            received: 0,
+        }
+    }
+
+    /// チャンクの処理 `job` を `scope` のスレッドプールで始める
+    pub fn spawn<'scope, F>(&mut self, scope: &rayon::Scope<'scope>, job: F)
+    where
+        F: FnOnce() -> T + Send + 'scope,
+        T: 'scope,
+    {
+        let index = self.spawned;
+        self.spawned += 1;
+        let sender = self.sender.clone();
+        scope.spawn(move |_| {
+            // パニックしても結果を送り、受け取り側が待ち続けないようにする
+            let result = panic::catch_unwind(AssertUnwindSafe(job));
+            // 受け取り側がエラーで先に終了した場合は、結果を捨ててよい
+            let _ = sender.send((index, result));
+        });
+    }
+
+    /// 処理を始めて、まだ結果を返していないチャンクの数
+    pub fn in_flight(&self) -> usize {
+        self.spawned - self.received
+    }
+
+    /// 最も先に始めたチャンクの結果を、処理が終わるまで待って返す。処理中のチャンクが無ければ `None`
+    ///
+    /// ワーカーがパニックした場合は、呼び出し側でパニックを再開する。
+    pub fn recv(&mut self) -> Option<T> {
+        if self.in_flight() == 0 {
+            return None;
+        }
+        loop {
+            if let Some(result) = self.pending.remove(&self.received) {
+                self.received += 1;
+                return Some(result.unwrap_or_else(|payload| panic::resume_unwind(payload)));
+            }
+            let (index, result) = self
+                .receiver
+                .recv()
+                .expect("送信側を自身で保持しているため切断されない");
+            self.pending.insert(index, result);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_pipeline_order() {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .build()
+            .unwrap();
+        let results = pool.in_place_scope(|scope| {
+            let mut pipeline = Pipeline::new();
+            for i in 0..8u64 {
+                // 先に始めたチャンクほど遅く終わるようにする
+                pipeline.spawn(scope, move || {
+                    thread::sleep(Duration::from_millis((8 - i) * 5));
+                    i
+                });
+            }
+            assert_eq!(pipeline.in_flight(), 8);
+            let mut results = Vec::new();
+            while let Some(result) = pipeline.recv() {
+                results.push(result);
+            }
+            results
+        });
+        assert_eq!(results, (0..8).collect::<Vec<u64>>());
+    }
+}
@@ -0,0 +1,120 @@
+//! 平面直角座標系 (JGD2011, EPSG:6669〜6687) の系番号の扱い
+//!
+//! 平面直角座標系の X は北向きの距離 (北距)、Y は東向きの距離 (東距) で、数学の x・y や
+//! 経度・緯度の順とは軸が逆になる。このため X の列は緯度と同じ位置 (`--lat`)、Y の列は経度と同じ位置
+//! (`--lon`) に指定し、変換時には経度・緯度の順にそろえて (Y, X) の順で PROJ に渡す。
+
+/// 系番号の表記 (I〜XIX)。添字 + 1 が系番号
+const ZONE_NAMES: [&str; 19] = [
+    "I", "II", "III", "IV", "V", "VI", "VII", "VIII", "IX", "X", "XI", "XII", "XIII", "XIV", "XV",
+    "XVI", "XVII", "XVIII", "XIX",
+];
+
+/// 各系の原点の (緯度, 経度)。添字 + 1 が系番号
+const ZONE_ORIGINS: [(f64, f64); 19] = [
+    (33.0, 129.0 + 30.0 / 60.0),
+    (33.0, 131.0),
+    (36.0, 132.0 + 10.0 / 60.0),
+    (33.0, 133.0 + 30.0 / 60.0),
+    (36.0, 134.0 + 20.0 / 60.0),
+    (36.0, 136.0),
+    (36.0, 137.0 + 10.0 / 60.0),
+    (36.0, 138.0 + 30.0 / 60.0),
+    (36.0, 139.0 + 50.0 / 60.0),
+    (40.0, 140.0 + 50.0 / 60.0),
+    (44.0, 140.0 + 15.0 / 60.0),
+    (44.0, 142.0 + 15.0 / 60.0),
+    (44.0, 144.0 + 15.0 / 60.0),
+    (26.0, 142.0),
+    (26.0, 127.0 + 30.0 / 60.0),
+    (26.0, 124.0),
+    (26.0, 131.0),
+    (20.0, 136.0),
+    (26.0, 154.0),
+];
+
+/// 系番号をローマ数字 (`VIII`) またはアラビア数字 (`8`) の表記から読む。大文字小文字は区別しない
+///
+/// ```
+/// assert_eq!(meshify::plane::parse_zone("VIII"), Some(8));
+/// assert_eq!(meshify::plane::parse_zone("19"), Some(19));
+/// assert_eq!(meshify::plane::parse_zone("XX"), None);
+/// ```
+pub fn parse_zone(s: &str) -> Option<u8> {
+    let s = s.trim();
+    let zone = match s.parse::<u8>() {
+        Ok(zone) => zone,
+        Err(_) => {
+            let position = ZONE_NAMES
+                .iter()
+                .position(|name| name.eq_ignore_ascii_case(s))?;
+            position as u8 + 1
+        }
+    };
+    (1..=19).contains(&zone).then_some(zone)
+}
+
+/// 系番号のローマ数字の表記
+pub fn zone_name(zone: u8) -> &'static str {
+    ZONE_NAMES[usize::from(zone) - 1]
+}
+
+/// 系番号に対応するCRS (第I系が `EPSG:6669`、第XIX系が `EPSG:6687`)
+pub fn zone_crs(zone: u8) -> String {
+    assert!((1..=19).contains(&zone), "系番号は1〜19");
+    format!("EPSG:{}", 6668 + u32::from(zone))
+}
+
+/// 緯度経度の概算から、原点が最も近い系を推定する
+///
+/// 系の区域は都道府県 (北海道・東京都は一部の市町村) 単位で決まるため、境界付近では実際の系と
+/// 異なる場合がある。経度方向の距離は緯度に応じて縮めて比べる。
+///
+/// ```
+/// // 東京は第IX系
+/// assert_eq!(meshify::plane::estimate_zone(35.68, 139.76), 9);
+/// ```
+pub fn estimate_zone(lat: f64, lon: f64) -> u8 {
+    let scale = lat.to_radians().cos();
+    let distance = |&(origin_lat, origin_lon): &(f64, f64)| {
+        let dy = lat - origin_lat;
+        let dx = (lon - origin_lon) * scale;
+        dy * dy + dx * dx
+    };
+    let (position, _) = ZONE_ORIGINS
+        .iter()
+        .map(distance)
+        .enumerate()
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+        .expect("系は19ある");
+    position as u8 + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_zone() {
+        assert_eq!(parse_zone("I"), Some(1));
+        assert_eq!(parse_zone("viii"), Some(8));
+        assert_eq!(parse_zone("XIX"), Some(19));
+        assert_eq!(parse_zone(" 9 "), Some(9));
+        assert_eq!(parse_zone("0"), None);
+        assert_eq!(parse_zone("20"), None);
+        assert_eq!(parse_zone("IIII"), None);
+        assert_eq!(zone_crs(1), "EPSG:6669");
+        assert_eq!(zone_crs(19), "EPSG:6687");
+        assert_eq!(zone_name(8), "VIII");
+    }
+
+    #[test]
+    fn test_estimate_zone() {
+        // 大阪 (第VI系)、札幌 (第XII系)、福岡 (第II系)、長崎 (第I系)、那覇 (第XV系)
+        assert_eq!(estimate_zone(34.69, 135.50), 6);
+        assert_eq!(estimate_zone(43.06, 141.35), 12);
+        assert_eq!(estimate_zone(33.59, 130.40), 2);
+        assert_eq!(estimate_zone(32.75, 129.87), 1);
+        assert_eq!(estimate_zone(26.21, 127.68), 15);
+    }
+}
@@ -0,0 +1,69 @@
+//! 出力する小数 (中心座標・面積・ジオメトリの座標) の桁数の丸め
+
+/// 小数を、小数点以下 `precision` 桁に四捨五入した文字列にする。指定しない場合は丸めない
+///
+/// 2進数の誤差で 2.675 が 2.67 に丸められることのないよう、値を誤差なく表せる最短の10進表記を
+/// 文字列のまま四捨五入する (0.5 は 1 に、-0.5 は -1 になる)。
+pub fn format_decimal(value: f64, precision: Option<usize>) -> String {
+    let text = value.to_string();
+    let Some(digits) = precision.filter(|_| value.is_finite()) else {
+        return text;
+    };
+    let (negative, text) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.as_str()),
+    };
+    let (integer, fraction) = text.split_once('.').unwrap_or((text, ""));
+
+    // 整数部と残す桁を1つの数字列にして、切り捨てる桁が5以上なら末尾に1を足す
+    let mut number = integer.as_bytes().to_vec();
+    number.extend(fraction.bytes().take(digits));
+    number.resize(integer.len() + digits, b'0');
+    if fraction.as_bytes().get(digits).is_some_and(|&d| d >= b'5') {
+        let carried = number.iter_mut().rev().all(|d| {
+            if *d == b'9' {
+                *d = b'0';
+                true
+            } else {
+                *d += 1;
+                false
+            }
+        });
+        if carried {
+            number.insert(0, b'1');
+        }
+    }
+
+    let point = number.len() - digits;
+    let mut rounded = String::with_capacity(number.len() + 2);
+    // 丸めて 0 になった場合は符号を付けない
+    if negative && number.iter().any(|&d| d != b'0') {
+        rounded.push('-');
+    }
+    rounded.push_str(std::str::from_utf8(&number[..point]).expect("数字のみ"));
+    if digits > 0 {
+        rounded.push('.');
+        rounded.push_str(std::str::from_utf8(&number[point..]).expect("数字のみ"));
+    }
+    rounded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_decimal() {
+        assert_eq!(format_decimal(35.6789, None), "35.6789");
+        assert_eq!(format_decimal(35.6789, Some(2)), "35.68");
+        assert_eq!(format_decimal(35.5, Some(0)), "36");
+        assert_eq!(format_decimal(139.0, Some(3)), "139.000");
+        // 2進数では 2.67499... だが、10進表記のまま四捨五入する
+        assert_eq!(format_decimal(2.675, Some(2)), "2.68");
+        assert_eq!(format_decimal(0.125, Some(2)), "0.13");
+        assert_eq!(format_decimal(9.9996, Some(3)), "10.000");
+        assert_eq!(format_decimal(-0.5, Some(0)), "-1");
+        assert_eq!(format_decimal(-0.0004, Some(3)), "0.000");
+        assert_eq!(format_decimal(f64::NAN, Some(2)), "NaN");
+    }
+}
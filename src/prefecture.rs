@@ -0,0 +1,354 @@
+//! メッシュコードから都道府県を概算で判定するための対応表
+//!
+//! 都道府県の範囲は、本土と主な離島ごとのおおよその矩形 (緯度経度) で持つ。行政界そのものではないため、
+//! 1次メッシュと矩形が重なるかどうかで候補を求める概算にしか使えない。
+
+use crate::mesh::{MeshError, level_of, mesh_code_to_bounds};
+
+/// 都道府県
+#[derive(Debug, PartialEq)]
+pub struct Prefecture {
+    /// JIS X 0401 の都道府県コード (`01`〜`47`)
+    pub code: &'static str,
+    pub name: &'static str,
+    /// おおよその範囲 (最小緯度, 最小経度, 最大緯度, 最大経度)。離島を含む都道府県は複数の矩形に分ける
+    bounds: &'static [(f64, f64, f64, f64)],
+}
+
+/// 都道府県の一覧 (都道府県コード順)
+static PREFECTURES: [Prefecture; 47] = [
+    Prefecture {
+        code: "01",
+        name: "北海道",
+        bounds: &[(41.35, 139.33, 45.56, 145.82), (43.3, 145.4, 45.6, 148.9)],
+    },
+    Prefecture {
+        code: "02",
+        name: "青森県",
+        bounds: &[(40.2, 139.85, 41.56, 141.69)],
+    },
+    Prefecture {
+        code: "03",
+        name: "岩手県",
+        bounds: &[(38.74, 140.65, 40.45, 142.08)],
+    },
+    Prefecture {
+        code: "04",
+        name: "宮城県",
+        bounds: &[(37.77, 140.27, 39.0, 141.68)],
+    },
+    Prefecture {
+        code: "05",
+        name: "秋田県",
+        bounds: &[(38.87, 139.69, 40.51, 140.99)],
+    },
+    Prefecture {
+        code: "06",
+        name: "山形県",
+        bounds: &[(37.73, 139.52, 39.21, 140.65)],
+    },
+    Prefecture {
+        code: "07",
+        name: "福島県",
+        bounds: &[(36.79, 139.16, 37.98, 141.05)],
+    },
+    Prefecture {
+        code: "08",
+        name: "茨城県",
+        bounds: &[(35.74, 139.69, 36.95, 140.85)],
+    },
+    Prefecture {
+        code: "09",
+        name: "栃木県",
+        bounds: &[(36.2, 139.33, 37.16, 140.3)],
+    },
+    Prefecture {
+        code: "10",
+        name: "群馬県",
+        bounds: &[(35.98, 138.4, 37.06, 139.67)],
+    },
+    Prefecture {
+        code: "11",
+        name: "埼玉県",
+        bounds: &[(35.75, 138.71, 36.28, 139.9)],
+    },
+    Prefecture {
+        code: "12",
+        name: "千葉県",
+        bounds: &[(34.9, 139.74, 36.1, 140.87)],
+    },
+    Prefecture {
+        code: "13",
+        name: "東京都",
+        bounds: &[
+            (35.5, 138.94, 35.9, 139.92),
+            // 伊豆諸島
+            (32.4, 139.1, 34.8, 140.0),
+            // 小笠原諸島
+            (24.0, 141.0, 27.8, 142.3),
+            // 沖ノ鳥島
+            (20.4, 136.0, 20.5, 136.1),
+            // 南鳥島
+            (24.2, 153.9, 24.4, 154.0),
+        ],
+    },
+    Prefecture {
+        code: "14",
+        name: "神奈川県",
+        bounds: &[(35.13, 138.91, 35.67, 139.84)],
+    },
+    Prefecture {
+        code: "15",
+        name: "新潟県",
+        bounds: &[(36.73, 137.63, 38.55, 139.9), (37.8, 138.2, 38.33, 138.6)],
+    },
+    Prefecture {
+        code: "16",
+        name: "富山県",
+        bounds: &[(36.27, 136.76, 36.98, 137.76)],
+    },
+    Prefecture {
+        code: "17",
+        name: "石川県",
+        bounds: &[(36.07, 136.24, 37.86, 137.37)],
+    },
+    Prefecture {
+        code: "18",
+        name: "福井県",
+        bounds: &[(35.34, 135.45, 36.3, 136.83)],
+    },
+    Prefecture {
+        code: "19",
+        name: "山梨県",
+        bounds: &[(35.17, 138.18, 35.97, 139.13)],
+    },
+    Prefecture {
+        code: "20",
+        name: "長野県",
+        bounds: &[(35.2, 137.32, 37.03, 138.74)],
+    },
+    Prefecture {
+        code: "21",
+        name: "岐阜県",
+        bounds: &[(35.13, 136.27, 36.46, 137.65)],
+    },
+    Prefecture {
+        code: "22",
+        name: "静岡県",
+        bounds: &[(34.57, 137.47, 35.65, 139.18)],
+    },
+    Prefecture {
+        code: "23",
+        name: "愛知県",
+        bounds: &[(34.57, 136.67, 35.42, 137.84)],
+    },
+    Prefecture {
+        code: "24",
+        name: "三重県",
+        bounds: &[(33.72, 135.85, 35.26, 136.99)],
+    },
+    Prefecture {
+        code: "25",
+        name: "滋賀県",
+        bounds: &[(34.79, 135.76, 35.7, 136.46)],
+    },
+    Prefecture {
+        code: "26",
+        name: "京都府",
+        bounds: &[(34.71, 134.85, 35.78, 136.06)],
+    },
+    Prefecture {
+        code: "27",
+        name: "大阪府",
+        bounds: &[(34.27, 135.09, 35.05, 135.75)],
+    },
+    Prefecture {
+        code: "28",
+        name: "兵庫県",
+        bounds: &[(34.15, 134.25, 35.67, 135.47)],
+    },
+    Prefecture {
+        code: "29",
+        name: "奈良県",
+        bounds: &[(33.86, 135.54, 34.78, 136.23)],
+    },
+    Prefecture {
+        code: "30",
+        name: "和歌山県",
+        bounds: &[(33.43, 135.0, 34.39, 136.01)],
+    },
+    Prefecture {
+        code: "31",
+        name: "鳥取県",
+        bounds: &[(35.05, 133.14, 35.62, 134.52)],
+    },
+    Prefecture {
+        code: "32",
+        name: "島根県",
+        bounds: &[
+            (34.3, 131.67, 35.6, 133.39),
+            // 隠岐諸島
+            (35.95, 132.6, 36.35, 133.4),
+            // 竹島
+            (37.2, 131.8, 37.3, 131.9),
+        ],
+    },
+    Prefecture {
+        code: "33",
+        name: "岡山県",
+        bounds: &[(34.3, 133.27, 35.35, 134.41)],
+    },
+    Prefecture {
+        code: "34",
+        name: "広島県",
+        bounds: &[(34.03, 132.04, 35.11, 133.47)],
+    },
+    Prefecture {
+        code: "35",
+        name: "山口県",
+        bounds: &[(33.71, 130.77, 34.8, 132.5)],
+    },
+    Prefecture {
+        code: "36",
+        name: "徳島県",
+        bounds: &[(33.54, 133.66, 34.25, 134.82)],
+    },
+    Prefecture {
+        code: "37",
+        name: "香川県",
+        bounds: &[(34.01, 133.45, 34.56, 134.45)],
+    },
+    Prefecture {
+        code: "38",
+        name: "愛媛県",
+        bounds: &[(32.88, 132.0, 34.3, 133.69)],
+    },
+    Prefecture {
+        code: "39",
+        name: "高知県",
+        bounds: &[(32.7, 132.48, 33.88, 134.31)],
+    },
+    Prefecture {
+        code: "40",
+        name: "福岡県",
+        bounds: &[(33.0, 129.95, 34.25, 131.19)],
+    },
+    Prefecture {
+        code: "41",
+        name: "佐賀県",
+        bounds: &[(32.95, 129.74, 33.62, 130.55)],
+    },
+    Prefecture {
+        code: "42",
+        name: "長崎県",
+        bounds: &[(32.57, 128.1, 34.73, 130.4)],
+    },
+    Prefecture {
+        code: "43",
+        name: "熊本県",
+        bounds: &[(32.09, 129.93, 33.2, 131.33)],
+    },
+    Prefecture {
+        code: "44",
+        name: "大分県",
+        bounds: &[(32.71, 130.82, 33.74, 132.1)],
+    },
+    Prefecture {
+        code: "45",
+        name: "宮崎県",
+        bounds: &[(31.35, 130.7, 32.84, 131.89)],
+    },
+    Prefecture {
+        code: "46",
+        name: "鹿児島県",
+        bounds: &[
+            (30.95, 129.55, 32.25, 131.2),
+            // 大隅諸島・トカラ列島・奄美群島
+            (27.0, 128.4, 30.9, 131.1),
+        ],
+    },
+    Prefecture {
+        code: "47",
+        name: "沖縄県",
+        bounds: &[
+            // 沖縄諸島
+            (26.0, 126.6, 27.9, 128.4),
+            // 先島諸島
+            (24.0, 122.9, 25.0, 125.5),
+            // 尖閣諸島
+            (25.7, 123.4, 26.0, 124.6),
+            // 大東諸島
+            (24.4, 131.1, 26.0, 131.4),
+        ],
+    },
+];
+
+/// メッシュコードの1次メッシュと範囲が重なる都道府県を、都道府県コード順に返す
+///
+/// 1次メッシュ (約80km四方) は複数の都道府県にまたがることが多いため、代表を1つに決めず、候補をすべて返す。
+/// 都道府県の範囲はおおよその矩形のため、実際には含まれない都道府県が候補に入ることもある。
+/// どの都道府県とも重ならない (海上など) 場合は空になる。
+///
+/// ```
+/// let names = meshify::prefecture::prefectures_of_mesh("53394611")
+///     .unwrap()
+///     .iter()
+///     .map(|prefecture| prefecture.name)
+///     .collect::<Vec<&str>>();
+/// assert!(names.contains(&"東京都") && names.contains(&"神奈川県"));
+/// ```
+pub fn prefectures_of_mesh(code: &str) -> Result<Vec<&'static Prefecture>, MeshError> {
+    level_of(code)?;
+    let (min_lon, min_lat, max_lon, max_lat) = mesh_code_to_bounds(&code[..4])?;
+    Ok(PREFECTURES
+        .iter()
+        .filter(|prefecture| {
+            prefecture.bounds.iter().any(|&(south, west, north, east)| {
+                south < max_lat && min_lat <= north && west < max_lon && min_lon <= east
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(code: &str) -> Vec<&'static str> {
+        prefectures_of_mesh(code)
+            .unwrap()
+            .iter()
+            .map(|prefecture| prefecture.name)
+            .collect()
+    }
+
+    #[test]
+    fn test_prefectures_of_mesh() {
+        // 札幌周辺は北海道だけ
+        assert_eq!(names("6441"), ["北海道"]);
+        assert_eq!(names("644142"), ["北海道"]);
+        // 沖ノ鳥島・与那国島のような離島も判定できる
+        assert_eq!(names("3036"), ["東京都"]);
+        assert_eq!(names("3622"), ["沖縄県"]);
+        // 複数の都道府県にまたがる1次メッシュは候補をすべて返す
+        let tokyo = prefectures_of_mesh("5339").unwrap();
+        assert!(tokyo.len() > 1);
+        assert!(tokyo.windows(2).all(|pair| pair[0].code < pair[1].code));
+        // 海上の1次メッシュ
+        assert!(names("4045").is_empty());
+
+        assert!(prefectures_of_mesh("53a9").is_err());
+        assert!(prefectures_of_mesh("533").is_err());
+    }
+
+    #[test]
+    fn test_prefecture_table() {
+        for (i, prefecture) in PREFECTURES.iter().enumerate() {
+            assert_eq!(prefecture.code, format!("{:02}", i + 1));
+            assert!(!prefecture.bounds.is_empty());
+            for &(south, west, north, east) in prefecture.bounds {
+                assert!(south < north && west < east, "{}", prefecture.name);
+            }
+        }
+    }
+}
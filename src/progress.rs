@@ -0,0 +1,90 @@
+//! 処理の進捗をstderrに表示する
+
+use indicatif::{ProgressBar, ProgressStyle};
+use std::io::{self, Read};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// 処理済み行数と推定残り時間を表示する進捗バー
+///
+/// 総量は入力ファイルのバイト数で表し、実際に読み込んだバイト数から進捗を求める。
+/// 入力の文字エンコーディングを変換する場合でも、変換前のバイト数で数えるため正しく進む。
+pub struct Progress {
+    bar: ProgressBar,
+    bytes_read: Arc<AtomicU64>,
+    rows: u64,
+}
+
+impl Progress {
+    /// 進捗表示を作成する。総バイト数が不明な場合 (標準入力など) はスピナー表示にする
+    pub fn new(total_bytes: Option<u64>) -> Self {
+        let bar = match total_bytes {
+            Some(total) => {
+                let bar = ProgressBar::new(total);
+                bar.set_style(
+                    ProgressStyle::with_template(
+                        "{spinner} [{elapsed_precise}] {wide_bar} {percent}% 残り{eta} {msg}",
+                    )
+                    .unwrap(),
+                );
+                bar
+            }
+            None => {
+                let bar = ProgressBar::new_spinner();
+                bar.set_style(
+                    ProgressStyle::with_template("{spinner} [{elapsed_precise}] {msg}").unwrap(),
+                );
+                bar
+            }
+        };
+        Progress {
+            bar,
+            bytes_read: Arc::new(AtomicU64::new(0)),
+            rows: 0,
+        }
+    }
+
+    /// 何も表示しない進捗表示を作成する
+    pub fn hidden() -> Self {
+        Progress {
+            bar: ProgressBar::hidden(),
+            bytes_read: Arc::new(AtomicU64::new(0)),
+            rows: 0,
+        }
+    }
+
+    /// 読み込んだバイト数を数えるように入力をラップする
+    pub fn wrap_reader(&self, inner: Box<dyn Read>) -> Box<dyn Read> {
+        Box::new(CountingReader {
+            inner,
+            count: Arc::clone(&self.bytes_read),
+        })
+    }
+
+    /// 処理済みの行数を加算して表示を更新する
+    pub fn inc_rows(&mut self, rows: u64) {
+        self.rows += rows;
+        self.bar
+            .set_position(self.bytes_read.load(Ordering::Relaxed));
+        self.bar.set_message(format!("{}行", self.rows));
+    }
+
+    /// 表示を消去して終了する
+    pub fn finish(&self) {
+        self.bar.finish_and_clear();
+    }
+}
+
+/// 読み込んだバイト数を数える `Read` のラッパー
+struct CountingReader<R> {
+    inner: R,
+    count: Arc<AtomicU64>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
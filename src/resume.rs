@@ -0,0 +1,170 @@
+//! 途中までの出力に追記して処理を再開する (`--resume-from`)
+
+use crate::output::RecordWriter;
+use encoding_rs::Encoding;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// 追記先の既存の出力
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExistingOutput {
+    /// 見出し行 (`--no-header` の場合は `None`)
+    pub headers: Option<Vec<String>>,
+    /// 見出し行を除いた、出力済みの行数
+    pub rows: u64,
+}
+
+impl ExistingOutput {
+    /// 既存の出力を読み、見出し行と出力済みの行数を求める
+    ///
+    /// 最後の行が改行で終わっていない場合は、書き出しの途中で止まった行が残っている可能性があるためエラーにする。
+    pub fn read(
+        path: &Path,
+        delimiter: u8,
+        has_headers: bool,
+        encoding: &'static Encoding,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut file = File::open(path)?;
+        if file.metadata()?.len() > 0 {
+            let mut last = [0];
+            file.seek(SeekFrom::End(-1))?;
+            file.read_exact(&mut last)?;
+            if last[0] != b'\n' {
+                return Err(
+                    "最後の行が改行で終わっていません (書きかけの行を削除してから再開してください)"
+                        .into(),
+                );
+            }
+            file.rewind()?;
+        }
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(delimiter)
+            .has_headers(has_headers)
+            .from_reader(BufReader::new(file));
+        let headers = if has_headers {
+            let headers = reader.byte_headers()?;
+            if headers.is_empty() {
+                return Err("見出し行がありません".into());
+            }
+            Some(
+                headers
+                    .iter()
+                    .map(|field| encoding.decode_without_bom_handling(field).0.into_owned())
+                    .collect(),
+            )
+        } else {
+            None
+        };
+        let mut rows = 0;
+        let mut record = csv::ByteRecord::new();
+        while reader.read_byte_record(&mut record)? {
+            rows += 1;
+        }
+        Ok(ExistingOutput { headers, rows })
+    }
+
+    /// 再開位置より前の入力の行数 (`input_rows`) と、出力済みの行数が矛盾しないかを確かめる
+    ///
+    /// `exact` は入力の行をすべて出力する (スキップ・絞り込みが無い) 場合で、行数が一致しなければエラーにする。
+    /// それ以外は出力しなかった行の分だけ少なくなるため、入力の行数を超えていないことだけを確かめる。
+    pub fn check_rows(&self, input_rows: u64, exact: bool) -> Result<(), String> {
+        if self.rows > input_rows || (exact && self.rows != input_rows) {
+            return Err(format!(
+                "出力済みの行数 ({}行) が、再開位置より前の入力の行数 ({}行) と合いません (--resume-from には最後に出力した行の次の行番号を指定してください)",
+                self.rows, input_rows
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// 既存の出力に追記する。見出し行は書き出さず、既存の見出し行と一致するかだけを確かめる
+pub struct AppendWriter<W: RecordWriter> {
+    writer: W,
+    headers: Option<Vec<String>>,
+}
+
+impl<W: RecordWriter> AppendWriter<W> {
+    pub fn new(writer: W, headers: Option<Vec<String>>) -> Self {
+        AppendWriter { writer, headers }
+    }
+}
+
+impl<W: RecordWriter> RecordWriter for AppendWriter<W> {
+    fn write_headers(&mut self, headers: &[String]) -> io::Result<()> {
+        match &self.headers {
+            Some(existing) if existing != headers => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "追記先の見出し行 ({}) が出力する列 ({}) と一致しません (前回と同じ指定で実行してください)",
+                    existing.join(","),
+                    headers.join(",")
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn write_row(&mut self, fields: &[&str], mesh_code: &str) -> io::Result<()> {
+        self.writer.write_row(fields, mesh_code)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_existing_output() {
+        let path =
+            std::env::temp_dir().join(format!("meshify_existing_output_{}", std::process::id()));
+        let read =
+            |has_headers: bool| ExistingOutput::read(&path, b',', has_headers, encoding_rs::UTF_8);
+
+        fs::write(&path, "\u{feff}id,\"mesh\ncode\"\n1,\"a\nb\"\n2,53394611\n").unwrap();
+        let existing = read(true).unwrap();
+        assert_eq!(
+            existing.headers,
+            Some(vec!["id".to_string(), "mesh\ncode".to_string()])
+        );
+        assert_eq!(existing.rows, 2);
+        assert_eq!(read(false).unwrap().rows, 3);
+
+        assert!(existing.check_rows(2, true).is_ok());
+        assert!(existing.check_rows(3, false).is_ok());
+        assert!(existing.check_rows(3, true).is_err());
+        assert!(existing.check_rows(1, false).is_err());
+
+        // 書きかけの行が残っている
+        fs::write(&path, "id,mesh_code\n1,5339").unwrap();
+        assert!(read(true).is_err());
+        fs::write(&path, "").unwrap();
+        assert!(read(true).is_err());
+        assert_eq!(read(false).unwrap().rows, 0);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_append_writer() {
+        let headers = vec!["id".to_string(), "mesh_code".to_string()];
+        let mut writer =
+            AppendWriter::new(csv::Writer::from_writer(Vec::new()), Some(headers.clone()));
+        writer.write_headers(&headers).unwrap();
+        writer.write_row(&["3", "53394611"], "53394611").unwrap();
+        assert!(writer.write_headers(&headers[..1]).is_err());
+        writer.finish().unwrap();
+        let output = String::from_utf8(writer.writer.into_inner().unwrap()).unwrap();
+        assert_eq!(output, "3,53394611\n");
+    }
+}
@@ -0,0 +1,355 @@
+//! メッシュコードを付与した行の SQLite のデータベースファイルでの書き出し
+
+use crate::output::RecordWriter;
+use crate::parquet_output::ColumnType;
+use clap::ValueEnum;
+use rusqlite::Connection;
+use rusqlite::types::Value;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// 列の型を推論する場合に、テーブルを作る前にためる行数
+const INFER_ROWS: usize = 100_000;
+
+/// 同じプロセスで複数のデータベースを作る場合に、作業用のファイル名を分ける番号
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// SQLiteの列の型の決め方
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum SqliteSchema {
+    /// すべての列を TEXT にする
+    Text,
+    /// 最初の10万行の値から、列ごとに INTEGER・REAL・TEXT のいずれかに決める (空欄は NULL にする)
+    ///
+    /// 先頭に0が付く値 (郵便番号など) は、数値にすると元の表記が失われるため TEXT として扱う。
+    Infer,
+}
+
+/// メッシュコードを付与した行をSQLiteのテーブルに書き出し、データベースファイルとして出力する
+///
+/// SQLiteはファイルを直接読み書きするため、作業用のファイルにデータベースを作り、書き終えてから出力先に
+/// 移す。行は1つのトランザクションでまとめて挿入し、インデックスは挿入を終えてから作る。
+pub struct SqliteWriter<W: Write> {
+    output: W,
+    schema: SqliteSchema,
+    table: String,
+    /// インデックスを張る列 (見出し行に無い列は無視する)
+    index_columns: Vec<String>,
+    connection: Option<Connection>,
+    /// 接続を閉じてから消すよう、`connection` の後に置く
+    database: TempFile,
+    headers: Vec<String>,
+    /// テーブルを作る前の、型の推論に使う行
+    pending: Vec<Vec<String>>,
+    /// テーブルを作る際に決める
+    types: Option<Vec<ColumnType>>,
+    insert_sql: String,
+}
+
+impl<W: Write> SqliteWriter<W> {
+    pub fn new(
+        output: W,
+        schema: SqliteSchema,
+        table: &str,
+        index_columns: Vec<String>,
+    ) -> io::Result<Self> {
+        let path = std::env::temp_dir().join(format!(
+            "meshify_{}_{}.sqlite",
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        let database = TempFile(path);
+        let connection = Connection::open(&database.0).map_err(io::Error::other)?;
+        // 作業用のファイルは書き終えてから出力先に移すため、途中で失われても困らない
+        connection
+            .execute_batch("PRAGMA journal_mode = OFF; PRAGMA synchronous = OFF; BEGIN;")
+            .map_err(io::Error::other)?;
+        Ok(SqliteWriter {
+            output,
+            schema,
+            table: table.to_string(),
+            index_columns,
+            connection: Some(connection),
+            database,
+            headers: Vec::new(),
+            pending: Vec::new(),
+            types: None,
+            insert_sql: String::new(),
+        })
+    }
+
+    #[cfg(test)]
+    pub fn into_inner(self) -> W {
+        self.output
+    }
+
+    fn connection(&self) -> &Connection {
+        self.connection
+            .as_ref()
+            .expect("書き出しを終えるまで開いている")
+    }
+
+    /// 列の型を決めてテーブルを作り、ためた行を挿入する
+    fn create_table(&mut self) -> io::Result<()> {
+        let types = match self.schema {
+            SqliteSchema::Text => vec![ColumnType::Utf8; self.headers.len()],
+            SqliteSchema::Infer => (0..self.headers.len())
+                .map(|i| {
+                    let values = self
+                        .pending
+                        .iter()
+                        .map(|row| row[i].clone())
+                        .collect::<Vec<String>>();
+                    ColumnType::infer(&values)
+                })
+                .collect(),
+        };
+        let columns = self
+            .headers
+            .iter()
+            .zip(&types)
+            .map(|(name, column_type)| {
+                let sql_type = match column_type {
+                    ColumnType::Utf8 => "TEXT",
+                    ColumnType::Int64 => "INTEGER",
+                    ColumnType::Double => "REAL",
+                };
+                format!("{} {}", quote(name), sql_type)
+            })
+            .collect::<Vec<String>>();
+        let sql = format!(
+            "CREATE TABLE {} ({})",
+            quote(&self.table),
+            columns.join(", ")
+        );
+        self.connection()
+            .execute(&sql, [])
+            .map_err(|e| io::Error::other(format!("テーブルを作れません: {}", e)))?;
+        self.types = Some(types);
+        self.insert_sql = format!(
+            "INSERT INTO {} VALUES ({})",
+            quote(&self.table),
+            vec!["?"; self.headers.len()].join(", ")
+        );
+        for row in std::mem::take(&mut self.pending) {
+            self.insert(&row.iter().map(String::as_str).collect::<Vec<&str>>())?;
+        }
+        Ok(())
+    }
+
+    /// 1行を挿入する。推論した型で表せない値はエラーにする
+    fn insert(&self, fields: &[&str]) -> io::Result<()> {
+        let types = self.types.as_ref().expect("テーブルを作成済み");
+        let nullable = self.schema == SqliteSchema::Infer;
+        let values = self
+            .headers
+            .iter()
+            .zip(types)
+            .zip(fields)
+            .map(|((name, &column_type), &value)| {
+                if nullable && value.is_empty() {
+                    return Ok(Value::Null);
+                }
+                if !column_type.accepts(value) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "列「{}」の値「{}」を、最初の{}行から推論した型 ({}) で書き出せません (--sqlite-schema text を指定してください)",
+                            name,
+                            value,
+                            INFER_ROWS,
+                            column_type.name()
+                        ),
+                    ));
+                }
+                Ok(match column_type {
+                    ColumnType::Utf8 => Value::Text(value.to_string()),
+                    ColumnType::Int64 => Value::Integer(value.parse().expect("整数と確かめた値")),
+                    ColumnType::Double => Value::Real(value.parse().expect("小数と確かめた値")),
+                })
+            })
+            .collect::<io::Result<Vec<Value>>>()?;
+        self.connection()
+            .prepare_cached(&self.insert_sql)
+            .and_then(|mut statement| statement.execute(rusqlite::params_from_iter(values)))
+            .map_err(io::Error::other)?;
+        Ok(())
+    }
+}
+
+/// テーブル名・列名を SQL の識別子として引用符で囲む
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+impl<W: Write> RecordWriter for SqliteWriter<W> {
+    fn write_headers(&mut self, headers: &[String]) -> io::Result<()> {
+        self.headers = headers.to_vec();
+        Ok(())
+    }
+
+    fn write_row(&mut self, fields: &[&str], _mesh_code: &str) -> io::Result<()> {
+        if self.types.is_some() {
+            return self.insert(fields);
+        }
+        self.pending
+            .push(fields.iter().map(|field| field.to_string()).collect());
+        let sample = match self.schema {
+            SqliteSchema::Text => 1,
+            SqliteSchema::Infer => INFER_ROWS,
+        };
+        if self.pending.len() == sample {
+            self.create_table()?;
+        }
+        Ok(())
+    }
+
+    /// データベースファイルは書き終えてから出力先に移すため、途中では何も送らない
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        // 行が無い場合も、列だけのテーブルを作る
+        if self.types.is_none() {
+            self.create_table()?;
+        }
+        for name in &self.index_columns {
+            if !self.headers.contains(name) {
+                continue;
+            }
+            let sql = format!(
+                "CREATE INDEX {} ON {} ({})",
+                quote(&format!("{}_{}_idx", self.table, name)),
+                quote(&self.table),
+                quote(name)
+            );
+            self.connection().execute(&sql, []).map_err(|e| {
+                io::Error::other(format!("列「{}」のインデックスを作れません: {}", name, e))
+            })?;
+        }
+        let connection = self
+            .connection
+            .take()
+            .expect("書き出しを終えるまで開いている");
+        connection
+            .execute_batch("COMMIT;")
+            .map_err(io::Error::other)?;
+        connection.close().map_err(|(_, e)| io::Error::other(e))?;
+
+        io::copy(&mut File::open(&self.database.0)?, &mut self.output)?;
+        self.output.flush()
+    }
+}
+
+/// 作業用のファイル。書き出しを終えた場合も途中でエラーになった場合も、破棄する際に消す
+struct TempFile(PathBuf);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 行を書き出したデータベースを開き直し、テーブルの内容を型の名前と値の組で返す
+    fn roundtrip(
+        schema: SqliteSchema,
+        rows: &[[&str; 3]],
+        index: bool,
+    ) -> io::Result<(Vec<Vec<String>>, Vec<String>)> {
+        let index_columns = if index {
+            vec!["mesh_code".to_string(), "missing".to_string()]
+        } else {
+            Vec::new()
+        };
+        let mut writer = SqliteWriter::new(Vec::new(), schema, "points", index_columns)?;
+        let headers = ["id", "zip", "mesh_code"].map(String::from);
+        writer.write_headers(&headers)?;
+        for row in rows {
+            writer.write_row(row, row[2])?;
+        }
+        writer.finish()?;
+
+        let path = std::env::temp_dir().join(format!(
+            "meshify_sqlite_{:?}_{}.db",
+            schema,
+            std::process::id()
+        ));
+        fs::write(&path, writer.into_inner())?;
+        let connection = Connection::open(&path).unwrap();
+        let mut statement = connection
+            .prepare(
+                "SELECT typeof(id), id, typeof(zip), zip, typeof(mesh_code), mesh_code FROM points",
+            )
+            .unwrap();
+        let values = statement
+            .query_map([], |row| {
+                (0..6)
+                    .map(|i| {
+                        let value: Value = row.get(i)?;
+                        Ok(match value {
+                            Value::Null => "NULL".to_string(),
+                            Value::Integer(n) => n.to_string(),
+                            Value::Real(x) => x.to_string(),
+                            Value::Text(s) => s,
+                            Value::Blob(_) => unreachable!("BLOBは書き出さない"),
+                        })
+                    })
+                    .collect::<rusqlite::Result<Vec<String>>>()
+            })
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .unwrap();
+        let indexes = connection
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'index'")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<String>>>()
+            .unwrap();
+        drop(statement);
+        connection.close().unwrap();
+        fs::remove_file(&path)?;
+        Ok((values, indexes))
+    }
+
+    const ROWS: [[&str; 3]; 2] = [["1", "0010001", "53394611"], ["2.5", "", "53394610"]];
+
+    #[test]
+    fn test_sqlite_writer() {
+        let (rows, indexes) = roundtrip(SqliteSchema::Text, &ROWS, false).unwrap();
+        assert_eq!(
+            rows,
+            [
+                ["text", "1", "text", "0010001", "text", "53394611"],
+                ["text", "2.5", "text", "", "text", "53394610"]
+            ]
+        );
+        assert!(indexes.is_empty());
+
+        let (rows, indexes) = roundtrip(SqliteSchema::Infer, &ROWS, true).unwrap();
+        assert_eq!(
+            rows,
+            [
+                ["real", "1", "text", "0010001", "integer", "53394611"],
+                ["real", "2.5", "null", "NULL", "integer", "53394610"]
+            ]
+        );
+        assert_eq!(indexes, ["points_mesh_code_idx"]);
+
+        let (rows, _) = roundtrip(SqliteSchema::Infer, &[], true).unwrap();
+        assert!(rows.is_empty());
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("points"), "\"points\"");
+        assert_eq!(quote("a\"b"), "\"a\"\"b\"");
+    }
+}
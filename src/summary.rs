@@ -0,0 +1,264 @@
+//! 処理終了時に表示する行数の集計
+
+use serde_json::{Map, Value, json};
+use std::fmt;
+use std::ops::AddAssign;
+
+/// 行をスキップした理由
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SkipReason {
+    /// 緯度経度列の値を緯度と経度に分割できない
+    InvalidLatLon,
+    /// 緯度の値が不正
+    InvalidLat,
+    /// 経度の値が不正
+    InvalidLon,
+    /// 座標が日本の範囲外
+    OutOfRange,
+    /// 集計に使う重みの値が不正
+    InvalidWeight,
+    /// NDJSONの行がJSONオブジェクトとして、GeoJSONの値がFeatureとして解釈できない
+    InvalidJson,
+    /// `--datum-column` の測地系の値が不正
+    InvalidDatum,
+    /// 緯度・経度の値が空欄
+    EmptyCoord,
+    /// GeoJSONのFeatureのジオメトリがPointでない (`--non-point skip`)
+    NonPoint,
+    /// `--z` の列の値が数値でない (`--invalid-z skip`)
+    InvalidZ,
+}
+
+impl SkipReason {
+    const ALL: [SkipReason; 10] = [
+        SkipReason::InvalidLatLon,
+        SkipReason::InvalidLat,
+        SkipReason::InvalidLon,
+        SkipReason::OutOfRange,
+        SkipReason::InvalidWeight,
+        SkipReason::InvalidJson,
+        SkipReason::InvalidDatum,
+        SkipReason::EmptyCoord,
+        SkipReason::NonPoint,
+        SkipReason::InvalidZ,
+    ];
+
+    /// `--stats-json` で内訳のキーにする名前
+    fn key(self) -> &'static str {
+        match self {
+            SkipReason::InvalidLatLon => "invalid_latlon",
+            SkipReason::InvalidLat => "invalid_lat",
+            SkipReason::InvalidLon => "invalid_lon",
+            SkipReason::OutOfRange => "out_of_range",
+            SkipReason::InvalidWeight => "invalid_weight",
+            SkipReason::InvalidJson => "invalid_json",
+            SkipReason::InvalidDatum => "invalid_datum",
+            SkipReason::EmptyCoord => "empty_coord",
+            SkipReason::NonPoint => "non_point",
+            SkipReason::InvalidZ => "invalid_z",
+        }
+    }
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SkipReason::InvalidLatLon => "緯度経度の分割不可",
+            SkipReason::InvalidLat => "緯度不正",
+            SkipReason::InvalidLon => "経度不正",
+            SkipReason::OutOfRange => "範囲外",
+            SkipReason::InvalidWeight => "重み不正",
+            SkipReason::InvalidJson => "JSON不正",
+            SkipReason::InvalidDatum => "測地系不正",
+            SkipReason::EmptyCoord => "座標空欄",
+            SkipReason::NonPoint => "Point以外",
+            SkipReason::InvalidZ => "Z値不正",
+        };
+        f.write_str(name)
+    }
+}
+
+/// 読み込み・出力・スキップした行数
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Summary {
+    /// 読み込んだデータ行数 (ヘッダーを除く)
+    pub read: u64,
+    /// 出力した行数
+    pub written: u64,
+    /// 範囲外のためメッシュコードを空欄にして出力した行数 (`written` に含まれる)
+    pub blank: u64,
+    /// 座標が空欄のため、メッシュコードを空欄にして出力した行数 (`--empty-as keep`。`written` に含まれる)
+    pub empty: u64,
+    /// 座標列の組のうち一部が不正などのため、その組のメッシュコードを空欄にして出力した行数 (`written` に含まれる)
+    pub partial: u64,
+    /// 座標などが不正なため、メッシュコードを空欄にして出力した行数 (`--keep-invalid`。`written` に含まれる)
+    pub invalid: u64,
+    /// メッシュコードが `--filter-mesh` の接頭辞で始まらないため出力しなかった行数 (スキップとは別に数える)
+    pub filtered: u64,
+    /// `--unique-mesh` で、メッシュコードが出力済みの行と同じため出力しなかった行数
+    pub duplicates: u64,
+    skipped: [u64; SkipReason::ALL.len()],
+}
+
+impl Summary {
+    /// スキップした行を理由ごとに数える
+    pub fn skip(&mut self, reason: SkipReason) {
+        self.skipped[reason as usize] += 1;
+    }
+
+    /// 指定の理由でスキップした行数
+    pub fn skipped_by(&self, reason: SkipReason) -> u64 {
+        self.skipped[reason as usize]
+    }
+
+    /// スキップした行数の合計
+    pub fn skipped(&self) -> u64 {
+        self.skipped.iter().sum()
+    }
+
+    /// 行数を機械可読なJSONのオブジェクトにする (`--stats-json`)。スキップの内訳は該当しない理由も0として含める
+    pub fn to_json(&self) -> Value {
+        let skipped_by_reason = SkipReason::ALL
+            .iter()
+            .map(|&reason| (reason.key().to_string(), json!(self.skipped_by(reason))))
+            .collect::<Map<String, Value>>();
+        json!({
+            "read": self.read,
+            "written": self.written,
+            "skipped": self.skipped(),
+            "skipped_by_reason": skipped_by_reason,
+            "blank": self.blank,
+            "empty": self.empty,
+            "partial": self.partial,
+            "invalid": self.invalid,
+            "filtered": self.filtered,
+            "duplicates": self.duplicates,
+        })
+    }
+}
+
+impl AddAssign<&Summary> for Summary {
+    fn add_assign(&mut self, other: &Summary) {
+        self.read += other.read;
+        self.written += other.written;
+        self.blank += other.blank;
+        self.empty += other.empty;
+        self.partial += other.partial;
+        self.invalid += other.invalid;
+        self.filtered += other.filtered;
+        self.duplicates += other.duplicates;
+        for (count, other) in self.skipped.iter_mut().zip(other.skipped) {
+            *count += other;
+        }
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "読み込み {}行、出力 {}行、スキップ {}行",
+            self.read,
+            self.written,
+            self.skipped()
+        )?;
+        // 内訳は該当する理由だけを表示する
+        let breakdown = SkipReason::ALL
+            .iter()
+            .filter(|&&reason| self.skipped_by(reason) > 0)
+            .map(|&reason| format!("{} {}行", reason, self.skipped_by(reason)))
+            .collect::<Vec<String>>();
+        if !breakdown.is_empty() {
+            write!(f, " ({})", breakdown.join("、"))?;
+        }
+        if self.filtered > 0 {
+            write!(f, "、--filter-mesh で除外 {}行", self.filtered)?;
+        }
+        if self.duplicates > 0 {
+            write!(f, "、--unique-mesh で除外 {}行", self.duplicates)?;
+        }
+        if self.blank > 0 {
+            write!(f, "。うち範囲外でメッシュコードが空欄の行 {}行", self.blank)?;
+        }
+        if self.empty > 0 {
+            write!(
+                f,
+                "。うち座標が空欄でメッシュコードが空欄の行 {}行",
+                self.empty
+            )?;
+        }
+        if self.invalid > 0 {
+            write!(
+                f,
+                "。うち不正な値でメッシュコードが空欄の行 {}行",
+                self.invalid
+            )?;
+        }
+        if self.partial > 0 {
+            write!(
+                f,
+                "。うち一部の座標の組のメッシュコードが空欄の行 {}行",
+                self.partial
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary_display() {
+        let mut summary = Summary {
+            read: 10,
+            written: 7,
+            ..Summary::default()
+        };
+        assert_eq!(summary.to_string(), "読み込み 10行、出力 7行、スキップ 0行");
+
+        summary.skip(SkipReason::InvalidLat);
+        summary.skip(SkipReason::OutOfRange);
+        summary.skip(SkipReason::OutOfRange);
+        summary.blank = 1;
+        assert_eq!(summary.skipped(), 3);
+        assert_eq!(
+            summary.to_string(),
+            "読み込み 10行、出力 7行、スキップ 3行 (緯度不正 1行、範囲外 2行)。うち範囲外でメッシュコードが空欄の行 1行"
+        );
+
+        summary.filtered = 4;
+        assert!(
+            summary
+                .to_string()
+                .contains("スキップ 3行 (緯度不正 1行、範囲外 2行)、--filter-mesh で除外 4行。")
+        );
+        summary.duplicates = 5;
+        assert!(
+            summary
+                .to_string()
+                .contains("、--filter-mesh で除外 4行、--unique-mesh で除外 5行。")
+        );
+
+        summary.partial = 2;
+        assert!(
+            summary
+                .to_string()
+                .ends_with("。うち一部の座標の組のメッシュコードが空欄の行 2行")
+        );
+
+        let json = summary.to_json();
+        assert_eq!(json["read"], 10);
+        assert_eq!(json["skipped"], 3);
+        assert_eq!(json["skipped_by_reason"]["out_of_range"], 2);
+        assert_eq!(json["skipped_by_reason"]["invalid_json"], 0);
+        assert_eq!(json["duplicates"], 5);
+
+        let mut total = Summary::default();
+        total += &summary;
+        total += &summary;
+        assert_eq!(total.read, 20);
+        assert_eq!(total.skipped_by(SkipReason::OutOfRange), 4);
+    }
+}
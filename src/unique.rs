@@ -0,0 +1,86 @@
+//! メッシュコードで重複を除いた出力 (`--unique-mesh`)
+
+use crate::output::RecordWriter;
+use clap::ValueEnum;
+use std::io;
+
+/// 同じメッシュコードの行のうち、最初の行をどのように出力するか
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum UniqueOutput {
+    /// 元の列をすべて残して出力する
+    Row,
+    /// メッシュコード列だけを出力する
+    Code,
+}
+
+/// 重複を除いた行の並び順
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum UniqueOrder {
+    /// 入力に最初に現れた順
+    Input,
+    /// メッシュコードの昇順 (すべての行をメモリに持ってから並べ替える)
+    Code,
+}
+
+/// 行をためておき、終了時にメッシュコードの昇順に並べ替えて書き出す
+///
+/// 同じメッシュコードの行は入力の順のまま並べる。
+pub struct SortedWriter<W: RecordWriter> {
+    writer: W,
+    rows: Vec<(String, Vec<String>)>,
+}
+
+impl<W: RecordWriter> SortedWriter<W> {
+    pub fn new(writer: W) -> Self {
+        SortedWriter {
+            writer,
+            rows: Vec::new(),
+        }
+    }
+}
+
+impl<W: RecordWriter> RecordWriter for SortedWriter<W> {
+    fn write_headers(&mut self, headers: &[String]) -> io::Result<()> {
+        self.writer.write_headers(headers)
+    }
+
+    fn write_row(&mut self, fields: &[&str], mesh_code: &str) -> io::Result<()> {
+        let fields = fields.iter().map(|field| field.to_string()).collect();
+        self.rows.push((mesh_code.to_string(), fields));
+        Ok(())
+    }
+
+    /// 並べ替えるまで書き出せないため、途中では何もしない
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let mut rows = std::mem::take(&mut self.rows);
+        rows.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for (mesh_code, fields) in &rows {
+            let fields = fields.iter().map(String::as_str).collect::<Vec<&str>>();
+            self.writer.write_row(&fields, mesh_code)?;
+        }
+        self.writer.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sorted_writer() {
+        let mut writer = SortedWriter::new(csv::Writer::from_writer(Vec::new()));
+        writer
+            .write_headers(&["id".to_string(), "mesh_code".to_string()])
+            .unwrap();
+        for (id, code) in [("1", "53394611"), ("2", "53394610"), ("3", "53394611")] {
+            writer.write_row(&[id, code], code).unwrap();
+        }
+        writer.finish().unwrap();
+        let output = String::from_utf8(writer.writer.into_inner().unwrap()).unwrap();
+        assert_eq!(output, "id,mesh_code\n2,53394610\n1,53394611\n3,53394611\n");
+    }
+}
@@ -0,0 +1,73 @@
+//! UTM 座標系 (WGS84, EPSG:32601〜32660・32701〜32760) のゾーンの扱い
+//!
+//! UTM の座標は東距 (X)・北距 (Y) の順に書かれることが多いが、平面直角座標系と同様に北距の列を緯度と同じ位置
+//! (`--lat`)、東距の列を経度と同じ位置 (`--lon`) に指定する。変換時には (東距, 北距) の順で PROJ に渡す。
+//! 日本は 51N (与那国島など) から 56N (南鳥島) にまたがり、本州は東経138度を境に 53N と 54N に分かれる。
+
+/// ゾーンの指定 (`54`・`54N`・`53S`) を読む。北半球・南半球の記号を省略した場合は北半球とする
+///
+/// ```
+/// assert_eq!(meshify::utm::parse_zone("54N"), Some((54, true)));
+/// assert_eq!(meshify::utm::parse_zone("53"), Some((53, true)));
+/// assert_eq!(meshify::utm::parse_zone("61"), None);
+/// ```
+pub fn parse_zone(s: &str) -> Option<(u8, bool)> {
+    let s = s.trim();
+    let (number, north) = match s.char_indices().last()? {
+        (i, 'N' | 'n') => (&s[..i], true),
+        (i, 'S' | 's') => (&s[..i], false),
+        _ => (s, true),
+    };
+    let zone = number.parse::<u8>().ok()?;
+    (1..=60).contains(&zone).then_some((zone, north))
+}
+
+/// ゾーンに対応するCRS (54N が `EPSG:32654`、54S が `EPSG:32754`)
+pub fn zone_crs((zone, north): (u8, bool)) -> String {
+    assert!((1..=60).contains(&zone), "ゾーン番号は1〜60");
+    let base = if north { 32600 } else { 32700 };
+    format!("EPSG:{}", base + u32::from(zone))
+}
+
+/// 経度が含まれるゾーンの番号 (経度6度ごと。ノルウェー・スバールバル周辺の例外は考慮しない)
+///
+/// ```
+/// // 東京 (東経139.76度) は 54N、大阪 (東経135.50度) は 53N
+/// assert_eq!(meshify::utm::zone_of(139.76), 54);
+/// assert_eq!(meshify::utm::zone_of(135.50), 53);
+/// ```
+pub fn zone_of(lon: f64) -> u8 {
+    let zone = ((lon + 180.0) / 6.0).floor() as i64 + 1;
+    zone.clamp(1, 60) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_zone() {
+        assert_eq!(parse_zone("53N"), Some((53, true)));
+        assert_eq!(parse_zone("54n"), Some((54, true)));
+        assert_eq!(parse_zone(" 54 "), Some((54, true)));
+        assert_eq!(parse_zone("1S"), Some((1, false)));
+        assert_eq!(parse_zone("0N"), None);
+        assert_eq!(parse_zone("N"), None);
+        assert_eq!(parse_zone("54X"), None);
+        assert_eq!(zone_crs((53, true)), "EPSG:32653");
+        assert_eq!(zone_crs((54, true)), "EPSG:32654");
+        assert_eq!(zone_crs((60, false)), "EPSG:32760");
+    }
+
+    #[test]
+    fn test_zone_of() {
+        // ゾーン53と54の境界は東経138度
+        assert_eq!(zone_of(137.99), 53);
+        assert_eq!(zone_of(138.0), 54);
+        // 那覇 (52N)、札幌 (54N)
+        assert_eq!(zone_of(127.68), 52);
+        assert_eq!(zone_of(141.35), 54);
+        assert_eq!(zone_of(180.0), 60);
+        assert_eq!(zone_of(-180.0), 1);
+    }
+}